@@ -0,0 +1,191 @@
+//! Getting the shared secret from peers.
+//!
+//! A joining node asks its attested peers for the secret one at a time, giving each the current
+//! backoff to answer before moving on to the next. Peers that don't hold the secret yet simply
+//! don't answer. Once every peer was asked the backoff doubles and a new round starts, and the
+//! node gives up after a deadline rather than waiting forever.
+
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, time::Duration};
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct SecretAcquisitionConfig {
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    /// Give up on getting the secret after this long.
+    pub deadline_secs: u64,
+}
+
+impl Default for SecretAcquisitionConfig {
+    fn default() -> Self {
+        Self {
+            initial_backoff_ms: 500,
+            max_backoff_ms: 10_000,
+            deadline_secs: 5 * 60,
+        }
+    }
+}
+
+/// Where the node is at getting the shared secret, see [`crate::LightClientHandler::status`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum SecretStatus {
+    /// No attested peer to ask yet.
+    WaitingForPeers {
+        attempts: u32,
+    },
+    /// Asked `peer` (hex encoded, `None` when broadcast) and waiting for an answer.
+    Requested {
+        peer: Option<String>,
+        attempts: u32,
+    },
+    Held,
+    Failed {
+        reason: String,
+    },
+}
+
+/// What to do on the next attempt.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Attempt {
+    Ask(Vec<u8>),
+    /// We don't know who's attested, ask everyone.
+    Broadcast,
+    WaitForPeers,
+}
+
+pub(crate) struct Acquisition {
+    config: SecretAcquisitionConfig,
+    deadline: Instant,
+    backoff: Duration,
+    next_attempt: Instant,
+    /// Peers asked this round.
+    asked: HashSet<Vec<u8>>,
+    attempts: u32,
+}
+
+impl Acquisition {
+    pub fn new(config: SecretAcquisitionConfig, now: Instant) -> Self {
+        Self {
+            deadline: now + Duration::from_secs(config.deadline_secs),
+            backoff: Duration::from_millis(config.initial_backoff_ms),
+            next_attempt: now,
+            asked: HashSet::new(),
+            attempts: 0,
+            config,
+        }
+    }
+
+    pub fn next_attempt(&self) -> Instant {
+        self.next_attempt
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// A peer got attested, no need to wait out the backoff if we had no one to ask.
+    pub fn peer_joined(&mut self, now: Instant) {
+        if self.asked.is_empty() {
+            self.next_attempt = self.next_attempt.min(now);
+        }
+    }
+
+    /// Picks the next peer among the `attested` ones, if known, and schedules the attempt after
+    /// it. Errors once past the deadline.
+    pub fn attempt(
+        &mut self,
+        attested: Option<&[Vec<u8>]>,
+        now: Instant,
+    ) -> anyhow::Result<Attempt> {
+        if now >= self.deadline {
+            return Err(anyhow::anyhow!(
+                "no peer sent the shared secret within {}s ({} requests)",
+                self.config.deadline_secs,
+                self.attempts
+            ));
+        }
+
+        let Some(attested) = attested else {
+            self.attempts += 1;
+            self.back_off();
+            self.next_attempt = now + self.backoff;
+            return Ok(Attempt::Broadcast);
+        };
+
+        let mut next = attested.iter().find(|peer| !self.asked.contains(*peer));
+        if next.is_none() && !attested.is_empty() {
+            // NB: everyone was asked, start a new round.
+            self.asked.clear();
+            self.back_off();
+            next = attested.first();
+        }
+
+        let attempt = match next {
+            Some(peer) => {
+                self.asked.insert(peer.clone());
+                self.attempts += 1;
+                Attempt::Ask(peer.clone())
+            }
+            None => {
+                self.back_off();
+                Attempt::WaitForPeers
+            }
+        };
+        self.next_attempt = now + self.backoff;
+
+        Ok(attempt)
+    }
+
+    fn back_off(&mut self) {
+        self.backoff = (self.backoff * 2).min(Duration::from_millis(self.config.max_backoff_ms));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn asks_peers_in_turn_until_deadline() {
+        let start = Instant::now();
+        let mut acquisition = Acquisition::new(
+            SecretAcquisitionConfig {
+                initial_backoff_ms: 100,
+                max_backoff_ms: 300,
+                deadline_secs: 1,
+            },
+            start,
+        );
+        let ms = |ms| start + Duration::from_millis(ms);
+
+        assert_eq!(
+            acquisition.attempt(Some(&[]), start).unwrap(),
+            Attempt::WaitForPeers
+        );
+        assert_eq!(acquisition.next_attempt(), ms(200));
+        acquisition.peer_joined(ms(50));
+        assert_eq!(acquisition.next_attempt(), ms(50));
+
+        let peers = [vec![1], vec![2]];
+        assert_eq!(
+            acquisition.attempt(Some(&peers), ms(50)).unwrap(),
+            Attempt::Ask(vec![1])
+        );
+        assert_eq!(
+            acquisition.attempt(Some(&peers), ms(250)).unwrap(),
+            Attempt::Ask(vec![2])
+        );
+        // A new round, with a longer backoff.
+        assert_eq!(
+            acquisition.attempt(Some(&peers), ms(450)).unwrap(),
+            Attempt::Ask(vec![1])
+        );
+        assert_eq!(acquisition.next_attempt(), ms(750));
+        assert_eq!(acquisition.attempts(), 3);
+
+        assert!(acquisition.attempt(Some(&peers), ms(1000)).is_err());
+    }
+}
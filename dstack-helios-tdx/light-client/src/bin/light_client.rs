@@ -1,9 +1,28 @@
-use anyhow::Result;
-use light_client::LightClientHandler;
-use overlay::utils::setup_overlay_from_config;
+use anyhow::{Context, Result};
+use light_client::{
+    acquisition::SecretAcquisitionConfig,
+    cluster_pubkey,
+    helios::Cosigning,
+    keys::ClusterKeys,
+    quorum::QuorumConfig,
+    rotation::RotationConfig,
+    sealing::{SealedSecret, SealedState},
+    sharing::SharingConfig,
+    state::StateConfig,
+    threshold::ThresholdSigningConfig,
+    LightClientHandler,
+};
+use mocks::attestation::{AttestationPolicy, ProviderConfig, VerificationCacheConfig};
+use overlay::{
+    cluster::SplitBrainPolicy, election::ElectionConfig, genesis::Genesis,
+    utils::setup_overlay_from_config, OverlayContext,
+};
+use secp256k1::{PublicKey, Secp256k1};
 use serde::Deserialize;
 use std::convert::Infallible;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::{oneshot, Mutex};
 use warp::Filter;
 
@@ -15,6 +34,77 @@ mod config_server {
         pub peers: Vec<String>,
         pub port: u16,
         pub execution_rpc: String,
+        /// Attestation backend, defaults to the one selected by the `tdx` feature.
+        #[serde(default)]
+        pub attestation: ProviderConfig,
+        /// What we require from peers' quotes, e.g. which TCB levels we accept.
+        #[serde(default)]
+        pub attestation_policy: AttestationPolicy,
+        /// Challenge connected peers for a fresh quote this often, disabled by default.
+        #[serde(default)]
+        pub reattestation_interval_secs: Option<u64>,
+        /// What to do with peers of another cluster: `refuse` (the default), `isolate` or
+        /// `follow_older`.
+        #[serde(default)]
+        pub split_brain: SplitBrainPolicy,
+        /// How many quote verification results we keep around, and for how long.
+        #[serde(default)]
+        pub verification_cache: VerificationCacheConfig,
+        /// Seal the shared secret there, under a key derived by the TEE, so that restarts don't
+        /// depend on live peers. Disabled by default.
+        #[serde(default)]
+        pub sealed_secret_path: Option<PathBuf>,
+        /// Hex encoded public key of the cluster to join. Shared secrets that don't derive it are
+        /// rejected and the peers sending them recorded as misbehaving.
+        #[serde(default)]
+        pub cluster_pubkey: Option<String>,
+        /// How long to keep asking peers for the shared secret, and how often.
+        #[serde(default)]
+        pub secret_acquisition: SecretAcquisitionConfig,
+        /// Rotate the shared secret every so often, disabled by default.
+        #[serde(default)]
+        pub secret_rotation: RotationConfig,
+        /// Split the shared secret among members instead of replicating it, disabled by default.
+        #[serde(default)]
+        pub secret_sharing: Option<SharingConfig>,
+        /// Sign responses along with other nodes under a key split among them, instead of under
+        /// the cluster key. Disabled by default.
+        #[serde(default)]
+        pub threshold_signing: Option<ThresholdSigningConfig>,
+        /// Have attested peers answer `/call`s along with us, and only serve answers they agree
+        /// on. Disabled by default.
+        #[serde(default)]
+        pub quorum_calls: Option<QuorumConfig>,
+        /// Elect a leader among members, the only one to propose rotations. Disabled by default.
+        #[serde(default)]
+        pub leader_election: Option<ElectionConfig>,
+        /// Replicate a key-value state across members, sealed to disk when given a path. Disabled
+        /// by default.
+        #[serde(default)]
+        pub replicated_state: Option<StateConfig>,
+    }
+
+    impl NodeConfig {
+        /// Rejects settings the attestation backend can't honour, rather than silently ignoring
+        /// them.
+        pub fn validate(&self) -> anyhow::Result<()> {
+            let sealing = self.sealed_secret_path.is_some()
+                || self
+                    .replicated_state
+                    .as_ref()
+                    .is_some_and(|state| state.sealed_path.is_some());
+            if sealing && !self.attestation.can_seal() {
+                anyhow::bail!(
+                    "the attestation backend can't derive a sealing key, unset sealed_secret_path \
+                     and replicated_state.sealed_path or use the dstack_guest backend"
+                );
+            }
+            // NB: members sharing the secret don't hold the cluster key to sign responses under.
+            if self.secret_sharing.is_some() && self.threshold_signing.is_none() {
+                anyhow::bail!("secret_sharing takes threshold_signing to sign responses");
+            }
+            Ok(())
+        }
     }
 
     pub async fn load_config_server(config_oneshot_sender: oneshot::Sender<NodeConfig>) {
@@ -73,39 +163,200 @@ async fn main() -> Result<()> {
     let config = config_rx.await?;
     server_handle.await?; // this should have already shut down
     tracing::info!("received configuration: {:?}", config);
+    config.validate()?;
 
+    let expected_pubkey = config
+        .cluster_pubkey
+        .as_deref()
+        .map(str::parse::<PublicKey>)
+        .transpose()?;
     let secret_key = mocks::get_node_secret();
-    let (comms_receiver, broadcast_tx, peers, mut handles) =
-        setup_overlay_from_config(secret_key, config.peers, config.port).await?;
+    let node_pubkey = secret_key
+        .public_key(&Secp256k1::new())
+        .serialize()
+        .to_vec();
+    let attestation = config.attestation.build()?;
+    let mut overlay = OverlayContext::new(attestation.clone())
+        .with_policy(config.attestation_policy)
+        .with_verification_cache(config.verification_cache)
+        .with_split_brain_policy(config.split_brain);
+    if let Some(interval) = config.reattestation_interval_secs {
+        overlay = overlay.with_reattestation(Duration::from_secs(interval));
+    }
+    let peer_events = overlay.peers.subscribe();
+    let peer_registry = overlay.peers.clone();
     let (oneshot_send, oneshot_rx) = tokio::sync::oneshot::channel();
 
-    let light_client_task = tokio::spawn(async move {
-        light_client::helios::run(oneshot_rx, config.execution_rpc)
-            .await
-            .map(|_| ())
+    let sealed = match &config.sealed_secret_path {
+        Some(path) => {
+            let sealed = SealedSecret::new(attestation.as_ref(), path)
+                .await?
+                .context("attestation backend can't derive a sealing key")?;
+            Some(sealed)
+        }
+        None => None,
+    };
+    let unsealed = sealed.as_ref().and_then(|sealed| {
+        sealed
+            .unseal()
+            .inspect_err(|e| tracing::warn!("ignoring sealed secret: {e}"))
+            .ok()
+            .flatten()
+            .filter(|unsealed| {
+                let matches = expected_pubkey.is_none()
+                    || unsealed
+                        .secret
+                        .as_deref()
+                        .is_none_or(|secret| cluster_pubkey(secret).ok() == expected_pubkey);
+                if !matches {
+                    tracing::warn!("ignoring sealed secret of another cluster");
+                }
+                matches
+            })
     });
 
-    let shared_secret = if peers.is_empty() {
+    let sealed_state = match config
+        .replicated_state
+        .as_ref()
+        .and_then(|state| state.sealed_path.as_ref())
+    {
+        Some(path) => {
+            let sealed = SealedState::new(attestation.as_ref(), path)
+                .await?
+                .context("attestation backend can't derive a sealing key")?;
+            Some(sealed)
+        }
+        None => None,
+    };
+
+    let mut share = None;
+    let mut signing_share = None;
+    let mut bootstrapped = false;
+    let (shared_secret, genesis, history) = if let Some(unsealed) = unsealed {
+        tracing::info!("Unsealed shared secret from a previous run");
+        // NB: only a share when sharing, the secret itself is never held.
+        share = unsealed.share;
+        signing_share = unsealed.signing_share;
+        (unsealed.secret, unsealed.genesis, unsealed.history)
+    } else if config.peers.is_empty() {
+        if expected_pubkey.is_some() {
+            anyhow::bail!("No peers provided to join the configured cluster");
+        }
         tracing::info!("No peers provided, bootstrapping");
-        Some(mocks::get_node_secret().secret_bytes().to_vec())
+        let secret = mocks::get_node_secret().secret_bytes();
+        let signing_key = ClusterKeys::new(&secret)?.signing_key();
+        let genesis = Genesis::create(&signing_key, attestation.as_ref(), &overlay.policy).await?;
+        tracing::info!("created cluster {}", hex::encode(genesis.id()));
+        bootstrapped = true;
+        (Some(secret.to_vec()), Some(genesis), Default::default())
     } else {
         tracing::info!("Got peers, skipping bootstrapping");
-        None
+        (None, None, Default::default())
     };
 
+    // NB: set before connecting, peers of another cluster are told apart in the handshake.
+    overlay.cluster.set(genesis.clone());
+    // Us and the peers we're configured with, what the leader election counts a majority of.
+    let configured_members = config.peers.len() + 1;
+    let (comms_receiver, broadcast_tx, _, mut handles) =
+        setup_overlay_from_config(secret_key, overlay.clone(), config.peers, config.port).await?;
     let solver = LightClientHandler::new(
         comms_receiver,
         broadcast_tx.clone(),
         oneshot_send,
         shared_secret,
-    );
+    )
+    .with_peer_events(peer_events)
+    .with_peer_registry(peer_registry)
+    .with_cluster_identity(overlay.cluster.clone())
+    .with_secret_acquisition(config.secret_acquisition)
+    .with_key_history(history)
+    .with_rotation(config.secret_rotation);
+    let solver = match expected_pubkey {
+        Some(cluster_pubkey) => solver.with_cluster_pubkey(cluster_pubkey),
+        None => solver,
+    };
+    let solver = match config.secret_sharing {
+        Some(sharing) => solver.with_secret_sharing(sharing, node_pubkey.clone()),
+        None => solver,
+    };
+    let solver = match share {
+        Some(share) => solver.with_share(share),
+        None => solver,
+    };
+    let solver = match config.threshold_signing {
+        Some(threshold) => solver.with_threshold_signing(threshold, node_pubkey.clone()),
+        None => solver,
+    };
+    // NB: the bootstrap node asks the first signers to generate the signing key.
+    let solver = if bootstrapped {
+        solver.with_key_generation()
+    } else {
+        solver
+    };
+    let solver = match signing_share {
+        Some(signing_share) => solver.with_signing_share(signing_share),
+        None => solver,
+    };
+    let solver = match config.quorum_calls {
+        Some(quorum) => solver.with_quorum_calls(quorum, secret_key),
+        None => solver,
+    };
+    let solver = match config.replicated_state {
+        Some(state) => solver.with_replicated_state(state, node_pubkey.clone(), sealed_state),
+        None => solver,
+    };
+    let solver = match config.leader_election {
+        Some(election) => solver.with_leader_election(
+            ElectionConfig {
+                cluster_size: election.cluster_size.or(Some(configured_members)),
+                ..election
+            },
+            node_pubkey,
+        ),
+        None => solver,
+    };
+    let solver = match sealed {
+        Some(sealed) => solver.with_sealed_secret(sealed),
+        None => solver,
+    };
+    let mut solver = match genesis {
+        Some(genesis) => solver.with_genesis(genesis),
+        None => solver,
+    };
+    let status = solver.status();
+    let genesis = solver.genesis();
+    let keys = solver.keys();
+    let cosigning = Cosigning {
+        threshold: solver.threshold_signer(),
+        threshold_checks: solver.threshold_checks(),
+        quorum: solver.quorum_calls(),
+    };
     let solver_task = tokio::spawn(async move { solver.handle_messages().await.map(|_| ()) });
+    let light_client_task = tokio::spawn(async move {
+        light_client::helios::run(
+            oneshot_rx,
+            config.execution_rpc,
+            overlay,
+            status,
+            genesis,
+            keys,
+            cosigning,
+        )
+        .await
+        .map(|_| ())
+    });
 
     handles.push(solver_task);
     handles.push(light_client_task);
 
+    // NB: any task stopping takes the node down, e.g. when we couldn't get the shared secret.
+    let mut tasks = tokio::task::JoinSet::new();
     for handle in handles {
-        handle.await.unwrap().unwrap();
+        tasks.spawn(async move { handle.await? });
+    }
+    while let Some(result) = tasks.join_next().await {
+        result??;
     }
     Ok(())
 }
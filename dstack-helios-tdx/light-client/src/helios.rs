@@ -1,25 +1,64 @@
+use crate::acquisition::SecretStatus;
+use crate::quorum::{Outcome, QuorumCalls};
+use crate::rotation::CurrentKeys;
+use crate::threshold::{ResponseCheck, ThresholdSigner};
 use alloy::primitives::Bytes;
 use alloy::rpc::types::TransactionRequest;
 use anyhow::{anyhow, Result};
 use helios::ethereum::{
     config::networks::Network, database::FileDB, EthereumClient, EthereumClientBuilder,
 };
+use mocks::attestation::{verify_event_log, AttestationProvider, EventLog, QuoteReport};
+use overlay::{genesis::Genesis, message::SignedResponse, OverlayContext};
 use secp256k1::{Message, Secp256k1, SecretKey};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::path::PathBuf;
 use std::sync::Arc;
-use warp::Filter;
+use tokio::sync::{mpsc, watch};
+use warp::{http::StatusCode, Filter, Reply};
+
+/// How far behind our latest block a block number we're asked to co-sign may be: peers see new
+/// blocks at slightly different times.
+const MAX_BLOCK_LAG: u64 = 2;
+
+/// Other nodes vouching for responses along with us, when enabled.
+#[derive(Default)]
+pub struct Cosigning {
+    /// Signs `/block` and `/call` responses instead of the cluster key.
+    pub threshold: Option<ThresholdSigner>,
+    /// Responses other signers ask us to sign, checked against our view first.
+    pub threshold_checks: Option<mpsc::Receiver<ResponseCheck>>,
+    /// Has peers answer `/call`s along with us.
+    pub quorum: Option<QuorumCalls>,
+}
 
 pub async fn run(
-    rx: tokio::sync::oneshot::Receiver<Vec<u8>>,
+    rx: tokio::sync::oneshot::Receiver<()>,
     execution_rpc: String,
+    overlay: OverlayContext,
+    status: watch::Receiver<SecretStatus>,
+    genesis: watch::Receiver<Option<Genesis>>,
+    keys: watch::Receiver<Option<CurrentKeys>>,
+    cosigning: Cosigning,
 ) -> Result<()> {
     tracing::info!("starting dstack light client awaiting for shared secret");
 
-    match rx.await {
-        Ok(node_secret_key) => {
-            run_server(node_secret_key.try_into().unwrap(), execution_rpc).await?;
+    // NB: only the status is served until we get the secret.
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+    let (_addr, status_server) = warp::serve(status_route(status.clone()))
+        .bind_with_graceful_shutdown(([0, 0, 0, 0], 3032), async {
+            shutdown_rx.await.ok();
+        });
+    let status_server = tokio::spawn(status_server);
+    let held = rx.await;
+    let _ = shutdown_tx.send(());
+    status_server.await?;
+
+    match held {
+        // NB: we sign under `keys`, they follow the rotations of the secret.
+        Ok(_) => {
+            run_server(execution_rpc, overlay, status, genesis, keys, cosigning).await?;
         }
         Err(_) => {
             panic!("channel dropped");
@@ -35,24 +74,124 @@ pub enum CallResponse {
     Error,
 }
 
-async fn get_attestation_handler(pubkey: [u8; 33]) -> Result<impl warp::Reply, warp::Rejection> {
-    let quote = mocks::get_quote(&pubkey)
+/// Where the node is at getting the shared secret.
+fn status_route(
+    status: watch::Receiver<SecretStatus>,
+) -> impl Filter<Extract = (warp::reply::Json,), Error = warp::Rejection> + Clone {
+    warp::path("status").map(move || warp::reply::json(&*status.borrow()))
+}
+
+async fn get_attestation_handler(
+    pubkey: Option<[u8; 33]>,
+    attestation: Arc<dyn AttestationProvider>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let pubkey = pubkey.ok_or_else(warp::reject::not_found)?;
+    let quote = attestation
+        .get_quote(&pubkey)
         .await
         .map_err(|_| warp::reject())?;
+    let mut response = serde_json::json!({"quote": quote, "pubkey": hex::encode(&pubkey)});
+    if let Some(event_log) = attestation.event_log().await.map_err(|_| warp::reject())? {
+        response["event_log"] = serde_json::json!(event_log);
+    }
+
+    Ok(warp::reply::json(&response.to_string()))
+}
+
+#[derive(Deserialize)]
+pub struct VerifyRequest {
+    pub quote: String,
+    /// Hex encoded pubkey the quote should commit to.
+    pub pubkey: String,
+    /// Event log from the `/attest` output, replayed against the quote's RTMRs.
+    #[serde(default)]
+    pub event_log: Option<EventLog>,
+}
+
+#[derive(Serialize)]
+pub struct VerifyResponse {
+    /// Whether the quote verified and satisfies our attestation policy.
+    pub valid: bool,
+    pub report: Option<QuoteReport>,
+    /// Why the quote was rejected.
+    pub error: Option<String>,
+}
+
+/// Verifies someone else's `/attest` output the same way we verify peers.
+async fn verify_attestation_handler(
+    request: VerifyRequest,
+    overlay: OverlayContext,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let pubkey = hex::decode(&request.pubkey).map_err(|_| warp::reject())?;
+    let verified = overlay
+        .verify_quote(&request.quote, &pubkey)
+        .await
+        .and_then(|mut report| {
+            if let Some(event_log) = &request.event_log {
+                report.events = verify_event_log(event_log, &report)?;
+            }
+            Ok(report)
+        });
+    let response = match verified {
+        Ok(report) => {
+            let verdict = overlay.policy.check(&report);
+            VerifyResponse {
+                valid: verdict.is_ok(),
+                error: verdict.err().map(|e| e.to_string()),
+                report: Some(report),
+            }
+        }
+        Err(e) => VerifyResponse {
+            valid: false,
+            report: None,
+            error: Some(e.to_string()),
+        },
+    };
 
     Ok(warp::reply::json(
-        &serde_json::json!({"quote": quote, "pubkey": hex::encode(&pubkey)}).to_string(),
+        &serde_json::to_string(&response).unwrap(),
     ))
 }
 
+/// Verifies a cluster's genesis record: its signature, and the bootstrap quote against our policy.
+async fn verify_genesis_handler(
+    genesis: Genesis,
+    overlay: OverlayContext,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let response = match overlay.verify_genesis(&genesis).await {
+        Ok(report) => VerifyResponse {
+            valid: true,
+            report: Some(report),
+            error: None,
+        },
+        Err(e) => VerifyResponse {
+            valid: false,
+            report: None,
+            error: Some(e.to_string()),
+        },
+    };
+
+    Ok(warp::reply::json(&response))
+}
+
 async fn run_server(
-    node_secret_key: [u8; 32],
-    untrusted_rpc_url: String
+    untrusted_rpc_url: String,
+    overlay: OverlayContext,
+    status: watch::Receiver<SecretStatus>,
+    genesis: watch::Receiver<Option<Genesis>>,
+    mut keys: watch::Receiver<Option<CurrentKeys>>,
+    Cosigning {
+        threshold,
+        threshold_checks,
+        quorum,
+    }: Cosigning,
 ) -> anyhow::Result<()> {
     tracing::info!("got secret, starting helios light client");
-    let secp = Secp256k1::new();
-    let secret_key = secp256k1::SecretKey::from_byte_array(&node_secret_key)?;
-    tracing::info!("node pubkey {}", secret_key.public_key(&secp));
+    keys.wait_for(Option::is_some).await?;
+    tracing::info!(
+        "node pubkey {}",
+        keys.borrow().as_ref().expect("waited for the keys").pubkey
+    );
     tracing::info!("Using untrusted RPC URL {}", untrusted_rpc_url);
 
     let consensus_rpc = "https://www.lightclientdata.org";
@@ -78,14 +217,55 @@ async fn run_server(
     tracing::info!("client synced");
 
     let client = Arc::new(client);
+    // NB: calls forwarded by peers are answered from our view, see `crate::quorum`.
+    let quorum = quorum.map(
+        |QuorumCalls {
+             caller,
+             mut forwarded,
+         }| {
+            let client = client.clone();
+            tokio::spawn(async move {
+                while let Some(call) = forwarded.recv().await {
+                    let client = client.clone();
+                    tokio::spawn(async move {
+                        let Ok(tx) = serde_json::from_str::<TransactionRequest>(&call.tx) else {
+                            return;
+                        };
+                        let response = execute_call(&client, &tx).await;
+                        let _ = call.reply.send(serde_json::to_string(&response).unwrap());
+                    });
+                }
+            });
+            caller
+        },
+    );
+    // NB: we only sign what we got the same from our view, see `crate::threshold`.
+    if let Some(mut checks) = threshold_checks {
+        let client = client.clone();
+        tokio::spawn(async move {
+            while let Some(check) = checks.recv().await {
+                let client = client.clone();
+                tokio::spawn(async move {
+                    let agreed = check_response(&client, &check.response).await;
+                    let _ = check.reply.send(agreed);
+                });
+            }
+        });
+    }
     let get_trusted_block = warp::path("block").and_then({
         let client = client.clone();
-        let node_secret_key = node_secret_key;
+        let keys = keys.clone();
+        let threshold = threshold.clone();
         move || {
             let client = client.clone();
+            let signing_key = signing_key(&keys);
+            let threshold = threshold.clone();
             async move {
                 let block = client.get_block_number().await.unwrap().to_string();
-                let signature = sign_message(node_secret_key, &block);
+                let signed = SignedResponse::Block {
+                    number: block.clone(),
+                };
+                let signature = sign_response(signing_key, threshold.as_ref(), signed).await?;
                 Ok::<_, warp::Rejection>(warp::reply::json(
                     &serde_json::json!({"signature": hex::encode(&signature), "blocknum": block})
                         .to_string(),
@@ -95,34 +275,115 @@ async fn run_server(
     });
 
     let get_attestation = warp::path("attest").and_then({
-        let pubkey = get_pubkey(node_secret_key);
-        move || async move { get_attestation_handler(pubkey).await }
+        let keys = keys.clone();
+        let attestation = overlay.attestation.clone();
+        let threshold = threshold.clone();
+        // NB: with threshold signing responses are signed under the group key, once dealt.
+        move || {
+            let pubkey = match &threshold {
+                Some(threshold) => threshold
+                    .group_pubkey()
+                    .and_then(|pubkey| pubkey.try_into().ok()),
+                None => signing_key(&keys).map(get_pubkey),
+            };
+            get_attestation_handler(pubkey, attestation.clone())
+        }
+    });
+
+    // NB: clients follow `links` from the genesis key to the one responses are signed under.
+    let get_keys = warp::path!("keys").map({
+        let keys = keys.clone();
+        let threshold = threshold.clone();
+        move || {
+            let keys = keys.borrow();
+            let current = keys.as_ref().expect("started with the secret");
+            warp::reply::json(&serde_json::json!({
+                "epoch": current.links.len(),
+                "pubkey": current.pubkey.to_string(),
+                "links": current.links,
+                "pending": current.pending,
+                "threshold_pubkey": threshold
+                    .as_ref()
+                    .and_then(ThresholdSigner::group_pubkey)
+                    .map(hex::encode),
+            }))
+        }
+    });
+
+    // NB: hit/miss counters of the quote verification cache.
+    let verification_cache_stats = warp::path!("attest" / "cache").map({
+        let cache = overlay.verification_cache.clone();
+        move || warp::reply::json(&cache.stats())
+    });
+
+    let verify_attestation = warp::path!("attest" / "verify")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and_then({
+            let overlay = overlay.clone();
+            move |request: VerifyRequest| verify_attestation_handler(request, overlay.clone())
+        });
+
+    // NB: `None` for clusters bootstrapped before genesis records.
+    let get_genesis = warp::path!("genesis").and_then(move || {
+        let genesis = genesis.borrow().clone();
+        async move {
+            genesis
+                .map(|genesis| warp::reply::json(&genesis))
+                .ok_or_else(warp::reject::not_found)
+        }
     });
 
+    let verify_genesis = warp::path!("genesis" / "verify")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and_then(move |genesis: Genesis| verify_genesis_handler(genesis, overlay.clone()));
+
     let rpc_call = warp::path("call")
         .and(warp::post())
         .and(warp::body::json())
         .and_then(move |tx: TransactionRequest| {
             let client = client.clone();
-            let node_secret_key = node_secret_key;
+            let signing_key = signing_key(&keys);
+            let threshold = threshold.clone();
+            let quorum = quorum.clone();
             async move {
-                let response = if let Ok(resp) = client
-                    .call(&tx, helios::common::types::BlockTag::Latest)
-                    .await
-                {
-                    CallResponse::Success(resp)
-                } else {
-                    CallResponse::Error
+                let response = execute_call(&client, &tx).await;
+                let message = serde_json::to_string(&response).unwrap();
+                let mut attestations = None;
+                if let Some(quorum) = quorum {
+                    let call = serde_json::to_string(&tx).unwrap();
+                    match quorum.attest(call, message.clone()).await {
+                        Ok(Outcome::Agreed(answers)) => attestations = Some(answers),
+                        Ok(Outcome::Diverged(answers)) => {
+                            tracing::warn!("peers answered a call otherwise than us");
+                            let divergence = serde_json::json!({ "divergence": answers });
+                            return Ok(warp::reply::with_status(
+                                warp::reply::json(&divergence.to_string()),
+                                StatusCode::CONFLICT,
+                            )
+                            .into_response());
+                        }
+                        Err(e) => {
+                            tracing::warn!("couldn't have peers answer the call: {e}");
+                            return Err(warp::reject());
+                        }
+                    }
+                }
+
+                let signed = SignedResponse::Call {
+                    tx: serde_json::to_string(&tx).unwrap(),
+                    response: message,
                 };
-                let signature =
-                    sign_message(node_secret_key, &serde_json::to_string(&response).unwrap());
-                Ok::<_, warp::Rejection>(warp::reply::json(
-                    &serde_json::json!({
-                        "signature": hex::encode(&signature),
-                        "response": response
-                    })
-                    .to_string(),
-                ))
+                let signature = sign_response(signing_key, threshold.as_ref(), signed).await?;
+                let mut reply = serde_json::json!({
+                    "signature": hex::encode(&signature),
+                    "response": response
+                });
+                if let Some(attestations) = attestations {
+                    reply["attestations"] = serde_json::json!(attestations);
+                }
+                Ok::<_, warp::Rejection>(warp::reply::json(&reply.to_string()).into_response())
             }
         });
 
@@ -131,13 +392,91 @@ async fn run_server(
         .allow_methods(vec!["GET", "POST"])
         .allow_headers(vec!["Content-Type"]);
 
-    warp::serve(get_trusted_block.or(rpc_call).or(get_attestation).with(cors))
-        .run(([0, 0, 0, 0], 3032))
-        .await;
+    warp::serve(
+        get_trusted_block
+            .or(rpc_call)
+            .or(verify_attestation)
+            .or(verification_cache_stats)
+            .or(get_attestation)
+            .or(get_genesis)
+            .or(verify_genesis)
+            .or(get_keys)
+            .or(status_route(status))
+            .with(cors),
+    )
+    .run(([0, 0, 0, 0], 3032))
+    .await;
 
     Ok(())
 }
 
+async fn execute_call(client: &EthereumClient<FileDB>, tx: &TransactionRequest) -> CallResponse {
+    match client
+        .call(tx, helios::common::types::BlockTag::Latest)
+        .await
+    {
+        Ok(resp) => CallResponse::Success(resp),
+        Err(_) => CallResponse::Error,
+    }
+}
+
+/// Whether we got the same response from our view. Blocks may be up to [`MAX_BLOCK_LAG`] behind
+/// our latest one, but not ahead of it.
+async fn check_response(client: &EthereumClient<FileDB>, response: &SignedResponse) -> bool {
+    match response {
+        SignedResponse::Block { number } => {
+            let (Ok(number), Ok(latest)) = (number.parse::<u64>(), client.get_block_number().await)
+            else {
+                return false;
+            };
+            let Ok(latest) = latest.to_string().parse::<u64>() else {
+                return false;
+            };
+            number <= latest && latest - number <= MAX_BLOCK_LAG
+        }
+        SignedResponse::Call { tx, response } => {
+            let Ok(tx) = serde_json::from_str::<TransactionRequest>(tx) else {
+                return false;
+            };
+            serde_json::to_string(&execute_call(client, &tx).await).unwrap() == *response
+        }
+    }
+}
+
+/// Key responses are currently signed under, derived for it from the shared secret. `None`
+/// when we only hold a share of the secret.
+fn signing_key(keys: &watch::Receiver<Option<CurrentKeys>>) -> Option<[u8; 32]> {
+    keys.borrow()
+        .as_ref()
+        .expect("started with the secret")
+        .keys
+        .as_ref()
+        .map(|keys| keys.signing_key().secret_bytes())
+}
+
+/// Signs a response, along with the other signers when threshold signing.
+async fn sign_response(
+    signing_key: Option<[u8; 32]>,
+    threshold: Option<&ThresholdSigner>,
+    response: SignedResponse,
+) -> Result<Vec<u8>, warp::Rejection> {
+    let Some(threshold) = threshold else {
+        // NB: sharing the secret takes threshold signing, see `NodeConfig::validate`.
+        let Some(signing_key) = signing_key else {
+            tracing::warn!("no key to sign the response under");
+            return Err(warp::reject());
+        };
+        return Ok(sign_message(signing_key, response.message()).to_vec());
+    };
+    match threshold.sign(response).await {
+        Ok(signature) => Ok(signature.to_bytes().to_vec()),
+        Err(e) => {
+            tracing::warn!("couldn't threshold sign the response: {e}");
+            Err(warp::reject())
+        }
+    }
+}
+
 fn get_pubkey(secret: [u8; 32]) -> [u8; 33] {
     let secp = Secp256k1::new();
     let secret_key = SecretKey::from_slice(&secret).unwrap();
@@ -0,0 +1,105 @@
+//! Keys derived from the shared cluster secret.
+//!
+//! The replicated secret is never used as a key itself. Each use gets its own key, derived with
+//! HKDF-SHA256 under a tag naming its purpose, the same way dstack derives app keys from a path
+//! (`get_derived_key`). New uses add a [`KeyPurpose`] instead of reusing an existing key, and
+//! leaking one derived key says nothing about the secret or the other keys.
+
+use hkdf::Hkdf;
+use secp256k1::{PublicKey, Secp256k1, SecretKey};
+use sha2::Sha256;
+
+const DERIVATION_SALT: &[u8] = b"tplus/cluster-keys";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyPurpose {
+    /// Signs `/block` and `/call` responses, its public key is the cluster's.
+    Signing,
+    Encryption,
+    /// Keys of a single tenant of the cluster.
+    Tenant(String),
+    /// Keys used on a single chain, by chain ID.
+    Chain(u64),
+}
+
+impl KeyPurpose {
+    /// What the key is derived under, e.g. `signing` or `tenant/<name>`.
+    pub fn tag(&self) -> String {
+        match self {
+            Self::Signing => "signing".to_string(),
+            Self::Encryption => "encryption".to_string(),
+            Self::Tenant(name) => format!("tenant/{name}"),
+            Self::Chain(id) => format!("chain/{id}"),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ClusterKeys {
+    hkdf: Hkdf<Sha256>,
+}
+
+impl ClusterKeys {
+    pub fn new(secret: &[u8]) -> anyhow::Result<Self> {
+        if secret.len() != 32 {
+            return Err(anyhow::anyhow!("shared secrets are 32 bytes"));
+        }
+
+        Ok(Self {
+            hkdf: Hkdf::new(Some(DERIVATION_SALT), secret),
+        })
+    }
+
+    pub fn derive(&self, purpose: &KeyPurpose) -> [u8; 32] {
+        let mut key = [0; 32];
+        self.hkdf
+            .expand(purpose.tag().as_bytes(), &mut key)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        key
+    }
+
+    /// The derived key as a secp256k1 key.
+    pub fn secp256k1_key(&self, purpose: &KeyPurpose) -> SecretKey {
+        // NB: out of the curve order with negligible probability.
+        SecretKey::from_byte_array(&self.derive(purpose)).expect("derived key out of range")
+    }
+
+    pub fn signing_key(&self) -> SecretKey {
+        self.secp256k1_key(&KeyPurpose::Signing)
+    }
+
+    /// Public key the cluster signs under.
+    pub fn cluster_pubkey(&self) -> PublicKey {
+        self.signing_key().public_key(&Secp256k1::new())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn purposes_get_distinct_keys() {
+        let keys = ClusterKeys::new(&[1; 32]).unwrap();
+        let derived = [
+            keys.derive(&KeyPurpose::Signing),
+            keys.derive(&KeyPurpose::Encryption),
+            keys.derive(&KeyPurpose::Tenant("acme".into())),
+            keys.derive(&KeyPurpose::Chain(1)),
+            keys.derive(&KeyPurpose::Chain(10)),
+        ];
+        for (i, key) in derived.iter().enumerate() {
+            assert_ne!(key, &[1; 32]);
+            assert!(derived[i + 1..].iter().all(|other| other != key));
+        }
+
+        // Deterministic, so that every node derives the same keys.
+        let replica = ClusterKeys::new(&[1; 32]).unwrap();
+        assert_eq!(replica.derive(&KeyPurpose::Signing), derived[0]);
+        assert_ne!(
+            ClusterKeys::new(&[2; 32]).unwrap().cluster_pubkey(),
+            keys.cluster_pubkey()
+        );
+        assert!(ClusterKeys::new(&[1; 31]).is_err());
+    }
+}
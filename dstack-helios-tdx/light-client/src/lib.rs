@@ -1,113 +1,1818 @@
 //! This is currently just a mock, it returns the channel to be inferred in the comms task
 //! else the task will halt and comms drops.
 
+pub mod acquisition;
 pub mod helios;
+pub mod keys;
+pub mod quorum;
+pub mod rotation;
+pub mod sealing;
+pub mod sharing;
+pub mod state;
+pub mod threshold;
 
-use std::time::Duration;
+use acquisition::{Acquisition, Attempt, SecretAcquisitionConfig, SecretStatus};
+use keys::ClusterKeys;
+use quorum::{AttestRequest, Quorum, QuorumCalls, QuorumConfig};
+use rotation::{CurrentKeys, RotationConfig};
+use sharing::{Reconstruction, SharingConfig};
+use state::{ReplicatedState, Replication, StateConfig, Write};
+use threshold::{
+    Checked, ResponseCheck, SignRequest, ThresholdSigner, ThresholdSigning, ThresholdSigningConfig,
+};
 
-use overlay::message::{MaybeEncrypted, NotifySharedSecret, OverlayMessage, OverlayMessageType};
-use tokio::sync::mpsc::Receiver;
+use overlay::cluster::{ClusterConflict, ClusterIdentity, Resolution};
+use overlay::codec;
+use overlay::dkg;
+use overlay::election::{self, Election, ElectionConfig, Leadership};
+use overlay::genesis::Genesis;
+use overlay::macros::helper::make_continue;
+use overlay::message::{
+    CallMessage, DkgMessage, ElectionMessage, FrostMessage, MaybeEncrypted, NotifyShare,
+    NotifySharedSecret, NotifyState, OverlayMessage, OverlayMessageType,
+};
+use overlay::peers::{PeerEvent, PeerRegistry};
+use overlay::rotation::{follow, KeyHistory, RotationProposal};
+use overlay::shamir::{DealingSignature, DealtShare};
+use sealing::{SealedSecret, SealedState, Unsealed};
+use secp256k1::{PublicKey, SecretKey};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc::Receiver, watch};
+use tokio::time::{sleep_until, Instant};
 
 pub struct LightClientHandler {
     /// sends messages to the overlay.
     pub overlay_broadcast_tx: tokio::sync::broadcast::Sender<OverlayMessage>,
     secret: Option<Vec<u8>>,
     receiver: Receiver<OverlayMessage>,
-    oneshot_sender: Option<tokio::sync::oneshot::Sender<Vec<u8>>>,
+    /// Starts the light client once we hold the secret, or a share of it.
+    oneshot_sender: Option<tokio::sync::oneshot::Sender<()>>,
+    peer_events: Option<broadcast::Receiver<PeerEvent>>,
+    /// Where the secret is persisted once we hold it.
+    sealed: Option<SealedSecret>,
+    /// Public key of the cluster we expect to join, secrets that don't derive it are rejected.
+    cluster_pubkey: Option<PublicKey>,
+    /// Where misbehaving peers are recorded, and the attested ones we ask for the secret.
+    peers: Option<PeerRegistry>,
+    acquisition: SecretAcquisitionConfig,
+    status: watch::Sender<SecretStatus>,
+    /// Record of the cluster the secret is of, handed to joining nodes along with it.
+    genesis: watch::Sender<Option<Genesis>>,
+    /// Rotations of the secret since genesis, and the next one.
+    history: KeyHistory,
+    rotation: RotationConfig,
+    keys: watch::Sender<Option<CurrentKeys>>,
+    /// Split the secret among members instead of replicating it, see [`sharing`].
+    sharing: Option<SharingConfig>,
+    /// Pubkey we're attested under, shares dealt to it are ours.
+    node_pubkey: Vec<u8>,
+    share: Option<DealtShare>,
+    /// Our share of the secret a pending rotation switches to.
+    pending_share: Option<DealtShare>,
+    reconstruction: Reconstruction,
+    /// What we're reconstructing the secret for, it's dropped once done.
+    operation: Option<SecretOperation>,
+    /// Sign responses along with other nodes instead of under the cluster key, see [`threshold`].
+    threshold: Option<ThresholdSigning>,
+    /// Have peers answer `/call`s along with us, see [`quorum`].
+    quorum: Option<Quorum>,
+    /// Leader among members, the only one to propose rotations, see [`overlay::election`].
+    election: Option<Election>,
+    /// Key-value state replicated across members, see [`state`].
+    state: Option<Replication>,
+    /// Where the overlay gets the genesis record it hands to peers, see [`overlay::cluster`].
+    cluster: Option<ClusterIdentity>,
+    /// Genesis record ID of the older cluster we're switching to.
+    following: Option<[u8; 32]>,
+}
+
+/// What members sharing the secret reconstruct it for, see [`sharing`].
+#[derive(Debug, Clone, Copy)]
+enum SecretOperation {
+    Reshare,
+    Rotate,
+}
+
+enum Incoming {
+    Message(Option<OverlayMessage>),
+    PeerEvent(Box<PeerEvent>),
+    RequestSecret,
+    ProposeRotation,
+    ActivateRotation,
+    Reshare,
+    Sign(SignRequest),
+    Checked(Checked),
+    Attest(AttestRequest),
+    KeyGenerationTimeout,
+    ElectionTick,
+    Write(Write),
 }
 
 impl LightClientHandler {
     pub fn new(
         receiver: Receiver<OverlayMessage>,
         overlay_broadcast_tx: tokio::sync::broadcast::Sender<OverlayMessage>,
-        oneshot_sender: tokio::sync::oneshot::Sender<Vec<u8>>,
+        oneshot_sender: tokio::sync::oneshot::Sender<()>,
         secret: Option<Vec<u8>>,
     ) -> Self {
-        let oneshot_sender = if let Some(secret) = &secret {
-            let _ = oneshot_sender.send(secret.clone());
-            None
-        } else {
-            Some(oneshot_sender)
-        };
+        let (status, _) = watch::channel(match secret {
+            Some(_) => SecretStatus::Held,
+            None => SecretStatus::WaitingForPeers { attempts: 0 },
+        });
 
-        Self {
+        let handler = Self {
             overlay_broadcast_tx,
             receiver,
             secret,
-            oneshot_sender,
+            oneshot_sender: Some(oneshot_sender),
+            peer_events: None,
+            sealed: None,
+            cluster_pubkey: None,
+            peers: None,
+            acquisition: SecretAcquisitionConfig::default(),
+            status,
+            genesis: watch::channel(None).0,
+            history: KeyHistory::default(),
+            rotation: RotationConfig::default(),
+            keys: watch::channel(None).0,
+            sharing: None,
+            node_pubkey: Vec::new(),
+            share: None,
+            pending_share: None,
+            reconstruction: Reconstruction::default(),
+            operation: None,
+            threshold: None,
+            quorum: None,
+            election: None,
+            state: None,
+            cluster: None,
+            following: None,
+        };
+        handler.publish_keys();
+        handler
+    }
+
+    /// Get notified of peers joining, being re-attested and leaving, see
+    /// [`overlay::peers::PeerRegistry::subscribe`].
+    pub fn with_peer_events(mut self, peer_events: broadcast::Receiver<PeerEvent>) -> Self {
+        self.peer_events = Some(peer_events);
+        self
+    }
+
+    /// Only accept the shared secret of the cluster with this public key. Without one, the first
+    /// secret we get decides which cluster we're in and different ones are rejected after that.
+    pub fn with_cluster_pubkey(mut self, cluster_pubkey: PublicKey) -> Self {
+        self.cluster_pubkey = Some(cluster_pubkey);
+        self
+    }
+
+    /// How hard we try to get the secret from peers, see [`acquisition`].
+    pub fn with_secret_acquisition(mut self, config: SecretAcquisitionConfig) -> Self {
+        self.acquisition = config;
+        self
+    }
+
+    /// Record misbehaving peers there, see [`PeerRegistry::record_misbehaviour`]. The secret is
+    /// then requested from its attested peers one at a time instead of broadcast.
+    pub fn with_peer_registry(mut self, peers: PeerRegistry) -> Self {
+        self.peers = Some(peers);
+        self
+    }
+
+    /// Seal the secret to disk once we hold it, see [`sealing`]. A secret unsealed on startup is
+    /// what should be passed to [`LightClientHandler::new`].
+    pub fn with_sealed_secret(mut self, sealed: SealedSecret) -> Self {
+        self.sealed = Some(sealed);
+        self
+    }
+
+    /// Record of the cluster the secret passed to [`LightClientHandler::new`] is of.
+    pub fn with_genesis(self, genesis: Genesis) -> Self {
+        self.set_genesis(Some(genesis));
+        self
+    }
+
+    /// Keep the overlay's record of our cluster up to date, see [`overlay::cluster`].
+    pub fn with_cluster_identity(mut self, cluster: ClusterIdentity) -> Self {
+        cluster.set(self.genesis.borrow().clone());
+        self.cluster = Some(cluster);
+        self
+    }
+
+    /// Rotations the secret passed to [`LightClientHandler::new`] went through.
+    pub fn with_key_history(mut self, history: KeyHistory) -> Self {
+        self.history = history;
+        self.publish_keys();
+        self
+    }
+
+    /// Propose new secrets to the cluster, see [`rotation`].
+    pub fn with_rotation(mut self, config: RotationConfig) -> Self {
+        self.rotation = config;
+        self
+    }
+
+    /// Split the secret among the members we're attested to as `node_pubkey`, see [`sharing`].
+    pub fn with_secret_sharing(mut self, config: SharingConfig, node_pubkey: Vec<u8>) -> Self {
+        self.sharing = Some(config);
+        self.node_pubkey = node_pubkey;
+        self
+    }
+
+    /// Our share of the secret, unsealed on startup. Along with the genesis record, it's all a
+    /// member sharing the secret holds.
+    pub fn with_share(mut self, share: DealtShare) -> Self {
+        self.share = Some(share);
+        self
+    }
+
+    /// Sign responses along with the other nodes we're attested to as `node_pubkey`, see
+    /// [`threshold`].
+    pub fn with_threshold_signing(
+        mut self,
+        config: ThresholdSigningConfig,
+        node_pubkey: Vec<u8>,
+    ) -> Self {
+        self.threshold = Some(ThresholdSigning::new(config));
+        self.node_pubkey = node_pubkey;
+        self
+    }
+
+    /// Ask the first signers to generate the threshold signing key once they're attested. Only
+    /// for the node bootstrapping the cluster.
+    pub fn with_key_generation(mut self) -> Self {
+        if let Some(threshold) = &mut self.threshold {
+            threshold.initiate_key_generation();
         }
+        self
     }
 
-    pub async fn handle_messages(mut self) -> anyhow::Result<Self> {
-        let overlay_send = self.overlay_broadcast_tx.clone();
-        if self.secret.is_none() {
-            tokio::time::sleep(Duration::from_secs(2)).await;
-            overlay_send
+    /// Our share of the threshold signing key, unsealed on startup.
+    pub fn with_signing_share(mut self, share: DealtShare) -> Self {
+        if let Some(threshold) = &mut self.threshold {
+            threshold.set_key(share);
+        }
+        self
+    }
+
+    /// Signs along with the other nodes, when threshold signing.
+    pub fn threshold_signer(&self) -> Option<ThresholdSigner> {
+        self.threshold.as_ref().map(ThresholdSigning::signer)
+    }
+
+    /// Responses peers ask us to threshold sign, for the light client to check against its own
+    /// view first. Only handed out once.
+    pub fn threshold_checks(&mut self) -> Option<Receiver<ResponseCheck>> {
+        self.threshold.as_mut().and_then(ThresholdSigning::checks)
+    }
+
+    /// Have `config.peers` attested peers answer `/call`s along with us, signing answers under
+    /// our `node_key`.
+    pub fn with_quorum_calls(mut self, config: QuorumConfig, node_key: SecretKey) -> Self {
+        self.quorum = Some(Quorum::new(config, node_key));
+        self
+    }
+
+    /// Both ends of quorum calls for the light client, when enabled. Only handed out once.
+    pub fn quorum_calls(&mut self) -> Option<QuorumCalls> {
+        self.quorum.as_mut().and_then(Quorum::calls)
+    }
+
+    /// Elect a leader among the node and its attested peers, see [`overlay::election`].
+    pub fn with_leader_election(mut self, config: ElectionConfig, node_pubkey: Vec<u8>) -> Self {
+        self.election = Some(Election::new(node_pubkey.clone(), config, Instant::now()));
+        self.node_pubkey = node_pubkey;
+        self
+    }
+
+    /// Who leads the cluster, and when that changes, when electing one.
+    pub fn leadership(&self) -> Option<Leadership> {
+        self.election.as_ref().map(Election::leadership)
+    }
+
+    /// Replicate a key-value state across members, sealed to `sealed` when given, see [`state`].
+    pub fn with_replicated_state(
+        mut self,
+        config: StateConfig,
+        node_pubkey: Vec<u8>,
+        sealed: Option<SealedState>,
+    ) -> Self {
+        self.state = Some(Replication::new(config, node_pubkey.clone(), sealed));
+        self.node_pubkey = node_pubkey;
+        self
+    }
+
+    /// Reads and writes the replicated state, when enabled.
+    pub fn replicated_state(&self) -> Option<ReplicatedState> {
+        self.state.as_ref().map(Replication::handle)
+    }
+
+    /// The keys we currently sign under, once we hold the secret.
+    pub fn keys(&self) -> watch::Receiver<Option<CurrentKeys>> {
+        self.keys.subscribe()
+    }
+
+    /// Record of our cluster, once we hold its secret.
+    pub fn genesis(&self) -> watch::Receiver<Option<Genesis>> {
+        self.genesis.subscribe()
+    }
+
+    /// Where we're at getting the shared secret.
+    pub fn status(&self) -> watch::Receiver<SecretStatus> {
+        self.status.subscribe()
+    }
+
+    /// Whether we hold the secret, or our share of it when sharing.
+    fn holds_secret(&self) -> bool {
+        self.secret.is_some() || (self.sharing.is_some() && self.share.is_some())
+    }
+
+    /// Key of the cluster we're in. Derived from the secret, or followed from the genesis key
+    /// when we only hold a share of it.
+    fn cluster_key(&self) -> Option<PublicKey> {
+        if let Some(secret) = &self.secret {
+            return cluster_pubkey(secret).ok();
+        }
+        self.sharing.as_ref()?;
+        let genesis = self.genesis.borrow().as_ref()?.verify_signature().ok()?;
+        follow(&genesis, &self.history.links).ok()
+    }
+
+    fn publish_keys(&self) {
+        let keys = self.cluster_key().map(|pubkey| CurrentKeys {
+            pubkey,
+            keys: self
+                .secret
+                .as_ref()
+                .map(|secret| ClusterKeys::new(secret).expect("checked shared secret")),
+            links: self.history.links.clone(),
+            pending: self
+                .history
+                .pending
+                .as_ref()
+                .map(|pending| pending.link.clone()),
+        });
+        self.keys.send_replace(keys);
+    }
+
+    fn seal_secret(&self) {
+        let Some(sealed) = &self.sealed else {
+            return;
+        };
+        let mut unsealed = Unsealed {
+            secret: self.secret.clone(),
+            share: self.share.clone(),
+            signing_share: self
+                .threshold
+                .as_ref()
+                .and_then(|threshold| threshold.key().cloned()),
+            genesis: self.genesis.borrow().clone(),
+            history: self.history.clone(),
+        };
+        if self.sharing.is_some() {
+            // NB: only our share leaves memory. A pending rotation isn't sealed, nor our share of
+            // its secret, we're dealt another one once it activates.
+            unsealed.secret = None;
+            unsealed.history.pending = None;
+        }
+        if unsealed.secret.is_none() && unsealed.share.is_none() && unsealed.signing_share.is_none()
+        {
+            return;
+        }
+
+        match sealed.seal(&unsealed) {
+            Ok(()) => tracing::debug!("sealed shared secret"),
+            // NB: we still hold it in memory, we'll only depend on peers after a restart.
+            Err(e) => tracing::warn!("couldn't seal shared secret: {e}"),
+        }
+    }
+
+    /// Publishes the genesis record of the cluster we're in, to subscribers and in handshakes.
+    fn set_genesis(&self, genesis: Option<Genesis>) {
+        if let Some(cluster) = &self.cluster {
+            cluster.set(genesis.clone());
+        }
+        self.genesis.send_replace(genesis);
+    }
+
+    /// Whether `genesis` is of the older cluster we're switching to.
+    fn switching_to(&self, genesis: Option<&Genesis>) -> bool {
+        self.following.is_some() && genesis.map(Genesis::id) == self.following
+    }
+
+    /// Asks a peer of an older cluster for its secret, when our policy is to follow it.
+    fn follow_cluster(&mut self, conflict: &ClusterConflict) -> anyhow::Result<()> {
+        if conflict.resolution != Resolution::Following {
+            return Ok(());
+        }
+        // NB: a node pinned to a cluster stays in it.
+        if self.cluster_pubkey.is_some() {
+            tracing::warn!("not following an older cluster, pinned to ours");
+            return Ok(());
+        }
+        // NB: its members would have to deal us in, we never take their secret whole.
+        if self.sharing.is_some() {
+            tracing::warn!("not following an older cluster, sharing the secret");
+            return Ok(());
+        }
+        tracing::info!(
+            "switching to the older cluster {}",
+            hex::encode(conflict.theirs.id())
+        );
+        self.following = Some(conflict.theirs.id());
+        let _ = self
+            .overlay_broadcast_tx
+            .send(OverlayMessage::new_p2p_encrypted(
+                Some(vec![conflict.pubkey.clone()]),
+                codec::encode(&OverlayMessageType::RequestSharedSecret)?,
+            ));
+        Ok(())
+    }
+
+    /// Checks that `secret` is the one of the cluster we expect, or of the one we're in, and that
+    /// its genesis record is signed under it. Either may be a key the secret's was rotated from.
+    fn check_secret(
+        &self,
+        secret: &[u8],
+        genesis: Option<&Genesis>,
+        history: &KeyHistory,
+    ) -> anyhow::Result<()> {
+        if let Some(pending) = &history.pending {
+            let activated = KeyHistory {
+                links: history.links.clone(),
+                pending: None,
+            };
+            rotation::check_proposal(secret, &activated, pending)?;
+        }
+        self.check_key(&cluster_pubkey(secret)?, genesis, history)
+    }
+
+    /// Checks that `pubkey` is the key of the cluster we expect, or of the one we're in, and that
+    /// `genesis` is signed under it, like [`Self::check_secret`] for the key of a dealing.
+    fn check_key(
+        &self,
+        pubkey: &PublicKey,
+        genesis: Option<&Genesis>,
+        history: &KeyHistory,
+    ) -> anyhow::Result<()> {
+        let pubkey = *pubkey;
+        if let Some(genesis) = genesis {
+            if follow(&genesis.verify_signature()?, &history.links)? != pubkey {
+                return Err(anyhow::anyhow!("genesis record of another cluster"));
+            }
+        }
+        let expected = match (&self.cluster_pubkey, self.cluster_key()) {
+            (Some(expected), _) => *expected,
+            // NB: the older cluster's secret replaces ours.
+            (None, Some(_)) if self.switching_to(genesis) => return Ok(()),
+            (None, Some(ours)) => ours,
+            (None, None) => return Ok(()),
+        };
+        if follow(&expected, &history.links)? != pubkey {
+            return Err(anyhow::anyhow!(
+                "secret derives {pubkey}, expected {expected}"
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Whether we lead the cluster, or anyone may when there's no election.
+    fn leads(&self) -> bool {
+        self.election.as_ref().is_none_or(Election::is_leader)
+    }
+
+    fn propose_rotation(&mut self) -> anyhow::Result<()> {
+        // NB: rotations proposed at once converge, but only the leader needs to propose one.
+        if !self.leads() {
+            return Ok(());
+        }
+        if self.sharing.is_some() {
+            // NB: the bootstrap node deals the secret it generated before rotating it.
+            if self.secret.is_some() {
+                return Ok(());
+            }
+            return self.reconstruct(SecretOperation::Rotate);
+        }
+        let Some(secret) = &self.secret else {
+            return Ok(());
+        };
+        let activates_at = rotation::unix_now() + self.rotation.activation_delay_secs;
+        let proposal = rotation::propose(secret, &self.history, activates_at)?;
+        tracing::info!(
+            "proposing rotation to {} at {activates_at}",
+            hex::encode(proposal.link.new_pubkey)
+        );
+        self.accept_rotation(proposal)
+    }
+
+    /// Pends `proposal` and passes it on to our peers.
+    fn accept_rotation(&mut self, proposal: RotationProposal) -> anyhow::Result<()> {
+        let message = codec::encode(&OverlayMessageType::Rotation(proposal.clone()))?;
+        let _ = self
+            .overlay_broadcast_tx
+            .send(OverlayMessage::new_p2p_encrypted(None, message));
+        self.history.pending = Some(Box::new(proposal));
+        self.seal_secret();
+        self.publish_keys();
+        Ok(())
+    }
+
+    /// Switches to the pending secret.
+    fn activate_rotation(&mut self) {
+        let Some(pending) = self.history.pending.take() else {
+            return;
+        };
+        tracing::info!(
+            "rotated to epoch {}, signing under {}",
+            pending.link.epoch,
+            hex::encode(pending.link.new_pubkey)
+        );
+        if self.sharing.is_some() {
+            // NB: without a share of the new secret we wait to be dealt one.
+            self.share = self.pending_share.take();
+        } else {
+            self.secret = Some(pending.secret);
+        }
+        self.history.links.push(pending.link);
+        self.seal_secret();
+        self.publish_keys();
+    }
+
+    /// The attested peers and us, who the secret is dealt to.
+    fn members(&self) -> Vec<Vec<u8>> {
+        let mut members: Vec<Vec<u8>> = self
+            .peers
+            .as_ref()
+            .map(|peers| peers.list().into_iter().map(|peer| peer.pubkey).collect())
+            .unwrap_or_default();
+        members.push(self.node_pubkey.clone());
+        members
+    }
+
+    /// Deals the secret to the attested peers and us. Only the bootstrap node holds it, and
+    /// forgets it once dealt, members reconstruct it from their shares to deal it again.
+    fn reshare(&mut self) -> anyhow::Result<()> {
+        let Some(config) = self.sharing.clone() else {
+            return Ok(());
+        };
+        if !self.holds_secret() || !self.leads() {
+            return Ok(());
+        }
+        let members = self.members();
+        if members.len() < config.threshold as usize {
+            tracing::info!(
+                "{} members, waiting for {} to share the secret",
+                members.len(),
+                config.threshold
+            );
+            return Ok(());
+        }
+
+        let Some(secret) = self.secret.clone() else {
+            return self.reconstruct(SecretOperation::Reshare);
+        };
+        if let Some(ours) = self.deal(&secret, members, &self.history)? {
+            self.accept_share(ours);
+        }
+        tracing::info!("dealt the bootstrapped secret, forgetting it");
+        self.secret = None;
+        self.seal_secret();
+        self.publish_keys();
+        Ok(())
+    }
+
+    /// Deals `secret` to `members`, signed under its cluster key, and hands them their share along
+    /// with `history`. Returns ours.
+    fn deal(
+        &self,
+        secret: &[u8],
+        members: Vec<Vec<u8>>,
+        history: &KeyHistory,
+    ) -> anyhow::Result<Option<DealtShare>> {
+        let Some(config) = &self.sharing else {
+            return Ok(None);
+        };
+        let held = self.share.as_ref().map(|held| &held.dealing);
+        let (dealing, shares) = sharing::deal(secret, held, config.threshold, members)?;
+        let signature = dealing.sign(&ClusterKeys::new(secret)?.signing_key());
+        tracing::info!(
+            "dealing a secret to {} members, generation {}",
+            dealing.members.len(),
+            dealing.generation
+        );
+        let mut ours = None;
+        for share in shares {
+            let member = dealing.members[share.index as usize - 1].clone();
+            let dealt = DealtShare {
+                dealing: dealing.clone(),
+                share,
+            };
+            if member == self.node_pubkey {
+                ours = Some(dealt);
+                continue;
+            }
+            let message = codec::encode(&OverlayMessageType::Share(NotifyShare {
+                dealt,
+                genesis: self.genesis.borrow().clone(),
+                history: history.clone(),
+                signature: Some(signature.clone()),
+            }))?;
+            let _ = self
+                .overlay_broadcast_tx
+                .send(OverlayMessage::new_p2p_encrypted(
+                    Some(vec![member]),
+                    message,
+                ));
+        }
+
+        Ok(ours)
+    }
+
+    /// Has our attested peers hand over their shares so that we can `operation`, see
+    /// [`sharing`].
+    fn reconstruct(&mut self, operation: SecretOperation) -> anyhow::Result<()> {
+        let Some(dealt) = self.share.clone() else {
+            return Ok(());
+        };
+        tracing::info!("reconstructing the secret to {operation:?}");
+        self.reconstruction = Reconstruction::default();
+        self.operation = Some(operation);
+        // NB: ours counts towards the threshold.
+        self.add_share(dealt)?;
+        if self.operation.is_some() {
+            let _ = self
+                .overlay_broadcast_tx
                 .send(OverlayMessage::new_p2p_encrypted(
                     None,
-                    bincode::serialize(&OverlayMessageType::RequestSharedSecret).unwrap(),
-                ))
-                .unwrap();
+                    codec::encode(&OverlayMessageType::RequestSharedSecret)?,
+                ));
+        }
+        Ok(())
+    }
+
+    /// Adds a share handed over while we're reconstructing the secret, and runs the operation
+    /// once there are enough.
+    fn add_share(&mut self, dealt: DealtShare) -> anyhow::Result<()> {
+        let Some(operation) = self.operation else {
+            return Ok(());
+        };
+        if let Some(secret) = self.reconstruction.add(dealt)? {
+            self.operation = None;
+            self.run_operation(operation, secret)?;
+        }
+        Ok(())
+    }
+
+    /// Runs `operation` with the secret reconstructed for it, which is dropped on return.
+    fn run_operation(&mut self, operation: SecretOperation, secret: Vec<u8>) -> anyhow::Result<()> {
+        // NB: a quorum of shares of another dealing, e.g. of the secret before a rotation.
+        if Some(cluster_pubkey(&secret)?) != self.cluster_key() {
+            return Err(anyhow::anyhow!("reconstructed another secret than ours"));
         }
+        let members = self.members();
+        match operation {
+            SecretOperation::Reshare => {
+                if let Some(ours) = self.deal(&secret, members, &self.history)? {
+                    self.accept_share(ours);
+                }
+            }
+            // NB: the new secret is dealt along with its link, it never travels whole.
+            SecretOperation::Rotate => {
+                let activates_at = rotation::unix_now() + self.rotation.activation_delay_secs;
+                let proposal = rotation::propose(&secret, &self.history, activates_at)?;
+                tracing::info!(
+                    "proposing rotation to {} at {activates_at}",
+                    hex::encode(proposal.link.new_pubkey)
+                );
+                let history = KeyHistory {
+                    links: self.history.links.clone(),
+                    pending: Some(Box::new(RotationProposal {
+                        secret: Vec::new(),
+                        link: proposal.link,
+                    })),
+                };
+                self.pending_share = self.deal(&proposal.secret, members, &history)?;
+                self.history = history;
+                self.seal_secret();
+                self.publish_keys();
+            }
+        }
+        Ok(())
+    }
+
+    /// Asks the first signers to generate the threshold signing key once enough are attested, if
+    /// we're to.
+    fn start_key_generation(&mut self) -> anyhow::Result<()> {
+        let Some(threshold) = &mut self.threshold else {
+            return Ok(());
+        };
+        let mut signers: Vec<Vec<u8>> = self
+            .peers
+            .as_ref()
+            .map(|peers| peers.list().into_iter().map(|peer| peer.pubkey).collect())
+            .unwrap_or_default();
+        signers.push(self.node_pubkey.clone());
+        let outgoing = threshold.start_key_generation(signers, &self.node_pubkey)?;
+        self.send_key_generation(outgoing)
+    }
+
+    /// Runs a round of key generation, sending on what it leads to.
+    fn handle_dkg(&mut self, message: DkgMessage, from_peer: Vec<u8>) -> anyhow::Result<()> {
+        let Some(threshold) = &mut self.threshold else {
+            return Ok(());
+        };
+        let outgoing = match message {
+            DkgMessage::Start {
+                session,
+                threshold: signing_threshold,
+                members,
+            } => threshold
+                .join_key_generation(session, signing_threshold, members, &self.node_pubkey)
+                .unwrap_or_else(|e| {
+                    tracing::warn!("not generating a key with {}: {e}", hex::encode(&from_peer));
+                    Vec::new()
+                }),
+            message => match threshold.key_generation(&from_peer, message) {
+                Ok(outgoing) => outgoing,
+                Err(e) => {
+                    tracing::warn!(
+                        "rejected key generation message from {}: {e}",
+                        hex::encode(&from_peer)
+                    );
+                    if let Some(peers) = &self.peers {
+                        peers.record_misbehaviour(
+                            &from_peer,
+                            format!("sent an invalid key generation message: {e}"),
+                        );
+                    }
+                    Vec::new()
+                }
+            },
+        };
+        self.send_key_generation(outgoing)
+    }
+
+    /// Sends key generation messages on, and keeps the key once the session is over.
+    fn send_key_generation(&mut self, outgoing: Vec<dkg::Outgoing>) -> anyhow::Result<()> {
+        for (targets, message) in outgoing {
+            if targets.is_empty() {
+                continue;
+            }
+            let message = codec::encode(&OverlayMessageType::Dkg(message))?;
+            let _ = self
+                .overlay_broadcast_tx
+                .send(OverlayMessage::new_p2p_encrypted(Some(targets), message));
+        }
+
+        let Some((generated, disqualified)) = self
+            .threshold
+            .as_mut()
+            .and_then(ThresholdSigning::generated)
+        else {
+            return Ok(());
+        };
+        if let Some(peers) = &self.peers {
+            for signer in disqualified
+                .iter()
+                .filter(|signer| **signer != self.node_pubkey)
+            {
+                peers.record_misbehaviour(signer, "was disqualified from key generation");
+            }
+        }
+        match generated {
+            Ok(()) => {
+                tracing::info!("generated the signing key along with the other signers");
+                self.seal_secret();
+            }
+            Err(e) => tracing::warn!("key generation failed: {e}"),
+        }
+
+        Ok(())
+    }
+
+    /// Answers a call forwarded by a peer, or takes a peer's answer to ours.
+    fn handle_call(&mut self, message: CallMessage, from_peer: Vec<u8>) -> anyhow::Result<()> {
+        let Some(quorum) = &mut self.quorum else {
+            return Ok(());
+        };
+        match message {
+            CallMessage::Request { request, tx } => {
+                let Some(answer) = quorum.forward(request, tx) else {
+                    tracing::debug!("too many forwarded calls, not answering");
+                    return Ok(());
+                };
+                let overlay_broadcast_tx = self.overlay_broadcast_tx.clone();
+                tokio::spawn(async move {
+                    let Some(answer) = answer.await else {
+                        return;
+                    };
+                    let Ok(message) = codec::encode(&OverlayMessageType::Call(answer)) else {
+                        return;
+                    };
+                    let _ = overlay_broadcast_tx.send(OverlayMessage::new_p2p_encrypted(
+                        Some(vec![from_peer]),
+                        message,
+                    ));
+                });
+            }
+            CallMessage::Response {
+                request,
+                response,
+                signature,
+            } => {
+                if let Err(e) = quorum.answered(&from_peer, request, response, signature) {
+                    tracing::warn!("rejected call answer from {}: {e}", hex::encode(&from_peer));
+                    if let Some(peers) = &self.peers {
+                        peers.record_misbehaviour(
+                            &from_peer,
+                            format!("sent an invalid call answer: {e}"),
+                        );
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_election(
+        &mut self,
+        message: ElectionMessage,
+        from_peer: Vec<u8>,
+    ) -> anyhow::Result<()> {
+        let Some(election) = &mut self.election else {
+            return Ok(());
+        };
+        let outgoing = election.handle(&from_peer, message, Instant::now());
+        self.send_election(outgoing)
+    }
+
+    fn send_election(&self, outgoing: Vec<election::Outgoing>) -> anyhow::Result<()> {
+        for (target, message) in outgoing {
+            let message = codec::encode(&OverlayMessageType::Election(message))?;
+            let _ = self
+                .overlay_broadcast_tx
+                .send(OverlayMessage::new_p2p_encrypted(
+                    target.map(|target| vec![target]),
+                    message,
+                ));
+        }
+        Ok(())
+    }
+
+    fn handle_state(&mut self, message: NotifyState, from_peer: Vec<u8>) {
+        let Some(state) = &mut self.state else {
+            return;
+        };
+        if let Err(e) = state.merge(message.entries) {
+            tracing::warn!("rejected state from {}: {e}", hex::encode(&from_peer));
+            if let Some(peers) = &self.peers {
+                peers.record_misbehaviour(&from_peer, format!("sent invalid state: {e}"));
+            }
+        }
+    }
+
+    fn send_state(
+        &self,
+        targets: Option<Vec<Vec<u8>>>,
+        message: NotifyState,
+    ) -> anyhow::Result<()> {
+        let message = codec::encode(&OverlayMessageType::State(message))?;
+        let _ = self
+            .overlay_broadcast_tx
+            .send(OverlayMessage::new_p2p_encrypted(targets, message));
+        Ok(())
+    }
+
+    /// Runs a round of threshold signing, sending on what it leads to.
+    fn handle_frost(&mut self, message: FrostMessage, from_peer: Vec<u8>) -> anyhow::Result<()> {
+        let Some(threshold) = &mut self.threshold else {
+            return Ok(());
+        };
+        let outgoing = match message {
+            // NB: committed to once the light client got the same response, see `Incoming::Checked`.
+            FrostMessage::Commit {
+                session,
+                dealing,
+                response,
+            } => {
+                if let Some(check) = threshold.check(session, dealing, response, from_peer) {
+                    tokio::spawn(check);
+                }
+                None
+            }
+            FrostMessage::Commitment {
+                session,
+                commitment,
+            } => match threshold.committed(session, commitment, Some(from_peer.clone())) {
+                Ok(outgoing) => outgoing,
+                Err(e) => {
+                    tracing::warn!(
+                        "rejected signing commitment from {}: {e}",
+                        hex::encode(&from_peer)
+                    );
+                    if let Some(peers) = &self.peers {
+                        peers.record_misbehaviour(
+                            &from_peer,
+                            format!("sent an invalid signing commitment: {e}"),
+                        );
+                    }
+                    None
+                }
+            },
+            FrostMessage::Sign {
+                session,
+                commitments,
+            } => match threshold.sign(session, &commitments) {
+                Ok(share) => Some((
+                    Some(vec![from_peer]),
+                    FrostMessage::SignatureShare { session, share },
+                )),
+                Err(e) => {
+                    tracing::debug!("not signing for {}: {e}", hex::encode(&from_peer));
+                    None
+                }
+            },
+            FrostMessage::SignatureShare { session, share } => {
+                if let Err(e) = threshold.signature_share(session, share) {
+                    tracing::warn!(
+                        "rejected signature share from {}: {e}",
+                        hex::encode(&from_peer)
+                    );
+                    if let Some(peers) = &self.peers {
+                        peers.record_misbehaviour(
+                            &from_peer,
+                            format!("sent an invalid signature share: {e}"),
+                        );
+                    }
+                }
+                None
+            }
+        };
+
+        if let Some((targets, message)) = outgoing {
+            let message = codec::encode(&OverlayMessageType::Frost(message))?;
+            let _ = self
+                .overlay_broadcast_tx
+                .send(OverlayMessage::new_p2p_encrypted(targets, message));
+        }
+        Ok(())
+    }
+
+    /// Keeps a share dealt to us if its dealing supersedes ours, and is of the secret we hold.
+    fn accept_share(&mut self, dealt: DealtShare) {
+        let held = self.share.as_ref().map(|held| &held.dealing);
+        if !sharing::supersedes(&dealt.dealing, held) {
+            return;
+        }
+        if let Some(secret) = &self.secret {
+            // NB: dealt before a rotation we already switched from.
+            if !dealt.dealing.shares_secret(secret).unwrap_or(false) {
+                tracing::debug!("ignoring a dealing of another secret");
+                return;
+            }
+        }
+
+        tracing::info!(
+            "holding share {} of generation {}",
+            dealt.share.index,
+            dealt.dealing.generation
+        );
+        self.share = Some(dealt);
+        self.seal_secret();
+    }
+
+    /// Takes a share dealt to us under `signature`, of our cluster's secret or of the one a
+    /// pending rotation switches to. The first one makes us a member.
+    fn accept_dealt(
+        &mut self,
+        dealt: DealtShare,
+        genesis: Option<Genesis>,
+        history: KeyHistory,
+        signature: DealingSignature,
+    ) -> anyhow::Result<()> {
+        if dealt.dealing.index_of(&self.node_pubkey) != Some(dealt.share.index) {
+            return Ok(());
+        }
+        dealt.dealing.verify_signature(&signature)?;
+        let dealer = PublicKey::from_slice(&signature.pubkey)?;
+
+        // A share of the next secret, dealt along with the link to it.
+        if let Some(pending) = history
+            .pending
+            .clone()
+            .filter(|pending| pending.link.new_pubkey == signature.pubkey)
+        {
+            let ours = self
+                .cluster_key()
+                .ok_or_else(|| anyhow::anyhow!("rotation of a cluster we're not in"))?;
+            // NB: already pending, or losing to the one pending.
+            if rotation::check_link(&ours, &self.history, &pending.link)? {
+                tracing::info!(
+                    "rotating to {} at {}, holding share {} of the new secret",
+                    hex::encode(pending.link.new_pubkey),
+                    pending.link.activates_at,
+                    dealt.share.index
+                );
+                self.history.pending = Some(pending);
+                self.pending_share = Some(dealt);
+                self.publish_keys();
+            }
+            return Ok(());
+        }
+
+        self.check_key(&dealer, genesis.as_ref(), &history)?;
+        if history.epoch() > self.history.epoch() {
+            // NB: we missed rotations, the dealer's key checked out as rotated from ours.
+            self.history = KeyHistory {
+                links: history.links,
+                pending: None,
+            };
+            self.pending_share = None;
+        }
+        if !self.holds_secret() {
+            if genesis.is_none() {
+                return Err(anyhow::anyhow!("no genesis record to tell the cluster by"));
+            }
+            tracing::info!("dealt a share of the secret, starting the light client");
+            self.set_genesis(genesis);
+            self.status.send_replace(SecretStatus::Held);
+            self.share = Some(dealt);
+            self.seal_secret();
+            self.publish_keys();
+            self.start_light_client();
+            return Ok(());
+        }
+        self.accept_share(dealt);
+        self.publish_keys();
+        Ok(())
+    }
+
+    /// Takes `secret`, already checked, and hands it to the light client.
+    fn hold_secret(&mut self, secret: Vec<u8>, genesis: Option<Genesis>, history: KeyHistory) {
+        tracing::info!("received shared dstack secret, sending to helios light client task");
+        if self.switching_to(genesis.as_ref()) {
+            tracing::info!("switched to the older cluster");
+            self.following = None;
+        }
+        self.secret = Some(secret);
+        self.set_genesis(genesis);
+        self.history = history;
+        self.status.send_replace(SecretStatus::Held);
+        self.seal_secret();
+        self.publish_keys();
+        self.start_light_client();
+    }
+
+    /// Has the light client start serving, once.
+    fn start_light_client(&mut self) {
+        if let Some(sender) = self.oneshot_sender.take() {
+            if sender.send(()).is_ok() {
+                tracing::info!("started the light client")
+            } else {
+                tracing::warn!("light client is gone")
+            }
+        }
+    }
+
+    pub async fn handle_messages(mut self) -> anyhow::Result<Self> {
+        // NB: a bootstrapped secret wasn't sealed yet.
+        self.seal_secret();
+        if self.holds_secret() {
+            self.status.send_replace(SecretStatus::Held);
+            self.publish_keys();
+            self.start_light_client();
+        }
+        let mut acquisition = Acquisition::new(self.acquisition.clone(), Instant::now());
+        let rotation_interval = self.rotation.interval_secs.map(Duration::from_secs);
+        let mut next_rotation = rotation_interval.map(|interval| Instant::now() + interval);
+        let reshare_delay = self
+            .sharing
+            .as_ref()
+            .map(|config| Duration::from_secs(config.reshare_delay_secs));
+        let mut next_reshare: Option<Instant> = None;
+
+        loop {
+            let holding = self.holds_secret();
+            // NB: members sharing the secret deal it to joining nodes.
+            let acquiring = !holding && self.sharing.is_none();
+            let next_attempt = acquisition.next_attempt();
+            let pending = self.history.pending.as_ref().map(|pending| &pending.link);
+            let activation = pending.map_or_else(Instant::now, rotation::activation);
+            let rotating = holding && pending.is_none() && next_rotation.is_some();
+            let proposal = next_rotation.unwrap_or_else(Instant::now);
+            let resharing = holding && next_reshare.is_some();
+            let reshare = next_reshare.unwrap_or_else(Instant::now);
+            let round_deadline = self
+                .threshold
+                .as_ref()
+                .and_then(ThresholdSigning::key_generation_deadline);
+            let generating = round_deadline.is_some();
+            let key_generation = round_deadline.unwrap_or_else(Instant::now);
+            let election_deadline = self.election.as_ref().map(Election::deadline);
+            let electing = election_deadline.is_some();
+            let election_tick = election_deadline.unwrap_or_else(Instant::now);
+            let incoming = tokio::select! {
+                message = self.receiver.recv() => Incoming::Message(message),
+                Some(event) = next_peer_event(&mut self.peer_events) => Incoming::PeerEvent(Box::new(event)),
+                Some(incoming) = next_threshold(&mut self.threshold) => incoming,
+                Some(request) = next_attest_request(&mut self.quorum) => Incoming::Attest(request),
+                Some(write) = next_write(&mut self.state) => Incoming::Write(write),
+                _ = sleep_until(next_attempt), if acquiring => Incoming::RequestSecret,
+                _ = sleep_until(proposal), if rotating => Incoming::ProposeRotation,
+                _ = sleep_until(activation), if pending.is_some() => Incoming::ActivateRotation,
+                _ = sleep_until(reshare), if resharing => Incoming::Reshare,
+                _ = sleep_until(key_generation), if generating => Incoming::KeyGenerationTimeout,
+                _ = sleep_until(election_tick), if electing => Incoming::ElectionTick,
+            };
+            let message = match incoming {
+                Incoming::Message(Some(message)) => message,
+                Incoming::Message(None) => break,
+                Incoming::PeerEvent(event) => {
+                    if matches!(*event, PeerEvent::Attested(_)) {
+                        acquisition.peer_joined(Instant::now());
+                        if let Err(e) = self.start_key_generation() {
+                            tracing::warn!("couldn't start generating the signing key: {e}");
+                        }
+                    }
+                    // NB: membership changed, deal again once it settles.
+                    if matches!(
+                        *event,
+                        PeerEvent::Attested(_) | PeerEvent::Disconnected { .. }
+                    ) {
+                        next_reshare = reshare_delay.map(|delay| Instant::now() + delay);
+                    }
+                    if let PeerEvent::SplitBrain(conflict) = &*event {
+                        if let Err(e) = self.follow_cluster(conflict) {
+                            tracing::warn!("couldn't follow the older cluster: {e}");
+                        }
+                    }
+                    // NB: hands a joining peer what it missed, it does the same for us.
+                    if let PeerEvent::Attested(peer) = &*event {
+                        let snapshot = self.state.as_ref().and_then(Replication::snapshot);
+                        if let Some(snapshot) = snapshot {
+                            self.send_state(Some(vec![peer.pubkey.clone()]), snapshot)?;
+                        }
+                    }
+                    if let Some(election) = &mut self.election {
+                        match &*event {
+                            PeerEvent::Attested(peer) => {
+                                election.member_joined(peer.pubkey.clone())
+                            }
+                            PeerEvent::Disconnected { pubkey, .. } => election.member_left(pubkey),
+                            _ => {}
+                        }
+                    }
+                    self.handle_peer_event(*event);
+                    continue;
+                }
+                Incoming::RequestSecret => {
+                    if let Err(e) = self.request_secret(&mut acquisition) {
+                        tracing::error!("giving up on the shared secret: {e}");
+                        self.status.send_replace(SecretStatus::Failed {
+                            reason: e.to_string(),
+                        });
+                        return Err(e);
+                    }
+                    continue;
+                }
+                Incoming::ProposeRotation => {
+                    next_rotation = rotation_interval.map(|interval| Instant::now() + interval);
+                    self.propose_rotation()?;
+                    continue;
+                }
+                Incoming::ActivateRotation => {
+                    self.activate_rotation();
+                    // NB: shares of the previous secret are useless now.
+                    next_reshare = reshare_delay.map(|_| Instant::now());
+                    continue;
+                }
+                Incoming::Sign(request) => {
+                    if let Some(threshold) = &mut self.threshold {
+                        let outgoing = threshold.start(request)?;
+                        if let Some((targets, message)) = outgoing {
+                            let message = codec::encode(&OverlayMessageType::Frost(message))?;
+                            let _ = self
+                                .overlay_broadcast_tx
+                                .send(OverlayMessage::new_p2p_encrypted(targets, message));
+                        }
+                    }
+                    continue;
+                }
+                Incoming::Checked(checked) => {
+                    let commitment = self.threshold.as_mut().and_then(|threshold| {
+                        threshold.commit(checked.session, checked.dealing, checked.message)
+                    });
+                    if let Some(commitment) = commitment {
+                        let message = codec::encode(&OverlayMessageType::Frost(commitment))?;
+                        let _ = self
+                            .overlay_broadcast_tx
+                            .send(OverlayMessage::new_p2p_encrypted(
+                                Some(vec![checked.coordinator]),
+                                message,
+                            ));
+                    }
+                    continue;
+                }
+                Incoming::Attest(request) => {
+                    let attested: Vec<Vec<u8>> = self
+                        .peers
+                        .as_ref()
+                        .map(|peers| peers.list().into_iter().map(|peer| peer.pubkey).collect())
+                        .unwrap_or_default();
+                    let outgoing = self
+                        .quorum
+                        .as_mut()
+                        .and_then(|quorum| quorum.start(request, attested));
+                    if let Some((targets, message)) = outgoing {
+                        let message = codec::encode(&OverlayMessageType::Call(message))?;
+                        let _ = self
+                            .overlay_broadcast_tx
+                            .send(OverlayMessage::new_p2p_encrypted(Some(targets), message));
+                    }
+                    continue;
+                }
+                Incoming::KeyGenerationTimeout => {
+                    if let Some(threshold) = &mut self.threshold {
+                        let outgoing = threshold.key_generation_timeout();
+                        self.send_key_generation(outgoing)?;
+                    }
+                    continue;
+                }
+                Incoming::ElectionTick => {
+                    if let Some(election) = &mut self.election {
+                        let outgoing = election.tick(Instant::now());
+                        self.send_election(outgoing)?;
+                    }
+                    continue;
+                }
+                Incoming::Write(write) => {
+                    if let Some(state) = &mut self.state {
+                        let message = state.write(write);
+                        self.send_state(None, message)?;
+                    }
+                    continue;
+                }
+                Incoming::Reshare => {
+                    next_reshare = None;
+                    if let Err(e) = self.reshare() {
+                        tracing::warn!("couldn't deal the shared secret: {e}");
+                    }
+                    continue;
+                }
+            };
 
-        while let Some(message) = self.receiver.recv().await {
             match message.message {
                 MaybeEncrypted::EncryptedP2P(decrypted) => {
+                    // NB: peers are attested but we still don't want a malformed message to take
+                    // the handler down.
                     let overlay_message: OverlayMessageType =
-                        bincode::deserialize(&decrypted).unwrap();
+                        make_continue!(codec::decode(&decrypted));
 
-                    self.handle_instruction(
-                        overlay_message,
-                        message.targets.as_ref().unwrap()[0].clone(),
-                    )
-                    .await?;
-                } // NB: full impl has more messages
+                    // NB: nor a message we can't act on, whatever the reason.
+                    let from_peer = message.targets.as_ref().unwrap()[0].clone();
+                    if let Err(e) = self
+                        .handle_instruction(overlay_message, from_peer.clone())
+                        .await
+                    {
+                        tracing::warn!(
+                            "couldn't handle a message from {}: {e}",
+                            hex::encode(&from_peer)
+                        );
+                        continue;
+                    }
+                }
+                // NB: handled by the overlay, never forwarded. Full impl has more messages.
+                MaybeEncrypted::Overlay(_) => {}
             }
         }
 
         Ok(self)
     }
 
+    /// Asks the next attested peer for the secret, or everyone when we don't know who's attested.
+    fn request_secret(&mut self, acquisition: &mut Acquisition) -> anyhow::Result<()> {
+        let attested = self.peers.as_ref().map(|peers| {
+            peers
+                .list()
+                .into_iter()
+                .map(|peer| peer.pubkey)
+                .collect::<Vec<_>>()
+        });
+        let targets = match acquisition.attempt(attested.as_deref(), Instant::now())? {
+            Attempt::Ask(peer) => Some(vec![peer]),
+            // NB: the broadcast only reaches attested peers anyway.
+            Attempt::Broadcast => None,
+            Attempt::WaitForPeers => {
+                tracing::debug!("no attested peer to request the shared secret from");
+                self.status.send_replace(SecretStatus::WaitingForPeers {
+                    attempts: acquisition.attempts(),
+                });
+                return Ok(());
+            }
+        };
+
+        let peer = targets.as_ref().map(|targets| hex::encode(&targets[0]));
+        tracing::info!(
+            "requesting shared secret from {}",
+            peer.as_deref().unwrap_or("all peers")
+        );
+        self.status.send_replace(SecretStatus::Requested {
+            peer,
+            attempts: acquisition.attempts(),
+        });
+        let _ = self
+            .overlay_broadcast_tx
+            .send(OverlayMessage::new_p2p_encrypted(
+                targets,
+                codec::encode(&OverlayMessageType::RequestSharedSecret)?,
+            ));
+
+        Ok(())
+    }
+
+    fn handle_peer_event(&mut self, event: PeerEvent) {
+        match event {
+            PeerEvent::Attested(peer) => {
+                tracing::info!("peer {} joined: {}", hex::encode(peer.pubkey), peer.report)
+            }
+            PeerEvent::Reattested(peer) => {
+                tracing::debug!("peer {} re-attested", hex::encode(peer.pubkey))
+            }
+            PeerEvent::Disconnected {
+                pubkey,
+                reason: Some(reason),
+            } => tracing::warn!("dropped peer {}: {reason}", hex::encode(pubkey)),
+            PeerEvent::Disconnected {
+                pubkey,
+                reason: None,
+            } => tracing::info!("peer {} left", hex::encode(pubkey)),
+            PeerEvent::Misbehaved(misbehaviour) => tracing::warn!(
+                "peer {} misbehaved: {}",
+                hex::encode(misbehaviour.pubkey),
+                misbehaviour.reason
+            ),
+            PeerEvent::SplitBrain(conflict) => tracing::warn!(
+                "peer {} is in cluster {}: {:?}",
+                hex::encode(conflict.pubkey),
+                hex::encode(conflict.theirs.id()),
+                conflict.resolution
+            ),
+        }
+    }
+
     pub async fn handle_instruction(
         &mut self,
         message: OverlayMessageType,
-        _from_peer: Vec<u8>,
+        from_peer: Vec<u8>,
     ) -> anyhow::Result<()> {
         match message {
-            OverlayMessageType::SharedSecret(NotifySharedSecret { secret }) => {
-                tracing::info!(
-                    "received shared dstack secret, sending to helios light client task"
+            // NB: members sharing the secret never take it whole.
+            OverlayMessageType::SharedSecret(_) | OverlayMessageType::Rotation(_)
+                if self.sharing.is_some() =>
+            {
+                tracing::warn!(
+                    "ignoring a whole secret from {}, we only hold shares",
+                    hex::encode(&from_peer)
                 );
-                self.secret = Some(secret.clone());
-                if let Some(sender) = self.oneshot_sender.take() {
-                    if sender.send(secret).is_ok() {
-                        tracing::info!("sent secret to light client")
-                    } else {
-                        tracing::warn!("already sent secret to light client")
+            }
+            OverlayMessageType::SharedSecret(NotifySharedSecret {
+                secret,
+                genesis,
+                history,
+            }) => {
+                if let Err(e) = self.check_secret(&secret, genesis.as_ref(), &history) {
+                    tracing::warn!(
+                        "rejected shared secret from {}: {e}",
+                        hex::encode(&from_peer)
+                    );
+                    if let Some(peers) = &self.peers {
+                        peers.record_misbehaviour(
+                            &from_peer,
+                            format!("sent a foreign shared secret: {e}"),
+                        );
                     }
+                    return Ok(());
+                }
+                // NB: answers to our own request from other peers, unless we missed rotations.
+                if self.secret.is_some()
+                    && history.epoch() <= self.history.epoch()
+                    && !self.switching_to(genesis.as_ref())
+                {
+                    return Ok(());
+                }
+
+                self.hold_secret(secret, genesis, history);
+            }
+            OverlayMessageType::RequestSharedSecret if self.sharing.is_some() => {
+                // NB: only the leader reconstructs the secret, to deal it again or rotate it.
+                let leader = self.election.as_ref().map(Election::current_leader);
+                if leader.is_some_and(|leader| leader.as_ref() != Some(&from_peer)) {
+                    tracing::debug!("not handing our share to {}", hex::encode(&from_peer));
+                    return Ok(());
+                }
+                // NB: the secret never travels whole, we hand over our share.
+                if let Some(dealt) = &self.share {
+                    tracing::debug!("sending our share to {}", hex::encode(&from_peer));
+                    let message = codec::encode(&OverlayMessageType::Share(NotifyShare {
+                        dealt: dealt.clone(),
+                        genesis: self.genesis.borrow().clone(),
+                        history: self.history.clone(),
+                        signature: None,
+                    }))?;
+                    let _ = self
+                        .overlay_broadcast_tx
+                        .send(OverlayMessage::new_p2p_encrypted(
+                            Some(vec![from_peer]),
+                            message,
+                        ));
                 }
             }
             OverlayMessageType::RequestSharedSecret => {
                 tracing::debug!("received request to get dstack secet");
                 if let Some(secret) = &self.secret {
                     tracing::debug!("we have shared secret and will share it");
-                    let message = bincode::serialize(&OverlayMessageType::SharedSecret(
-                        NotifySharedSecret {
+                    let message =
+                        codec::encode(&OverlayMessageType::SharedSecret(NotifySharedSecret {
                             secret: secret.clone(),
-                        },
-                    ))?;
+                            genesis: self.genesis.borrow().clone(),
+                            history: self.history.clone(),
+                        }))?;
 
-                    let _ = self.overlay_broadcast_tx.send(OverlayMessage {
-                        targets: None,
-                        message: overlay::message::MaybeEncrypted::EncryptedP2P(message),
-                    });
+                    let _ = self
+                        .overlay_broadcast_tx
+                        .send(OverlayMessage::new_p2p_encrypted(
+                            Some(vec![from_peer]),
+                            message,
+                        ));
                 } else {
                     tracing::debug!("we don't have shared secret");
                 }
             }
+            OverlayMessageType::Rotation(proposal) => {
+                let Some(secret) = &self.secret else {
+                    return Ok(());
+                };
+                match rotation::check_proposal(secret, &self.history, &proposal) {
+                    Ok(true) => {
+                        tracing::info!(
+                            "rotating to {} at {}",
+                            hex::encode(proposal.link.new_pubkey),
+                            proposal.link.activates_at
+                        );
+                        self.accept_rotation(proposal)?;
+                    }
+                    // NB: already pending, or losing to the one pending.
+                    Ok(false) => {}
+                    Err(e) => {
+                        tracing::warn!("rejected rotation from {}: {e}", hex::encode(&from_peer));
+                        if let Some(peers) = &self.peers {
+                            peers.record_misbehaviour(
+                                &from_peer,
+                                format!("sent an invalid rotation: {e}"),
+                            );
+                        }
+                    }
+                }
+            }
+            OverlayMessageType::Share(NotifyShare {
+                dealt,
+                genesis,
+                history,
+                signature,
+            }) => {
+                if let Err(e) = dealt.dealing.verify_share(&dealt.share) {
+                    tracing::warn!("rejected share from {}: {e}", hex::encode(&from_peer));
+                    if let Some(peers) = &self.peers {
+                        peers
+                            .record_misbehaviour(&from_peer, format!("sent an invalid share: {e}"));
+                    }
+                    return Ok(());
+                }
+                let Some(signature) = signature else {
+                    return self.add_share(dealt);
+                };
+                if let Err(e) = self.accept_dealt(dealt, genesis, history, signature) {
+                    tracing::warn!("rejected dealt share from {}: {e}", hex::encode(&from_peer));
+                    if let Some(peers) = &self.peers {
+                        peers
+                            .record_misbehaviour(&from_peer, format!("dealt a foreign share: {e}"));
+                    }
+                }
+            }
+            OverlayMessageType::Dkg(message) => self.handle_dkg(message, from_peer)?,
+            OverlayMessageType::Call(message) => self.handle_call(message, from_peer)?,
+            OverlayMessageType::Election(message) => self.handle_election(message, from_peer)?,
+            OverlayMessageType::State(message) => self.handle_state(message, from_peer),
+            OverlayMessageType::Frost(message) => self.handle_frost(message, from_peer)?,
             _ => (),
         }
 
         Ok(())
     }
 }
+
+/// Public key the cluster with this shared secret signs under, see [`keys::ClusterKeys`].
+pub fn cluster_pubkey(secret: &[u8]) -> anyhow::Result<PublicKey> {
+    Ok(keys::ClusterKeys::new(secret)?.cluster_pubkey())
+}
+
+/// Our own signing requests, and peers' we checked. Pending forever when not threshold signing,
+/// like [`next_peer_event`].
+async fn next_threshold(threshold: &mut Option<ThresholdSigning>) -> Option<Incoming> {
+    match threshold {
+        Some(threshold) => tokio::select! {
+            Some(request) = threshold.requests.recv() => Some(Incoming::Sign(request)),
+            Some(checked) = threshold.checked.recv() => Some(Incoming::Checked(checked)),
+            else => None,
+        },
+        None => std::future::pending().await,
+    }
+}
+
+/// Pending forever without quorum calls, like [`next_peer_event`].
+async fn next_attest_request(quorum: &mut Option<Quorum>) -> Option<AttestRequest> {
+    match quorum {
+        Some(quorum) => quorum.requests.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Pending forever without replicated state, like [`next_peer_event`].
+async fn next_write(state: &mut Option<Replication>) -> Option<Write> {
+    match state {
+        Some(state) => state.writes.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Pending forever when not subscribed, so that it can sit in a `select!`.
+async fn next_peer_event(
+    peer_events: &mut Option<broadcast::Receiver<PeerEvent>>,
+) -> Option<PeerEvent> {
+    let Some(receiver) = peer_events else {
+        return std::future::pending().await;
+    };
+
+    match receiver.recv().await {
+        Ok(event) => Some(event),
+        Err(broadcast::error::RecvError::Lagged(n)) => {
+            tracing::warn!("missed {n} peer events");
+            None
+        }
+        Err(broadcast::error::RecvError::Closed) => {
+            *peer_events = None;
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mocks::attestation::MockProvider;
+
+    fn handler(secret: Option<Vec<u8>>) -> LightClientHandler {
+        let (_, receiver) = tokio::sync::mpsc::channel(1);
+        let (broadcast_tx, _) = broadcast::channel(1);
+        let (oneshot_send, _) = tokio::sync::oneshot::channel();
+        LightClientHandler::new(receiver, broadcast_tx, oneshot_send, secret)
+    }
+
+    fn shared_secret(secret: [u8; 32], genesis: Option<Genesis>) -> OverlayMessageType {
+        OverlayMessageType::SharedSecret(NotifySharedSecret {
+            secret: secret.to_vec(),
+            genesis,
+            history: Default::default(),
+        })
+    }
+
+    async fn genesis(secret: [u8; 32]) -> Genesis {
+        let signing_key = keys::ClusterKeys::new(&secret).unwrap().signing_key();
+        Genesis::create(&signing_key, &MockProvider::new(), &Default::default())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn rejects_foreign_secrets() {
+        let peers = PeerRegistry::new();
+        let expected = cluster_pubkey(&[1; 32]).unwrap();
+        let mut joining = handler(None)
+            .with_cluster_pubkey(expected)
+            .with_peer_registry(peers.clone());
+
+        joining
+            .handle_instruction(shared_secret([2; 32], None), vec![2])
+            .await
+            .unwrap();
+        // The right secret, but with the record of another cluster.
+        joining
+            .handle_instruction(
+                shared_secret([1; 32], Some(genesis([2; 32]).await)),
+                vec![5],
+            )
+            .await
+            .unwrap();
+        assert!(joining.secret.is_none());
+
+        let genesis = genesis([1; 32]).await;
+        joining
+            .handle_instruction(shared_secret([1; 32], Some(genesis.clone())), vec![1])
+            .await
+            .unwrap();
+        assert_eq!(joining.secret.as_deref(), Some(&[1; 32][..]));
+        assert_eq!(*joining.status().borrow(), SecretStatus::Held);
+        assert_eq!(*joining.genesis().borrow(), Some(genesis));
+
+        // Without an expected key, the secret we hold decides.
+        let mut member = handler(Some(vec![3; 32])).with_peer_registry(peers.clone());
+        member
+            .handle_instruction(shared_secret([4; 32], None), vec![4])
+            .await
+            .unwrap();
+        assert_eq!(member.secret.as_deref(), Some(&[3; 32][..]));
+
+        let misbehaving: Vec<_> = peers.misbehaviour().into_iter().map(|m| m.pubkey).collect();
+        assert_eq!(misbehaving, [vec![2], vec![5], vec![4]]);
+    }
+    fn dealt_share(
+        dealing: &overlay::shamir::Dealing,
+        share: &overlay::shamir::Share,
+        genesis: Option<Genesis>,
+        history: KeyHistory,
+        signature: Option<DealingSignature>,
+    ) -> OverlayMessageType {
+        OverlayMessageType::Share(NotifyShare {
+            dealt: DealtShare {
+                dealing: dealing.clone(),
+                share: share.clone(),
+            },
+            genesis,
+            history,
+            signature,
+        })
+    }
+
+    fn dealer(secret: &[u8]) -> SecretKey {
+        keys::ClusterKeys::new(secret).unwrap().signing_key()
+    }
+
+    #[tokio::test]
+    async fn joins_with_a_dealt_share() {
+        let peers = PeerRegistry::new();
+        let mut joining = handler(None)
+            .with_secret_sharing(SharingConfig::default(), vec![3])
+            .with_peer_registry(peers.clone());
+        let members = vec![vec![1], vec![2], vec![3]];
+        let (dealing, shares) = sharing::deal(&[1; 32], None, 2, members).unwrap();
+        let genesis = genesis([1; 32]).await;
+        let share = |share, signature| {
+            dealt_share(
+                &dealing,
+                share,
+                Some(genesis.clone()),
+                Default::default(),
+                signature,
+            )
+        };
+
+        // Handed over for a reconstruction we didn't start.
+        joining
+            .handle_instruction(share(&shares[2], None), vec![1])
+            .await
+            .unwrap();
+        let mut forged = shares[2].clone();
+        forged.value = shares[1].value;
+        let signature = dealing.sign(&dealer(&[1; 32]));
+        joining
+            .handle_instruction(share(&forged, Some(signature.clone())), vec![1])
+            .await
+            .unwrap();
+        // A peer dealing a secret it made up.
+        joining
+            .handle_instruction(
+                share(&shares[2], Some(dealing.sign(&dealer(&[2; 32])))),
+                vec![2],
+            )
+            .await
+            .unwrap();
+        assert!(joining.share.is_none());
+        assert_ne!(*joining.status().borrow(), SecretStatus::Held);
+
+        joining
+            .handle_instruction(share(&shares[2], Some(signature.clone())), vec![1])
+            .await
+            .unwrap();
+        assert_eq!(joining.share.as_ref().unwrap().share, shares[2]);
+        assert!(joining.secret.is_none());
+        assert_eq!(*joining.status().borrow(), SecretStatus::Held);
+        assert_eq!(*joining.genesis().borrow(), Some(genesis.clone()));
+        let keys = joining.keys().borrow().clone().unwrap();
+        assert_eq!(keys.pubkey, cluster_pubkey(&[1; 32]).unwrap());
+        assert!(keys.keys.is_none());
+
+        // Dealt to another member, ours stays.
+        joining
+            .handle_instruction(share(&shares[1], Some(signature)), vec![1])
+            .await
+            .unwrap();
+        assert_eq!(joining.share.as_ref().unwrap().share, shares[2]);
+
+        let misbehaving: Vec<_> = peers.misbehaviour().into_iter().map(|m| m.pubkey).collect();
+        assert_eq!(misbehaving, [vec![1], vec![2]]);
+    }
+
+    #[tokio::test]
+    async fn rotates_by_dealing_shares() {
+        let config = SharingConfig {
+            threshold: 1,
+            ..Default::default()
+        };
+        let (dealing, shares) = sharing::deal(&[1; 32], None, 1, vec![vec![1]]).unwrap();
+        let mut leader = handler(None)
+            .with_secret_sharing(config.clone(), vec![1])
+            .with_share(DealtShare {
+                dealing,
+                share: shares[0].clone(),
+            })
+            .with_genesis(genesis([1; 32]).await);
+
+        // Our share alone reconstructs the secret, only for as long as it takes to rotate it.
+        leader.propose_rotation().unwrap();
+        assert!(leader.secret.is_none());
+        let link = leader.history.pending.as_ref().unwrap().link.clone();
+        assert!(leader.history.pending.as_ref().unwrap().secret.is_empty());
+        assert!(leader.pending_share.is_some());
+        leader.activate_rotation();
+        assert!(leader.secret.is_none());
+        assert_eq!(leader.share.as_ref().unwrap().dealing.generation, 2);
+        let keys = leader.keys().borrow().clone().unwrap();
+        assert_eq!(keys.pubkey.serialize(), link.new_pubkey);
+        assert_eq!(keys.links, [link]);
+
+        // A member is dealt its share of the next secret along with the link.
+        let peers = PeerRegistry::new();
+        let genesis = genesis([1; 32]).await;
+        let members = vec![vec![1], vec![2]];
+        let (dealing, shares) = sharing::deal(&[1; 32], None, 2, members.clone()).unwrap();
+        let mut member = handler(None)
+            .with_secret_sharing(SharingConfig::default(), vec![2])
+            .with_peer_registry(peers.clone());
+        member
+            .handle_instruction(
+                dealt_share(
+                    &dealing,
+                    &shares[1],
+                    Some(genesis.clone()),
+                    Default::default(),
+                    Some(dealing.sign(&dealer(&[1; 32]))),
+                ),
+                vec![1],
+            )
+            .await
+            .unwrap();
+        let rotated = |from: &[u8]| {
+            let proposal = rotation::propose(from, &KeyHistory::default(), 0).unwrap();
+            let (next, shares) =
+                sharing::deal(&proposal.secret, Some(&dealing), 2, members.clone()).unwrap();
+            let signature = next.sign(&dealer(&proposal.secret));
+            let history = KeyHistory {
+                links: vec![],
+                pending: Some(Box::new(RotationProposal {
+                    secret: vec![],
+                    link: proposal.link,
+                })),
+            };
+            dealt_share(
+                &next,
+                &shares[1],
+                Some(genesis.clone()),
+                history,
+                Some(signature),
+            )
+        };
+        member
+            .handle_instruction(rotated(&[9; 32]), vec![1])
+            .await
+            .unwrap();
+        assert!(member.pending_share.is_none());
+        member
+            .handle_instruction(rotated(&[1; 32]), vec![1])
+            .await
+            .unwrap();
+        let pending = member.history.pending.clone().unwrap();
+        assert!(member.pending_share.is_some());
+        member.activate_rotation();
+        assert!(member.secret.is_none());
+        assert_eq!(member.share.as_ref().unwrap().dealing.generation, 2);
+        assert_eq!(
+            member.keys().borrow().as_ref().unwrap().pubkey.serialize(),
+            pending.link.new_pubkey
+        );
+
+        let misbehaving: Vec<_> = peers.misbehaviour().into_iter().map(|m| m.pubkey).collect();
+        assert_eq!(misbehaving, [vec![1]]);
+    }
+}
@@ -0,0 +1,391 @@
+//! Quorum-attested `/call` responses.
+//!
+//! A response is otherwise only as good as the view of the node that answered. With quorum calls
+//! the answering node also forwards the transaction request to `peers` attested peers, each of
+//! which calls it from its own view and signs its answer under its node key, the key its quotes
+//! commit to. The response is only served along with the signatures of every node that answered
+//! the same, and when any of them didn't, the diverging answers are served instead.
+
+use mocks::attestation::hex_bytes;
+use overlay::message::CallMessage;
+use secp256k1::rand::{self, seq::SliceRandom};
+use secp256k1::{ecdsa, Message, PublicKey, Secp256k1, SecretKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+
+const CALL_DOMAIN: &[u8] = b"tplus/call";
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct QuorumConfig {
+    /// Peers that have to answer the same as us.
+    pub peers: u16,
+    /// How long a response waits for their answers.
+    pub timeout_ms: u64,
+}
+
+impl Default for QuorumConfig {
+    fn default() -> Self {
+        Self {
+            peers: 2,
+            timeout_ms: 5_000,
+        }
+    }
+}
+
+/// What an answer's signature is over: `sha256("tplus/call" || sha256(tx) || response)`.
+pub fn call_digest(tx: &str, response: &str) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(CALL_DOMAIN);
+    hasher.update(Sha256::digest(tx));
+    hasher.update(response);
+    hasher.finalize().into()
+}
+
+/// A node's answer to a call.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Answer {
+    #[serde(with = "hex_bytes")]
+    pub pubkey: Vec<u8>,
+    pub response: String,
+    #[serde(with = "hex_bytes")]
+    pub signature: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// Every node answered the same, ours first.
+    Agreed(Vec<Answer>),
+    /// Some nodes answered otherwise, ours first.
+    Diverged(Vec<Answer>),
+}
+
+/// Call and our response to it, and where the outcome goes.
+pub(crate) struct AttestRequest {
+    tx: String,
+    response: String,
+    reply: oneshot::Sender<anyhow::Result<Outcome>>,
+}
+
+/// Call forwarded by a peer, to answer from our view with the JSON encoded response.
+pub struct ForwardedCall {
+    pub tx: String,
+    pub reply: oneshot::Sender<String>,
+}
+
+/// Has peers answer calls along with us, see [`crate::LightClientHandler::quorum_calls`].
+#[derive(Clone)]
+pub struct QuorumCaller {
+    requests: mpsc::Sender<AttestRequest>,
+    timeout: Duration,
+}
+
+impl QuorumCaller {
+    /// Forwards `tx` to peers and compares their answers with our `response`, both JSON encoded.
+    pub async fn attest(&self, tx: String, response: String) -> anyhow::Result<Outcome> {
+        let (reply, outcome) = oneshot::channel();
+        self.requests
+            .send(AttestRequest {
+                tx,
+                response,
+                reply,
+            })
+            .await
+            .map_err(|_| anyhow::anyhow!("handler stopped"))?;
+
+        tokio::time::timeout(self.timeout, outcome)
+            .await
+            .map_err(|_| anyhow::anyhow!("peers didn't answer within {:?}", self.timeout))?
+            .map_err(|_| anyhow::anyhow!("quorum call dropped"))?
+    }
+}
+
+/// Both ends of quorum calls for the light client: calls to attest, and calls to answer.
+pub struct QuorumCalls {
+    pub caller: QuorumCaller,
+    pub forwarded: mpsc::Receiver<ForwardedCall>,
+}
+
+/// A message to send to the given peers.
+pub(crate) type Outgoing = (Vec<Vec<u8>>, CallMessage);
+
+pub(crate) struct Quorum {
+    config: QuorumConfig,
+    node_key: SecretKey,
+    pub requests: mpsc::Receiver<AttestRequest>,
+    caller: QuorumCaller,
+    forward: mpsc::Sender<ForwardedCall>,
+    forwarded: Option<mpsc::Receiver<ForwardedCall>>,
+    pending: HashMap<[u8; 32], Pending>,
+}
+
+/// A call we wait on answers for.
+struct Pending {
+    tx: String,
+    ours: Answer,
+    peers: Vec<Vec<u8>>,
+    answers: Vec<Answer>,
+    reply: oneshot::Sender<anyhow::Result<Outcome>>,
+}
+
+impl Quorum {
+    pub fn new(config: QuorumConfig, node_key: SecretKey) -> Self {
+        let (requests_tx, requests) = mpsc::channel(64);
+        let (forward, forwarded) = mpsc::channel(64);
+        Self {
+            caller: QuorumCaller {
+                requests: requests_tx,
+                timeout: Duration::from_millis(config.timeout_ms),
+            },
+            config,
+            node_key,
+            requests,
+            forward,
+            forwarded: Some(forwarded),
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Both ends of quorum calls, only handed out once.
+    pub fn calls(&mut self) -> Option<QuorumCalls> {
+        Some(QuorumCalls {
+            caller: self.caller.clone(),
+            forwarded: self.forwarded.take()?,
+        })
+    }
+
+    /// Forwards a call to `peers` of the attested ones, returns the request for their answers.
+    pub fn start(
+        &mut self,
+        request: AttestRequest,
+        mut attested: Vec<Vec<u8>>,
+    ) -> Option<Outgoing> {
+        self.pending.retain(|_, pending| !pending.reply.is_closed());
+        if attested.len() < self.config.peers as usize {
+            let _ = request.reply.send(Err(anyhow::anyhow!(
+                "{} attested peers, {} have to answer",
+                attested.len(),
+                self.config.peers
+            )));
+            return None;
+        }
+        attested.shuffle(&mut rand::thread_rng());
+        attested.truncate(self.config.peers as usize);
+
+        let id: [u8; 32] = rand::random();
+        let ours = self.answer(&request.tx, request.response);
+        let message = CallMessage::Request {
+            request: id,
+            tx: request.tx.clone(),
+        };
+        let pending = Pending {
+            tx: request.tx,
+            ours,
+            peers: attested.clone(),
+            answers: Vec::new(),
+            reply: request.reply,
+        };
+        // NB: nobody else to wait for.
+        if pending.peers.is_empty() {
+            let _ = pending.reply.send(Ok(Outcome::Agreed(vec![pending.ours])));
+            return None;
+        }
+        self.pending.insert(id, pending);
+
+        Some((attested, message))
+    }
+
+    /// Has the light client answer the call `request` forwarded by a peer, resolves to our signed
+    /// answer once it did.
+    pub fn forward(
+        &self,
+        request: [u8; 32],
+        tx: String,
+    ) -> Option<impl Future<Output = Option<CallMessage>> + Send + 'static> {
+        let (reply, response) = oneshot::channel();
+        // NB: we'd rather not answer than hold the handler up.
+        self.forward
+            .try_send(ForwardedCall {
+                tx: tx.clone(),
+                reply,
+            })
+            .ok()?;
+        let node_key = self.node_key;
+        Some(async move {
+            let answer = sign_answer(&node_key, &tx, response.await.ok()?);
+            Some(CallMessage::Response {
+                request,
+                response: answer.response,
+                signature: answer.signature.try_into().ok()?,
+            })
+        })
+    }
+
+    /// Our answer to `tx`, signed under our node key.
+    fn answer(&self, tx: &str, response: String) -> Answer {
+        sign_answer(&self.node_key, tx, response)
+    }
+
+    /// A peer's answer to the call `request`. Errors on answers it wasn't asked for or that
+    /// aren't signed under its key.
+    pub fn answered(
+        &mut self,
+        from: &[u8],
+        request: [u8; 32],
+        response: String,
+        signature: [u8; 64],
+    ) -> anyhow::Result<()> {
+        let Some(pending) = self.pending.get_mut(&request) else {
+            return Ok(());
+        };
+        if !pending.peers.iter().any(|peer| peer == from) {
+            anyhow::bail!("answered a call it wasn't asked");
+        }
+        if pending.answers.iter().any(|answer| answer.pubkey == from) {
+            return Ok(());
+        }
+        let digest = Message::from_digest(call_digest(&pending.tx, &response));
+        Secp256k1::verification_only().verify_ecdsa(
+            &digest,
+            &ecdsa::Signature::from_compact(&signature)?,
+            &PublicKey::from_slice(from)?,
+        )?;
+
+        pending.answers.push(Answer {
+            pubkey: from.to_vec(),
+            response,
+            signature: signature.to_vec(),
+        });
+        if pending.answers.len() < pending.peers.len() {
+            return Ok(());
+        }
+
+        let pending = self.pending.remove(&request).expect("checked above");
+        let diverged = pending
+            .answers
+            .iter()
+            .any(|answer| answer.response != pending.ours.response);
+        let mut answers = vec![pending.ours];
+        answers.extend(pending.answers);
+        let outcome = if diverged {
+            Outcome::Diverged(answers)
+        } else {
+            Outcome::Agreed(answers)
+        };
+        let _ = pending.reply.send(Ok(outcome));
+
+        Ok(())
+    }
+}
+
+/// Signs the answer `response` to `tx` under `node_key`.
+pub fn sign_answer(node_key: &SecretKey, tx: &str, response: String) -> Answer {
+    let secp = Secp256k1::new();
+    let digest = Message::from_digest(call_digest(tx, &response));
+    Answer {
+        pubkey: node_key.public_key(&secp).serialize().to_vec(),
+        signature: secp
+            .sign_ecdsa(&digest, node_key)
+            .serialize_compact()
+            .to_vec(),
+        response,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn answer(key: &SecretKey, tx: &str, response: &str) -> (Vec<u8>, String, [u8; 64]) {
+        let answer = sign_answer(key, tx, response.to_string());
+        (
+            answer.pubkey,
+            answer.response,
+            answer.signature.try_into().unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn flags_diverging_answers() {
+        let config = QuorumConfig {
+            peers: 2,
+            timeout_ms: 1_000,
+        };
+        let keys: Vec<SecretKey> = (1..=4)
+            .map(|key| SecretKey::from_slice(&[key; 32]).unwrap())
+            .collect();
+        let mut quorum = Quorum::new(config, keys[0]);
+        let peers: Vec<Vec<u8>> = keys[1..]
+            .iter()
+            .map(|key| key.public_key(&Secp256k1::new()).serialize().to_vec())
+            .collect();
+        let call = |quorum: &mut Quorum, attested: Vec<Vec<u8>>| {
+            let (reply, outcome) = oneshot::channel();
+            let request = AttestRequest {
+                tx: "tx".to_string(),
+                response: "ok".to_string(),
+                reply,
+            };
+            (quorum.start(request, attested), outcome)
+        };
+
+        // Not enough peers to ask.
+        let (outgoing, outcome) = call(&mut quorum, peers[..1].to_vec());
+        assert!(outgoing.is_none());
+        assert!(outcome.await.unwrap().is_err());
+
+        // Every asked peer agrees.
+        let (outgoing, outcome) = call(&mut quorum, peers[..2].to_vec());
+        let (asked, CallMessage::Request { request, tx }) = outgoing.unwrap() else {
+            panic!("expected a request");
+        };
+        assert_eq!(tx, "tx");
+        for peer in &asked {
+            let key = &keys[1 + peers.iter().position(|other| other == peer).unwrap()];
+            let (from, response, signature) = answer(key, "tx", "ok");
+            // An answer under another key is rejected.
+            let (_, _, forged) = answer(&keys[3], "tx", "ok");
+            assert!(quorum
+                .answered(&from, request, response.clone(), forged)
+                .is_err());
+            quorum
+                .answered(&from, request, response, signature)
+                .unwrap();
+        }
+        let Outcome::Agreed(answers) = outcome.await.unwrap().unwrap() else {
+            panic!("expected the peers to agree");
+        };
+        assert_eq!(answers.len(), 3);
+        assert_eq!(
+            answers[0].pubkey,
+            keys[0].public_key(&Secp256k1::new()).serialize()
+        );
+
+        // One of them answers otherwise.
+        let (outgoing, outcome) = call(&mut quorum, peers.clone());
+        let (asked, CallMessage::Request { request, .. }) = outgoing.unwrap() else {
+            panic!("expected a request");
+        };
+        let outsider = peers.iter().find(|peer| !asked.contains(peer)).unwrap();
+        let key = &keys[1 + peers.iter().position(|other| other == outsider).unwrap()];
+        let (from, response, signature) = answer(key, "tx", "ok");
+        assert!(quorum
+            .answered(&from, request, response, signature)
+            .is_err());
+        for (peer, response) in asked.iter().zip(["ok", "other"]) {
+            let key = &keys[1 + peers.iter().position(|other| other == peer).unwrap()];
+            let (from, response, signature) = answer(key, "tx", response);
+            quorum
+                .answered(&from, request, response, signature)
+                .unwrap();
+        }
+        let Outcome::Diverged(answers) = outcome.await.unwrap().unwrap() else {
+            panic!("expected the peers to diverge");
+        };
+        assert_eq!(answers[2].response, "other");
+    }
+}
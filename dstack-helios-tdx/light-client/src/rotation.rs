@@ -0,0 +1,158 @@
+//! Rotating the shared secret, see [`overlay::rotation`].
+//!
+//! A member configured to rotate proposes a new secret every so often. Members check that the
+//! proposal rotates the key they hold, pass it on to their own peers and switch to it at the
+//! activation time of its [`KeyLink`]. Until then responses are still signed under the old key,
+//! and both keys are published along with the link so that clients can follow the switch.
+//! Members proposing at the same time converge on the proposal with the lowest new key.
+//!
+//! When members share the secret, see [`crate::sharing`], the new secret never travels whole: the
+//! leader deals it to members along with the link, and members switch to their share of it.
+
+use crate::{cluster_pubkey, keys::ClusterKeys};
+use overlay::rotation::{KeyHistory, KeyLink, RotationProposal};
+use secp256k1::PublicKey;
+use serde::Deserialize;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct RotationConfig {
+    /// Propose a new secret this often, disabled by default. One member rotating is enough.
+    pub interval_secs: Option<u64>,
+    /// How long members get to receive the new secret before switching to it.
+    pub activation_delay_secs: u64,
+}
+
+impl Default for RotationConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: None,
+            activation_delay_secs: 60,
+        }
+    }
+}
+
+/// What we sign under, and how clients get to it from the genesis key.
+#[derive(Clone)]
+pub struct CurrentKeys {
+    pub pubkey: PublicKey,
+    /// Derived from the secret, `None` when members only hold shares of it.
+    pub keys: Option<ClusterKeys>,
+    pub links: Vec<KeyLink>,
+    /// The rotation members switch to at its activation time.
+    pub pending: Option<KeyLink>,
+}
+
+/// A new secret for the epoch after `history`'s, replacing `secret` at `activates_at`.
+pub fn propose(
+    secret: &[u8],
+    history: &KeyHistory,
+    activates_at: u64,
+) -> anyhow::Result<RotationProposal> {
+    let new_secret: [u8; 32] = secp256k1::rand::random();
+    let link = KeyLink::new(
+        history.epoch() + 1,
+        activates_at,
+        &ClusterKeys::new(secret)?.signing_key(),
+        &ClusterKeys::new(&new_secret)?.signing_key(),
+    );
+
+    Ok(RotationProposal {
+        secret: new_secret.to_vec(),
+        link,
+    })
+}
+
+/// Checks `proposal` rotates the key of `secret` to the next epoch. Returns whether it should
+/// replace the rotation already pending, if any, `false` for past rotations.
+pub fn check_proposal(
+    secret: &[u8],
+    history: &KeyHistory,
+    proposal: &RotationProposal,
+) -> anyhow::Result<bool> {
+    let replaces = check_link(&cluster_pubkey(secret)?, history, &proposal.link)?;
+    if proposal.link.new_pubkey != cluster_pubkey(&proposal.secret)?.serialize() {
+        return Err(anyhow::anyhow!(
+            "rotation to a key the secret doesn't derive"
+        ));
+    }
+
+    Ok(replaces)
+}
+
+/// Checks `link` rotates `pubkey` to the next epoch, like [`check_proposal`] without the new
+/// secret.
+pub fn check_link(
+    pubkey: &PublicKey,
+    history: &KeyHistory,
+    link: &KeyLink,
+) -> anyhow::Result<bool> {
+    link.verify()?;
+    // NB: peers passing on a rotation we already switched to.
+    if link.epoch <= history.epoch() {
+        return Ok(false);
+    }
+    if link.epoch != history.epoch() + 1 {
+        return Err(anyhow::anyhow!(
+            "rotation to epoch {} while at {}",
+            link.epoch,
+            history.epoch()
+        ));
+    }
+    if link.old_pubkey != pubkey.serialize() {
+        return Err(anyhow::anyhow!("rotation of another key"));
+    }
+
+    Ok(history
+        .pending
+        .as_ref()
+        .is_none_or(|pending| link.new_pubkey < pending.link.new_pubkey))
+}
+
+pub(crate) fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// When to switch to the key of `link`.
+pub(crate) fn activation(link: &KeyLink) -> Instant {
+    Instant::now() + Duration::from_secs(link.activates_at.saturating_sub(unix_now()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn checks_proposals() {
+        let secret = [1; 32];
+        let history = KeyHistory::default();
+        let proposal = propose(&secret, &history, 10).unwrap();
+        assert!(check_proposal(&secret, &history, &proposal).unwrap());
+
+        // Concurrent proposals for the same epoch, everyone ends up with the same one.
+        let other = propose(&secret, &history, 10).unwrap();
+        let pending = |proposal: &RotationProposal| KeyHistory {
+            links: vec![],
+            pending: Some(Box::new(proposal.clone())),
+        };
+        assert_ne!(
+            check_proposal(&secret, &pending(&proposal), &other).unwrap(),
+            check_proposal(&secret, &pending(&other), &proposal).unwrap()
+        );
+
+        assert!(check_proposal(&[2; 32], &history, &proposal).is_err());
+        let rotated = KeyHistory {
+            links: vec![proposal.link.clone()],
+            pending: None,
+        };
+        assert!(!check_proposal(&proposal.secret, &rotated, &proposal).unwrap());
+        let mut swapped = proposal.clone();
+        swapped.secret = other.secret;
+        assert!(check_proposal(&secret, &history, &swapped).is_err());
+    }
+}
@@ -0,0 +1,230 @@
+//! Shared secret sealed to disk.
+//!
+//! The secret is encrypted under a key the TEE derives from our measurements (see
+//! [`AttestationProvider::sealing_key`]), so a restarted node running the same build gets it
+//! back without a live peer, and a cluster survives restarting as a whole. Other builds, and
+//! anything outside the TEE, only see ciphertext. Backends that can't derive a sealing key (TDX
+//! and SEV-SNP on their own, mocks unless told to) are refused a sealing path at config load.
+//! The cluster's genesis record and key history are sealed along with it. Nodes sharing the
+//! secret (see [`crate::sharing`]) seal their share instead.
+//!
+//! The replicated state (see [`crate::state`]) is sealed the same way, under a key of its own.
+
+use aes_gcm::{
+    aead::{generic_array::GenericArray, Aead, Payload},
+    Aes256Gcm, KeyInit,
+};
+use anyhow::anyhow;
+use mocks::attestation::AttestationProvider;
+use overlay::{
+    codec, genesis::Genesis, rotation::KeyHistory, shamir::DealtShare, state::ReplicatedMap,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// What the sealing key is derived for.
+pub const SHARED_SECRET_LABEL: &str = "tplus/shared-secret";
+pub const STATE_LABEL: &str = "tplus/state";
+
+const NONCE_LEN: usize = 12;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Unsealed {
+    /// `None` when sharing the secret.
+    pub secret: Option<Vec<u8>>,
+    pub share: Option<DealtShare>,
+    /// Our share of the threshold signing key.
+    pub signing_share: Option<DealtShare>,
+    pub genesis: Option<Genesis>,
+    pub history: KeyHistory,
+}
+
+/// A sealed file: `nonce || AES-256-GCM ciphertext` of the encoded contents, authenticated
+/// along with what they are.
+struct Sealed {
+    path: PathBuf,
+    cipher: Aes256Gcm,
+    label: &'static str,
+}
+
+impl Sealed {
+    async fn new(
+        attestation: &dyn AttestationProvider,
+        path: impl AsRef<Path>,
+        label: &'static str,
+    ) -> anyhow::Result<Option<Self>> {
+        Ok(attestation
+            .sealing_key(label)
+            .await?
+            .map(|key| Self::with_key(path, key, label)))
+    }
+
+    fn with_key(path: impl AsRef<Path>, key: [u8; 32], label: &'static str) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            cipher: Aes256Gcm::new(&key.into()),
+            label,
+        }
+    }
+
+    fn unseal<T: DeserializeOwned>(&self) -> anyhow::Result<Option<T>> {
+        let sealed = match std::fs::read(&self.path) {
+            Ok(sealed) => sealed,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        if sealed.len() < NONCE_LEN {
+            return Err(anyhow!("truncated sealed file {}", self.path.display()));
+        }
+
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let unsealed = self
+            .cipher
+            .decrypt(
+                GenericArray::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: self.label.as_bytes(),
+                },
+            )
+            .map_err(|_| anyhow!("couldn't unseal {}", self.path.display()))?;
+
+        Ok(Some(codec::decode(&unsealed)?))
+    }
+
+    fn seal<T: Serialize>(&self, contents: &T) -> anyhow::Result<()> {
+        let plaintext = codec::encode(contents)?;
+        let nonce: [u8; NONCE_LEN] = secp256k1::rand::random();
+        let mut sealed = nonce.to_vec();
+        sealed.extend(
+            self.cipher
+                .encrypt(
+                    GenericArray::from_slice(&nonce),
+                    Payload {
+                        msg: &plaintext,
+                        aad: self.label.as_bytes(),
+                    },
+                )
+                .map_err(|e| anyhow!(e))?,
+        );
+
+        // NB: written aside and renamed so that a crash never leaves a torn file behind.
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let staged = self.path.with_extension("tmp");
+        std::fs::write(&staged, sealed)?;
+        std::fs::rename(staged, &self.path)?;
+
+        Ok(())
+    }
+}
+
+/// The sealed secret file, of the encoded [`Unsealed`].
+pub struct SealedSecret(Sealed);
+
+impl SealedSecret {
+    /// Seals to `path` under the key `attestation` derives, `None` when it can't derive one.
+    pub async fn new(
+        attestation: &dyn AttestationProvider,
+        path: impl AsRef<Path>,
+    ) -> anyhow::Result<Option<Self>> {
+        Ok(Sealed::new(attestation, path, SHARED_SECRET_LABEL)
+            .await?
+            .map(Self))
+    }
+
+    pub fn with_key(path: impl AsRef<Path>, key: [u8; 32]) -> Self {
+        Self(Sealed::with_key(path, key, SHARED_SECRET_LABEL))
+    }
+
+    /// The secret sealed by an earlier run, `None` if there was none. Errors if it was sealed
+    /// under another key (i.e. by another build) or tampered with.
+    pub fn unseal(&self) -> anyhow::Result<Option<Unsealed>> {
+        self.0.unseal()
+    }
+
+    /// Replaces the sealed secret.
+    pub fn seal(&self, unsealed: &Unsealed) -> anyhow::Result<()> {
+        self.0.seal(unsealed)
+    }
+}
+
+/// The sealed replicated state file.
+pub struct SealedState(Sealed);
+
+impl SealedState {
+    /// Seals to `path` under the key `attestation` derives, `None` when it can't derive one.
+    pub async fn new(
+        attestation: &dyn AttestationProvider,
+        path: impl AsRef<Path>,
+    ) -> anyhow::Result<Option<Self>> {
+        Ok(Sealed::new(attestation, path, STATE_LABEL).await?.map(Self))
+    }
+
+    pub fn with_key(path: impl AsRef<Path>, key: [u8; 32]) -> Self {
+        Self(Sealed::with_key(path, key, STATE_LABEL))
+    }
+
+    /// The state sealed by an earlier run, `None` if there was none.
+    pub fn unseal(&self) -> anyhow::Result<Option<ReplicatedMap>> {
+        self.0.unseal()
+    }
+
+    pub fn seal(&self, map: &ReplicatedMap) -> anyhow::Result<()> {
+        self.0.seal(map)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mocks::attestation::{Measurement, MockProvider};
+
+    fn path() -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "sealed-{:x}/secret",
+            secp256k1::rand::random::<u64>()
+        ))
+    }
+
+    #[tokio::test]
+    async fn unseals_on_the_same_build_only() {
+        let path = path();
+        assert!(SealedSecret::new(&MockProvider::new(), &path)
+            .await
+            .unwrap()
+            .is_none());
+
+        let node = MockProvider::new().with_insecure_sealing();
+        let sealed = SealedSecret::new(&node, &path).await.unwrap().unwrap();
+        assert!(sealed.unseal().unwrap().is_none());
+
+        let unsealed = Unsealed {
+            secret: Some(b"dstack secret".to_vec()),
+            share: None,
+            signing_share: None,
+            genesis: None,
+            history: KeyHistory::default(),
+        };
+        sealed.seal(&unsealed).unwrap();
+        // After a restart.
+        let restarted = SealedSecret::new(&node, &path).await.unwrap().unwrap();
+        assert_eq!(restarted.unseal().unwrap().unwrap(), unsealed);
+
+        let other_build = MockProvider::new()
+            .with_measurements(Measurement([1; 48]), Default::default())
+            .with_insecure_sealing();
+        assert!(SealedSecret::new(&other_build, &path)
+            .await
+            .unwrap()
+            .unwrap()
+            .unseal()
+            .is_err());
+
+        let mut tampered = std::fs::read(&path).unwrap();
+        *tampered.last_mut().unwrap() ^= 1;
+        std::fs::write(&path, tampered).unwrap();
+        assert!(restarted.unseal().is_err());
+    }
+}
@@ -0,0 +1,127 @@
+//! Splitting the shared secret among members, see [`overlay::shamir`].
+//!
+//! With sharing enabled members never hold the secret, only their share of it. The bootstrap node
+//! deals the secret it generated once there are `threshold` members, and forgets it. From then on,
+//! once membership settles after a change, the leader (any member without an election) has its
+//! attested peers hand over their shares, reconstructs the secret, deals it anew to them and
+//! itself, and drops it. Rotations go the same way, the new secret being dealt along with the
+//! link to it. Dealings are signed under the cluster key of the secret they split, so a joining
+//! node takes a share once the dealer's key follows from the genesis record, and members converge
+//! on the latest dealing, the lowest one when several were dealt at once. Only a member's share
+//! is sealed, and responses are signed along with other nodes, see [`crate::threshold`].
+
+use overlay::shamir::{self, Dealing, DealtShare, Share};
+use serde::Deserialize;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct SharingConfig {
+    /// Shares needed to reconstruct the secret. Nothing is dealt while there are fewer members.
+    pub threshold: u16,
+    /// How long membership has to stay put before the secret is dealt again.
+    pub reshare_delay_secs: u64,
+}
+
+impl Default for SharingConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 2,
+            reshare_delay_secs: 10,
+        }
+    }
+}
+
+/// Deals `secret` to `members`, as the dealing after `previous`.
+pub fn deal(
+    secret: &[u8],
+    previous: Option<&Dealing>,
+    threshold: u16,
+    mut members: Vec<Vec<u8>>,
+) -> anyhow::Result<(Dealing, Vec<Share>)> {
+    let secret: &[u8; 32] = secret
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("shared secrets are 32 bytes"))?;
+    members.sort();
+    members.dedup();
+    let generation = previous.map_or(1, |previous| previous.generation + 1);
+
+    Ok(shamir::split(secret, generation, threshold, members)?)
+}
+
+/// Whether members holding a share of `held` should switch to `dealing`.
+pub fn supersedes(dealing: &Dealing, held: Option<&Dealing>) -> bool {
+    held.is_none_or(|held| (dealing.generation, held.id()) > (held.generation, dealing.id()))
+}
+
+/// Shares handed over by peers while we're reconstructing the secret.
+#[derive(Default)]
+pub(crate) struct Reconstruction {
+    collected: Vec<DealtShare>,
+}
+
+impl Reconstruction {
+    /// Adds a share sent by a peer, returns the secret once enough shares of its dealing are in.
+    /// Errors on shares that don't match their dealing.
+    pub fn add(&mut self, dealt: DealtShare) -> anyhow::Result<Option<Vec<u8>>> {
+        dealt.dealing.verify_share(&dealt.share)?;
+        let id = dealt.dealing.id();
+        let dealing = dealt.dealing.clone();
+        self.collected.push(dealt);
+
+        let shares: Vec<Share> = self
+            .collected
+            .iter()
+            .filter(|other| other.dealing.id() == id)
+            .map(|other| other.share.clone())
+            .collect();
+        match shamir::reconstruct(&dealing, &shares) {
+            Ok(secret) => {
+                self.collected.clear();
+                Ok(Some(secret.to_vec()))
+            }
+            // NB: we checked every share, we're still missing some.
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reconstructs_from_peer_shares() {
+        let members = vec![vec![3], vec![1], vec![2]];
+        let (dealing, shares) = deal(&[5; 32], None, 2, members).unwrap();
+        assert_eq!(dealing.members, [vec![1], vec![2], vec![3]]);
+        let dealt = |share: &Share| DealtShare {
+            dealing: dealing.clone(),
+            share: share.clone(),
+        };
+
+        let mut reconstruction = Reconstruction::default();
+        let mut forged = dealt(&shares[0]);
+        forged.share.value = shares[1].value;
+        assert!(reconstruction.add(forged).is_err());
+        assert_eq!(reconstruction.add(dealt(&shares[2])).unwrap(), None);
+        // The same share twice doesn't make a quorum.
+        assert_eq!(reconstruction.add(dealt(&shares[2])).unwrap(), None);
+        assert_eq!(
+            reconstruction.add(dealt(&shares[0])).unwrap(),
+            Some(vec![5; 32])
+        );
+
+        // The next dealing wins, and concurrent ones settle on the same one everywhere.
+        let (next, _) = deal(&[5; 32], Some(&dealing), 2, vec![vec![1], vec![2]]).unwrap();
+        let (concurrent, _) = deal(&[5; 32], Some(&dealing), 2, vec![vec![1], vec![2]]).unwrap();
+        assert_eq!(next.generation, 2);
+        assert!(supersedes(&dealing, None));
+        assert!(supersedes(&next, Some(&dealing)));
+        assert!(!supersedes(&dealing, Some(&next)));
+        assert!(!supersedes(&next, Some(&next)));
+        assert_ne!(
+            supersedes(&next, Some(&concurrent)),
+            supersedes(&concurrent, Some(&next))
+        );
+    }
+}
@@ -0,0 +1,249 @@
+//! Key-value state replicated across members, see [`overlay::state`].
+//!
+//! Applications read and write through a [`ReplicatedState`]. The handler sends every write to
+//! our attested peers, and everything we hold to peers as they join, so a member that missed
+//! writes while away catches up once back. Concurrent writes to a key go to the latest one,
+//! there's no ordering across keys. The state is sealed to disk (see
+//! [`crate::sealing::SealedState`]) after each change, so a restarted node starts from where it
+//! stopped rather than from what its peers hand it.
+
+use crate::sealing::SealedState;
+use overlay::message::NotifyState;
+use overlay::state::{Entry, ReplicatedMap};
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, oneshot, watch};
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct StateConfig {
+    /// Where the state is sealed, not persisted when `None`.
+    pub sealed_path: Option<PathBuf>,
+    /// How far ahead of our clock entries may be written. Later ones are rejected, they would
+    /// win over every write until then.
+    pub max_clock_skew_ms: u64,
+}
+
+impl Default for StateConfig {
+    fn default() -> Self {
+        Self {
+            sealed_path: None,
+            max_clock_skew_ms: 60_000,
+        }
+    }
+}
+
+/// A write to replicate, and where to tell it's done.
+pub(crate) struct Write {
+    key: String,
+    value: Option<Vec<u8>>,
+    reply: oneshot::Sender<()>,
+}
+
+/// Reads and writes the replicated state, see [`crate::LightClientHandler::replicated_state`].
+#[derive(Clone)]
+pub struct ReplicatedState {
+    writes: mpsc::Sender<Write>,
+    map: watch::Receiver<ReplicatedMap>,
+}
+
+impl ReplicatedState {
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        self.map.borrow().get(key).map(<[u8]>::to_vec)
+    }
+
+    /// Every live key and its value.
+    pub fn entries(&self) -> Vec<(String, Vec<u8>)> {
+        self.map
+            .borrow()
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_vec()))
+            .collect()
+    }
+
+    /// Writes `value` to `key`, returns once applied locally.
+    pub async fn set(&self, key: impl Into<String>, value: Vec<u8>) -> anyhow::Result<()> {
+        self.write(key.into(), Some(value)).await
+    }
+
+    pub async fn delete(&self, key: impl Into<String>) -> anyhow::Result<()> {
+        self.write(key.into(), None).await
+    }
+
+    /// Waits for the state to change, locally or from a peer.
+    pub async fn changed(&mut self) -> anyhow::Result<()> {
+        self.map
+            .changed()
+            .await
+            .map_err(|_| anyhow::anyhow!("handler stopped"))
+    }
+
+    async fn write(&self, key: String, value: Option<Vec<u8>>) -> anyhow::Result<()> {
+        let (reply, done) = oneshot::channel();
+        self.writes
+            .send(Write { key, value, reply })
+            .await
+            .map_err(|_| anyhow::anyhow!("handler stopped"))?;
+        done.await.map_err(|_| anyhow::anyhow!("handler stopped"))
+    }
+}
+
+/// Our replica, kept by the handler.
+pub(crate) struct Replication {
+    config: StateConfig,
+    node_pubkey: Vec<u8>,
+    map: watch::Sender<ReplicatedMap>,
+    pub(crate) writes: mpsc::Receiver<Write>,
+    handle: ReplicatedState,
+    sealed: Option<SealedState>,
+}
+
+impl Replication {
+    /// Starts from the state `sealed` by an earlier run, if any.
+    pub(crate) fn new(
+        config: StateConfig,
+        node_pubkey: Vec<u8>,
+        sealed: Option<SealedState>,
+    ) -> Self {
+        let map = sealed
+            .as_ref()
+            .and_then(|sealed| {
+                sealed
+                    .unseal()
+                    .inspect_err(|e| tracing::warn!("ignoring sealed state: {e}"))
+                    .ok()
+                    .flatten()
+            })
+            .unwrap_or_default();
+        let (map, reader) = watch::channel(map);
+        let (writes_tx, writes) = mpsc::channel(64);
+        Self {
+            config,
+            node_pubkey,
+            map,
+            writes,
+            handle: ReplicatedState {
+                writes: writes_tx,
+                map: reader,
+            },
+            sealed,
+        }
+    }
+
+    pub(crate) fn handle(&self) -> ReplicatedState {
+        self.handle.clone()
+    }
+
+    /// Applies our write, returns it for our peers.
+    pub(crate) fn write(&mut self, write: Write) -> NotifyState {
+        let Write { key, value, reply } = write;
+        let mut entry = None;
+        self.map.send_modify(|map| {
+            entry = Some(map.write(key.clone(), value, self.node_pubkey.clone(), unix_now_ms()));
+        });
+        self.seal();
+        let _ = reply.send(());
+        NotifyState {
+            entries: entry.map(|entry| (key, entry)).into_iter().collect(),
+        }
+    }
+
+    /// Everything we hold, for a peer catching up. `None` when empty.
+    pub(crate) fn snapshot(&self) -> Option<NotifyState> {
+        let entries = self.map.borrow().entries();
+        (!entries.is_empty()).then_some(NotifyState { entries })
+    }
+
+    /// Takes the newer of a peer's `entries`. Errors, taking none, if some were written in the
+    /// future.
+    pub(crate) fn merge(&mut self, entries: Vec<(String, Entry)>) -> anyhow::Result<()> {
+        let horizon = unix_now_ms() + self.config.max_clock_skew_ms;
+        if let Some((key, _)) = entries
+            .iter()
+            .find(|(_, entry)| entry.version.timestamp_ms > horizon)
+        {
+            return Err(anyhow::anyhow!("{key} was written in the future"));
+        }
+
+        let changed = self.map.send_if_modified(|map| {
+            entries.into_iter().fold(false, |changed, (key, entry)| {
+                map.merge(key, entry) || changed
+            })
+        });
+        if changed {
+            self.seal();
+        }
+        Ok(())
+    }
+
+    fn seal(&self) {
+        if let Some(sealed) = &self.sealed {
+            if let Err(e) = sealed.seal(&self.map.borrow()) {
+                tracing::warn!("couldn't seal the replicated state: {e}");
+            }
+        }
+    }
+}
+
+fn unix_now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn path() -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "state-{:x}/state",
+            secp256k1::rand::random::<u64>()
+        ))
+    }
+
+    #[tokio::test]
+    async fn replicates_writes_and_survives_restarts() {
+        let path = path();
+        let key = secp256k1::rand::random();
+        let mut ours = Replication::new(
+            StateConfig::default(),
+            vec![1],
+            Some(SealedState::with_key(&path, key)),
+        );
+        let mut theirs = Replication::new(StateConfig::default(), vec![2], None);
+        let state = ours.handle();
+
+        let set = tokio::spawn({
+            let state = state.clone();
+            async move { state.set("allowlist", b"mrtd".to_vec()).await }
+        });
+        let write = ours.writes.recv().await.unwrap();
+        let write = ours.write(write);
+        theirs.merge(write.entries).unwrap();
+        assert_eq!(state.get("allowlist"), Some(b"mrtd".to_vec()));
+        assert_eq!(
+            theirs.handle().entries(),
+            vec![("allowlist".to_string(), b"mrtd".to_vec())]
+        );
+
+        // Peers don't get to pin a key with a write from the future.
+        let mut future = ReplicatedMap::default();
+        let entry = future.write("allowlist".into(), None, vec![2], unix_now_ms() + 3_600_000);
+        assert!(ours.merge(vec![("allowlist".into(), entry)]).is_err());
+        assert_eq!(state.get("allowlist"), Some(b"mrtd".to_vec()));
+
+        let restarted = Replication::new(
+            StateConfig::default(),
+            vec![1],
+            Some(SealedState::with_key(&path, key)),
+        );
+        assert_eq!(
+            restarted.snapshot().unwrap().entries,
+            ours.snapshot().unwrap().entries
+        );
+        set.await.unwrap().unwrap();
+    }
+}
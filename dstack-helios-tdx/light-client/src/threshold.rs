@@ -0,0 +1,740 @@
+//! Threshold signing of responses, see [`overlay::frost`].
+//!
+//! The key responses are signed under is split among the cluster's nodes, and any `threshold` of
+//! them sign `/block` and `/call` responses together: the node serving the request coordinates,
+//! asking its attested peers for nonce commitments, then the first ones to commit for their
+//! signature shares. Signers are sent the response rather than its digest, and only commit once
+//! the light client got the same from its own view, see [`ResponseCheck`]. Only nodes holding a
+//! share can coordinate. The key is generated by the first
+//! `signers` nodes together, see [`overlay::dkg`]: once they're attested to it, the node
+//! bootstrapping the cluster asks them to, and none of them ever holds the whole key.
+
+use overlay::dkg::{self, Dkg};
+use overlay::frost::{self, Signature, SignatureShare, SigningCommitment, SigningNonces};
+use overlay::message::{DkgMessage, FrostMessage, SignedResponse};
+use overlay::shamir::DealtShare;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::time::Instant;
+
+/// Nonces kept for sessions we committed to, the oldest are dropped first.
+const PENDING_NONCES: usize = 256;
+/// Key generation messages kept until we join their session.
+const EARLY_MESSAGES: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct ThresholdSigningConfig {
+    /// Signers needed for a signature.
+    pub threshold: u16,
+    /// Nodes generating the key.
+    pub signers: u16,
+    /// How long a response waits for the other signers.
+    pub timeout_ms: u64,
+    /// How long signers wait on each other in each round of key generation.
+    pub key_generation_round_ms: u64,
+}
+
+impl Default for ThresholdSigningConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 2,
+            signers: 3,
+            timeout_ms: 5_000,
+            key_generation_round_ms: 10_000,
+        }
+    }
+}
+
+/// Response to sign, and where the signature goes.
+pub(crate) struct SignRequest {
+    response: SignedResponse,
+    reply: oneshot::Sender<anyhow::Result<Signature>>,
+}
+
+/// Response a peer asks us to sign, for the light client to tell whether it got the same.
+pub struct ResponseCheck {
+    pub response: SignedResponse,
+    pub reply: oneshot::Sender<bool>,
+}
+
+/// A response the light client agreed with, to commit to.
+pub(crate) struct Checked {
+    pub session: [u8; 32],
+    pub dealing: [u8; 32],
+    pub message: [u8; 32],
+    pub coordinator: Vec<u8>,
+}
+
+/// What a response's signature is over.
+fn digest(response: &SignedResponse) -> [u8; 32] {
+    Sha256::digest(response.message()).into()
+}
+
+/// Has messages signed by the cluster's signers, see
+/// [`crate::LightClientHandler::threshold_signer`].
+#[derive(Clone)]
+pub struct ThresholdSigner {
+    requests: mpsc::Sender<SignRequest>,
+    group_pubkey: watch::Receiver<Option<Vec<u8>>>,
+    timeout: Duration,
+}
+
+impl ThresholdSigner {
+    /// Compressed key signatures verify under, once the key was dealt.
+    pub fn group_pubkey(&self) -> Option<Vec<u8>> {
+        self.group_pubkey.borrow().clone()
+    }
+
+    /// Signs the SHA-256 digest of the response's message along with other signers, once they
+    /// got the same response.
+    pub async fn sign(&self, response: SignedResponse) -> anyhow::Result<Signature> {
+        let (reply, signature) = oneshot::channel();
+        self.requests
+            .send(SignRequest { response, reply })
+            .await
+            .map_err(|_| anyhow::anyhow!("handler stopped"))?;
+
+        tokio::time::timeout(self.timeout, signature)
+            .await
+            .map_err(|_| anyhow::anyhow!("signers didn't answer within {:?}", self.timeout))?
+            .map_err(|_| anyhow::anyhow!("signing session dropped"))?
+    }
+}
+
+/// A message to send, to the given peers or to everyone.
+pub(crate) type Outgoing = (Option<Vec<Vec<u8>>>, FrostMessage);
+
+pub(crate) struct ThresholdSigning {
+    pub config: ThresholdSigningConfig,
+    /// Our share of the key, once dealt.
+    key: Option<DealtShare>,
+    /// Whether we ask the first signers to generate the key, until they did.
+    initiator: bool,
+    /// The key generation session we take part in, with the round it's at and when that round
+    /// times out.
+    dkg: Option<(Dkg, u8, Instant)>,
+    /// Messages of a session we didn't join yet.
+    early: Vec<(Vec<u8>, DkgMessage)>,
+    pub requests: mpsc::Receiver<SignRequest>,
+    signer: ThresholdSigner,
+    group_pubkey: watch::Sender<Option<Vec<u8>>>,
+    check: mpsc::Sender<ResponseCheck>,
+    checks: Option<mpsc::Receiver<ResponseCheck>>,
+    checked_tx: mpsc::Sender<Checked>,
+    pub checked: mpsc::Receiver<Checked>,
+    /// Nonces by session, along with the digest they're to sign.
+    nonces: VecDeque<([u8; 32], [u8; 32], SigningNonces)>,
+    sessions: HashMap<[u8; 32], Coordination>,
+}
+
+/// A session we coordinate.
+struct Coordination {
+    message: [u8; 32],
+    /// Who committed, `None` for us.
+    committed: Vec<(SigningCommitment, Option<Vec<u8>>)>,
+    /// Set once `threshold` signers committed.
+    commitments: Option<Vec<SigningCommitment>>,
+    shares: Vec<SignatureShare>,
+    reply: oneshot::Sender<anyhow::Result<Signature>>,
+}
+
+impl ThresholdSigning {
+    pub fn new(config: ThresholdSigningConfig) -> Self {
+        let (requests_tx, requests) = mpsc::channel(64);
+        let (group_pubkey, group_pubkey_rx) = watch::channel(None);
+        let (check, checks) = mpsc::channel(64);
+        let (checked_tx, checked) = mpsc::channel(64);
+        Self {
+            signer: ThresholdSigner {
+                requests: requests_tx,
+                group_pubkey: group_pubkey_rx,
+                timeout: Duration::from_millis(config.timeout_ms),
+            },
+            config,
+            key: None,
+            initiator: false,
+            dkg: None,
+            early: Vec::new(),
+            requests,
+            group_pubkey,
+            check,
+            checks: Some(checks),
+            checked_tx,
+            checked,
+            nonces: VecDeque::new(),
+            sessions: HashMap::new(),
+        }
+    }
+
+    pub fn signer(&self) -> ThresholdSigner {
+        self.signer.clone()
+    }
+
+    /// Responses peers ask us to sign, for the light client to check. Only handed out once.
+    pub fn checks(&mut self) -> Option<mpsc::Receiver<ResponseCheck>> {
+        self.checks.take()
+    }
+
+    pub fn key(&self) -> Option<&DealtShare> {
+        self.key.as_ref()
+    }
+
+    pub fn set_key(&mut self, key: DealtShare) {
+        self.group_pubkey
+            .send_replace(frost::group_pubkey(&key.dealing).ok());
+        self.key = Some(key);
+    }
+
+    /// Has us ask the first signers to generate the key.
+    pub fn initiate_key_generation(&mut self) {
+        self.initiator = true;
+    }
+
+    /// Asks `signers` (including `us`) to generate the key, if we're to and there are enough of
+    /// them. Returns the messages to send.
+    pub fn start_key_generation(
+        &mut self,
+        mut signers: Vec<Vec<u8>>,
+        us: &[u8],
+    ) -> anyhow::Result<Vec<dkg::Outgoing>> {
+        signers.sort();
+        signers.dedup();
+        if !self.initiator
+            || self.key.is_some()
+            || self.dkg.is_some()
+            || signers.len() < self.config.signers as usize
+        {
+            return Ok(Vec::new());
+        }
+
+        let signers = signers[..self.config.signers as usize].to_vec();
+        let session: [u8; 32] = secp256k1::rand::random();
+        let others: Vec<Vec<u8>> = signers
+            .iter()
+            .filter(|signer| *signer != us)
+            .cloned()
+            .collect();
+        let mut outgoing = vec![(
+            others,
+            DkgMessage::Start {
+                session,
+                threshold: self.config.threshold,
+                members: signers.clone(),
+            },
+        )];
+        outgoing.extend(self.join_key_generation(session, self.config.threshold, signers, us)?);
+        Ok(outgoing)
+    }
+
+    /// Takes part in the key generation session we were asked to, unless we already hold a key
+    /// or take part in another one. Returns the messages to send.
+    pub fn join_key_generation(
+        &mut self,
+        session: [u8; 32],
+        threshold: u16,
+        members: Vec<Vec<u8>>,
+        us: &[u8],
+    ) -> anyhow::Result<Vec<dkg::Outgoing>> {
+        if self.key.is_some() || self.dkg.is_some() {
+            return Ok(Vec::new());
+        }
+        if threshold != self.config.threshold {
+            anyhow::bail!(
+                "asked for a threshold of {threshold}, we sign with {}",
+                self.config.threshold
+            );
+        }
+
+        let (dkg, mut outgoing) = Dkg::start(session, threshold, members, us)?;
+        self.dkg = Some((dkg, 0, self.round_deadline()));
+        for (from, message) in std::mem::take(&mut self.early) {
+            // NB: early messages of misbehaving members are dropped along with them.
+            if let Ok(more) = self.key_generation(&from, message) {
+                outgoing.extend(more);
+            }
+        }
+        self.track_round();
+        Ok(outgoing)
+    }
+
+    /// A key generation message of `from`, returns the messages to send. Errors on messages no
+    /// honest signer sends.
+    pub fn key_generation(
+        &mut self,
+        from: &[u8],
+        message: DkgMessage,
+    ) -> anyhow::Result<Vec<dkg::Outgoing>> {
+        let Some((dkg, _, _)) = &mut self.dkg else {
+            if self.key.is_none() && self.early.len() < EARLY_MESSAGES {
+                self.early.push((from.to_vec(), message));
+            }
+            return Ok(Vec::new());
+        };
+        // NB: e.g. late messages of a session that failed.
+        if message.session() != dkg.session() {
+            return Ok(Vec::new());
+        }
+        let outgoing = dkg.handle(from, message)?;
+        self.track_round();
+        Ok(outgoing)
+    }
+
+    /// When the current round of key generation times out, if we take part in one.
+    pub fn key_generation_deadline(&self) -> Option<Instant> {
+        self.dkg.as_ref().map(|(_, _, deadline)| *deadline)
+    }
+
+    /// Gives up on the signers we didn't hear from in the current round of key generation,
+    /// returns the messages to send.
+    pub fn key_generation_timeout(&mut self) -> Vec<dkg::Outgoing> {
+        let Some((dkg, _, _)) = &mut self.dkg else {
+            return Vec::new();
+        };
+        let outgoing = dkg.timeout();
+        self.track_round();
+        outgoing
+    }
+
+    /// Ends the key generation session once it's over, keeping the key it generated. Returns
+    /// whether it did, and the signers it disqualified.
+    pub fn generated(&mut self) -> Option<(anyhow::Result<()>, Vec<Vec<u8>>)> {
+        let outcome = self.dkg.as_ref()?.0.outcome()?.cloned();
+        let (dkg, _, _) = self.dkg.take().expect("checked above");
+        let outcome = match outcome {
+            Ok(key) => {
+                self.initiator = false;
+                self.set_key(key);
+                Ok(())
+            }
+            Err(e) => Err(e.into()),
+        };
+        Some((outcome, dkg.disqualified()))
+    }
+
+    fn round_deadline(&self) -> Instant {
+        Instant::now() + Duration::from_millis(self.config.key_generation_round_ms)
+    }
+
+    /// Gives the round the session moved on to its own time.
+    fn track_round(&mut self) {
+        let deadline = self.round_deadline();
+        if let Some((dkg, round, round_deadline)) = &mut self.dkg {
+            if dkg.round() != *round {
+                *round = dkg.round();
+                *round_deadline = deadline;
+            }
+        }
+    }
+
+    /// Starts coordinating the signature of `request`, returns the request for commitments.
+    pub fn start(&mut self, request: SignRequest) -> anyhow::Result<Option<Outgoing>> {
+        self.sessions
+            .retain(|_, session| !session.reply.is_closed());
+        let Some(key) = &self.key else {
+            let _ = request.reply.send(Err(anyhow::anyhow!(
+                "we don't hold a share of the signing key"
+            )));
+            return Ok(None);
+        };
+
+        let session: [u8; 32] = secp256k1::rand::random();
+        let dealing = key.dealing.id();
+        let message = digest(&request.response);
+        let nonces = frost::commit(key.share.index);
+        let commitment = nonces.commitment().clone();
+        self.remember(session, message, nonces);
+        self.sessions.insert(
+            session,
+            Coordination {
+                message,
+                committed: Vec::new(),
+                commitments: None,
+                shares: Vec::new(),
+                reply: request.reply,
+            },
+        );
+
+        // NB: nobody else to wait for with a threshold of one.
+        if let Some(sign) = self.committed(session, commitment, None)? {
+            return Ok(Some(sign));
+        }
+        if !self.sessions.contains_key(&session) {
+            return Ok(None);
+        }
+        Ok(Some((
+            None,
+            FrostMessage::Commit {
+                session,
+                dealing,
+                response: request.response,
+            },
+        )))
+    }
+
+    /// A signer committed to `session`, returns the request for signature shares once enough did.
+    /// Errors if we can't sign with the commitments, failing the session.
+    pub fn committed(
+        &mut self,
+        session: [u8; 32],
+        commitment: SigningCommitment,
+        peer: Option<Vec<u8>>,
+    ) -> anyhow::Result<Option<Outgoing>> {
+        let Some(threshold) = self.key.as_ref().map(|key| key.dealing.threshold as usize) else {
+            return Ok(None);
+        };
+        let Some(coordination) = self.sessions.get_mut(&session) else {
+            return Ok(None);
+        };
+        if coordination.commitments.is_some()
+            || coordination
+                .committed
+                .iter()
+                .any(|(other, _)| other.index == commitment.index)
+        {
+            return Ok(None);
+        }
+        coordination.committed.push((commitment, peer));
+        if coordination.committed.len() < threshold {
+            return Ok(None);
+        }
+
+        coordination
+            .committed
+            .sort_by_key(|(commitment, _)| commitment.index);
+        let commitments: Vec<SigningCommitment> = coordination
+            .committed
+            .iter()
+            .map(|(commitment, _)| commitment.clone())
+            .collect();
+        let peers: Vec<Vec<u8>> = coordination
+            .committed
+            .iter()
+            .filter_map(|(_, peer)| peer.clone())
+            .collect();
+        coordination.commitments = Some(commitments.clone());
+
+        let signed = self
+            .sign(session, &commitments)
+            .and_then(|share| self.signature_share(session, share));
+        if let Err(e) = signed {
+            if let Some(coordination) = self.sessions.remove(&session) {
+                let _ = coordination.reply.send(Err(anyhow::anyhow!(
+                    "couldn't sign with the commitments: {e}"
+                )));
+            }
+            return Err(e);
+        }
+        if peers.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some((
+            Some(peers),
+            FrostMessage::Sign {
+                session,
+                commitments,
+            },
+        )))
+    }
+
+    /// A signer's share of the signature of `session`. Errors if it doesn't verify, failing the
+    /// session.
+    pub fn signature_share(
+        &mut self,
+        session: [u8; 32],
+        share: SignatureShare,
+    ) -> anyhow::Result<()> {
+        let Some(key) = &self.key else {
+            return Ok(());
+        };
+        let Some(coordination) = self.sessions.get_mut(&session) else {
+            return Ok(());
+        };
+        let Some(commitments) = &coordination.commitments else {
+            return Ok(());
+        };
+        if coordination
+            .shares
+            .iter()
+            .any(|other| other.index == share.index)
+        {
+            return Ok(());
+        }
+        if let Err(e) =
+            frost::verify_share(&key.dealing, &coordination.message, commitments, &share)
+        {
+            let coordination = self.sessions.remove(&session).expect("checked above");
+            let _ = coordination
+                .reply
+                .send(Err(anyhow::anyhow!("signer {} misbehaved", share.index)));
+            return Err(e.into());
+        }
+
+        coordination.shares.push(share);
+        if coordination.shares.len() == commitments.len() {
+            let coordination = self.sessions.remove(&session).expect("checked above");
+            let commitments = coordination.commitments.expect("checked above");
+            let signature = frost::aggregate(
+                &key.dealing,
+                &coordination.message,
+                &commitments,
+                &coordination.shares,
+            );
+            let _ = coordination.reply.send(signature.map_err(Into::into));
+        }
+
+        Ok(())
+    }
+
+    /// Has the light client check `response` if we hold a share of `dealing`, returns the check
+    /// to run. Once it agrees, the response comes back out of [`Self::checked`] to commit to.
+    pub fn check(
+        &mut self,
+        session: [u8; 32],
+        dealing: [u8; 32],
+        response: SignedResponse,
+        coordinator: Vec<u8>,
+    ) -> Option<impl Future<Output = ()> + Send + 'static> {
+        self.key
+            .as_ref()
+            .filter(|key| key.dealing.id() == dealing)?;
+        let message = digest(&response);
+        let (reply, agreed) = oneshot::channel();
+        // NB: not checked, nor signed, when the light client has too many checks queued.
+        self.check
+            .try_send(ResponseCheck { response, reply })
+            .ok()?;
+
+        let checked = self.checked_tx.clone();
+        Some(async move {
+            if agreed.await != Ok(true) {
+                tracing::debug!("not signing a response we got otherwise");
+                return;
+            }
+            let _ = checked
+                .send(Checked {
+                    session,
+                    dealing,
+                    message,
+                    coordinator,
+                })
+                .await;
+        })
+    }
+
+    /// Commits to sign `message` in `session` if we hold a share of `dealing`.
+    pub fn commit(
+        &mut self,
+        session: [u8; 32],
+        dealing: [u8; 32],
+        message: [u8; 32],
+    ) -> Option<FrostMessage> {
+        let key = self
+            .key
+            .as_ref()
+            .filter(|key| key.dealing.id() == dealing)?;
+        let nonces = frost::commit(key.share.index);
+        let commitment = nonces.commitment().clone();
+        self.remember(session, message, nonces);
+        Some(FrostMessage::Commitment {
+            session,
+            commitment,
+        })
+    }
+
+    /// Our share of the signature of what we committed to sign in `session`, with the nonces we
+    /// committed.
+    pub fn sign(
+        &mut self,
+        session: [u8; 32],
+        commitments: &[SigningCommitment],
+    ) -> anyhow::Result<SignatureShare> {
+        let key = self
+            .key
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("we don't hold a share of the signing key"))?;
+        let position = self
+            .nonces
+            .iter()
+            .position(|(committed, _, _)| *committed == session)
+            .ok_or_else(|| anyhow::anyhow!("no commitment for this session"))?;
+        // NB: taken out whether signing succeeds or not, nonces never sign twice.
+        let (_, message, nonces) = self.nonces.remove(position).expect("found above");
+
+        Ok(frost::sign(key, nonces, &message, commitments)?)
+    }
+
+    fn remember(&mut self, session: [u8; 32], message: [u8; 32], nonces: SigningNonces) {
+        self.nonces.push_back((session, message, nonces));
+        if self.nonces.len() > PENDING_NONCES {
+            self.nonces.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::VecDeque;
+
+    #[tokio::test]
+    async fn signs_over_sessions() {
+        let config = ThresholdSigningConfig {
+            threshold: 2,
+            signers: 3,
+            timeout_ms: 1_000,
+            key_generation_round_ms: 1_000,
+        };
+        let members = vec![vec![1], vec![2], vec![3]];
+        let mut nodes: Vec<ThresholdSigning> = members
+            .iter()
+            .map(|_| ThresholdSigning::new(config.clone()))
+            .collect();
+
+        // Node 0 asks the others to generate the key once there are enough of them.
+        nodes[0].initiate_key_generation();
+        assert!(nodes[0]
+            .start_key_generation(members[..2].to_vec(), &members[0])
+            .unwrap()
+            .is_empty());
+        let mut queue = VecDeque::new();
+        let send = |queue: &mut VecDeque<_>, from: &[u8], outgoing: Vec<dkg::Outgoing>| {
+            for (to, message) in outgoing {
+                for member in to {
+                    queue.push_back((from.to_vec(), member, message.clone()));
+                }
+            }
+        };
+        send(
+            &mut queue,
+            &members[0],
+            nodes[0]
+                .start_key_generation(members.clone(), &members[0])
+                .unwrap(),
+        );
+        while let Some((from, to, message)) = queue.pop_front() {
+            let node = &mut nodes[to[0] as usize - 1];
+            let outgoing = match message {
+                DkgMessage::Start {
+                    session,
+                    threshold,
+                    members,
+                } => node
+                    .join_key_generation(session, threshold, members, &to)
+                    .unwrap(),
+                message => node.key_generation(&from, message).unwrap(),
+            };
+            send(&mut queue, &to, outgoing);
+        }
+        for node in &mut nodes {
+            let (generated, disqualified) = node.generated().unwrap();
+            generated.unwrap();
+            assert!(disqualified.is_empty());
+        }
+        assert!(nodes[0]
+            .start_key_generation(members.clone(), &members[0])
+            .unwrap()
+            .is_empty());
+
+        let signer = nodes[0].signer();
+        let group_pubkey = signer.group_pubkey().unwrap();
+
+        // Node 0 coordinates, node 2 answers first and node 1 is left out.
+        let response = SignedResponse::Block { number: "4".into() };
+        let (reply, signature) = oneshot::channel();
+        let request = SignRequest {
+            response: response.clone(),
+            reply,
+        };
+        let (targets, commit) = nodes[0].start(request).unwrap().unwrap();
+        assert!(targets.is_none());
+        let FrostMessage::Commit {
+            session,
+            dealing,
+            response: asked,
+        } = commit
+        else {
+            panic!("expected a request for commitments");
+        };
+        assert_eq!(asked, response);
+        let late = nodes[1].commit(session, dealing, digest(&asked)).unwrap();
+
+        // Node 2 only commits once its light client got the same response.
+        let mut checks = nodes[2].checks().unwrap();
+        let check = nodes[2]
+            .check(session, dealing, asked.clone(), vec![1])
+            .unwrap();
+        let ResponseCheck { response, reply } = checks.recv().await.unwrap();
+        assert_eq!(response, asked);
+        reply.send(true).unwrap();
+        check.await;
+        let checked = nodes[2].checked.recv().await.unwrap();
+        assert_eq!(checked.coordinator, vec![1]);
+        let Some(FrostMessage::Commitment { commitment, .. }) =
+            nodes[2].commit(checked.session, checked.dealing, checked.message)
+        else {
+            panic!("expected a commitment");
+        };
+        let (targets, sign) = nodes[0]
+            .committed(session, commitment, Some(vec![3]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(targets, Some(vec![vec![3]]));
+        let FrostMessage::Commitment { commitment, .. } = late else {
+            panic!("expected a commitment");
+        };
+        assert!(nodes[0]
+            .committed(session, commitment, Some(vec![2]))
+            .unwrap()
+            .is_none());
+
+        let FrostMessage::Sign { commitments, .. } = sign else {
+            panic!("expected a request for shares");
+        };
+        let share = nodes[2].sign(session, &commitments).unwrap();
+        // Nonces are gone once used.
+        assert!(nodes[2].sign(session, &commitments).is_err());
+        nodes[0].signature_share(session, share).unwrap();
+
+        let signature = signature.await.unwrap().unwrap();
+        // NB: clients check it over the SHA-256 of the block number.
+        let message: [u8; 32] = Sha256::digest("4").into();
+        signature.verify(&group_pubkey, &message).unwrap();
+
+        // Responses the light client got otherwise aren't committed to.
+        let check = nodes[2]
+            .check(
+                [6; 32],
+                dealing,
+                SignedResponse::Block { number: "5".into() },
+                vec![1],
+            )
+            .unwrap();
+        let ResponseCheck { reply, .. } = checks.recv().await.unwrap();
+        reply.send(false).unwrap();
+        check.await;
+        assert!(nodes[2].checked.try_recv().is_err());
+
+        // A commitment we can't sign with fails the session.
+        let (reply, failed) = oneshot::channel();
+        let request = SignRequest {
+            response: SignedResponse::Block { number: "5".into() },
+            reply,
+        };
+        let (_, commit) = nodes[0].start(request).unwrap().unwrap();
+        let FrostMessage::Commit { session, .. } = commit else {
+            panic!("expected a request for commitments");
+        };
+        let invalid = SigningCommitment {
+            index: 3,
+            hiding: vec![0; 33],
+            binding: vec![0; 33],
+        };
+        assert!(nodes[0].committed(session, invalid, Some(vec![3])).is_err());
+        assert!(failed.await.unwrap().is_err());
+    }
+}
@@ -0,0 +1,159 @@
+//! Authenticode digest of PE images, which is what the firmware measures for the EFI
+//! applications it starts (the kernel's EFI stub included).
+
+use super::MeasureError;
+use sha2::{Digest, Sha384};
+
+const PE_SIGNATURE: &[u8; 4] = b"PE\0\0";
+const PE32_MAGIC: u16 = 0x10b;
+const PE32_PLUS_MAGIC: u16 = 0x20b;
+const CHECKSUM_OFFSET: usize = 64;
+const SECTION_HEADER_LEN: usize = 40;
+/// Index of the certificate table in the data directories.
+const CERTIFICATE_TABLE: usize = 4;
+
+/// SHA384 Authenticode digest: the image without its checksum, its certificate table entry and
+/// the certificates themselves, with sections in file order.
+pub fn authenticode_sha384(image: &[u8]) -> Result<[u8; 48], MeasureError> {
+    let malformed = |reason: &str| MeasureError::Kernel(reason.to_string());
+    let u16_at = |offset: usize| {
+        image
+            .get(offset..offset + 2)
+            .map(|bytes| u16::from_le_bytes(bytes.try_into().unwrap()))
+            .ok_or_else(|| malformed("truncated PE headers"))
+    };
+    let u32_at = |offset: usize| {
+        image
+            .get(offset..offset + 4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+            .ok_or_else(|| malformed("truncated PE headers"))
+    };
+
+    let pe = u32_at(0x3c)? as usize;
+    if image.get(pe..pe + 4) != Some(PE_SIGNATURE) {
+        return Err(malformed("not a PE image"));
+    }
+    let sections = u16_at(pe + 6)? as usize;
+    let optional_header_len = u16_at(pe + 20)? as usize;
+    let optional_header = pe + 24;
+    let (rva_count_offset, directories) = match u16_at(optional_header)? {
+        PE32_MAGIC => (92, 96),
+        PE32_PLUS_MAGIC => (108, 112),
+        _ => return Err(malformed("unknown optional header magic")),
+    };
+    let headers_len = u32_at(optional_header + 60)? as usize;
+    if headers_len > image.len() {
+        return Err(malformed("headers past the end of the image"));
+    }
+
+    let checksum = optional_header + CHECKSUM_OFFSET;
+    let mut hasher = Sha384::new();
+    hasher.update(&image[..checksum]);
+    let (certificates_len, after_directory) =
+        if u32_at(optional_header + rva_count_offset)? as usize > CERTIFICATE_TABLE {
+            let entry = optional_header + directories + CERTIFICATE_TABLE * 8;
+            hasher.update(
+                image
+                    .get(checksum + 4..entry)
+                    .ok_or_else(|| malformed("certificate table entry past the headers"))?,
+            );
+            (u32_at(entry + 4)? as usize, entry + 8)
+        } else {
+            (0, checksum + 4)
+        };
+    hasher.update(
+        image
+            .get(after_directory..headers_len)
+            .ok_or_else(|| malformed("headers too short"))?,
+    );
+
+    let section_table = optional_header + optional_header_len;
+    let mut raw_sections = (0..sections)
+        .map(|i| {
+            let header = section_table + i * SECTION_HEADER_LEN;
+            Ok((u32_at(header + 20)? as usize, u32_at(header + 16)? as usize))
+        })
+        .collect::<Result<Vec<_>, MeasureError>>()?;
+    raw_sections.sort();
+
+    let mut hashed = headers_len;
+    for (offset, len) in raw_sections.into_iter().filter(|(_, len)| *len > 0) {
+        hasher.update(
+            image
+                .get(offset..offset + len)
+                .ok_or_else(|| malformed("section past the end of the image"))?,
+        );
+        hashed += len;
+    }
+
+    // Anything after the sections that isn't the certificates, e.g. a bzImage's payload.
+    if let Some(trailing) = image.len().checked_sub(hashed + certificates_len) {
+        hasher.update(&image[hashed..hashed + trailing]);
+    }
+
+    Ok(hasher.finalize().into())
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    /// PE32+ image with one section and room for a certificate table entry.
+    pub(crate) fn pe_image() -> Vec<u8> {
+        let mut image = vec![0; 0x400];
+        image[..2].copy_from_slice(b"MZ");
+        image[0x3c..0x40].copy_from_slice(&0x80_u32.to_le_bytes());
+        image[0x80..0x84].copy_from_slice(PE_SIGNATURE);
+        image[0x86..0x88].copy_from_slice(&1_u16.to_le_bytes());
+        image[0x94..0x96].copy_from_slice(&0xf0_u16.to_le_bytes());
+        let optional_header = 0x98;
+        image[optional_header..optional_header + 2].copy_from_slice(&PE32_PLUS_MAGIC.to_le_bytes());
+        image[optional_header + 60..optional_header + 64].copy_from_slice(&0x200_u32.to_le_bytes());
+        image[optional_header + 108..optional_header + 112].copy_from_slice(&16_u32.to_le_bytes());
+
+        let section = optional_header + 0xf0;
+        image[section..section + 6].copy_from_slice(b".text\0");
+        image[section + 16..section + 20].copy_from_slice(&0x200_u32.to_le_bytes());
+        image[section + 20..section + 24].copy_from_slice(&0x200_u32.to_le_bytes());
+        image[0x200..].fill(0xcc);
+
+        image
+    }
+
+    #[test]
+    fn ignores_checksum_and_signatures() {
+        let image = pe_image();
+        let digest = authenticode_sha384(&image).unwrap();
+
+        let mut checksummed = image.clone();
+        checksummed[0x98 + CHECKSUM_OFFSET] = 0x42;
+        assert_eq!(authenticode_sha384(&checksummed).unwrap(), digest);
+
+        // Appending certificates and pointing the directory entry at them.
+        let mut signed = image.clone();
+        let entry = 0x98 + 112 + CERTIFICATE_TABLE * 8;
+        signed[entry..entry + 4].copy_from_slice(&0x400_u32.to_le_bytes());
+        signed[entry + 4..entry + 8].copy_from_slice(&0x10_u32.to_le_bytes());
+        signed.extend_from_slice(&[0xee; 0x10]);
+        assert_eq!(authenticode_sha384(&signed).unwrap(), digest);
+
+        let mut patched = image;
+        patched[0x300] = 0;
+        assert_ne!(authenticode_sha384(&patched).unwrap(), digest);
+    }
+
+    #[test]
+    fn trailing_data_is_measured() {
+        let image = pe_image();
+        let mut appended = image.clone();
+        appended.extend_from_slice(b"payload");
+        assert_ne!(
+            authenticode_sha384(&appended).unwrap(),
+            authenticode_sha384(&image).unwrap()
+        );
+        assert!(matches!(
+            authenticode_sha384(&image[..0x100]),
+            Err(MeasureError::Kernel(_))
+        ));
+    }
+}
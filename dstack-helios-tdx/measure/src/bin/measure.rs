@@ -0,0 +1,95 @@
+//! Computes the expected measurements of a TD image and emits them as an attestation policy
+//! nodes can be configured with (the `attestation_policy` field of the setup request).
+//!
+//! Usage: `measure --firmware <OVMF.fd> --kernel <bzImage> --initrd <rootfs> [--cmdline <cmdline>]
+//! [--rtmr0 <hex>] [--event <name>=<hex>] [--event-file <name>=<path>] [--two-pass]
+//! [--policy <policy.json>] [--quote <quote.hex>]`
+//!
+//! `--event-file` pins the sha256 of a file (e.g. dstack's `compose-hash` of the app compose
+//! file). With `--policy` the entry is appended to the file's allowlist, so that a new build can
+//! be rolled out next to the current one, otherwise a fresh policy is printed. With `--quote`, a
+//! hex encoded quote of a node booted from the image, nothing is emitted unless its registers are
+//! the expected ones.
+
+use anyhow::{bail, Context, Result};
+use measure::{ImageArtifacts, PageAddOrder};
+use mocks::attestation::AttestationPolicy;
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+
+const USAGE: &str = "usage: measure --firmware <OVMF.fd> --kernel <bzImage> --initrd <rootfs> \
+    [--cmdline <cmdline>] [--rtmr0 <hex>] [--event <name>=<hex>] [--event-file <name>=<path>] \
+    [--two-pass] [--policy <policy.json>] [--quote <quote.hex>]";
+
+fn main() -> Result<()> {
+    let mut artifacts = ImageArtifacts::default();
+    let (mut firmware, mut kernel, mut initrd, mut policy_path) = (None, None, None, None);
+    let mut quote = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().context(USAGE);
+        match arg.as_str() {
+            "--firmware" => firmware = Some(value()?),
+            "--kernel" => kernel = Some(value()?),
+            "--initrd" => initrd = Some(value()?),
+            "--cmdline" => artifacts.cmdline = value()?,
+            // NB: same hex parsing as policy files.
+            "--rtmr0" => artifacts.rtmr0 = Some(serde_json::from_value(value()?.into())?),
+            "--event" => {
+                let (event, payload) = named(&value()?)?;
+                artifacts.events.insert(event, hex::decode(payload)?);
+            }
+            "--event-file" => {
+                let (event, path) = named(&value()?)?;
+                let payload = Sha256::digest(std::fs::read(&path).context(path)?);
+                artifacts.events.insert(event, payload.to_vec());
+            }
+            "--two-pass" => artifacts.page_add_order = PageAddOrder::TwoPass,
+            "--policy" => policy_path = Some(PathBuf::from(value()?)),
+            "--quote" => quote = Some(value()?),
+            _ => bail!("unknown argument {arg}\n{USAGE}"),
+        }
+    }
+
+    let read = |path: Option<String>| -> Result<Vec<u8>> {
+        let path = path.context(USAGE)?;
+        std::fs::read(&path).context(path)
+    };
+    artifacts.firmware = read(firmware)?;
+    artifacts.kernel = read(kernel)?;
+    artifacts.initrd = read(initrd)?;
+
+    let expected = artifacts.expected_measurements()?;
+    eprintln!(
+        "mrtd={} rtmr1={} rtmr2={}",
+        expected.mrtd.unwrap_or_default(),
+        expected.rtmrs[1].unwrap_or_default(),
+        expected.rtmrs[2].unwrap_or_default()
+    );
+    if let Some(path) = quote {
+        let quote = hex::decode(std::fs::read_to_string(&path).context(path)?.trim())?;
+        measure::check_quote(&expected, &quote)?;
+        eprintln!("matches the quote");
+    }
+
+    let mut policy = match &policy_path {
+        Some(path) if path.exists() => serde_json::from_slice(&std::fs::read(path)?)?,
+        _ => AttestationPolicy::default(),
+    };
+    policy.measurements.push(expected);
+    let policy = serde_json::to_string_pretty(&policy)?;
+
+    match policy_path {
+        Some(path) => std::fs::write(path, policy)?,
+        None => println!("{policy}"),
+    }
+
+    Ok(())
+}
+
+/// Splits `<name>=<value>`.
+fn named(arg: &str) -> Result<(String, String)> {
+    let (name, value) = arg.split_once('=').context(USAGE)?;
+    Ok((name.to_string(), value.to_string()))
+}
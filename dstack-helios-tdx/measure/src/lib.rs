@@ -0,0 +1,227 @@
+//! Expected measurements of a TD image, computed from its build artifacts rather than read off a
+//! running node, and turned into an entry of the overlay's measurement allowlist.
+//!
+//! - MRTD is replayed from the TDVF firmware, see [`tdvf`].
+//! - RTMR1 and RTMR2 are replayed from the boot events the firmware measures for a direct
+//!   kernel boot: the kernel's Authenticode digest, then its command line and initrd (which is
+//!   the rootfs, app binary included, on our minimal images).
+//! - RTMR0 depends on the VMM's configuration (ACPI tables, TD HOB, EFI variables), so it's only
+//!   pinned when given, e.g. read off a reference quote.
+//! - RTMR3 carries per instance runtime events, it's left open and the app specific events
+//!   (e.g. `compose-hash`) are pinned instead, see [`mocks::attestation::verify_event_log`].
+//!
+//! NB: no golden vector of a published image is checked in yet, so the replay is only known to
+//! be self-consistent. Cross-check an entry against a quote of a node booted from the same image,
+//! see [`check_quote`], before relying on it.
+
+use mocks::attestation::{
+    replay_rtmrs, AllowedMeasurements, EventLog, EventLogEntry, Measurement, TeeType,
+};
+use sha2::{Digest, Sha384};
+use std::collections::BTreeMap;
+use thiserror::Error;
+
+pub mod authenticode;
+pub mod tdvf;
+
+pub use authenticode::authenticode_sha384;
+pub use tdvf::{PageAddOrder, Tdvf};
+
+const EV_SEPARATOR: u32 = 0x4;
+const EV_EVENT_TAG: u32 = 0x6;
+const EV_EFI_BOOT_SERVICES_APPLICATION: u32 = 0x80000003;
+const EV_EFI_ACTION: u32 = 0x80000007;
+
+#[derive(Debug, Error)]
+pub enum MeasureError {
+    #[error("Malformed firmware: {0}")]
+    Firmware(String),
+
+    #[error("Malformed kernel: {0}")]
+    Kernel(String),
+
+    #[error("Malformed event log: {0}")]
+    EventLog(#[from] mocks::attestation::VerificationError),
+
+    #[error("Quote doesn't match: {0}")]
+    Quote(String),
+}
+
+/// What goes into a TD image.
+#[derive(Debug, Clone, Default)]
+pub struct ImageArtifacts {
+    /// TDVF (OVMF) firmware.
+    pub firmware: Vec<u8>,
+    /// Kernel as the firmware loads it. NB: QEMU patches a few setup header fields of bzImages
+    /// it boots directly, the hash must be over the patched image.
+    pub kernel: Vec<u8>,
+    pub cmdline: String,
+    pub initrd: Vec<u8>,
+    pub page_add_order: PageAddOrder,
+    /// Pinned as is, see the module docs.
+    pub rtmr0: Option<Measurement>,
+    /// Runtime events the app must have measured, by name, with their payload.
+    pub events: BTreeMap<String, Vec<u8>>,
+}
+
+/// Boot events the firmware measures into RTMR1 and RTMR2 for a direct kernel boot.
+pub fn boot_event_log(
+    kernel: &[u8],
+    cmdline: &str,
+    initrd: &[u8],
+) -> Result<EventLog, MeasureError> {
+    // NB: the firmware hands the command line to the kernel as a NUL terminated UTF-16 string.
+    let mut cmdline = cmdline
+        .encode_utf16()
+        .flat_map(u16::to_le_bytes)
+        .collect::<Vec<_>>();
+    cmdline.extend_from_slice(&[0, 0]);
+
+    Ok(vec![
+        boot_event(
+            1,
+            EV_EFI_BOOT_SERVICES_APPLICATION,
+            authenticode_sha384(kernel)?,
+        ),
+        boot_event(
+            1,
+            EV_EFI_ACTION,
+            sha384(b"Calling EFI Application from Boot Option"),
+        ),
+        boot_event(1, EV_SEPARATOR, sha384(&[0; 4])),
+        boot_event(1, EV_EFI_ACTION, sha384(b"Exit Boot Services Invocation")),
+        boot_event(
+            1,
+            EV_EFI_ACTION,
+            sha384(b"Exit Boot Services Returned with Success"),
+        ),
+        boot_event(2, EV_EVENT_TAG, sha384(&cmdline)),
+        boot_event(2, EV_EVENT_TAG, sha384(initrd)),
+    ])
+}
+
+fn boot_event(imr: u32, event_type: u32, digest: [u8; 48]) -> EventLogEntry {
+    EventLogEntry {
+        imr,
+        event_type,
+        digest: Measurement(digest),
+        event: String::new(),
+        event_payload: vec![],
+    }
+}
+
+fn sha384(data: &[u8]) -> [u8; 48] {
+    Sha384::digest(data).into()
+}
+
+impl ImageArtifacts {
+    /// Allowlist entry matching TDs booted from these artifacts.
+    pub fn expected_measurements(&self) -> Result<AllowedMeasurements, MeasureError> {
+        let mrtd = Tdvf::parse(&self.firmware)?.mrtd(self.page_add_order);
+        let rtmrs = replay_rtmrs(&boot_event_log(&self.kernel, &self.cmdline, &self.initrd)?)?;
+
+        Ok(AllowedMeasurements {
+            tee: Some(TeeType::Tdx),
+            mrtd: Some(mrtd),
+            rtmrs: [self.rtmr0, Some(rtmrs[1]), Some(rtmrs[2]), None],
+            events: self
+                .events
+                .iter()
+                .map(|(event, payload)| (event.clone(), hex::encode(payload)))
+                .collect(),
+        })
+    }
+}
+
+/// Checks the registers `expected` pins against the ones of `quote`, a TD quote of a node booted
+/// from the same image. The quote is only read, not verified.
+pub fn check_quote(expected: &AllowedMeasurements, quote: &[u8]) -> Result<(), MeasureError> {
+    let quote =
+        dcap_qvl::quote::Quote::parse(quote).map_err(|e| MeasureError::Quote(e.to_string()))?;
+    let report = quote
+        .report
+        .as_td10()
+        .ok_or_else(|| MeasureError::Quote("not a TD quote".into()))?;
+
+    let registers = [
+        ("mrtd", expected.mrtd, report.mr_td),
+        ("rtmr0", expected.rtmrs[0], report.rt_mr0),
+        ("rtmr1", expected.rtmrs[1], report.rt_mr1),
+        ("rtmr2", expected.rtmrs[2], report.rt_mr2),
+        ("rtmr3", expected.rtmrs[3], report.rt_mr3),
+    ];
+    let differing: Vec<_> = registers
+        .iter()
+        .filter(|(_, expected, actual)| expected.is_some_and(|expected| expected.0 != *actual))
+        .map(|(register, ..)| *register)
+        .collect();
+    if !differing.is_empty() {
+        return Err(MeasureError::Quote(format!(
+            "{} differ",
+            differing.join(", ")
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mocks::attestation::{
+        verify_event_log, AttestationPolicy, AttestationProvider, MockProvider,
+    };
+
+    fn artifacts() -> ImageArtifacts {
+        ImageArtifacts {
+            firmware: tdvf::test::firmware(),
+            kernel: authenticode::test::pe_image(),
+            cmdline: "console=ttyS0 root=/dev/ram0".into(),
+            initrd: b"rootfs with the light client".to_vec(),
+            events: [("compose-hash".to_string(), vec![3; 32])].into(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn policy_accepts_the_image_it_was_built_from() {
+        let artifacts = artifacts();
+        let policy = AttestationPolicy {
+            measurements: vec![artifacts.expected_measurements().unwrap()],
+            ..Default::default()
+        };
+        // The emitted file is what nodes load.
+        let policy: AttestationPolicy =
+            serde_json::from_str(&serde_json::to_string(&policy).unwrap()).unwrap();
+
+        // A mock TD booted from the artifacts, that measured the app at runtime.
+        let mut event_log =
+            boot_event_log(&artifacts.kernel, &artifacts.cmdline, &artifacts.initrd).unwrap();
+        event_log.push(EventLogEntry::runtime("instance-id", &[9; 20]));
+        event_log.push(EventLogEntry::runtime("compose-hash", &[3; 32]));
+        let mrtd = Tdvf::parse(&artifacts.firmware)
+            .unwrap()
+            .mrtd(PageAddOrder::SinglePass);
+        let node = MockProvider::new()
+            .with_measurements(mrtd, Default::default())
+            .with_event_log(event_log.clone())
+            .unwrap();
+
+        let quote = node.get_quote(b"pubkey").await.unwrap();
+        let mut report = node.verify_quote(&quote, b"pubkey").await.unwrap();
+        report.events = verify_event_log(&event_log, &report).unwrap();
+        assert!(policy.check(&report).is_ok());
+        let quote = hex::decode(&quote).unwrap();
+        check_quote(&policy.measurements[0], &quote).unwrap();
+
+        // Same image, another command line.
+        let other = ImageArtifacts {
+            cmdline: "console=ttyS0 root=/dev/ram0 init=/bin/sh".into(),
+            ..artifacts
+        };
+        let other = other.expected_measurements().unwrap();
+        assert!(!other.matches(&report));
+        let e = check_quote(&other, &quote).unwrap_err();
+        assert_eq!(e.to_string(), "Quote doesn't match: rtmr2 differ");
+    }
+}
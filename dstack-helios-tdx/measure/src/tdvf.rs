@@ -0,0 +1,298 @@
+//! MRTD of a TDVF (TDX OVMF) firmware image.
+//!
+//! The VMM loads the firmware sections listed in the TDVF metadata into the TD before it runs,
+//! and the TDX module measures each `TDH.MEM.PAGE.ADD`, and each `TDH.MR.EXTEND` of a section
+//! flagged for it, into MRTD. Replaying those on the firmware file gives the expected MRTD.
+
+use super::MeasureError;
+use mocks::attestation::Measurement;
+use sha2::{Digest, Sha384};
+
+const PAGE_SIZE: u64 = 0x1000;
+const EXTEND_CHUNK: usize = 256;
+
+/// `96b582de-1fb2-45f7-baea-a366c55a082d`, ends the OVMF GUIDed table.
+const TABLE_FOOTER_GUID: [u8; 16] = [
+    0xde, 0x82, 0xb5, 0x96, 0xb2, 0x1f, 0xf7, 0x45, 0xba, 0xea, 0xa3, 0x66, 0xc5, 0x5a, 0x08, 0x2d,
+];
+/// `e47a6535-984a-4798-865e-4685a7bf8ec2`, its entry holds the offset of the TDVF metadata from
+/// the end of the image.
+const TDX_METADATA_GUID: [u8; 16] = [
+    0x35, 0x65, 0x7a, 0xe4, 0x4a, 0x98, 0x98, 0x47, 0x86, 0x5e, 0x46, 0x85, 0xa7, 0xbf, 0x8e, 0xc2,
+];
+/// The GUIDed table ends 0x20 bytes before the end of the image, right before the reset vector.
+const TABLE_END_OFFSET: usize = 0x20;
+
+const METADATA_SIGNATURE: &[u8; 4] = b"TDVF";
+const METADATA_HEADER_LEN: usize = 16;
+const SECTION_LEN: usize = 32;
+
+/// Measure the section into MRTD on top of adding it.
+const ATTRIBUTE_MR_EXTEND: u32 = 1;
+/// Accepted by the TD at runtime, not added by the VMM.
+const ATTRIBUTE_PAGE_AUG: u32 = 2;
+
+/// In which order the VMM adds and measures pages. Current KVM extends each page right after
+/// adding it, older versions added every section first and extended them after.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PageAddOrder {
+    #[default]
+    SinglePass,
+    TwoPass,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TdvfSection {
+    pub data_offset: u32,
+    pub raw_data_size: u32,
+    pub memory_address: u64,
+    pub memory_data_size: u64,
+    pub section_type: u32,
+    pub attributes: u32,
+}
+
+/// Sections of a TDVF image, with the image itself.
+#[derive(Debug, Clone)]
+pub struct Tdvf<'a> {
+    image: &'a [u8],
+    pub sections: Vec<TdvfSection>,
+}
+
+impl<'a> Tdvf<'a> {
+    pub fn parse(image: &'a [u8]) -> Result<Self, MeasureError> {
+        let malformed = |reason: &str| MeasureError::Firmware(reason.to_string());
+
+        let offset = metadata_offset(image).ok_or_else(|| malformed("no TDX metadata entry"))?;
+        let metadata = image
+            .len()
+            .checked_sub(offset as usize)
+            .and_then(|start| image.get(start..))
+            .ok_or_else(|| malformed("TDX metadata offset out of bounds"))?;
+        if metadata.len() < METADATA_HEADER_LEN || &metadata[..4] != METADATA_SIGNATURE {
+            return Err(malformed("bad TDVF metadata signature"));
+        }
+
+        let sections = u32_at(metadata, 12) as usize;
+        let sections = metadata[METADATA_HEADER_LEN..]
+            .chunks_exact(SECTION_LEN)
+            .take(sections)
+            .map(|section| TdvfSection {
+                data_offset: u32_at(section, 0),
+                raw_data_size: u32_at(section, 4),
+                memory_address: u64_at(section, 8),
+                memory_data_size: u64_at(section, 16),
+                section_type: u32_at(section, 24),
+                attributes: u32_at(section, 28),
+            })
+            .collect::<Vec<_>>();
+        if sections.len() != u32_at(metadata, 12) as usize {
+            return Err(malformed("truncated TDVF sections"));
+        }
+
+        for section in &sections {
+            let end = section.data_offset as usize + section.raw_data_size as usize;
+            if end > image.len()
+                || section.raw_data_size as u64 > section.memory_data_size
+                || section.memory_address % PAGE_SIZE != 0
+                || section.memory_data_size % PAGE_SIZE != 0
+            {
+                return Err(malformed("bad TDVF section bounds"));
+            }
+        }
+
+        Ok(Self { image, sections })
+    }
+
+    pub fn mrtd(&self, order: PageAddOrder) -> Measurement {
+        let mut mrtd = Sha384::new();
+        let added = || {
+            self.sections
+                .iter()
+                .filter(|section| section.attributes & ATTRIBUTE_PAGE_AUG == 0)
+        };
+
+        for section in added() {
+            for page in 0..section.memory_data_size / PAGE_SIZE {
+                let gpa = section.memory_address + page * PAGE_SIZE;
+                mrtd.update(page_add(gpa));
+                if order == PageAddOrder::SinglePass {
+                    self.extend_page(&mut mrtd, section, page);
+                }
+            }
+        }
+        if order == PageAddOrder::TwoPass {
+            for section in added() {
+                for page in 0..section.memory_data_size / PAGE_SIZE {
+                    self.extend_page(&mut mrtd, section, page);
+                }
+            }
+        }
+
+        Measurement(mrtd.finalize().into())
+    }
+
+    fn extend_page(&self, mrtd: &mut Sha384, section: &TdvfSection, page: u64) {
+        if section.attributes & ATTRIBUTE_MR_EXTEND == 0 {
+            return;
+        }
+
+        // NB: the section is zero padded from its raw data up to its memory size.
+        let mut content = [0; PAGE_SIZE as usize];
+        let page_start = page * PAGE_SIZE;
+        if page_start < section.raw_data_size as u64 {
+            let start = section.data_offset as usize + page_start as usize;
+            let end = section.data_offset as usize + section.raw_data_size as usize;
+            let data = &self.image[start..end.min(start + PAGE_SIZE as usize)];
+            content[..data.len()].copy_from_slice(data);
+        }
+
+        for (i, chunk) in content.chunks(EXTEND_CHUNK).enumerate() {
+            let gpa = section.memory_address + page_start + (i * EXTEND_CHUNK) as u64;
+            mrtd.update(mr_extend(gpa));
+            mrtd.update(chunk);
+        }
+    }
+}
+
+/// Buffer the TDX module hashes for `TDH.MEM.PAGE.ADD`.
+fn page_add(gpa: u64) -> [u8; 128] {
+    operation(b"MEM.PAGE.ADD", gpa)
+}
+
+/// Buffer the TDX module hashes for `TDH.MR.EXTEND`, followed by the extended chunk.
+fn mr_extend(gpa: u64) -> [u8; 128] {
+    operation(b"MR.EXTEND", gpa)
+}
+
+fn operation(name: &[u8], gpa: u64) -> [u8; 128] {
+    let mut buffer = [0; 128];
+    buffer[..name.len()].copy_from_slice(name);
+    buffer[16..24].copy_from_slice(&gpa.to_le_bytes());
+    buffer
+}
+
+/// Walks the OVMF GUIDed table backwards from its footer for the TDX metadata entry.
+fn metadata_offset(image: &[u8]) -> Option<u32> {
+    let footer_end = image.len().checked_sub(TABLE_END_OFFSET)?;
+    let footer = image.get(footer_end.checked_sub(18)?..footer_end)?;
+    if footer[2..] != TABLE_FOOTER_GUID {
+        return None;
+    }
+
+    let table_len = u16::from_le_bytes([footer[0], footer[1]]) as usize;
+    let table_start = footer_end.checked_sub(table_len)?;
+    let mut end = footer_end - 18;
+    while end >= table_start + 18 {
+        let guid = &image[end - 16..end];
+        let entry_len = u16::from_le_bytes([image[end - 18], image[end - 17]]) as usize;
+        if entry_len < 18 || end < table_start + entry_len {
+            return None;
+        }
+        if guid == TDX_METADATA_GUID && entry_len >= 22 {
+            return Some(u32_at(image, end - 22));
+        }
+        end -= entry_len;
+    }
+
+    None
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    /// Minimal TDVF image: a BFV section measured into MRTD and a TD HOB section that is only
+    /// added.
+    pub(crate) fn firmware() -> Vec<u8> {
+        let mut image = vec![0xaa; 0x1800];
+        image[0x1000..].fill(0);
+
+        let metadata_start = image.len();
+        image.extend_from_slice(METADATA_SIGNATURE);
+        image.extend_from_slice(&((METADATA_HEADER_LEN + 2 * SECTION_LEN) as u32).to_le_bytes());
+        image.extend_from_slice(&1_u32.to_le_bytes());
+        image.extend_from_slice(&2_u32.to_le_bytes());
+        for (data_offset, raw_data_size, memory_address, memory_data_size, kind, attributes) in [
+            (
+                0_u32,
+                0x1800_u32,
+                0xffff_e000_u64,
+                0x2000_u64,
+                0_u32,
+                ATTRIBUTE_MR_EXTEND,
+            ),
+            (0, 0, 0x80_0000, 0x1000, 2, 0),
+        ] {
+            image.extend_from_slice(&data_offset.to_le_bytes());
+            image.extend_from_slice(&raw_data_size.to_le_bytes());
+            image.extend_from_slice(&memory_address.to_le_bytes());
+            image.extend_from_slice(&memory_data_size.to_le_bytes());
+            image.extend_from_slice(&kind.to_le_bytes());
+            image.extend_from_slice(&attributes.to_le_bytes());
+        }
+
+        // GUIDed table: the metadata entry, then the footer.
+        let image_len = image.len() + 22 + 18 + TABLE_END_OFFSET;
+        image.extend_from_slice(&((image_len - metadata_start) as u32).to_le_bytes());
+        image.extend_from_slice(&22_u16.to_le_bytes());
+        image.extend_from_slice(&TDX_METADATA_GUID);
+        image.extend_from_slice(&(22_u16 + 18).to_le_bytes());
+        image.extend_from_slice(&TABLE_FOOTER_GUID);
+        image.extend_from_slice(&[0; TABLE_END_OFFSET]);
+        assert_eq!(image.len(), image_len);
+
+        image
+    }
+
+    #[test]
+    fn parses_sections() {
+        let image = firmware();
+        let tdvf = Tdvf::parse(&image).unwrap();
+        assert_eq!(tdvf.sections.len(), 2);
+        assert_eq!(tdvf.sections[0].memory_address, 0xffff_e000);
+        assert_eq!(tdvf.sections[1].section_type, 2);
+
+        assert!(matches!(
+            Tdvf::parse(&image[..image.len() - 1]),
+            Err(MeasureError::Firmware(_))
+        ));
+    }
+
+    #[test]
+    fn mrtd_replays_page_adds_and_extends() {
+        let image = firmware();
+        let tdvf = Tdvf::parse(&image).unwrap();
+
+        let mut expected = Sha384::new();
+        for page in 0..2_u64 {
+            let gpa = 0xffff_e000 + page * PAGE_SIZE;
+            expected.update(page_add(gpa));
+            let mut content = [0; PAGE_SIZE as usize];
+            if page == 0 {
+                content.fill(0xaa);
+            }
+            for chunk in 0..16 {
+                expected.update(mr_extend(gpa + chunk * EXTEND_CHUNK as u64));
+                expected.update(&content[..EXTEND_CHUNK]);
+            }
+        }
+        expected.update(page_add(0x80_0000));
+        assert_eq!(
+            tdvf.mrtd(PageAddOrder::SinglePass),
+            Measurement(expected.finalize().into())
+        );
+
+        assert_ne!(
+            tdvf.mrtd(PageAddOrder::SinglePass),
+            tdvf.mrtd(PageAddOrder::TwoPass)
+        );
+    }
+}
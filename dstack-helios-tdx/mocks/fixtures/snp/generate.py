@@ -0,0 +1,110 @@
+"""Generates the SEV-SNP test chain and report under this directory.
+
+A test ARK and ASK (RSA-PSS like AMD's, but 2048 bits) certify a VCEK (P-384) carrying the
+chip ID and TCB extensions of real VCEKs. `report.bin` is a version 2 report signed by the VCEK
+over report data committing to `b"pubkey"`, `vcek.key` lets tests sign more reports.
+"""
+
+import datetime
+import hashlib
+import os
+import struct
+
+from cryptography import x509
+from cryptography.hazmat.primitives import hashes, serialization
+from cryptography.hazmat.primitives.asymmetric import ec, padding, rsa
+from cryptography.hazmat.primitives.asymmetric.utils import decode_dss_signature
+from cryptography.x509.oid import NameOID
+
+HERE = os.path.dirname(os.path.abspath(__file__))
+CHIP_ID = bytes([0x22] * 64)
+# bootloader, tee, snp, microcode
+TCB = (3, 0, 8, 115)
+MEASUREMENT = bytes([0x11] * 48)
+PSS = padding.PSS(mgf=padding.MGF1(hashes.SHA384()), salt_length=48)
+
+
+def name(common_name):
+    return x509.Name(
+        [
+            x509.NameAttribute(NameOID.ORGANIZATION_NAME, "tplus test AMD"),
+            x509.NameAttribute(NameOID.COMMON_NAME, common_name),
+        ]
+    )
+
+
+def cert(subject, issuer, public_key, signing_key, extensions=()):
+    builder = (
+        x509.CertificateBuilder()
+        .subject_name(name(subject))
+        .issuer_name(name(issuer))
+        .public_key(public_key)
+        .serial_number(x509.random_serial_number())
+        .not_valid_before(datetime.datetime(2025, 1, 1))
+        .not_valid_after(datetime.datetime(2050, 1, 1))
+    )
+    for extension in extensions:
+        builder = builder.add_extension(extension, critical=False)
+    return builder.sign(signing_key, hashes.SHA384(), rsa_padding=PSS)
+
+
+def der_integer(value):
+    return bytes([0x02, 1, value]) if value < 0x80 else bytes([0x02, 2, 0, value])
+
+
+def report_data(appdata):
+    digest = hashlib.sha256(b"register" + appdata.hex().encode()).digest()
+    return digest + bytes(32)
+
+
+ark_key = rsa.generate_private_key(65537, 2048)
+ask_key = rsa.generate_private_key(65537, 2048)
+vcek_key = ec.generate_private_key(ec.SECP384R1())
+
+ark = cert("ARK-Test", "ARK-Test", ark_key.public_key(), ark_key)
+ask = cert("SEV-Test", "ARK-Test", ask_key.public_key(), ark_key)
+oid = lambda suffix: x509.ObjectIdentifier("1.3.6.1.4.1.3704.1." + suffix)
+vcek = cert(
+    "SEV-VCEK",
+    "SEV-Test",
+    vcek_key.public_key(),
+    ask_key,
+    [
+        x509.UnrecognizedExtension(oid("3.1"), der_integer(TCB[0])),
+        x509.UnrecognizedExtension(oid("3.2"), der_integer(TCB[1])),
+        x509.UnrecognizedExtension(oid("3.3"), der_integer(TCB[2])),
+        x509.UnrecognizedExtension(oid("3.8"), der_integer(TCB[3])),
+        x509.UnrecognizedExtension(oid("4"), CHIP_ID),
+    ],
+)
+
+tcb = bytes([TCB[0], TCB[1], 0, 0, 0, 0, TCB[2], TCB[3]])
+report = bytearray(1184)
+struct.pack_into("<IIQ", report, 0, 2, 0, 0x30000)
+struct.pack_into("<II", report, 0x30, 0, 1)
+report[0x38:0x40] = tcb
+report[0x50:0x90] = report_data(b"pubkey")
+report[0x90:0xC0] = MEASUREMENT
+report[0x180:0x188] = tcb
+report[0x1A0:0x1E0] = CHIP_ID
+report[0x1E0:0x1E8] = tcb
+r, s = decode_dss_signature(vcek_key.sign(bytes(report[:0x2A0]), ec.ECDSA(hashes.SHA384())))
+report[0x2A0:0x2A0 + 72] = r.to_bytes(72, "little")
+report[0x2A0 + 72:0x2A0 + 144] = s.to_bytes(72, "little")
+
+for file, data in [
+    ("ark.der", ark.public_bytes(serialization.Encoding.DER)),
+    ("ask.der", ask.public_bytes(serialization.Encoding.DER)),
+    ("vcek.der", vcek.public_bytes(serialization.Encoding.DER)),
+    (
+        "vcek.key",
+        vcek_key.private_bytes(
+            serialization.Encoding.DER,
+            serialization.PrivateFormat.PKCS8,
+            serialization.NoEncryption(),
+        ),
+    ),
+    ("report.bin", bytes(report)),
+]:
+    with open(os.path.join(HERE, file), "wb") as out:
+        out.write(data)
@@ -0,0 +1,289 @@
+//! Attestation providers.
+//!
+//! The overlay and the light client only talk to an [`AttestationProvider`] trait object, so
+//! the backend that produces and verifies quotes can be picked at runtime (see
+//! [`ProviderConfig`]) and swapped for [`MockProvider`] in tests.
+//!
+//! Quotes are either TD quotes or SEV-SNP evidence (see [`snp`]), every provider verifies both
+//! so that clusters mixing TDX and SEV-SNP nodes attest each other.
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use thiserror::Error;
+
+mod cache;
+mod collateral;
+mod dstack;
+mod event_log;
+mod fixture;
+mod mock;
+mod policy;
+mod report;
+pub mod snp;
+mod tdx;
+
+pub use cache::{CacheStats, VerificationCache, VerificationCacheConfig};
+pub use collateral::{CachedCollateral, CollateralCache, CollateralConfig, INTEL_PCS_URL};
+pub use dstack::DstackGuestProvider;
+pub use event_log::{
+    replay_rtmrs, verify_event_log, EventLog, EventLogEntry, DSTACK_RUNTIME_EVENT_TYPE,
+};
+pub use fixture::{test_root, verify_chain, MockQuote};
+pub use mock::MockProvider;
+pub use policy::{AllowedMeasurements, AttestationPolicy, PolicyViolation, TcbPolicy};
+pub use report::{
+    hex_array, hex_bytes, Measurement, QuoteReport, TcbStatus, TeeType, SNP_POLICY_DEBUG,
+    TD_ATTRIBUTES_DEBUG,
+};
+pub use snp::{SnpConfig, SnpProvider, SnpVerifier};
+pub use tdx::TdxProvider;
+
+/// Hex encoded quote.
+pub type Quote = String;
+
+#[derive(Debug, Error)]
+pub enum VerificationError {
+    #[error("Malformed quote: {0}")]
+    Malformed(String),
+
+    #[error("Couldn't get quote collateral: {0}")]
+    Collateral(String),
+
+    #[error("Quote verification failed: {0}")]
+    Invalid(String),
+
+    #[error("Quote is not a TD report or an SEV-SNP report")]
+    UnsupportedReport,
+
+    #[error("Quote report data doesn't commit to the expected app data")]
+    ReportDataMismatch,
+
+    #[error("Event log doesn't replay to the quote's RTMR{0}")]
+    EventLogMismatch(usize),
+}
+
+#[async_trait]
+pub trait AttestationProvider: Send + Sync {
+    /// Generates a quote committing to `appdata`.
+    async fn get_quote(&self, appdata: &[u8]) -> anyhow::Result<Quote>;
+
+    /// Verifies `quote` and checks that its report data commits to `appdata`.
+    async fn verify_quote(
+        &self,
+        quote: &str,
+        appdata: &[u8],
+    ) -> Result<QuoteReport, VerificationError>;
+
+    /// The TD event log our quotes' RTMRs were built from, if the backend can get it.
+    async fn event_log(&self) -> anyhow::Result<Option<EventLog>> {
+        Ok(None)
+    }
+
+    /// Key the TEE derives for `label` from our measurements: the same across restarts of the
+    /// same build, out of reach of any other. `None` when the backend can't derive one.
+    async fn sealing_key(&self, _label: &str) -> anyhow::Result<Option<[u8; 32]>> {
+        Ok(None)
+    }
+
+    /// Changes whenever the collateral quotes are verified against is updated, so that results
+    /// verified under the previous one aren't reused, see [`VerificationCache`].
+    fn collateral_epoch(&self) -> u64 {
+        0
+    }
+
+    /// Key of what verifying `quote` shares with every quote of the same platform: the
+    /// certification chain, collateral and TCB evaluation. `None` when quotes are only verified
+    /// whole, see [`VerificationCache`].
+    fn platform_key(&self, _quote: &str) -> Option<[u8; 32]> {
+        None
+    }
+
+    /// Verifies what's specific to `quote`, its signature and that it commits to `appdata`,
+    /// given the report of a full verification of a quote with the same
+    /// [`Self::platform_key`].
+    fn verify_on_platform(
+        &self,
+        _quote: &str,
+        _appdata: &[u8],
+        _platform: &QuoteReport,
+    ) -> Result<QuoteReport, VerificationError> {
+        Err(VerificationError::UnsupportedReport)
+    }
+}
+
+/// Report data committing to `appdata`. This is the hashing our tsm quote generation does
+/// internally, every provider sticks to it so that nodes on different backends can verify
+/// each other.
+pub fn report_data(appdata: &[u8]) -> [u8; 64] {
+    let preimage = format!("register{}", hex::encode(appdata));
+    let hashed = Sha256::digest(preimage);
+    let mut padded_report_data = [0_u8; 64];
+    padded_report_data[..hashed.len()].copy_from_slice(&hashed);
+
+    padded_report_data
+}
+
+/// Verifies a TD quote through DCAP collateral, or SEV-SNP evidence with `snp`.
+async fn verify_hardware_quote(
+    collateral: &CollateralCache,
+    snp: &SnpVerifier,
+    quote: &str,
+    appdata: &[u8],
+) -> Result<QuoteReport, VerificationError> {
+    let quote = hex::decode(quote.trim_start_matches("0x"))
+        .map_err(|e| VerificationError::Malformed(e.to_string()))?;
+    if snp::is_snp_evidence(&quote) {
+        snp.verify(&quote, appdata)
+    } else {
+        tdx::verify_td_quote(collateral, &quote, appdata).await
+    }
+}
+
+/// [`AttestationProvider::platform_key`] of the backends that produce real quotes, TD quotes
+/// only.
+fn hardware_platform_key(quote: &str) -> Option<[u8; 32]> {
+    let quote = hex::decode(quote.trim_start_matches("0x")).ok()?;
+    if snp::is_snp_evidence(&quote) {
+        return None;
+    }
+    tdx::td_platform_key(&quote)
+}
+
+/// [`AttestationProvider::verify_on_platform`] of the backends that produce real quotes.
+fn verify_hardware_quote_on(
+    quote: &str,
+    appdata: &[u8],
+    platform: &QuoteReport,
+) -> Result<QuoteReport, VerificationError> {
+    let quote = hex::decode(quote.trim_start_matches("0x"))
+        .map_err(|e| VerificationError::Malformed(e.to_string()))?;
+    tdx::verify_td_quote_on(&quote, appdata, platform)
+}
+
+/// Selects the attestation backend, typically from the node configuration.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ProviderConfig {
+    /// Quotes from the local TSM interface.
+    Tdx {
+        #[serde(default)]
+        collateral: CollateralConfig,
+        /// Trust in SEV-SNP peers.
+        #[serde(default)]
+        snp: SnpConfig,
+    },
+    /// Quotes from a dstack guest agent over HTTP.
+    DstackGuest {
+        endpoint: String,
+        #[serde(default)]
+        collateral: CollateralConfig,
+        #[serde(default)]
+        snp: SnpConfig,
+    },
+    /// SEV-SNP reports from the local TSM interface.
+    Snp {
+        #[serde(default)]
+        collateral: CollateralConfig,
+        #[serde(default)]
+        snp: SnpConfig,
+    },
+    /// No TEE, see [`MockProvider`]. Our quotes carry the given measurements, with an event log
+    /// the RTMRs are the ones it replays to. With `tee: sev_snp` they are SEV-SNP reports whose
+    /// launch measurement is `mrtd`.
+    Mock {
+        #[serde(default)]
+        tee: TeeType,
+        #[serde(default)]
+        mrtd: Measurement,
+        #[serde(default)]
+        rtmrs: [Measurement; 4],
+        #[serde(default)]
+        event_log: Option<EventLog>,
+        /// Derive sealing keys from the measurements, see [`MockProvider::with_insecure_sealing`].
+        #[serde(default)]
+        insecure_sealing: bool,
+    },
+}
+
+impl Default for ProviderConfig {
+    fn default() -> Self {
+        if cfg!(feature = "tdx") {
+            Self::Tdx {
+                collateral: CollateralConfig::default(),
+                snp: SnpConfig::default(),
+            }
+        } else {
+            Self::Mock {
+                tee: TeeType::Tdx,
+                mrtd: Measurement::default(),
+                rtmrs: Default::default(),
+                event_log: None,
+                insecure_sealing: false,
+            }
+        }
+    }
+}
+
+impl ProviderConfig {
+    /// Whether the backend derives a [`AttestationProvider::sealing_key`]. TDX and SEV-SNP have
+    /// no sealing key of their own, and one derived from our measurements alone would be known
+    /// to anyone who knows the build, which is why mocks only seal when explicitly told to.
+    pub fn can_seal(&self) -> bool {
+        match self {
+            Self::DstackGuest { .. } => true,
+            Self::Mock {
+                insecure_sealing, ..
+            } => *insecure_sealing,
+            Self::Tdx { .. } | Self::Snp { .. } => false,
+        }
+    }
+
+    pub fn build(&self) -> anyhow::Result<Arc<dyn AttestationProvider>> {
+        Ok(match self {
+            Self::Tdx { collateral, snp } => Arc::new(
+                TdxProvider::with_collateral(Arc::new(CollateralCache::new(collateral.clone())))
+                    .with_snp(Arc::new(SnpVerifier::new(snp)?)),
+            ),
+            Self::DstackGuest {
+                endpoint,
+                collateral,
+                snp,
+            } => Arc::new(
+                DstackGuestProvider::new(endpoint)
+                    .with_collateral(Arc::new(CollateralCache::new(collateral.clone())))
+                    .with_snp(Arc::new(SnpVerifier::new(snp)?)),
+            ),
+            Self::Snp { collateral, snp } => Arc::new(
+                SnpProvider::new(snp.clone())?
+                    .with_collateral(Arc::new(CollateralCache::new(collateral.clone()))),
+            ),
+            Self::Mock {
+                tee,
+                mrtd,
+                rtmrs,
+                event_log,
+                insecure_sealing,
+            } => {
+                let mut provider = MockProvider::new()
+                    .with_tee(*tee)
+                    .with_measurements(*mrtd, *rtmrs);
+                if *insecure_sealing {
+                    provider = provider.with_insecure_sealing();
+                }
+                match event_log {
+                    Some(event_log) => Arc::new(provider.with_event_log(event_log.clone())?),
+                    None => Arc::new(provider),
+                }
+            }
+        })
+    }
+}
+
+/// Provider selected by the `tdx` feature.
+pub fn default_provider() -> Arc<dyn AttestationProvider> {
+    ProviderConfig::default()
+        .build()
+        .expect("default providers take no event log or ARK")
+}
@@ -0,0 +1,383 @@
+//! Quote verification results cache.
+//!
+//! A full verification checks the quote's whole certificate chain against collateral and
+//! evaluates the platform's TCB, which is the same for every quote of a platform: handshake
+//! quotes only differ in the fresh challenge they commit to. Results are kept by
+//! [`AttestationProvider::platform_key`] for a while, bounded in number, and later quotes of the
+//! platform only have their own signature and report data checked. Quotes without a platform key
+//! (SEV-SNP evidence) are kept by quote hash, e.g. for `/attest/verify` callers polling the same
+//! attestation.
+//!
+//! A result is only as good as the collateral and the TCB policy it was produced under: new
+//! collateral can revoke a platform, and changing the TCB policy is how operators react to a new
+//! advisory. Both make up the cache's epoch, when it changes every entry is dropped and quotes go
+//! through a full verification again.
+//!
+//! Hits, misses, evictions and invalidations are counted in [`CacheStats`] and reported through
+//! the `metrics` facade as `attestation_cache_*`.
+
+use super::{
+    collateral::unix_now, report_data, AttestationProvider, QuoteReport, TcbPolicy,
+    VerificationError,
+};
+use metrics::{counter, gauge};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, sync::Mutex};
+use tracing::debug;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct VerificationCacheConfig {
+    /// Most results kept, the oldest is evicted first. Zero disables the cache.
+    pub max_entries: usize,
+    pub ttl_secs: u64,
+}
+
+impl Default for VerificationCacheConfig {
+    fn default() -> Self {
+        Self {
+            max_entries: 1024,
+            ttl_secs: 10 * 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Entries dropped once expired, or to make room for a new one.
+    pub evictions: u64,
+    /// Times the whole cache was dropped for new collateral or a new TCB policy.
+    pub invalidations: u64,
+    pub entries: usize,
+}
+
+struct CachedReport {
+    report: QuoteReport,
+    cached_at: u64,
+}
+
+#[derive(Default)]
+struct CacheState {
+    epoch: [u8; 32],
+    entries: HashMap<[u8; 32], CachedReport>,
+    stats: CacheStats,
+}
+
+pub struct VerificationCache {
+    config: VerificationCacheConfig,
+    state: Mutex<CacheState>,
+}
+
+impl Default for VerificationCache {
+    fn default() -> Self {
+        Self::new(VerificationCacheConfig::default())
+    }
+}
+
+impl VerificationCache {
+    pub fn new(config: VerificationCacheConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(CacheState::default()),
+        }
+    }
+
+    /// Verifies `quote` with `provider` unless we hold a fresh result for its platform, or for
+    /// the quote itself. `tcb` is the policy the result is checked against. Only successful
+    /// verifications are cached, failures may be transient (e.g. the PCCS being unreachable).
+    pub async fn verify(
+        &self,
+        provider: &dyn AttestationProvider,
+        tcb: &TcbPolicy,
+        quote: &str,
+        appdata: &[u8],
+    ) -> Result<QuoteReport, VerificationError> {
+        let platform = provider.platform_key(quote);
+        let key = platform.unwrap_or_else(|| {
+            Sha256::digest(quote.trim_start_matches("0x").to_ascii_lowercase()).into()
+        });
+        let epoch = epoch(provider.collateral_epoch(), tcb);
+
+        if let Some(report) = self.lookup(&key, epoch, unix_now()) {
+            if platform.is_some() {
+                return provider.verify_on_platform(quote, appdata, &report);
+            }
+            // NB: the cached report was checked against the app data of whoever sent the quote
+            // first.
+            if report.report_data != report_data(appdata) {
+                return Err(VerificationError::ReportDataMismatch);
+            }
+            return Ok(report);
+        }
+
+        let report = provider.verify_quote(quote, appdata).await?;
+        self.insert(key, epoch, report.clone(), unix_now());
+        Ok(report)
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let state = self.state.lock().unwrap();
+        CacheStats {
+            entries: state.entries.len(),
+            ..state.stats
+        }
+    }
+
+    fn lookup(&self, key: &[u8; 32], epoch: [u8; 32], now: u64) -> Option<QuoteReport> {
+        let mut state = self.state.lock().unwrap();
+        if state.epoch != epoch {
+            if !state.entries.is_empty() {
+                debug!("collateral or TCB policy changed, dropping cached verifications");
+                state.entries.clear();
+                state.stats.invalidations += 1;
+                counter!("attestation_cache_invalidations").increment(1);
+                gauge!("attestation_cache_entries").set(0.0);
+            }
+            state.epoch = epoch;
+        }
+
+        let ttl = self.config.ttl_secs;
+        match state.entries.get(key) {
+            Some(cached) if now < cached.cached_at + ttl => {
+                let report = cached.report.clone();
+                state.stats.hits += 1;
+                counter!("attestation_cache_hits").increment(1);
+                Some(report)
+            }
+            expired => {
+                if expired.is_some() {
+                    state.entries.remove(key);
+                    state.evicted(1);
+                }
+                state.stats.misses += 1;
+                counter!("attestation_cache_misses").increment(1);
+                None
+            }
+        }
+    }
+
+    fn insert(&self, key: [u8; 32], epoch: [u8; 32], report: QuoteReport, now: u64) {
+        let mut state = self.state.lock().unwrap();
+        // NB: the epoch moved while we were verifying, the result may already be stale.
+        if state.epoch != epoch || self.config.max_entries == 0 {
+            return;
+        }
+
+        let ttl = self.config.ttl_secs;
+        let before = state.entries.len();
+        state
+            .entries
+            .retain(|_, cached| now < cached.cached_at + ttl);
+        let expired = before - state.entries.len();
+        state.evicted(expired as u64);
+        if state.entries.len() >= self.config.max_entries && !state.entries.contains_key(&key) {
+            let oldest = state
+                .entries
+                .iter()
+                .min_by_key(|(_, cached)| cached.cached_at)
+                .map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                state.entries.remove(&oldest);
+                state.evicted(1);
+            }
+        }
+
+        state.entries.insert(
+            key,
+            CachedReport {
+                report,
+                cached_at: now,
+            },
+        );
+        gauge!("attestation_cache_entries").set(state.entries.len() as f64);
+    }
+}
+
+impl CacheState {
+    fn evicted(&mut self, count: u64) {
+        if count > 0 {
+            self.stats.evictions += count;
+            counter!("attestation_cache_evictions").increment(count);
+        }
+    }
+}
+
+fn epoch(collateral_epoch: u64, tcb: &TcbPolicy) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(collateral_epoch.to_le_bytes());
+    hasher.update(serde_json::to_vec(tcb).unwrap());
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::attestation::{MockProvider, Quote, TcbStatus};
+    use async_trait::async_trait;
+    use secp256k1::SecretKey;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    /// Counts verifications, with a collateral epoch we control.
+    #[derive(Default)]
+    struct Counting {
+        inner: MockProvider,
+        verifications: AtomicU64,
+        collateral_epoch: AtomicU64,
+    }
+
+    #[async_trait]
+    impl AttestationProvider for Counting {
+        async fn get_quote(&self, appdata: &[u8]) -> anyhow::Result<Quote> {
+            self.inner.get_quote(appdata).await
+        }
+
+        async fn verify_quote(
+            &self,
+            quote: &str,
+            appdata: &[u8],
+        ) -> Result<QuoteReport, VerificationError> {
+            self.verifications.fetch_add(1, Ordering::Relaxed);
+            self.inner.verify_quote(quote, appdata).await
+        }
+
+        fn collateral_epoch(&self) -> u64 {
+            self.collateral_epoch.load(Ordering::Relaxed)
+        }
+
+        fn platform_key(&self, quote: &str) -> Option<[u8; 32]> {
+            self.inner.platform_key(quote)
+        }
+
+        fn verify_on_platform(
+            &self,
+            quote: &str,
+            appdata: &[u8],
+            platform: &QuoteReport,
+        ) -> Result<QuoteReport, VerificationError> {
+            self.inner.verify_on_platform(quote, appdata, platform)
+        }
+    }
+
+    #[tokio::test]
+    async fn handshakes_of_a_platform_hit() {
+        let provider = Counting::default();
+        let cache = VerificationCache::default();
+        let tcb = TcbPolicy::default();
+
+        // Each handshake quote commits to a fresh challenge.
+        let first = provider.get_quote(b"challenge 1").await.unwrap();
+        let second = provider.get_quote(b"challenge 2").await.unwrap();
+        assert_ne!(first, second);
+        cache
+            .verify(&provider, &tcb, &first, b"challenge 1")
+            .await
+            .unwrap();
+        let report = cache
+            .verify(&provider, &tcb, &second, b"challenge 2")
+            .await
+            .unwrap();
+        assert_eq!(report.report_data, report_data(b"challenge 2"));
+        assert_eq!(provider.verifications.load(Ordering::Relaxed), 1);
+        assert_eq!(cache.stats().hits, 1);
+
+        // The quote's own signature and report data are still checked.
+        assert!(matches!(
+            cache.verify(&provider, &tcb, &second, b"challenge 1").await,
+            Err(VerificationError::ReportDataMismatch)
+        ));
+        let mut tampered = hex::decode(&second).unwrap();
+        tampered[48 + 136] ^= 1;
+        assert!(matches!(
+            cache
+                .verify(&provider, &tcb, &hex::encode(tampered), b"challenge 2")
+                .await,
+            Err(VerificationError::Invalid(_))
+        ));
+        assert_eq!(provider.verifications.load(Ordering::Relaxed), 1);
+
+        // Quotes of another platform go through a full verification, this one isn't trusted.
+        let other = MockProvider::new().with_root(SecretKey::from_byte_array(&[7; 32]).unwrap());
+        let quote = other.get_quote(b"challenge 3").await.unwrap();
+        assert!(matches!(
+            cache.verify(&provider, &tcb, &quote, b"challenge 3").await,
+            Err(VerificationError::Invalid(_))
+        ));
+        assert_eq!(provider.verifications.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn reuses_results_until_epoch_changes() {
+        let provider = Counting::default();
+        let cache = VerificationCache::default();
+        let quote = provider.get_quote(b"pubkey").await.unwrap();
+        let tcb = TcbPolicy::default();
+
+        for _ in 0..3 {
+            cache
+                .verify(&provider, &tcb, &quote, b"pubkey")
+                .await
+                .unwrap();
+        }
+        assert_eq!(provider.verifications.load(Ordering::Relaxed), 1);
+        // A cached quote still has to commit to the caller's app data.
+        assert!(matches!(
+            cache.verify(&provider, &tcb, &quote, b"other").await,
+            Err(VerificationError::ReportDataMismatch)
+        ));
+
+        provider.collateral_epoch.store(1, Ordering::Relaxed);
+        cache
+            .verify(&provider, &tcb, &quote, b"pubkey")
+            .await
+            .unwrap();
+        cache
+            .verify(&provider, &TcbPolicy::strict(), &quote, b"pubkey")
+            .await
+            .unwrap();
+        assert_eq!(provider.verifications.load(Ordering::Relaxed), 3);
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 3,
+                misses: 3,
+                evictions: 0,
+                invalidations: 2,
+                entries: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn bounded_with_ttl() {
+        let cache = VerificationCache::new(VerificationCacheConfig {
+            max_entries: 2,
+            ttl_secs: 10,
+        });
+        let report = QuoteReport {
+            tee: Default::default(),
+            mrtd: Default::default(),
+            rtmrs: Default::default(),
+            report_data: [0; 64],
+            td_attributes: [0; 8],
+            xfam: [0; 8],
+            tcb_status: TcbStatus::UpToDate,
+            advisory_ids: vec![],
+            events: Default::default(),
+        };
+        let epoch = epoch(0, &TcbPolicy::default());
+        assert!(cache.lookup(&[1; 32], epoch, 0).is_none());
+
+        cache.insert([1; 32], epoch, report.clone(), 0);
+        cache.insert([2; 32], epoch, report.clone(), 1);
+        cache.insert([3; 32], epoch, report, 2);
+        assert!(cache.lookup(&[1; 32], epoch, 5).is_none());
+        assert!(cache.lookup(&[2; 32], epoch, 5).is_some());
+        assert!(cache.lookup(&[3; 32], epoch, 12).is_none());
+        assert_eq!(cache.stats().entries, 1);
+        // The oldest for room, the rest as they expired.
+        assert_eq!(cache.stats().evictions, 2);
+    }
+}
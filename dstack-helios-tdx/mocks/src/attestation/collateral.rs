@@ -0,0 +1,349 @@
+//! Quote collateral (TCB info, QE identity, CRLs) cache.
+//!
+//! Verifying a TD quote needs Intel's collateral for the platform that produced it. Collateral
+//! only changes when Intel publishes a TCB recovery so we keep it around until its `nextUpdate`
+//! instead of asking the PCCS on every handshake, and optionally on disk so a node (or CI) can
+//! verify quotes without reaching the PCCS at all.
+
+use super::VerificationError;
+use dcap_qvl::QuoteCollateralV3;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        RwLock,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tracing::warn;
+
+pub const INTEL_PCS_URL: &str = "https://api.trustedservices.intel.com";
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct CollateralConfig {
+    /// PCS or PCCS base url, e.g. [`INTEL_PCS_URL`] or a [`crate::pccs::LocalPccs`].
+    pub pccs_url: String,
+    /// Where fetched collateral is persisted, one json file per platform.
+    pub cache_dir: Option<PathBuf>,
+    /// Refetch at least this often even when the collateral claims to be valid for longer.
+    pub max_age_secs: u64,
+    /// How long we keep using expired collateral while the PCCS can't be reached.
+    pub grace_secs: u64,
+    pub timeout_secs: u64,
+}
+
+impl Default for CollateralConfig {
+    fn default() -> Self {
+        Self {
+            pccs_url: INTEL_PCS_URL.to_string(),
+            cache_dir: None,
+            max_age_secs: 24 * 60 * 60,
+            grace_secs: 0,
+            timeout_secs: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedCollateral {
+    pub collateral: QuoteCollateralV3,
+    /// Unix seconds.
+    pub fetched_at: u64,
+    /// Unix seconds, earliest of the TCB info and QE identity `nextUpdate` and `max_age_secs`.
+    pub expires_at: u64,
+}
+
+pub struct CollateralCache {
+    config: CollateralConfig,
+    entries: RwLock<HashMap<String, CachedCollateral>>,
+    /// Bumped whenever a platform's collateral is replaced with different collateral.
+    generation: AtomicU64,
+}
+
+impl CollateralCache {
+    pub fn new(config: CollateralConfig) -> Self {
+        Self {
+            config,
+            entries: RwLock::new(HashMap::new()),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    /// Collateral for the platform that produced `quote`, fetched from the PCCS when we don't
+    /// hold a fresh copy.
+    pub async fn get(&self, quote: &[u8]) -> Result<QuoteCollateralV3, VerificationError> {
+        let key = platform_key(quote);
+        let now = unix_now();
+
+        let cached = self.lookup(&key);
+        if let Some(cached) = &cached {
+            if now < cached.expires_at {
+                return Ok(cached.collateral.clone());
+            }
+        }
+
+        let fetched = tokio::time::timeout(
+            Duration::from_secs(self.config.timeout_secs),
+            dcap_qvl::collateral::get_collateral(&self.config.pccs_url, quote),
+        )
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|fetched| fetched);
+        match fetched {
+            Ok(collateral) => {
+                self.insert(&key, collateral.clone(), now);
+                Ok(collateral)
+            }
+            Err(e) => match cached {
+                Some(cached) if now < cached.expires_at + self.config.grace_secs => {
+                    warn!("couldn't refresh collateral, using expired copy: {e}");
+                    Ok(cached.collateral)
+                }
+                _ => Err(VerificationError::Collateral(e.to_string())),
+            },
+        }
+    }
+
+    /// Every platform we currently hold collateral for.
+    pub fn entries(&self) -> Vec<CachedCollateral> {
+        self.entries.read().unwrap().values().cloned().collect()
+    }
+
+    /// Number of times collateral we held was replaced with different collateral, see
+    /// [`super::AttestationProvider::collateral_epoch`]. Collateral of a new platform doesn't
+    /// count, no verification could have been made against it yet.
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    fn lookup(&self, key: &str) -> Option<CachedCollateral> {
+        if let Some(cached) = self.entries.read().unwrap().get(key) {
+            return Some(cached.clone());
+        }
+
+        let path = self.config.cache_dir.as_ref()?.join(format!("{key}.json"));
+        let cached: CachedCollateral = serde_json::from_slice(&std::fs::read(path).ok()?).ok()?;
+        self.entries
+            .write()
+            .unwrap()
+            .insert(key.to_string(), cached.clone());
+        Some(cached)
+    }
+
+    fn insert(&self, key: &str, collateral: QuoteCollateralV3, now: u64) {
+        let max_age = now + self.config.max_age_secs;
+        let cached = CachedCollateral {
+            expires_at: next_update(&collateral).map_or(max_age, |next| next.min(max_age)),
+            collateral,
+            fetched_at: now,
+        };
+
+        if let Some(dir) = &self.config.cache_dir {
+            let persisted = std::fs::create_dir_all(dir).and_then(|_| {
+                std::fs::write(
+                    dir.join(format!("{key}.json")),
+                    serde_json::to_vec(&cached).unwrap(),
+                )
+            });
+            if let Err(e) = persisted {
+                warn!("couldn't persist collateral: {e}");
+            }
+        }
+
+        let collateral = cached.collateral.clone();
+        let replaced = self
+            .entries
+            .write()
+            .unwrap()
+            .insert(key.to_string(), cached);
+        if replaced.is_some_and(|replaced| replaced.collateral != collateral) {
+            self.generation.fetch_add(1, Ordering::Release);
+        }
+    }
+}
+
+/// Collateral is per platform. The PCK leaf certificate embedded in the quote identifies it
+/// without having to decode the certification data, quotes without one (which won't verify
+/// anyways) are keyed by their own hash.
+fn platform_key(quote: &[u8]) -> String {
+    const BEGIN: &[u8] = b"-----BEGIN CERTIFICATE-----";
+    const END: &[u8] = b"-----END CERTIFICATE-----";
+
+    let find = |haystack: &[u8], needle: &[u8]| {
+        haystack
+            .windows(needle.len())
+            .position(|window| window == needle)
+    };
+    let pck = find(quote, BEGIN).and_then(|start| {
+        find(&quote[start..], END).map(|len| &quote[start..start + len + END.len()])
+    });
+
+    hex::encode(Sha256::digest(pck.unwrap_or(quote)))
+}
+
+/// Earliest `nextUpdate` of the TCB info and QE identity.
+fn next_update(collateral: &QuoteCollateralV3) -> Option<u64> {
+    let field = |json: &str, outer: &str| {
+        let value: serde_json::Value = serde_json::from_str(json).ok()?;
+        // The PCCS serves the signed body alone, some recordings keep the envelope.
+        let body = value.get(outer).unwrap_or(&value);
+        parse_timestamp(body.get("nextUpdate")?.as_str()?)
+    };
+
+    [
+        field(&collateral.tcb_info, "tcbInfo"),
+        field(&collateral.qe_identity, "enclaveIdentity"),
+    ]
+    .into_iter()
+    .flatten()
+    .min()
+}
+
+/// Unix seconds of an ISO 8601 UTC timestamp as found in Intel collateral
+/// (`2025-04-05T10:12:37Z`, fractional seconds ignored).
+fn parse_timestamp(timestamp: &str) -> Option<u64> {
+    let timestamp = timestamp.trim_end_matches('Z');
+    let (date, time) = timestamp.split_once('T')?;
+    let time = time.split('.').next()?;
+
+    let mut date = date.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let mut time = time.splitn(3, ':').map(|part| part.parse::<i64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+
+    // Days since the epoch of a proleptic gregorian date, see
+    // http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+
+    u64::try_from(days * 86400 + hour * 3600 + minute * 60 + second).ok()
+}
+
+pub(super) fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::pccs::LocalPccs;
+
+    /// A TD quote from the dcap-qvl samples, see the README.
+    const FIXTURE_QUOTE: &str = include_str!("../../fixtures/tdx_quote.hex");
+
+    /// Structurally valid collateral that only verifies against itself.
+    pub(crate) fn collateral(next_update: &str) -> QuoteCollateralV3 {
+        QuoteCollateralV3 {
+            pck_crl_issuer_chain: "pck crl chain".into(),
+            root_ca_crl: vec![1, 2, 3],
+            pck_crl: vec![4, 5, 6],
+            tcb_info_issuer_chain: "tcb info chain".into(),
+            tcb_info: format!(r#"{{"id":"TDX","version":3,"nextUpdate":"{next_update}"}}"#),
+            tcb_info_signature: vec![7; 64],
+            qe_identity_issuer_chain: "qe identity chain".into(),
+            qe_identity: format!(r#"{{"id":"TD_QE","version":2,"nextUpdate":"{next_update}"}}"#),
+            qe_identity_signature: vec![8; 64],
+            pck_certificate_chain: None,
+        }
+    }
+
+    fn quote() -> Vec<u8> {
+        hex::decode(FIXTURE_QUOTE.trim()).unwrap()
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(parse_timestamp("2025-04-05T10:12:37Z"), Some(1743847957));
+        assert_eq!(
+            parse_timestamp("2024-02-29T00:00:00.123Z"),
+            Some(1709164800)
+        );
+        assert_eq!(parse_timestamp("yesterday"), None);
+    }
+
+    #[test]
+    fn expiry_is_earliest_next_update() {
+        let mut collateral = collateral("2030-01-01T00:00:00Z");
+        collateral.qe_identity =
+            r#"{"enclaveIdentity":{"nextUpdate":"2029-01-01T00:00:00Z"}}"#.into();
+        assert_eq!(
+            next_update(&collateral),
+            parse_timestamp("2029-01-01T00:00:00Z")
+        );
+    }
+
+    #[tokio::test]
+    async fn caches_until_next_update() {
+        let pccs = LocalPccs::new(collateral("2100-01-01T00:00:00Z"));
+        let cache = CollateralCache::new(CollateralConfig {
+            pccs_url: pccs.spawn(),
+            ..Default::default()
+        });
+
+        let fetched = cache.get(&quote()).await.unwrap();
+        assert_eq!(fetched.pck_crl, pccs.collateral().pck_crl);
+        assert_eq!(next_update(&fetched), next_update(&pccs.collateral()));
+        let requests = pccs.requests();
+        cache.get(&quote()).await.unwrap();
+        assert_eq!(pccs.requests(), requests);
+        assert_eq!(cache.generation(), 0);
+
+        // Expired collateral is refetched on every use.
+        let pccs = LocalPccs::new(collateral("2000-01-01T00:00:00Z"));
+        let cache = CollateralCache::new(CollateralConfig {
+            pccs_url: pccs.spawn(),
+            ..Default::default()
+        });
+        cache.get(&quote()).await.unwrap();
+        let requests = pccs.requests();
+        cache.get(&quote()).await.unwrap();
+        assert!(pccs.requests() > requests);
+        // Only new content is a new epoch.
+        assert_eq!(cache.generation(), 0);
+        pccs.set_collateral(collateral("2001-01-01T00:00:00Z"));
+        cache.get(&quote()).await.unwrap();
+        assert_eq!(cache.generation(), 1);
+    }
+
+    #[tokio::test]
+    async fn offline_from_cache_dir() {
+        let dir =
+            std::env::temp_dir().join(format!("collateral-{:x}", secp256k1::rand::random::<u64>()));
+        let pccs = LocalPccs::new(collateral("2100-01-01T00:00:00Z"));
+        CollateralCache::new(CollateralConfig {
+            pccs_url: pccs.spawn(),
+            cache_dir: Some(dir.clone()),
+            ..Default::default()
+        })
+        .get(&quote())
+        .await
+        .unwrap();
+
+        // Nothing listens there.
+        let offline = CollateralCache::new(CollateralConfig {
+            pccs_url: "http://127.0.0.1:9".into(),
+            cache_dir: Some(dir.clone()),
+            timeout_secs: 1,
+            ..Default::default()
+        });
+        assert!(offline.get(&quote()).await.is_ok());
+        assert!(matches!(
+            offline.get(b"another platform").await,
+            Err(VerificationError::Collateral(_))
+        ));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
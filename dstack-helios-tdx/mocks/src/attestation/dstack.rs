@@ -0,0 +1,159 @@
+use super::{
+    report_data, verify_hardware_quote, AttestationProvider, CollateralCache, CollateralConfig,
+    EventLog, Quote, QuoteReport, SnpVerifier, VerificationError,
+};
+use async_trait::async_trait;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::sync::{Arc, RwLock};
+
+/// Gets quotes from a dstack guest agent (tappd) instead of the TSM interface, for apps that
+/// run virtualized on a dstack base image. Verification is local.
+pub struct DstackGuestProvider {
+    endpoint: String,
+    client: reqwest::Client,
+    collateral: Arc<CollateralCache>,
+    snp: Arc<SnpVerifier>,
+    /// JSON encoded event log handed out with our last quote.
+    event_log: RwLock<Option<String>>,
+}
+
+#[derive(Deserialize)]
+struct TdxQuoteResponse {
+    quote: Quote,
+    /// JSON encoded [`EventLog`].
+    #[serde(default)]
+    event_log: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DeriveKeyResponse {
+    /// PEM encoded.
+    key: String,
+}
+
+impl DstackGuestProvider {
+    pub fn new(endpoint: &str) -> Self {
+        Self {
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            client: reqwest::Client::new(),
+            collateral: Arc::new(CollateralCache::new(CollateralConfig::default())),
+            snp: Arc::new(SnpVerifier::default()),
+            event_log: RwLock::new(None),
+        }
+    }
+
+    pub fn with_collateral(mut self, collateral: Arc<CollateralCache>) -> Self {
+        self.collateral = collateral;
+        self
+    }
+
+    /// Verifies SEV-SNP peers, see [`super::TdxProvider::with_snp`].
+    pub fn with_snp(mut self, snp: Arc<SnpVerifier>) -> Self {
+        self.snp = snp;
+        self
+    }
+
+    async fn tdx_quote(&self, report_data: &[u8; 64]) -> anyhow::Result<TdxQuoteResponse> {
+        Ok(self
+            .client
+            .post(format!("{}/prpc/Tappd.TdxQuote?json", self.endpoint))
+            .json(&serde_json::json!({ "report_data": hex::encode(report_data) }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+}
+
+#[async_trait]
+impl AttestationProvider for DstackGuestProvider {
+    async fn get_quote(&self, appdata: &[u8]) -> anyhow::Result<Quote> {
+        // NB: the guest agent takes the report data as is, so we hash on our side the same way
+        // tsm quote generation does.
+        let response = self.tdx_quote(&report_data(appdata)).await?;
+        *self.event_log.write().unwrap() = response.event_log;
+        Ok(response.quote)
+    }
+
+    async fn verify_quote(
+        &self,
+        quote: &str,
+        appdata: &[u8],
+    ) -> Result<QuoteReport, VerificationError> {
+        verify_hardware_quote(&self.collateral, &self.snp, quote, appdata).await
+    }
+
+    async fn event_log(&self) -> anyhow::Result<Option<EventLog>> {
+        // NB: the guest agent only hands out the event log along with a quote, we keep the one of
+        // the last quote we got rather than asking for another.
+        let event_log = self.event_log.read().unwrap().clone();
+        Ok(event_log
+            .map(|event_log| serde_json::from_str(&event_log))
+            .transpose()?)
+    }
+
+    /// The guest agent derives keys through dstack's KMS, which only hands them to instances of
+    /// the app they were derived for.
+    async fn sealing_key(&self, label: &str) -> anyhow::Result<Option<[u8; 32]>> {
+        let response: DeriveKeyResponse = self
+            .client
+            .post(format!("{}/prpc/Tappd.DeriveKey?json", self.endpoint))
+            .json(&serde_json::json!({ "path": label, "subject": label }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(Some(Sha256::digest(response.key).into()))
+    }
+
+    fn collateral_epoch(&self) -> u64 {
+        self.collateral.generation()
+    }
+
+    fn platform_key(&self, quote: &str) -> Option<[u8; 32]> {
+        super::hardware_platform_key(quote)
+    }
+
+    fn verify_on_platform(
+        &self,
+        quote: &str,
+        appdata: &[u8],
+        platform: &QuoteReport,
+    ) -> Result<QuoteReport, VerificationError> {
+        super::verify_hardware_quote_on(quote, appdata, platform)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::attestation::EventLogEntry;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use warp::Filter;
+
+    #[tokio::test]
+    async fn event_log_comes_with_the_quote() {
+        let quotes = Arc::new(AtomicUsize::new(0));
+        let log = vec![EventLogEntry::runtime("app-id", &[2; 20])];
+        let tappd = warp::path!("prpc" / "Tappd.TdxQuote").map({
+            let quotes = quotes.clone();
+            let log = serde_json::to_string(&log).unwrap();
+            move || {
+                quotes.fetch_add(1, Ordering::SeqCst);
+                warp::reply::json(&serde_json::json!({ "quote": "00", "event_log": log }))
+            }
+        });
+        let (addr, server) = warp::serve(tappd).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let provider = DstackGuestProvider::new(&format!("http://{addr}"));
+        assert_eq!(provider.event_log().await.unwrap(), None);
+        provider.get_quote(b"appdata").await.unwrap();
+        assert_eq!(provider.event_log().await.unwrap(), Some(log));
+        assert_eq!(quotes.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,204 @@
+//! TD event log replay.
+//!
+//! Every extension of a runtime measurement register is recorded in the TD event log, replaying
+//! the log must give back the registers the quote carries. Once it does, the events the log
+//! records are as trustworthy as the registers themselves, which lets the policy reason about
+//! what was measured (the app, its configuration) instead of opaque register values.
+//!
+//! Entries follow the JSON format of dstack's guest agent. Only dstack runtime events are
+//! exposed: their digest commits to the event name and payload so we can check it, boot time
+//! events are replayed by digest only.
+
+use super::{hex_bytes, Measurement, QuoteReport, VerificationError};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha384};
+use std::collections::BTreeMap;
+
+/// Event type of the events dstack extends into RTMR3 at runtime.
+pub const DSTACK_RUNTIME_EVENT_TYPE: u32 = 0x08000001;
+
+/// Register dstack runtime events are extended into.
+const RUNTIME_EVENTS_RTMR: u32 = 3;
+
+/// Ordered TD event log.
+pub type EventLog = Vec<EventLogEntry>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventLogEntry {
+    /// Index of the RTMR the event was extended into.
+    pub imr: u32,
+    pub event_type: u32,
+    /// Extended into the register.
+    pub digest: Measurement,
+    /// Name of runtime events, empty for boot time ones.
+    #[serde(default)]
+    pub event: String,
+    #[serde(default, with = "hex_bytes")]
+    pub event_payload: Vec<u8>,
+}
+
+impl EventLogEntry {
+    /// A dstack runtime event, e.g. `compose-hash` with the hash of the app's compose file.
+    pub fn runtime(event: &str, payload: &[u8]) -> Self {
+        Self {
+            imr: RUNTIME_EVENTS_RTMR,
+            event_type: DSTACK_RUNTIME_EVENT_TYPE,
+            digest: runtime_event_digest(DSTACK_RUNTIME_EVENT_TYPE, event, payload),
+            event: event.to_string(),
+            event_payload: payload.to_vec(),
+        }
+    }
+
+    fn is_runtime(&self) -> bool {
+        self.event_type == DSTACK_RUNTIME_EVENT_TYPE
+    }
+}
+
+fn runtime_event_digest(event_type: u32, event: &str, payload: &[u8]) -> Measurement {
+    let mut hasher = Sha384::new();
+    hasher.update(event_type.to_le_bytes());
+    hasher.update(b":");
+    hasher.update(event.as_bytes());
+    hasher.update(b":");
+    hasher.update(payload);
+    Measurement(hasher.finalize().into())
+}
+
+/// Recomputes RTMR0-3 from the log.
+pub fn replay_rtmrs(log: &[EventLogEntry]) -> Result<[Measurement; 4], VerificationError> {
+    let mut rtmrs = [Measurement::default(); 4];
+    for entry in log {
+        let rtmr = rtmrs
+            .get_mut(entry.imr as usize)
+            .ok_or_else(|| VerificationError::Malformed(format!("no RTMR{}", entry.imr)))?;
+
+        let mut hasher = Sha384::new();
+        hasher.update(rtmr.0);
+        hasher.update(entry.digest.0);
+        *rtmr = Measurement(hasher.finalize().into());
+    }
+
+    Ok(rtmrs)
+}
+
+/// Checks that `log` replays to the registers of a verified quote and returns the runtime events
+/// it records, by name, with hex encoded payloads.
+pub fn verify_event_log(
+    log: &[EventLogEntry],
+    report: &QuoteReport,
+) -> Result<BTreeMap<String, String>, VerificationError> {
+    let replayed = replay_rtmrs(log)?;
+    if let Some(rtmr) = (0..4).find(|&i| replayed[i] != report.rtmrs[i]) {
+        return Err(VerificationError::EventLogMismatch(rtmr));
+    }
+
+    let mut events = BTreeMap::new();
+    for entry in log.iter().filter(|entry| entry.is_runtime()) {
+        if entry.digest
+            != runtime_event_digest(entry.event_type, &entry.event, &entry.event_payload)
+        {
+            return Err(VerificationError::Malformed(format!(
+                "event {} doesn't match its digest",
+                entry.event
+            )));
+        }
+        // NB: policies match events by name, a second value would make it ambiguous.
+        if events
+            .insert(entry.event.clone(), hex::encode(&entry.event_payload))
+            .is_some()
+        {
+            return Err(VerificationError::Malformed(format!(
+                "event {} measured twice",
+                entry.event
+            )));
+        }
+    }
+
+    Ok(events)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::attestation::TcbStatus;
+
+    fn log() -> EventLog {
+        vec![
+            EventLogEntry {
+                imr: 0,
+                event_type: 0x80000001,
+                digest: Measurement([1; 48]),
+                event: String::new(),
+                event_payload: vec![],
+            },
+            EventLogEntry::runtime("app-id", &[2; 20]),
+            EventLogEntry::runtime("compose-hash", &[3; 32]),
+        ]
+    }
+
+    fn report(rtmrs: [Measurement; 4]) -> QuoteReport {
+        QuoteReport {
+            tee: Default::default(),
+            mrtd: Measurement::default(),
+            rtmrs,
+            report_data: [0; 64],
+            td_attributes: [0; 8],
+            xfam: [0; 8],
+            tcb_status: TcbStatus::UpToDate,
+            advisory_ids: vec![],
+            events: Default::default(),
+        }
+    }
+
+    #[test]
+    fn replays_to_the_quote() {
+        let log = log();
+        let rtmrs = replay_rtmrs(&log).unwrap();
+        assert_ne!(rtmrs[0], Measurement::default());
+        assert_eq!(rtmrs[1], Measurement::default());
+        assert_ne!(rtmrs[3], Measurement::default());
+
+        let events = verify_event_log(&log, &report(rtmrs)).unwrap();
+        assert_eq!(events["compose-hash"], hex::encode([3; 32]));
+        assert_eq!(events.len(), 2);
+    }
+
+    #[test]
+    fn rejects_tampered_logs() {
+        let log = log();
+        let report = report(replay_rtmrs(&log).unwrap());
+
+        // An event that wasn't measured.
+        let mut extra = log.clone();
+        extra.push(EventLogEntry::runtime("key-provider", b"kms"));
+        assert!(matches!(
+            verify_event_log(&extra, &report),
+            Err(VerificationError::EventLogMismatch(3))
+        ));
+
+        // The measured digest with a different payload.
+        let mut swapped = log.clone();
+        swapped[2].event_payload = vec![4; 32];
+        assert!(matches!(
+            verify_event_log(&swapped, &report),
+            Err(VerificationError::Malformed(_))
+        ));
+
+        let mut out_of_range = log;
+        out_of_range[0].imr = 4;
+        assert!(replay_rtmrs(&out_of_range).is_err());
+    }
+
+    #[test]
+    fn dstack_json() {
+        let entry: EventLogEntry = serde_json::from_value(serde_json::json!({
+            "imr": 3,
+            "event_type": DSTACK_RUNTIME_EVENT_TYPE,
+            "digest": EventLogEntry::runtime("app-id", &[2; 20]).digest,
+            "event": "app-id",
+            "event_payload": hex::encode([2; 20]),
+        }))
+        .unwrap();
+        assert_eq!(entry, EventLogEntry::runtime("app-id", &[2; 20]));
+    }
+}
@@ -0,0 +1,271 @@
+//! Structurally valid TD quotes for builds without a TEE.
+//!
+//! Quotes follow the v4 TDX layout (header, TD10 report body, ECDSA quote signature data with QE
+//! report certification data) so they go through the same parser as real ones. The chain is
+//! shortened: a test root key signs the QE report in place of the PCK, and the certification
+//! data carries the root's pubkey in place of the PCK certificate chain. Signatures are secp256k1
+//! rather than P-256, the encodings (64 bytes signatures and 64 bytes keys) are the same. Like a
+//! platform's QE, a root signs every quote with the same attestation key.
+
+use super::{tdx::td_quote_signature, Measurement, VerificationError};
+use secp256k1::{ecdsa::Signature, Message, PublicKey, Secp256k1, SecretKey};
+use sha2::{Digest, Sha256};
+
+const HEADER_LEN: usize = 48;
+const TD10_REPORT_LEN: usize = 584;
+const QE_REPORT_LEN: usize = 384;
+/// Offset of the report data in the QE (SGX enclave) report.
+const QE_REPORT_DATA_OFFSET: usize = 320;
+
+const QUOTE_VERSION: u16 = 4;
+const ATTESTATION_KEY_ECDSA_P256: u16 = 2;
+const TEE_TYPE_TDX: u32 = 0x81;
+const INTEL_QE_VENDOR_ID: [u8; 16] = [
+    0x93, 0x9a, 0x72, 0x33, 0xf7, 0x9c, 0x4c, 0xa9, 0x94, 0x0a, 0x0d, 0xb3, 0x95, 0x7f, 0x06, 0x07,
+];
+const CERT_TYPE_PCK_CHAIN: u16 = 5;
+const CERT_TYPE_QE_REPORT: u16 = 6;
+
+/// Root every [`super::MockProvider`] trusts unless told otherwise.
+pub fn test_root() -> SecretKey {
+    let seed: [u8; 32] = Sha256::digest(b"tplus/mocks/test-root").into();
+    SecretKey::from_byte_array(&seed).unwrap()
+}
+
+/// Attestation key of the mock platform under `root`.
+fn attestation_key(root: &SecretKey) -> SecretKey {
+    let seed: [u8; 32] = Sha256::new()
+        .chain_update(b"tplus/mocks/attestation-key")
+        .chain_update(root.secret_bytes())
+        .finalize()
+        .into();
+    SecretKey::from_byte_array(&seed).unwrap()
+}
+
+/// Contents of a mock quote.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockQuote {
+    pub mrtd: Measurement,
+    pub rtmrs: [Measurement; 4],
+    pub report_data: [u8; 64],
+    pub td_attributes: [u8; 8],
+    pub xfam: [u8; 8],
+}
+
+impl MockQuote {
+    pub fn new(report_data: [u8; 64]) -> Self {
+        Self {
+            mrtd: Measurement::default(),
+            rtmrs: Default::default(),
+            report_data,
+            td_attributes: [0; 8],
+            xfam: [0; 8],
+        }
+    }
+
+    pub fn with_mrtd(mut self, mrtd: Measurement) -> Self {
+        self.mrtd = mrtd;
+        self
+    }
+
+    pub fn with_rtmr(mut self, index: usize, rtmr: Measurement) -> Self {
+        self.rtmrs[index] = rtmr;
+        self
+    }
+
+    /// Encodes the quote, signed with the attestation key certified by `root`.
+    pub fn sign(&self, root: &SecretKey) -> Vec<u8> {
+        let secp = Secp256k1::new();
+        let attestation_key = attestation_key(root);
+
+        let mut quote = header();
+        quote.extend_from_slice(&self.td10_report());
+        let signature = sign(&secp, &attestation_key, &quote);
+
+        // The QE report binds the attestation key, and is itself signed by the root.
+        let attestation_pubkey = raw_pubkey(&attestation_key.public_key(&secp));
+        let qe_auth_data = b"tplus mock qe".to_vec();
+        let mut qe_report = [0_u8; QE_REPORT_LEN];
+        qe_report[QE_REPORT_DATA_OFFSET..QE_REPORT_DATA_OFFSET + 32]
+            .copy_from_slice(&qe_report_data(&attestation_pubkey, &qe_auth_data));
+        let qe_report_signature = sign(&secp, root, &qe_report);
+
+        let mut qe_certification = qe_report.to_vec();
+        qe_certification.extend_from_slice(&qe_report_signature);
+        qe_certification.extend_from_slice(&(qe_auth_data.len() as u16).to_le_bytes());
+        qe_certification.extend_from_slice(&qe_auth_data);
+        certification_data(
+            &mut qe_certification,
+            CERT_TYPE_PCK_CHAIN,
+            &root.public_key(&secp).serialize(),
+        );
+
+        let mut auth_data = signature.to_vec();
+        auth_data.extend_from_slice(&attestation_pubkey);
+        certification_data(&mut auth_data, CERT_TYPE_QE_REPORT, &qe_certification);
+
+        quote.extend_from_slice(&(auth_data.len() as u32).to_le_bytes());
+        quote.extend_from_slice(&auth_data);
+        quote
+    }
+
+    fn td10_report(&self) -> Vec<u8> {
+        let mut body = Vec::with_capacity(TD10_REPORT_LEN);
+        // tee_tcb_svn, mr_seam, mr_signer_seam, seam_attributes
+        body.extend_from_slice(&[0; 16 + 48 + 48 + 8]);
+        body.extend_from_slice(&self.td_attributes);
+        body.extend_from_slice(&self.xfam);
+        body.extend_from_slice(&self.mrtd.0);
+        // mr_config_id, mr_owner, mr_owner_config
+        body.extend_from_slice(&[0; 48 * 3]);
+        for rtmr in &self.rtmrs {
+            body.extend_from_slice(&rtmr.0);
+        }
+        body.extend_from_slice(&self.report_data);
+        body
+    }
+}
+
+/// Checks the signature chain of a mock quote up to `root`. The TD report itself is left to
+/// the quote parser.
+pub fn verify_chain(quote: &[u8], root: &PublicKey) -> Result<(), VerificationError> {
+    let malformed = || VerificationError::Malformed("truncated mock quote".into());
+    let invalid = |reason: &str| VerificationError::Invalid(reason.to_string());
+    let mut reader = Reader(quote);
+
+    let signed = reader
+        .take(HEADER_LEN + TD10_REPORT_LEN)
+        .ok_or_else(malformed)?;
+    let auth_data_len = reader.u32().ok_or_else(malformed)?;
+    let mut auth_data = Reader(reader.take(auth_data_len as usize).ok_or_else(malformed)?);
+    let signature = auth_data.take(64).ok_or_else(malformed)?;
+    let attestation_pubkey = auth_data.take(64).ok_or_else(malformed)?;
+
+    let (cert_type, qe_certification) = auth_data.certification_data().ok_or_else(malformed)?;
+    if cert_type != CERT_TYPE_QE_REPORT {
+        return Err(invalid("expected QE report certification data"));
+    }
+    let mut qe_certification = Reader(qe_certification);
+    let qe_report = qe_certification.take(QE_REPORT_LEN).ok_or_else(malformed)?;
+    let qe_report_signature = qe_certification.take(64).ok_or_else(malformed)?;
+    let qe_auth_data_len = qe_certification.u16().ok_or_else(malformed)?;
+    let qe_auth_data = qe_certification
+        .take(qe_auth_data_len as usize)
+        .ok_or_else(malformed)?;
+    let (cert_type, chain) = qe_certification
+        .certification_data()
+        .ok_or_else(malformed)?;
+    if cert_type != CERT_TYPE_PCK_CHAIN || chain != root.serialize() {
+        return Err(invalid("quote isn't certified by the test root"));
+    }
+
+    let secp = Secp256k1::verification_only();
+    verify(&secp, root, qe_report, qe_report_signature)
+        .map_err(|_| invalid("bad QE report signature"))?;
+    if qe_report[QE_REPORT_DATA_OFFSET..QE_REPORT_DATA_OFFSET + 32]
+        != qe_report_data(attestation_pubkey, qe_auth_data)
+    {
+        return Err(invalid("QE report doesn't bind the attestation key"));
+    }
+
+    let mut uncompressed = [4_u8; 65];
+    uncompressed[1..].copy_from_slice(attestation_pubkey);
+    let attestation_pubkey =
+        PublicKey::from_slice(&uncompressed).map_err(|_| invalid("bad attestation key"))?;
+    verify(&secp, &attestation_pubkey, signed, signature)
+        .map_err(|_| invalid("bad quote signature"))
+}
+
+/// Checks the quote signature of a mock quote under its attestation key, for quotes whose chain
+/// was checked before.
+pub(super) fn verify_signature(quote: &[u8]) -> Result<(), VerificationError> {
+    let invalid = |reason: &str| VerificationError::Invalid(reason.to_string());
+    let (signed, signature, attestation_pubkey) = td_quote_signature(quote)
+        .ok_or_else(|| VerificationError::Malformed("truncated mock quote".into()))?;
+    let mut uncompressed = [4_u8; 65];
+    uncompressed[1..].copy_from_slice(attestation_pubkey);
+    let attestation_pubkey =
+        PublicKey::from_slice(&uncompressed).map_err(|_| invalid("bad attestation key"))?;
+    verify(
+        &Secp256k1::verification_only(),
+        &attestation_pubkey,
+        signed,
+        signature,
+    )
+    .map_err(|_| invalid("bad quote signature"))
+}
+
+fn header() -> Vec<u8> {
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    header.extend_from_slice(&QUOTE_VERSION.to_le_bytes());
+    header.extend_from_slice(&ATTESTATION_KEY_ECDSA_P256.to_le_bytes());
+    header.extend_from_slice(&TEE_TYPE_TDX.to_le_bytes());
+    // reserved
+    header.extend_from_slice(&[0; 4]);
+    header.extend_from_slice(&INTEL_QE_VENDOR_ID);
+    // user data
+    header.extend_from_slice(&[0; 20]);
+    header
+}
+
+fn certification_data(out: &mut Vec<u8>, cert_type: u16, body: &[u8]) {
+    out.extend_from_slice(&cert_type.to_le_bytes());
+    out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    out.extend_from_slice(body);
+}
+
+/// Uncompressed pubkey without the `0x04` prefix, as quotes encode attestation keys.
+fn raw_pubkey(pubkey: &PublicKey) -> [u8; 64] {
+    pubkey.serialize_uncompressed()[1..].try_into().unwrap()
+}
+
+fn qe_report_data(attestation_pubkey: &[u8], qe_auth_data: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(attestation_pubkey);
+    hasher.update(qe_auth_data);
+    hasher.finalize().into()
+}
+
+fn sign<C: secp256k1::Signing>(secp: &Secp256k1<C>, key: &SecretKey, data: &[u8]) -> [u8; 64] {
+    let message = Message::from_digest(Sha256::digest(data).into());
+    secp.sign_ecdsa(&message, key).serialize_compact()
+}
+
+fn verify<C: secp256k1::Verification>(
+    secp: &Secp256k1<C>,
+    key: &PublicKey,
+    data: &[u8],
+    signature: &[u8],
+) -> Result<(), secp256k1::Error> {
+    let message = Message::from_digest(Sha256::digest(data).into());
+    secp.verify_ecdsa(&message, &Signature::from_compact(signature)?, key)
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(taken)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2)
+            .map(|bytes| u16::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn certification_data(&mut self) -> Option<(u16, &'a [u8])> {
+        let cert_type = self.u16()?;
+        let len = self.u32()?;
+        Some((cert_type, self.take(len as usize)?))
+    }
+}
@@ -0,0 +1,367 @@
+use super::{
+    fixture::{self, MockQuote},
+    replay_rtmrs, report_data,
+    snp::{self, mock_snp_evidence},
+    tdx::{td_platform_key, td_quote_report},
+    AttestationProvider, EventLog, Measurement, Quote, QuoteReport, SnpVerifier, TcbStatus,
+    TeeType, VerificationError, TD_ATTRIBUTES_DEBUG,
+};
+use async_trait::async_trait;
+use secp256k1::{Secp256k1, SecretKey};
+use sha2::{Digest, Sha256};
+
+/// No TEE involved: quotes are [`MockQuote`]s with the configured measurements, signed by a test
+/// root. Verification checks the chain up to that root, parses the quote like a real one and
+/// checks the report data binding, unless the provider was built with
+/// [`MockProvider::rejecting`]. The TCB status there's no collateral for is configured too.
+///
+/// With [`TeeType::SevSnp`] quotes are SEV-SNP evidence signed by the test VCEK of
+/// [`snp::TEST_ARK`] instead. Either kind verifies, like real providers do.
+///
+/// Sealing keys are only derived with [`MockProvider::with_insecure_sealing`], from the root and
+/// our measurements: mock nodes of another build can't unseal, anyone who knows the build can.
+#[derive(Debug, Clone)]
+pub struct MockProvider {
+    tee: TeeType,
+    root: SecretKey,
+    snp: SnpVerifier,
+    mrtd: Measurement,
+    rtmrs: [Measurement; 4],
+    event_log: Option<EventLog>,
+    tcb_status: TcbStatus,
+    advisory_ids: Vec<String>,
+    reject: bool,
+    debug: bool,
+    insecure_sealing: bool,
+}
+
+impl Default for MockProvider {
+    fn default() -> Self {
+        Self {
+            tee: TeeType::Tdx,
+            root: fixture::test_root(),
+            snp: SnpVerifier::trusting(snp::TEST_ARK),
+            mrtd: Measurement::default(),
+            rtmrs: Default::default(),
+            event_log: None,
+            tcb_status: TcbStatus::UpToDate,
+            advisory_ids: vec![],
+            reject: false,
+            debug: false,
+            insecure_sealing: false,
+        }
+    }
+}
+
+impl MockProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A provider that fails every verification, to test peer rejection.
+    pub fn rejecting() -> Self {
+        Self {
+            reject: true,
+            ..Self::default()
+        }
+    }
+
+    /// Kind of quotes we produce.
+    pub fn with_tee(mut self, tee: TeeType) -> Self {
+        self.tee = tee;
+        self
+    }
+
+    /// Signs and only trusts quotes under `root` instead of [`fixture::test_root`].
+    pub fn with_root(mut self, root: SecretKey) -> Self {
+        self.root = root;
+        self
+    }
+
+    /// Measurements our own quotes carry.
+    pub fn with_measurements(mut self, mrtd: Measurement, rtmrs: [Measurement; 4]) -> Self {
+        self.mrtd = mrtd;
+        self.rtmrs = rtmrs;
+        self
+    }
+
+    /// Our quotes carry the RTMRs `event_log` replays to, and we hand it out alongside them.
+    pub fn with_event_log(mut self, event_log: EventLog) -> anyhow::Result<Self> {
+        self.rtmrs = replay_rtmrs(&event_log)?;
+        self.event_log = Some(event_log);
+        Ok(self)
+    }
+
+    /// Our quotes are of a debuggable guest: the TD `DEBUG` attribute, or the debug bit of the
+    /// SEV-SNP guest policy.
+    pub fn with_debug(mut self) -> Self {
+        self.debug = true;
+        self
+    }
+
+    /// TCB level reported for the quotes we verify.
+    pub fn with_tcb_status(mut self, tcb_status: TcbStatus, advisory_ids: Vec<String>) -> Self {
+        self.tcb_status = tcb_status;
+        self.advisory_ids = advisory_ids;
+        self
+    }
+
+    /// Derives sealing keys from the test root and our measurements, which aren't secret. Only
+    /// for exercising sealing without a TEE.
+    pub fn with_insecure_sealing(mut self) -> Self {
+        self.insecure_sealing = true;
+        self
+    }
+}
+
+#[async_trait]
+impl AttestationProvider for MockProvider {
+    async fn get_quote(&self, appdata: &[u8]) -> anyhow::Result<Quote> {
+        if self.tee == TeeType::SevSnp {
+            return Ok(hex::encode(mock_snp_evidence(
+                self.mrtd,
+                report_data(appdata),
+                self.debug,
+            )));
+        }
+
+        let mut quote = MockQuote::new(report_data(appdata)).with_mrtd(self.mrtd);
+        quote.rtmrs = self.rtmrs;
+        if self.debug {
+            quote.td_attributes = TD_ATTRIBUTES_DEBUG.to_le_bytes();
+        }
+
+        Ok(hex::encode(quote.sign(&self.root)))
+    }
+
+    async fn verify_quote(
+        &self,
+        quote: &str,
+        appdata: &[u8],
+    ) -> Result<QuoteReport, VerificationError> {
+        if self.reject {
+            return Err(VerificationError::Invalid("rejected by mock".into()));
+        }
+
+        let quote = hex::decode(quote).map_err(|e| VerificationError::Malformed(e.to_string()))?;
+        if snp::is_snp_evidence(&quote) {
+            return Ok(QuoteReport {
+                tcb_status: self.tcb_status.clone(),
+                advisory_ids: self.advisory_ids.clone(),
+                ..self.snp.verify(&quote, appdata)?
+            });
+        }
+
+        fixture::verify_chain(&quote, &self.root.public_key(&Secp256k1::new()))?;
+        let parsed = dcap_qvl::quote::Quote::parse(&quote)
+            .map_err(|e| VerificationError::Malformed(e.to_string()))?;
+
+        td_quote_report(
+            &parsed.report,
+            appdata,
+            self.tcb_status.clone(),
+            self.advisory_ids.clone(),
+        )
+    }
+
+    async fn event_log(&self) -> anyhow::Result<Option<EventLog>> {
+        Ok(self.event_log.clone())
+    }
+
+    fn platform_key(&self, quote: &str) -> Option<[u8; 32]> {
+        let quote = hex::decode(quote).ok()?;
+        if snp::is_snp_evidence(&quote) {
+            return None;
+        }
+        td_platform_key(&quote)
+    }
+
+    fn verify_on_platform(
+        &self,
+        quote: &str,
+        appdata: &[u8],
+        platform: &QuoteReport,
+    ) -> Result<QuoteReport, VerificationError> {
+        if self.reject {
+            return Err(VerificationError::Invalid("rejected by mock".into()));
+        }
+
+        let quote = hex::decode(quote).map_err(|e| VerificationError::Malformed(e.to_string()))?;
+        fixture::verify_signature(&quote)?;
+        let parsed = dcap_qvl::quote::Quote::parse(&quote)
+            .map_err(|e| VerificationError::Malformed(e.to_string()))?;
+
+        td_quote_report(
+            &parsed.report,
+            appdata,
+            platform.tcb_status.clone(),
+            platform.advisory_ids.clone(),
+        )
+    }
+
+    async fn sealing_key(&self, label: &str) -> anyhow::Result<Option<[u8; 32]>> {
+        if !self.insecure_sealing {
+            return Ok(None);
+        }
+
+        let mut hasher = Sha256::new();
+        hasher.update(b"tplus/mocks/sealing");
+        hasher.update(self.root.secret_bytes());
+        hasher.update([self.tee as u8]);
+        hasher.update(self.mrtd.0);
+        for rtmr in &self.rtmrs {
+            hasher.update(rtmr.0);
+        }
+        hasher.update(label.as_bytes());
+        Ok(Some(hasher.finalize().into()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::attestation::{verify_event_log, AttestationPolicy, EventLogEntry, PolicyViolation};
+
+    #[tokio::test]
+    async fn verifies_own_quotes() {
+        let provider = MockProvider::new();
+        let quote = provider.get_quote(b"pubkey").await.unwrap();
+
+        assert!(provider.verify_quote(&quote, b"pubkey").await.is_ok());
+        assert!(matches!(
+            provider.verify_quote(&quote, b"other").await,
+            Err(VerificationError::ReportDataMismatch)
+        ));
+        assert!(matches!(
+            MockProvider::rejecting()
+                .verify_quote(&quote, b"pubkey")
+                .await,
+            Err(VerificationError::Invalid(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn chain_up_to_root() {
+        let quote = MockProvider::new().get_quote(b"pubkey").await.unwrap();
+        let other_root = SecretKey::from_byte_array(&[7; 32]).unwrap();
+        assert!(matches!(
+            MockProvider::new()
+                .with_root(other_root)
+                .verify_quote(&quote, b"pubkey")
+                .await,
+            Err(VerificationError::Invalid(_))
+        ));
+
+        // Swap the report data after signing.
+        let mut tampered = hex::decode(&quote).unwrap();
+        tampered[48 + 520..48 + 584].copy_from_slice(&report_data(b"other"));
+        assert!(matches!(
+            MockProvider::new()
+                .verify_quote(&hex::encode(tampered), b"other")
+                .await,
+            Err(VerificationError::Invalid(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn measurements_reach_policy() {
+        let mrtd = Measurement([1; 48]);
+        let rtmrs = [0, 1, 2, 3].map(|i| Measurement([0x10 + i; 48]));
+        let node = MockProvider::new().with_measurements(mrtd, rtmrs);
+        let quote = node.get_quote(b"pubkey").await.unwrap();
+
+        let report = MockProvider::new()
+            .verify_quote(&quote, b"pubkey")
+            .await
+            .unwrap();
+        assert_eq!(report.mrtd, mrtd);
+        assert_eq!(report.rtmrs, rtmrs);
+        assert_eq!(report.report_data, report_data(b"pubkey"));
+
+        let policy: AttestationPolicy = serde_json::from_value(serde_json::json!({
+            "measurements": [{ "mrtd": mrtd, "rtmrs": [null, null, null, rtmrs[3]] }]
+        }))
+        .unwrap();
+        assert!(policy.check(&report).is_ok());
+
+        // Default (zero) measurements.
+        let other = MockProvider::new().get_quote(b"pubkey").await.unwrap();
+        let other = MockProvider::new()
+            .verify_quote(&other, b"pubkey")
+            .await
+            .unwrap();
+        assert!(matches!(
+            policy.check(&other),
+            Err(PolicyViolation::Measurements { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn debug_guests_need_opt_in() {
+        let policy = AttestationPolicy::default();
+        let allow_debug = AttestationPolicy {
+            allow_debug: true,
+            ..Default::default()
+        };
+        for tee in [TeeType::Tdx, TeeType::SevSnp] {
+            let node = MockProvider::new().with_tee(tee);
+            let quote = node.get_quote(b"pubkey").await.unwrap();
+            let report = node.verify_quote(&quote, b"pubkey").await.unwrap();
+            assert!(policy.check(&report).is_ok());
+
+            let debug = node.with_debug();
+            let quote = debug.get_quote(b"pubkey").await.unwrap();
+            let report = debug.verify_quote(&quote, b"pubkey").await.unwrap();
+            assert_eq!(policy.check(&report), Err(PolicyViolation::Debug(tee)));
+            assert!(allow_debug.check(&report).is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn verifies_snp_peers() {
+        let snp = MockProvider::new()
+            .with_tee(TeeType::SevSnp)
+            .with_measurements(Measurement([4; 48]), Default::default());
+        let tdx = MockProvider::new();
+
+        // Both ways, so mixed clusters attest each other.
+        let quote = snp.get_quote(b"pubkey").await.unwrap();
+        let report = tdx.verify_quote(&quote, b"pubkey").await.unwrap();
+        assert_eq!(report.tee, TeeType::SevSnp);
+        assert_eq!(report.mrtd, Measurement([4; 48]));
+        assert!(matches!(
+            tdx.verify_quote(&quote, b"other").await,
+            Err(VerificationError::ReportDataMismatch)
+        ));
+
+        let quote = tdx.get_quote(b"pubkey").await.unwrap();
+        let report = snp.verify_quote(&quote, b"pubkey").await.unwrap();
+        assert_eq!(report.tee, TeeType::Tdx);
+    }
+
+    #[tokio::test]
+    async fn events_reach_policy() {
+        let node = MockProvider::new()
+            .with_event_log(vec![
+                EventLogEntry::runtime("app-id", &[1; 20]),
+                EventLogEntry::runtime("compose-hash", &[2; 32]),
+            ])
+            .unwrap();
+        let quote = node.get_quote(b"pubkey").await.unwrap();
+        let log = node.event_log().await.unwrap().unwrap();
+
+        let mut report = MockProvider::new()
+            .verify_quote(&quote, b"pubkey")
+            .await
+            .unwrap();
+        let policy: AttestationPolicy = serde_json::from_value(serde_json::json!({
+            "measurements": [{ "events": { "compose-hash": hex::encode([2; 32]) } }]
+        }))
+        .unwrap();
+        // Without the log we don't know what was measured.
+        assert!(policy.check(&report).is_err());
+
+        report.events = verify_event_log(&log, &report).unwrap();
+        assert!(policy.check(&report).is_ok());
+    }
+}
@@ -0,0 +1,245 @@
+use super::{Measurement, QuoteReport, TcbStatus, TeeType};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use thiserror::Error;
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum PolicyViolation {
+    #[error("TCB status {0:?} is not allowed")]
+    TcbStatus(TcbStatus),
+
+    #[error("Advisories {0:?} are not allowed")]
+    Advisories(Vec<String>),
+
+    #[error("Debuggable {0:?} guests are not allowed")]
+    Debug(TeeType),
+
+    #[error("Measurements mrtd={mrtd} rtmrs={rtmrs:?} events={events:?} are not allowed")]
+    Measurements {
+        mrtd: Measurement,
+        rtmrs: Box<[Measurement; 4]>,
+        events: BTreeMap<String, String>,
+    },
+}
+
+/// Which platform TCB levels we accept from peers.
+///
+/// A quote from an outdated platform still verifies, it's up to the operator to decide how far
+/// behind a peer is allowed to be. Advisories are only checked for quotes whose status is allowed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TcbPolicy {
+    pub allowed_statuses: Vec<TcbStatus>,
+    /// Advisory IDs tolerated on an allowed level. `None` tolerates any advisory.
+    pub allowed_advisory_ids: Option<Vec<String>>,
+}
+
+impl Default for TcbPolicy {
+    /// Up to date platforms, or up to date platforms that need software mitigations.
+    fn default() -> Self {
+        Self {
+            allowed_statuses: vec![TcbStatus::UpToDate, TcbStatus::SWHardeningNeeded],
+            allowed_advisory_ids: None,
+        }
+    }
+}
+
+impl TcbPolicy {
+    /// Only up to date platforms with no advisories.
+    pub fn strict() -> Self {
+        Self {
+            allowed_statuses: vec![TcbStatus::UpToDate],
+            allowed_advisory_ids: Some(vec![]),
+        }
+    }
+
+    pub fn check(&self, report: &QuoteReport) -> Result<(), PolicyViolation> {
+        if !self.allowed_statuses.contains(&report.tcb_status) {
+            return Err(PolicyViolation::TcbStatus(report.tcb_status.clone()));
+        }
+
+        if let Some(allowed) = &self.allowed_advisory_ids {
+            let disallowed: Vec<String> = report
+                .advisory_ids
+                .iter()
+                .filter(|id| !allowed.contains(id))
+                .cloned()
+                .collect();
+            if !disallowed.is_empty() {
+                return Err(PolicyViolation::Advisories(disallowed));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// One allowed TD build. Registers left out match any value.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AllowedMeasurements {
+    /// Hardware the build runs on, any when left out. SEV-SNP builds pin their launch
+    /// measurement as `mrtd`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tee: Option<TeeType>,
+    pub mrtd: Option<Measurement>,
+    pub rtmrs: [Option<Measurement>; 4],
+    /// Runtime events the peer must have measured, by name, with their hex encoded payload (e.g.
+    /// `"compose-hash": "<hex>"`). Only peers that sent an event log replaying to their quote
+    /// have events.
+    pub events: BTreeMap<String, String>,
+}
+
+impl AllowedMeasurements {
+    pub fn matches(&self, report: &QuoteReport) -> bool {
+        self.tee.is_none_or(|tee| tee == report.tee)
+            && self.mrtd.is_none_or(|mrtd| mrtd == report.mrtd)
+            && self
+                .rtmrs
+                .iter()
+                .zip(&report.rtmrs)
+                .all(|(allowed, rtmr)| allowed.is_none_or(|allowed| allowed == *rtmr))
+            && self.events.iter().all(|(event, payload)| {
+                report.events.get(event).is_some_and(|measured| {
+                    measured.eq_ignore_ascii_case(payload.trim_start_matches("0x"))
+                })
+            })
+    }
+}
+
+/// Everything a verified quote must satisfy for us to trust the peer behind it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AttestationPolicy {
+    pub tcb: TcbPolicy,
+    /// Builds we accept, empty accepts any build.
+    pub measurements: Vec<AllowedMeasurements>,
+    /// Accept TDs with the `DEBUG` attribute and SEV-SNP guests whose policy allows debugging.
+    /// Their host can read their memory whatever their measurements, only for development.
+    pub allow_debug: bool,
+}
+
+impl AttestationPolicy {
+    pub fn check(&self, report: &QuoteReport) -> Result<(), PolicyViolation> {
+        if !self.allow_debug && report.debug() {
+            return Err(PolicyViolation::Debug(report.tee));
+        }
+
+        self.tcb.check(report)?;
+
+        if !self.measurements.is_empty()
+            && !self
+                .measurements
+                .iter()
+                .any(|allowed| allowed.matches(report))
+        {
+            return Err(PolicyViolation::Measurements {
+                mrtd: report.mrtd,
+                rtmrs: Box::new(report.rtmrs),
+                events: report.events.clone(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::attestation::Measurement;
+
+    fn report(tcb_status: TcbStatus, advisory_ids: &[&str]) -> QuoteReport {
+        QuoteReport {
+            tee: Default::default(),
+            mrtd: Measurement::default(),
+            rtmrs: Default::default(),
+            report_data: [0; 64],
+            td_attributes: [0; 8],
+            xfam: [0; 8],
+            tcb_status,
+            advisory_ids: advisory_ids.iter().map(|id| id.to_string()).collect(),
+            events: Default::default(),
+        }
+    }
+
+    #[test]
+    fn tcb_levels() {
+        let policy = TcbPolicy::default();
+        assert!(policy.check(&report(TcbStatus::UpToDate, &[])).is_ok());
+        assert!(policy
+            .check(&report(TcbStatus::SWHardeningNeeded, &["INTEL-SA-00615"]))
+            .is_ok());
+        assert_eq!(
+            policy.check(&report(TcbStatus::OutOfDate, &[])),
+            Err(PolicyViolation::TcbStatus(TcbStatus::OutOfDate))
+        );
+
+        let strict = TcbPolicy::strict();
+        assert!(strict
+            .check(&report(TcbStatus::SWHardeningNeeded, &[]))
+            .is_err());
+    }
+
+    #[test]
+    fn advisories() {
+        let policy = TcbPolicy {
+            allowed_statuses: vec![TcbStatus::UpToDate, TcbStatus::OutOfDate],
+            allowed_advisory_ids: Some(vec!["INTEL-SA-00615".into()]),
+        };
+        assert!(policy
+            .check(&report(TcbStatus::OutOfDate, &["INTEL-SA-00615"]))
+            .is_ok());
+        assert_eq!(
+            policy.check(&report(
+                TcbStatus::OutOfDate,
+                &["INTEL-SA-00615", "INTEL-SA-00837"]
+            )),
+            Err(PolicyViolation::Advisories(vec!["INTEL-SA-00837".into()]))
+        );
+    }
+
+    #[test]
+    fn pins_tee_type() {
+        let allowed: AllowedMeasurements =
+            serde_json::from_value(serde_json::json!({ "tee": "sev_snp" })).unwrap();
+        let mut report = report(TcbStatus::UpToDate, &[]);
+        assert!(!allowed.matches(&report));
+
+        report.tee = TeeType::SevSnp;
+        assert!(allowed.matches(&report));
+        assert!(AllowedMeasurements::default().matches(&report));
+    }
+
+    #[test]
+    fn rejects_debug_guests() {
+        let mut report = report(TcbStatus::UpToDate, &[]);
+        report.td_attributes = 1u64.to_le_bytes();
+        let policy = AttestationPolicy::default();
+        assert_eq!(
+            policy.check(&report),
+            Err(PolicyViolation::Debug(TeeType::Tdx))
+        );
+
+        // On SEV-SNP that bit isn't debug, bit 19 of the guest policy is.
+        report.tee = TeeType::SevSnp;
+        assert!(policy.check(&report).is_ok());
+        report.td_attributes = (1u64 << 19).to_le_bytes();
+        assert!(policy.check(&report).is_err());
+
+        let allow_debug = AttestationPolicy {
+            allow_debug: true,
+            ..Default::default()
+        };
+        assert!(allow_debug.check(&report).is_ok());
+    }
+
+    #[test]
+    fn deserializes_partial_config() {
+        let policy: AttestationPolicy =
+            serde_json::from_str(r#"{"tcb": {"allowed_statuses": ["UpToDate", "OutOfDate"]}}"#)
+                .unwrap();
+        assert_eq!(policy.tcb.allowed_advisory_ids, None);
+        assert!(policy.check(&report(TcbStatus::OutOfDate, &[])).is_ok());
+    }
+}
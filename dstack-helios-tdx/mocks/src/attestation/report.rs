@@ -0,0 +1,214 @@
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+use std::{collections::BTreeMap, fmt};
+
+/// A 48 bytes (SHA384) measurement register. (De)serializes as hex.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Measurement(pub [u8; 48]);
+
+impl Default for Measurement {
+    fn default() -> Self {
+        Self([0; 48])
+    }
+}
+
+impl fmt::Display for Measurement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&hex::encode(self.0))
+    }
+}
+
+impl fmt::Debug for Measurement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl Serialize for Measurement {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        hex_array::serialize(&self.0, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Measurement {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        hex_array::deserialize(deserializer).map(Self)
+    }
+}
+
+/// TCB level of the platform that produced a quote, as reported by the collateral.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TcbStatus {
+    UpToDate,
+    SWHardeningNeeded,
+    ConfigurationNeeded,
+    ConfigurationAndSWHardeningNeeded,
+    OutOfDate,
+    OutOfDateConfigurationNeeded,
+    Revoked,
+    /// Anything the collateral reports that we don't know about.
+    Unknown(String),
+}
+
+impl TcbStatus {
+    pub fn from_status(status: &str) -> Self {
+        match status {
+            "UpToDate" => Self::UpToDate,
+            "SWHardeningNeeded" => Self::SWHardeningNeeded,
+            "ConfigurationNeeded" => Self::ConfigurationNeeded,
+            "ConfigurationAndSWHardeningNeeded" => Self::ConfigurationAndSWHardeningNeeded,
+            "OutOfDate" => Self::OutOfDate,
+            "OutOfDateConfigurationNeeded" => Self::OutOfDateConfigurationNeeded,
+            "Revoked" => Self::Revoked,
+            other => Self::Unknown(other.to_string()),
+        }
+    }
+
+    /// Whether this is a worse TCB level than `other`, e.g. a platform that was up to date and
+    /// now needs software hardening. Unknown levels are the worst.
+    pub fn is_worse_than(&self, other: &Self) -> bool {
+        self.severity() > other.severity()
+    }
+
+    fn severity(&self) -> u8 {
+        match self {
+            Self::UpToDate => 0,
+            Self::SWHardeningNeeded | Self::ConfigurationNeeded => 1,
+            Self::ConfigurationAndSWHardeningNeeded => 2,
+            Self::OutOfDate => 3,
+            Self::OutOfDateConfigurationNeeded => 4,
+            Self::Revoked | Self::Unknown(_) => 5,
+        }
+    }
+}
+
+/// Hardware a quote comes from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TeeType {
+    #[default]
+    Tdx,
+    SevSnp,
+}
+
+/// What a provider extracted from a quote it verified.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuoteReport {
+    #[serde(default)]
+    pub tee: TeeType,
+    /// Initial TD memory measurement (firmware), or the launch measurement of an SEV-SNP guest.
+    pub mrtd: Measurement,
+    /// Runtime measurement registers 0 to 3, zero on SEV-SNP.
+    pub rtmrs: [Measurement; 4],
+    #[serde(with = "hex_array")]
+    pub report_data: [u8; 64],
+    /// TD attributes, or the guest policy on SEV-SNP.
+    #[serde(with = "hex_array")]
+    pub td_attributes: [u8; 8],
+    #[serde(with = "hex_array")]
+    pub xfam: [u8; 8],
+    pub tcb_status: TcbStatus,
+    /// Intel security advisories that apply to the platform's TCB level.
+    pub advisory_ids: Vec<String>,
+    /// Runtime events (e.g. `compose-hash`) with hex encoded payloads, known when the quote came
+    /// with an event log that replays to its RTMRs, see [`super::verify_event_log`].
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub events: BTreeMap<String, String>,
+}
+
+/// `DEBUG` bit of the TD attributes: the host can read and write the TD's memory.
+pub const TD_ATTRIBUTES_DEBUG: u64 = 1;
+
+/// `DEBUG` bit of an SEV-SNP guest policy, which allows the same.
+pub const SNP_POLICY_DEBUG: u64 = 1 << 19;
+
+impl QuoteReport {
+    /// Whether the guest is debuggable, in which case its measurements say nothing about what
+    /// runs in it.
+    pub fn debug(&self) -> bool {
+        let attributes = u64::from_le_bytes(self.td_attributes);
+        match self.tee {
+            TeeType::Tdx => attributes & TD_ATTRIBUTES_DEBUG != 0,
+            TeeType::SevSnp => attributes & SNP_POLICY_DEBUG != 0,
+        }
+    }
+}
+
+impl fmt::Display for QuoteReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "tee={:?} mrtd={} rtmr0={} rtmr1={} rtmr2={} rtmr3={} tcb={:?} advisories={:?}",
+            self.tee,
+            self.mrtd,
+            self.rtmrs[0],
+            self.rtmrs[1],
+            self.rtmrs[2],
+            self.rtmrs[3],
+            self.tcb_status,
+            self.advisory_ids
+        )
+    }
+}
+
+/// Hex (de)serialization for fixed size byte arrays.
+pub mod hex_array {
+    use super::*;
+
+    pub fn serialize<S: Serializer, const N: usize>(
+        bytes: &[u8; N],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(
+        deserializer: D,
+    ) -> Result<[u8; N], D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        hex::decode(encoded.trim_start_matches("0x"))
+            .map_err(D::Error::custom)?
+            .try_into()
+            .map_err(|_| D::Error::custom(format!("expected {N} bytes")))
+    }
+}
+
+/// Hex (de)serialization for byte vectors.
+pub mod hex_bytes {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        hex::decode(encoded.trim_start_matches("0x")).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn report_json_roundtrip() {
+        let report = QuoteReport {
+            tee: TeeType::Tdx,
+            mrtd: Measurement([1; 48]),
+            rtmrs: [Measurement([2; 48]); 4],
+            report_data: [3; 64],
+            td_attributes: [0; 8],
+            xfam: [0xe7, 0x02, 0x06, 0, 0, 0, 0, 0],
+            tcb_status: TcbStatus::from_status("SWHardeningNeeded"),
+            advisory_ids: vec!["INTEL-SA-00837".into()],
+            events: Default::default(),
+        };
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["mrtd"], hex::encode([1; 48]));
+        assert_eq!(json["tcb_status"], "SWHardeningNeeded");
+        assert!(json.get("events").is_none());
+        assert_eq!(json["tee"], "tdx");
+        assert_eq!(serde_json::from_value::<QuoteReport>(json).unwrap(), report);
+    }
+}
@@ -0,0 +1,732 @@
+//! AMD SEV-SNP attestation reports.
+//!
+//! An SNP report is signed by the chip's VCEK, which AMD's KDS certifies through the ASK up to
+//! the ARK root of the product line. SNP evidence is the report followed by that chain, so that
+//! peers verify it without reaching out to the KDS:
+//!
+//! ```text
+//! report (1184 bytes) || (u32 LE length || DER certificate) for the VCEK, ASK and ARK
+//! ```
+//!
+//! TD quotes start with their version and attestation key type (`0x00020004`), SNP reports with
+//! a small version, which is how [`is_snp_evidence`] tells them apart.
+//!
+//! The verified report maps onto a [`QuoteReport`] with [`TeeType::SevSnp`]: the launch
+//! measurement goes in `mrtd`, the guest policy in `td_attributes`, RTMRs and XFAM are zero.
+
+use super::{
+    collateral::unix_now, report_data, AttestationProvider, CollateralCache, CollateralConfig,
+    Measurement, Quote, QuoteReport, TcbStatus, TeeType, VerificationError, SNP_POLICY_DEBUG,
+};
+use async_trait::async_trait;
+use p384::{
+    ecdsa::{
+        signature::{Signer as _, Verifier as _},
+        Signature, SigningKey, VerifyingKey,
+    },
+    pkcs8::DecodePrivateKey,
+};
+use rsa::{pkcs8::DecodePublicKey, pss, RsaPublicKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha384};
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use x509_cert::{
+    der::{oid::ObjectIdentifier, Decode, Encode},
+    Certificate,
+};
+
+pub const SNP_REPORT_LEN: usize = 0x4a0;
+/// The signature covers the report up to itself.
+const SIGNED_LEN: usize = 0x2a0;
+/// ECDSA P-384 with SHA-384, the only algorithm VCEKs sign with.
+const SIGNATURE_ALGO_ECDSA_P384_SHA384: u32 = 1;
+
+/// Where AMD's Key Distribution Service serves VCEKs and product chains.
+pub const AMD_KDS_URL: &str = "https://kdsintf.amd.com";
+
+const TSM_REPORT_DIR: &str = "/sys/kernel/config/tsm/report";
+
+/// `1.2.840.113549.1.1.10`, RSASSA-PSS. AMD signs with SHA-384 and a 48 bytes salt.
+const RSASSA_PSS: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.10");
+/// VCEK extensions carrying the chip ID and the TCB the VCEK was derived for.
+const HW_ID: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.6.1.4.1.3704.1.4");
+const BOOTLOADER_SPL: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.6.1.4.1.3704.1.3.1");
+const TEE_SPL: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.6.1.4.1.3704.1.3.2");
+const SNP_SPL: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.6.1.4.1.3704.1.3.3");
+const MICROCODE_SPL: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.6.1.4.1.3704.1.3.8");
+
+/// GUIDs of the certificate table the host may hand out along with reports, as they appear in
+/// memory.
+const VCEK_GUID: [u8; 16] = guid(
+    0x63da758d,
+    0xe664,
+    0x4564,
+    [0xad, 0xc5, 0xf4, 0xb9, 0x3b, 0xe8, 0xac, 0xcd],
+);
+const ASK_GUID: [u8; 16] = guid(
+    0x4ab7b379,
+    0xbbac,
+    0x4fe4,
+    [0xa0, 0x2f, 0x05, 0xae, 0xf3, 0x27, 0xc7, 0x82],
+);
+const ARK_GUID: [u8; 16] = guid(
+    0xc0b406a4,
+    0xa803,
+    0x4952,
+    [0x97, 0x43, 0x3f, 0xb6, 0x01, 0x4c, 0xd0, 0xae],
+);
+
+/// Security patch levels of the platform's firmware, as encoded on Milan and Genoa.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SnpTcb {
+    pub bootloader: u8,
+    pub tee: u8,
+    pub snp: u8,
+    pub microcode: u8,
+}
+
+impl SnpTcb {
+    fn from_bytes(bytes: [u8; 8]) -> Self {
+        Self {
+            bootloader: bytes[0],
+            tee: bytes[1],
+            snp: bytes[6],
+            microcode: bytes[7],
+        }
+    }
+
+    /// Whether every component is at least at `other`'s level.
+    pub fn is_at_least(&self, other: &Self) -> bool {
+        self.bootloader >= other.bootloader
+            && self.tee >= other.tee
+            && self.snp >= other.snp
+            && self.microcode >= other.microcode
+    }
+}
+
+/// The parts of an attestation report we use.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnpReport {
+    pub version: u32,
+    pub policy: u64,
+    pub signature_algo: u32,
+    pub report_data: [u8; 64],
+    pub measurement: Measurement,
+    pub host_data: [u8; 32],
+    /// TCB the VCEK signing the report is derived from.
+    pub reported_tcb: SnpTcb,
+    pub chip_id: [u8; 64],
+}
+
+impl SnpReport {
+    pub fn parse(report: &[u8]) -> Result<Self, VerificationError> {
+        if report.len() != SNP_REPORT_LEN {
+            return Err(VerificationError::Malformed(format!(
+                "SNP report is {} bytes",
+                report.len()
+            )));
+        }
+
+        Ok(Self {
+            version: u32::from_le_bytes(report[0..4].try_into().unwrap()),
+            policy: u64::from_le_bytes(report[8..16].try_into().unwrap()),
+            signature_algo: u32::from_le_bytes(report[0x34..0x38].try_into().unwrap()),
+            report_data: report[0x50..0x90].try_into().unwrap(),
+            measurement: Measurement(report[0x90..0xc0].try_into().unwrap()),
+            host_data: report[0xc0..0xe0].try_into().unwrap(),
+            reported_tcb: SnpTcb::from_bytes(report[0x180..0x188].try_into().unwrap()),
+            chip_id: report[0x1a0..0x1e0].try_into().unwrap(),
+        })
+    }
+}
+
+/// A report with the chain of its VCEK, DER encoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnpEvidence {
+    pub report: Vec<u8>,
+    pub vcek: Vec<u8>,
+    pub ask: Vec<u8>,
+    pub ark: Vec<u8>,
+}
+
+impl SnpEvidence {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut evidence = self.report.clone();
+        for cert in [&self.vcek, &self.ask, &self.ark] {
+            evidence.extend_from_slice(&(cert.len() as u32).to_le_bytes());
+            evidence.extend_from_slice(cert);
+        }
+        evidence
+    }
+
+    pub fn decode(evidence: &[u8]) -> Result<Self, VerificationError> {
+        let malformed = || VerificationError::Malformed("truncated SNP evidence".into());
+        let report = evidence.get(..SNP_REPORT_LEN).ok_or_else(malformed)?;
+
+        let mut rest = &evidence[SNP_REPORT_LEN..];
+        let mut certs = Vec::with_capacity(3);
+        for _ in 0..3 {
+            let len = rest
+                .get(..4)
+                .map(|len| u32::from_le_bytes(len.try_into().unwrap()) as usize)
+                .ok_or_else(malformed)?;
+            certs.push(rest.get(4..4 + len).ok_or_else(malformed)?.to_vec());
+            rest = &rest[4 + len..];
+        }
+        if !rest.is_empty() {
+            return Err(VerificationError::Malformed(
+                "trailing bytes after SNP evidence".into(),
+            ));
+        }
+
+        let [vcek, ask, ark] = certs.try_into().unwrap();
+        Ok(Self {
+            report: report.to_vec(),
+            vcek,
+            ask,
+            ark,
+        })
+    }
+}
+
+/// Whether `quote` is SNP evidence rather than a TD quote.
+pub fn is_snp_evidence(quote: &[u8]) -> bool {
+    quote.len() > SNP_REPORT_LEN
+        && (2..=5).contains(&u32::from_le_bytes(quote[0..4].try_into().unwrap()))
+}
+
+/// Which ARKs and TCB levels we trust SNP reports under.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SnpConfig {
+    /// Hex SHA-256 fingerprints of the DER encoded ARKs we accept chains up to, e.g. AMD's
+    /// Milan and Genoa ARKs from `<kds>/vcek/v1/<product>/cert_chain`. Empty rejects every SNP
+    /// report.
+    pub trusted_arks: Vec<String>,
+    /// Reports from a platform below this TCB are [`TcbStatus::OutOfDate`].
+    pub minimum_tcb: SnpTcb,
+    /// Serves VCEKs when the host doesn't provide them along with our own reports, defaults to
+    /// [`AMD_KDS_URL`].
+    pub kds_url: Option<String>,
+    /// Product line of the local platform, e.g. `Milan` or `Genoa`, to fetch our VCEK.
+    pub product: Option<String>,
+}
+
+/// Verifies SNP evidence against pinned ARKs.
+#[derive(Debug, Clone, Default)]
+pub struct SnpVerifier {
+    trusted_arks: Vec<[u8; 32]>,
+    minimum_tcb: SnpTcb,
+}
+
+impl SnpVerifier {
+    pub fn new(config: &SnpConfig) -> anyhow::Result<Self> {
+        let trusted_arks = config
+            .trusted_arks
+            .iter()
+            .map(|fingerprint| {
+                let fingerprint = hex::decode(fingerprint.trim_start_matches("0x"))?;
+                fingerprint
+                    .try_into()
+                    .map_err(|_| anyhow::anyhow!("ARK fingerprints are 32 bytes"))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            trusted_arks,
+            minimum_tcb: config.minimum_tcb,
+        })
+    }
+
+    /// Trusts chains up to the DER encoded `ark`.
+    pub fn trusting(ark: &[u8]) -> Self {
+        Self {
+            trusted_arks: vec![Sha256::digest(ark).into()],
+            minimum_tcb: SnpTcb::default(),
+        }
+    }
+
+    /// Checks the VCEK chain up to a trusted ARK, the report signature and that the report data
+    /// commits to `appdata`.
+    pub fn verify(
+        &self,
+        evidence: &[u8],
+        appdata: &[u8],
+    ) -> Result<QuoteReport, VerificationError> {
+        let evidence = SnpEvidence::decode(evidence)?;
+        let report = SnpReport::parse(&evidence.report)?;
+
+        let ark_fingerprint: [u8; 32] = Sha256::digest(&evidence.ark).into();
+        if !self.trusted_arks.contains(&ark_fingerprint) {
+            return Err(VerificationError::Invalid(format!(
+                "untrusted ARK {}",
+                hex::encode(ark_fingerprint)
+            )));
+        }
+        let ark = parse_cert(&evidence.ark)?;
+        let ask = parse_cert(&evidence.ask)?;
+        let vcek = parse_cert(&evidence.vcek)?;
+        let now = unix_now();
+        verify_issued_by(&ark, &ark, now)?;
+        verify_issued_by(&ask, &ark, now)?;
+        verify_issued_by(&vcek, &ask, now)?;
+
+        if extension(&vcek, HW_ID).map(octet_string) != Some(&report.chip_id[..]) {
+            return Err(VerificationError::Invalid(
+                "VCEK was issued for another chip".into(),
+            ));
+        }
+        let vcek_tcb = SnpTcb {
+            bootloader: spl(&vcek, BOOTLOADER_SPL)?,
+            tee: spl(&vcek, TEE_SPL)?,
+            snp: spl(&vcek, SNP_SPL)?,
+            microcode: spl(&vcek, MICROCODE_SPL)?,
+        };
+        if vcek_tcb != report.reported_tcb {
+            return Err(VerificationError::Invalid(format!(
+                "VCEK was issued for TCB {vcek_tcb:?}, report is at {:?}",
+                report.reported_tcb
+            )));
+        }
+
+        if report.signature_algo != SIGNATURE_ALGO_ECDSA_P384_SHA384 {
+            return Err(VerificationError::Malformed(format!(
+                "unknown signature algorithm {}",
+                report.signature_algo
+            )));
+        }
+        let vcek_key = VerifyingKey::from_sec1_bytes(
+            vcek.tbs_certificate
+                .subject_public_key_info
+                .subject_public_key
+                .raw_bytes(),
+        )
+        .map_err(|e| VerificationError::Malformed(format!("VCEK key: {e}")))?;
+        vcek_key
+            .verify(
+                &evidence.report[..SIGNED_LEN],
+                &report_signature(&evidence.report)?,
+            )
+            .map_err(|_| VerificationError::Invalid("bad report signature".into()))?;
+
+        if report.report_data != report_data(appdata) {
+            return Err(VerificationError::ReportDataMismatch);
+        }
+
+        Ok(QuoteReport {
+            tee: TeeType::SevSnp,
+            mrtd: report.measurement,
+            rtmrs: Default::default(),
+            report_data: report.report_data,
+            td_attributes: report.policy.to_le_bytes(),
+            xfam: [0; 8],
+            tcb_status: if report.reported_tcb.is_at_least(&self.minimum_tcb) {
+                TcbStatus::UpToDate
+            } else {
+                TcbStatus::OutOfDate
+            },
+            advisory_ids: vec![],
+            events: Default::default(),
+        })
+    }
+}
+
+/// The report's signature, whose scalars are little endian and zero padded to 72 bytes.
+fn report_signature(report: &[u8]) -> Result<Signature, VerificationError> {
+    let scalar = |offset: usize| {
+        let mut scalar: [u8; 48] = report[offset..offset + 48].try_into().unwrap();
+        scalar.reverse();
+        scalar
+    };
+    Signature::from_scalars(scalar(SIGNED_LEN), scalar(SIGNED_LEN + 72))
+        .map_err(|e| VerificationError::Malformed(format!("report signature: {e}")))
+}
+
+fn parse_cert(der: &[u8]) -> Result<Certificate, VerificationError> {
+    Certificate::from_der(der).map_err(|e| VerificationError::Malformed(e.to_string()))
+}
+
+/// Checks `cert`'s validity and its RSASSA-PSS signature by `issuer`.
+fn verify_issued_by(
+    cert: &Certificate,
+    issuer: &Certificate,
+    now: u64,
+) -> Result<(), VerificationError> {
+    let invalid = |reason: &str| VerificationError::Invalid(reason.to_string());
+    let tbs = &cert.tbs_certificate;
+
+    let validity = &tbs.validity;
+    if now < validity.not_before.to_unix_duration().as_secs()
+        || now > validity.not_after.to_unix_duration().as_secs()
+    {
+        return Err(invalid("certificate expired or not yet valid"));
+    }
+    if tbs.issuer != issuer.tbs_certificate.subject || cert.signature_algorithm.oid != RSASSA_PSS {
+        return Err(invalid("certificate isn't signed by its issuer"));
+    }
+
+    let issuer_key = issuer
+        .tbs_certificate
+        .subject_public_key_info
+        .to_der()
+        .ok()
+        .and_then(|spki| RsaPublicKey::from_public_key_der(&spki).ok())
+        .ok_or_else(|| invalid("issuer key isn't RSA"))?;
+    let signature = cert
+        .signature
+        .as_bytes()
+        .and_then(|signature| pss::Signature::try_from(signature).ok())
+        .ok_or_else(|| invalid("malformed certificate signature"))?;
+    let signed = tbs
+        .to_der()
+        .map_err(|e| VerificationError::Malformed(e.to_string()))?;
+
+    pss::VerifyingKey::<Sha384>::new(issuer_key)
+        .verify(&signed, &signature)
+        .map_err(|_| invalid("bad certificate signature"))
+}
+
+fn extension(cert: &Certificate, oid: ObjectIdentifier) -> Option<&[u8]> {
+    cert.tbs_certificate
+        .extensions
+        .as_ref()?
+        .iter()
+        .find(|extension| extension.extn_id == oid)
+        .map(|extension| extension.extn_value.as_bytes())
+}
+
+/// Extension values are raw on some VCEKs and DER octet strings on others.
+fn octet_string(value: &[u8]) -> &[u8] {
+    match value {
+        [0x04, len, inner @ ..] if *len as usize == inner.len() => inner,
+        _ => value,
+    }
+}
+
+/// A security patch level extension, a DER integer.
+fn spl(vcek: &Certificate, oid: ObjectIdentifier) -> Result<u8, VerificationError> {
+    match extension(vcek, oid) {
+        Some([0x02, 1, spl]) => Ok(*spl),
+        Some([0x02, 2, 0, spl]) => Ok(*spl),
+        _ => Err(VerificationError::Malformed(format!(
+            "VCEK has no TCB extension {oid}"
+        ))),
+    }
+}
+
+const fn guid(a: u32, b: u16, c: u16, d: [u8; 8]) -> [u8; 16] {
+    let a = a.to_le_bytes();
+    let b = b.to_le_bytes();
+    let c = c.to_le_bytes();
+    [
+        a[0], a[1], a[2], a[3], b[0], b[1], c[0], c[1], d[0], d[1], d[2], d[3], d[4], d[5], d[6],
+        d[7],
+    ]
+}
+
+/// Generates reports through the configfs TSM interface of an SNP guest. The VCEK chain comes
+/// from the host's certificate table when it provides one, from the KDS otherwise. TD quotes
+/// still verify, through DCAP collateral, so the node can join clusters running on both.
+pub struct SnpProvider {
+    config: SnpConfig,
+    verifier: Arc<SnpVerifier>,
+    collateral: Arc<CollateralCache>,
+    client: reqwest::Client,
+    /// Names the TSM report entries we create.
+    requests: AtomicU64,
+}
+
+impl SnpProvider {
+    pub fn new(config: SnpConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            verifier: Arc::new(SnpVerifier::new(&config)?),
+            config,
+            collateral: Arc::new(CollateralCache::new(CollateralConfig::default())),
+            client: reqwest::Client::new(),
+            requests: AtomicU64::new(0),
+        })
+    }
+
+    pub fn with_collateral(mut self, collateral: Arc<CollateralCache>) -> Self {
+        self.collateral = collateral;
+        self
+    }
+
+    /// Raw report and certificate table for `report_data`.
+    async fn tsm_report(&self, report_data: &[u8; 64]) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
+        let entry = PathBuf::from(TSM_REPORT_DIR).join(format!(
+            "tplus-{}-{}",
+            std::process::id(),
+            self.requests.fetch_add(1, Ordering::Relaxed)
+        ));
+        tokio::fs::create_dir(&entry).await?;
+        let report = async {
+            tokio::fs::write(entry.join("inblob"), report_data).await?;
+            let report = tokio::fs::read(entry.join("outblob")).await?;
+            // NB: missing unless the host registered certificates for the guest.
+            let certs = tokio::fs::read(entry.join("auxblob"))
+                .await
+                .unwrap_or_default();
+            anyhow::Ok((report, certs))
+        }
+        .await;
+        tokio::fs::remove_dir(&entry).await?;
+
+        report
+    }
+
+    /// The VCEK chain for `report` from the KDS.
+    async fn kds_chain(&self, report: &SnpReport) -> anyhow::Result<[Vec<u8>; 3]> {
+        let kds = self.config.kds_url.as_deref().unwrap_or(AMD_KDS_URL);
+        let product = self.config.product.as_deref().unwrap_or("Milan");
+        let tcb = report.reported_tcb;
+        let vcek = self
+            .client
+            .get(format!(
+                "{kds}/vcek/v1/{product}/{}?blSPL={}&teeSPL={}&snpSPL={}&ucodeSPL={}",
+                hex::encode(report.chip_id),
+                tcb.bootloader,
+                tcb.tee,
+                tcb.snp,
+                tcb.microcode
+            ))
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?
+            .to_vec();
+        let chain = self
+            .client
+            .get(format!("{kds}/vcek/v1/{product}/cert_chain"))
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+
+        // NB: the chain is PEM, ASK first.
+        let chain = Certificate::load_pem_chain(&chain)?;
+        let [ask, ark] = <[Certificate; 2]>::try_from(chain)
+            .map_err(|_| anyhow::anyhow!("KDS chain isn't an ASK and an ARK"))?;
+        Ok([vcek, ask.to_der()?, ark.to_der()?])
+    }
+}
+
+/// Looks the VCEK chain up in the certificate table the host hands out with reports.
+fn certificate_table(table: &[u8]) -> Option<[Vec<u8>; 3]> {
+    let cert = |guid: [u8; 16]| {
+        table.chunks_exact(24).find_map(|entry| {
+            if entry[..16] != guid {
+                return None;
+            }
+            let offset = u32::from_le_bytes(entry[16..20].try_into().unwrap()) as usize;
+            let len = u32::from_le_bytes(entry[20..24].try_into().unwrap()) as usize;
+            table.get(offset..offset + len).map(<[u8]>::to_vec)
+        })
+    };
+    Some([cert(VCEK_GUID)?, cert(ASK_GUID)?, cert(ARK_GUID)?])
+}
+
+#[async_trait]
+impl AttestationProvider for SnpProvider {
+    async fn get_quote(&self, appdata: &[u8]) -> anyhow::Result<Quote> {
+        let (report, table) = self.tsm_report(&report_data(appdata)).await?;
+        let [vcek, ask, ark] = match certificate_table(&table) {
+            Some(chain) => chain,
+            None => self.kds_chain(&SnpReport::parse(&report)?).await?,
+        };
+
+        Ok(hex::encode(
+            SnpEvidence {
+                report,
+                vcek,
+                ask,
+                ark,
+            }
+            .encode(),
+        ))
+    }
+
+    async fn verify_quote(
+        &self,
+        quote: &str,
+        appdata: &[u8],
+    ) -> Result<QuoteReport, VerificationError> {
+        super::verify_hardware_quote(&self.collateral, &self.verifier, quote, appdata).await
+    }
+
+    fn collateral_epoch(&self) -> u64 {
+        self.collateral.generation()
+    }
+
+    fn platform_key(&self, quote: &str) -> Option<[u8; 32]> {
+        super::hardware_platform_key(quote)
+    }
+
+    fn verify_on_platform(
+        &self,
+        quote: &str,
+        appdata: &[u8],
+        platform: &QuoteReport,
+    ) -> Result<QuoteReport, VerificationError> {
+        super::verify_hardware_quote_on(quote, appdata, platform)
+    }
+}
+
+/// Test ARK that [`super::MockProvider`] trusts, see `fixtures/snp/generate.py`.
+pub const TEST_ARK: &[u8] = include_bytes!("../../fixtures/snp/ark.der");
+const TEST_ASK: &[u8] = include_bytes!("../../fixtures/snp/ask.der");
+const TEST_VCEK: &[u8] = include_bytes!("../../fixtures/snp/vcek.der");
+const TEST_VCEK_KEY: &[u8] = include_bytes!("../../fixtures/snp/vcek.key");
+/// Signed by the test VCEK over [`report_data`] of `b"pubkey"`, on the chip and at the TCB the
+/// VCEK was issued for.
+const TEST_REPORT: &[u8] = include_bytes!("../../fixtures/snp/report.bin");
+
+/// Evidence for a report with `measurement` and `report_data`, signed by the test VCEK, for
+/// builds without a TEE. `debug` sets the debug bit of the guest policy.
+pub fn mock_snp_evidence(measurement: Measurement, report_data: [u8; 64], debug: bool) -> Vec<u8> {
+    let mut report = TEST_REPORT.to_vec();
+    if debug {
+        let policy = u64::from_le_bytes(report[8..16].try_into().unwrap()) | SNP_POLICY_DEBUG;
+        report[8..16].copy_from_slice(&policy.to_le_bytes());
+    }
+    report[0x50..0x90].copy_from_slice(&report_data);
+    report[0x90..0xc0].copy_from_slice(&measurement.0);
+
+    let key = SigningKey::from_pkcs8_der(TEST_VCEK_KEY).expect("valid test VCEK key");
+    let signature: Signature = key.sign(&report[..SIGNED_LEN]);
+    let (r, s) = signature.split_bytes();
+    for (offset, scalar) in [(SIGNED_LEN, r), (SIGNED_LEN + 72, s)] {
+        let mut scalar = scalar.to_vec();
+        scalar.reverse();
+        report[offset..offset + 72].fill(0);
+        report[offset..offset + 48].copy_from_slice(&scalar);
+    }
+
+    SnpEvidence {
+        report,
+        vcek: TEST_VCEK.to_vec(),
+        ask: TEST_ASK.to_vec(),
+        ark: TEST_ARK.to_vec(),
+    }
+    .encode()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use super::{TEST_ARK as ARK, TEST_ASK as ASK, TEST_REPORT as REPORT, TEST_VCEK as VCEK};
+
+    fn evidence() -> SnpEvidence {
+        SnpEvidence {
+            report: REPORT.to_vec(),
+            vcek: VCEK.to_vec(),
+            ask: ASK.to_vec(),
+            ark: ARK.to_vec(),
+        }
+    }
+
+    #[test]
+    fn verifies_fixture_report() {
+        let evidence = evidence().encode();
+        assert!(is_snp_evidence(&evidence));
+        assert_eq!(SnpEvidence::decode(&evidence).unwrap(), self::evidence());
+
+        let report = SnpVerifier::trusting(ARK)
+            .verify(&evidence, b"pubkey")
+            .unwrap();
+        assert_eq!(report.tee, TeeType::SevSnp);
+        assert_eq!(report.mrtd, Measurement([0x11; 48]));
+        assert_eq!(report.report_data, report_data(b"pubkey"));
+        assert_eq!(report.tcb_status, TcbStatus::UpToDate);
+
+        assert!(matches!(
+            SnpVerifier::trusting(ARK).verify(&evidence, b"other"),
+            Err(VerificationError::ReportDataMismatch)
+        ));
+        let config = SnpConfig {
+            trusted_arks: vec![hex::encode(Sha256::digest(ARK))],
+            minimum_tcb: SnpTcb {
+                microcode: 200,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            SnpVerifier::new(&config)
+                .unwrap()
+                .verify(&evidence, b"pubkey")
+                .unwrap()
+                .tcb_status,
+            TcbStatus::OutOfDate
+        );
+    }
+
+    #[test]
+    fn rejects_tampered_evidence() {
+        let verifier = SnpVerifier::trusting(ARK);
+
+        let mut measurement = evidence();
+        measurement.report[0x90] ^= 1;
+        assert!(matches!(
+            verifier.verify(&measurement.encode(), b"pubkey"),
+            Err(VerificationError::Invalid(_))
+        ));
+
+        // A VCEK for another chip.
+        let mut chip = evidence();
+        chip.report[0x1a0] ^= 1;
+        assert!(matches!(
+            verifier.verify(&chip.encode(), b"pubkey"),
+            Err(VerificationError::Invalid(_))
+        ));
+
+        // The ASK standing in for the VCEK's issuer.
+        let mut chain = evidence();
+        chain.ask = VCEK.to_vec();
+        assert!(verifier.verify(&chain.encode(), b"pubkey").is_err());
+
+        assert!(matches!(
+            SnpVerifier::default().verify(&evidence().encode(), b"pubkey"),
+            Err(VerificationError::Invalid(_))
+        ));
+        assert!(SnpEvidence::decode(&evidence().encode()[..SNP_REPORT_LEN + 10]).is_err());
+    }
+
+    #[test]
+    fn signs_mock_reports() {
+        let evidence = mock_snp_evidence(Measurement([5; 48]), report_data(b"other"), false);
+        let report = SnpVerifier::trusting(ARK)
+            .verify(&evidence, b"other")
+            .unwrap();
+        assert_eq!(report.mrtd, Measurement([5; 48]));
+    }
+
+    #[test]
+    fn reads_certificate_table() {
+        let mut table = vec![];
+        let mut data = vec![];
+        let header_len = 4 * 24;
+        for (guid, cert) in [(VCEK_GUID, VCEK), (ASK_GUID, ASK), (ARK_GUID, ARK)] {
+            table.extend_from_slice(&guid);
+            table.extend_from_slice(&((header_len + data.len()) as u32).to_le_bytes());
+            table.extend_from_slice(&(cert.len() as u32).to_le_bytes());
+            data.extend_from_slice(cert);
+        }
+        table.extend_from_slice(&[0; 24]);
+        table.extend_from_slice(&data);
+
+        let [vcek, ask, ark] = certificate_table(&table).unwrap();
+        assert_eq!((&vcek[..], &ask[..], &ark[..]), (VCEK, ASK, ARK));
+        assert!(certificate_table(&table[..header_len]).is_none());
+    }
+}
@@ -0,0 +1,279 @@
+use super::{
+    collateral::unix_now, report_data, verify_hardware_quote, AttestationProvider, CollateralCache,
+    CollateralConfig, Measurement, Quote, QuoteReport, SnpVerifier, TcbStatus, TeeType,
+    VerificationError,
+};
+use async_trait::async_trait;
+use dcap_qvl::quote::{Quote as TdQuote, Report};
+use p256::ecdsa::{signature::Verifier as _, Signature, VerifyingKey};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use tdx_attestation::{Attestation, InnerAttestationHelper};
+
+/// Header and TD report body of a v4 TD quote, what the quote signature is over.
+const SIGNED_LEN: usize = 48 + 584;
+/// Header and the TD report fields up to `xfam`: the TDX module's TCB, which the collateral
+/// evaluates, and the TD's attributes.
+const PLATFORM_LEN: usize = 48 + 136;
+/// Offset of the quote signature, past the length of the signature data.
+const SIGNATURE_OFFSET: usize = SIGNED_LEN + 4;
+/// Offset of the attestation key, and of the QE report and PCK certificate chain after it.
+const ATTESTATION_KEY_OFFSET: usize = SIGNATURE_OFFSET + 64;
+
+/// Generates quotes through the local TSM interface, verification goes through Intel's DCAP
+/// collateral. SEV-SNP peers are verified against the ARKs of [`TdxProvider::with_snp`], none by
+/// default.
+pub struct TdxProvider {
+    inner: Attestation,
+    collateral: Arc<CollateralCache>,
+    snp: Arc<SnpVerifier>,
+}
+
+impl TdxProvider {
+    /// Collateral from Intel's PCS, kept in memory.
+    pub fn new() -> Self {
+        Self::with_collateral(Arc::new(CollateralCache::new(CollateralConfig::default())))
+    }
+
+    pub fn with_collateral(collateral: Arc<CollateralCache>) -> Self {
+        Self {
+            inner: Attestation::new(),
+            collateral,
+            snp: Arc::new(SnpVerifier::default()),
+        }
+    }
+
+    pub fn with_snp(mut self, snp: Arc<SnpVerifier>) -> Self {
+        self.snp = snp;
+        self
+    }
+}
+
+impl Default for TdxProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl AttestationProvider for TdxProvider {
+    async fn get_quote(&self, appdata: &[u8]) -> anyhow::Result<Quote> {
+        // NB: our tsm-quote-generation lib takes in any bytes and does the hashing itself,
+        // see [`report_data`].
+        self.inner.get_quote(appdata.to_vec()).await
+    }
+
+    async fn verify_quote(
+        &self,
+        quote: &str,
+        appdata: &[u8],
+    ) -> Result<QuoteReport, VerificationError> {
+        verify_hardware_quote(&self.collateral, &self.snp, quote, appdata).await
+    }
+
+    fn collateral_epoch(&self) -> u64 {
+        self.collateral.generation()
+    }
+
+    fn platform_key(&self, quote: &str) -> Option<[u8; 32]> {
+        super::hardware_platform_key(quote)
+    }
+
+    fn verify_on_platform(
+        &self,
+        quote: &str,
+        appdata: &[u8],
+        platform: &QuoteReport,
+    ) -> Result<QuoteReport, VerificationError> {
+        super::verify_hardware_quote_on(quote, appdata, platform)
+    }
+}
+
+/// DCAP verification of a TD quote, shared by the backends that produce real quotes.
+pub(super) async fn verify_td_quote(
+    collateral: &CollateralCache,
+    quote: &[u8],
+    appdata: &[u8],
+) -> Result<QuoteReport, VerificationError> {
+    verify_td_quote_at(collateral, quote, appdata, unix_now()).await
+}
+
+/// [`verify_td_quote`] as of `now` (unix seconds).
+async fn verify_td_quote_at(
+    collateral: &CollateralCache,
+    quote: &[u8],
+    appdata: &[u8],
+    now: u64,
+) -> Result<QuoteReport, VerificationError> {
+    let collateral = collateral.get(quote).await?;
+    let verification = dcap_qvl::verify::verify(quote, &collateral, now)
+        .map_err(|e| VerificationError::Invalid(e.to_string()))?;
+    td_quote_report(
+        &verification.report,
+        appdata,
+        TcbStatus::from_status(&verification.status),
+        verification.advisory_ids,
+    )
+}
+
+/// Hash of everything in a v4 TD quote but the TD's own measurements and report data, and the
+/// signature over them: what DCAP verification depends on besides the quote signature, the same
+/// for every quote of a platform until its TCB changes. `None` for other quotes.
+pub(super) fn td_platform_key(quote: &[u8]) -> Option<[u8; 32]> {
+    let version = u16::from_le_bytes(quote.get(..2)?.try_into().unwrap());
+    let tee_type = u32::from_le_bytes(quote.get(4..8)?.try_into().unwrap());
+    if version != 4 || tee_type != 0x81 || quote.len() < ATTESTATION_KEY_OFFSET + 64 {
+        return None;
+    }
+
+    let mut hasher = Sha256::new();
+    hasher.update(&quote[..PLATFORM_LEN]);
+    hasher.update(&quote[ATTESTATION_KEY_OFFSET..]);
+    Some(hasher.finalize().into())
+}
+
+/// The signature of a v4 TD quote: the signed header and report, the signature and the raw
+/// attestation key.
+pub(super) fn td_quote_signature(quote: &[u8]) -> Option<(&[u8], &[u8], &[u8])> {
+    Some((
+        quote.get(..SIGNED_LEN)?,
+        quote.get(SIGNATURE_OFFSET..ATTESTATION_KEY_OFFSET)?,
+        quote.get(ATTESTATION_KEY_OFFSET..ATTESTATION_KEY_OFFSET + 64)?,
+    ))
+}
+
+/// Verifies what's specific to a TD quote of a platform DCAP already verified a quote of, see
+/// [`td_platform_key`]: its P-256 signature under the attestation key the QE certified, and its
+/// report data. The TCB level is the platform's.
+pub(super) fn verify_td_quote_on(
+    quote: &[u8],
+    appdata: &[u8],
+    platform: &QuoteReport,
+) -> Result<QuoteReport, VerificationError> {
+    let invalid = |reason: &str| VerificationError::Invalid(reason.to_string());
+    let (signed, signature, attestation_key) = td_quote_signature(quote)
+        .ok_or_else(|| VerificationError::Malformed("truncated TD quote".into()))?;
+    let mut uncompressed = [4_u8; 65];
+    uncompressed[1..].copy_from_slice(attestation_key);
+    let attestation_key =
+        VerifyingKey::from_sec1_bytes(&uncompressed).map_err(|_| invalid("bad attestation key"))?;
+    let signature = Signature::from_slice(signature).map_err(|_| invalid("bad quote signature"))?;
+    attestation_key
+        .verify(signed, &signature)
+        .map_err(|_| invalid("bad quote signature"))?;
+
+    let parsed = TdQuote::parse(quote).map_err(|e| VerificationError::Malformed(e.to_string()))?;
+    td_quote_report(
+        &parsed.report,
+        appdata,
+        platform.tcb_status.clone(),
+        platform.advisory_ids.clone(),
+    )
+}
+
+/// Checks that a verified TD report commits to `appdata` and extracts what we keep from it.
+pub(super) fn td_quote_report(
+    report: &Report,
+    appdata: &[u8],
+    tcb_status: TcbStatus,
+    advisory_ids: Vec<String>,
+) -> Result<QuoteReport, VerificationError> {
+    let report = report
+        .as_td10()
+        .ok_or(VerificationError::UnsupportedReport)?;
+
+    if report.report_data != report_data(appdata) {
+        return Err(VerificationError::ReportDataMismatch);
+    }
+
+    Ok(QuoteReport {
+        tee: TeeType::Tdx,
+        mrtd: Measurement(report.mr_td),
+        rtmrs: [
+            Measurement(report.rt_mr0),
+            Measurement(report.rt_mr1),
+            Measurement(report.rt_mr2),
+            Measurement(report.rt_mr3),
+        ],
+        report_data: report.report_data,
+        td_attributes: report.td_attributes,
+        xfam: report.xfam,
+        tcb_status,
+        advisory_ids,
+        events: Default::default(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{attestation::collateral::CachedCollateral, pccs::LocalPccs};
+
+    /// A TD quote and the collateral of its platform, as recorded by the dcap-qvl authors.
+    const RECORDED_QUOTE: &str = include_str!("../../fixtures/tdx_quote.hex");
+    const RECORDED_COLLATERAL: &str = include_str!(
+        "../../fixtures/collateral/2d41a845253266a5378f181073add3e96a9a616bc5859868c770c2b7b3a44a5c.json"
+    );
+
+    /// Verifies against a [`LocalPccs`] serving the recorded collateral, as of when it was
+    /// fetched so that the test keeps passing once it expires.
+    #[tokio::test]
+    async fn verifies_recorded_quote() {
+        let recorded: CachedCollateral = serde_json::from_str(RECORDED_COLLATERAL).unwrap();
+        let pccs = LocalPccs::new(recorded.collateral);
+        let collateral = CollateralCache::new(CollateralConfig {
+            pccs_url: pccs.spawn(),
+            ..Default::default()
+        });
+        let mut quote = hex::decode(RECORDED_QUOTE.trim()).unwrap();
+
+        // NB: the quote doesn't commit to any of our appdata, the report data is only checked
+        // once DCAP verification passed.
+        assert!(matches!(
+            verify_td_quote_at(&collateral, &quote, b"", recorded.fetched_at).await,
+            Err(VerificationError::ReportDataMismatch)
+        ));
+        assert!(matches!(
+            verify_td_quote_at(&collateral, &quote, b"", recorded.expires_at + 1).await,
+            Err(VerificationError::Invalid(_))
+        ));
+
+        // Flip a bit of the MRTD.
+        quote[48 + 136] ^= 1;
+        assert!(matches!(
+            verify_td_quote_at(&collateral, &quote, b"", recorded.fetched_at).await,
+            Err(VerificationError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn checks_signature_on_platform() {
+        let mut quote = hex::decode(RECORDED_QUOTE.trim()).unwrap();
+        let platform = QuoteReport {
+            tee: TeeType::Tdx,
+            mrtd: Default::default(),
+            rtmrs: Default::default(),
+            report_data: [0; 64],
+            td_attributes: [0; 8],
+            xfam: [0; 8],
+            tcb_status: TcbStatus::UpToDate,
+            advisory_ids: vec![],
+            events: Default::default(),
+        };
+        let key = td_platform_key(&quote).unwrap();
+
+        // NB: the signature checks out, the report data is checked next.
+        assert!(matches!(
+            verify_td_quote_on(&quote, b"", &platform),
+            Err(VerificationError::ReportDataMismatch)
+        ));
+
+        // The MRTD isn't part of the platform, the signature covers it.
+        quote[48 + 136] ^= 1;
+        assert_eq!(td_platform_key(&quote), Some(key));
+        assert!(matches!(
+            verify_td_quote_on(&quote, b"", &platform),
+            Err(VerificationError::Invalid(_))
+        ));
+    }
+}
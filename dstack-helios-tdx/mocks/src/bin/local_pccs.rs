@@ -0,0 +1,32 @@
+//! Serves recorded collateral as a PCCS, for CI and air-gapped nodes.
+//!
+//! Usage: `local_pccs <collateral.json> [port]`, where the file holds a `QuoteCollateralV3` or a
+//! collateral cache entry.
+
+use anyhow::{Context, Result};
+use mocks::{attestation::CachedCollateral, pccs::LocalPccs};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let path = args
+        .next()
+        .context("usage: local_pccs <collateral.json> [port]")?;
+    let port: u16 = args
+        .next()
+        .map(|port| port.parse())
+        .transpose()?
+        .unwrap_or(8081);
+
+    let recorded = std::fs::read(&path)?;
+    let collateral = match serde_json::from_slice::<CachedCollateral>(&recorded) {
+        Ok(cached) => cached.collateral,
+        Err(_) => serde_json::from_slice(&recorded)?,
+    };
+
+    let addr = LocalPccs::new(collateral).serve(([0, 0, 0, 0], port).into());
+    println!("serving {path} on http://{addr}");
+    std::future::pending::<()>().await;
+
+    Ok(())
+}
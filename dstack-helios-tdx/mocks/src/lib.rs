@@ -1,51 +1,11 @@
-/// Returns the report data as hex string. Should be replaced with call to the dstack-guest backend to get
-/// a quote.
-#[cfg(feature = "tdx")]
-use tdx_attestation::{Attestation, InnerAttestationHelper};
-
-#[cfg(feature = "tdx")]
-pub async fn get_quote(report_data: &[u8]) -> anyhow::Result<String> {
-    let attestation = Attestation::new();
-    attestation.get_quote(report_data.to_vec()).await
-}
-
-#[cfg(not(feature = "tdx"))]
-pub async fn get_quote(report_data: &[u8]) -> anyhow::Result<String> {
-    Ok(hex::encode(report_data))
-}
+pub mod attestation;
+pub mod pccs;
 
 /// Returns a random secret.
 pub fn get_node_secret() -> secp256k1::SecretKey {
     secp256k1::SecretKey::new(&mut secp256k1::rand::thread_rng())
 }
 
-// dummy type.
-pub struct QuoteVerifyMock {
-    pub is_valid: bool,
-}
-
-// TODO: actually verify measurements.
-#[cfg(feature = "tdx")]
-pub async fn verify_quote(quote: &str, _appdata: &[u8]) -> QuoteVerifyMock {
-    let attestation = Attestation::new();
-    let verification = attestation.verify_quote(quote.to_string()).await;
-
-    if verification.is_ok() {
-        QuoteVerifyMock {
-            is_valid: true,
-        }
-    } else {
-        QuoteVerifyMock {
-            is_valid: false,
-        }
-    }
-}
-
-#[cfg(not(feature = "tdx"))]
-pub async fn verify_quote(_quote: &str, _appdata: &[u8]) -> QuoteVerifyMock {
-    QuoteVerifyMock { is_valid: true }
-}
-
 /// Should return information about the virtal tsc.
 pub fn get_tsc(infer: u64) -> u64 {
     infer
@@ -53,8 +13,7 @@ pub fn get_tsc(infer: u64) -> u64 {
 
 #[cfg(test)]
 mod test {
-    use sha2::{Digest, Sha256};
-    use tdx_attestation::{Attestation, InnerAttestationHelper};
+    use crate::attestation::{report_data, AttestationProvider, TdxProvider};
 
     use serde::Deserialize;
 
@@ -64,17 +23,6 @@ mod test {
         pubkey: String,
     }
 
-    fn calc_report_data(appdata: &[u8]) -> [u8; 64] {
-        let preimage = format!("register{}", hex::encode(appdata));
-        let mut hasher = Sha256::new();
-        hasher.update(preimage);
-        let hashed: Vec<u8> = hasher.finalize().to_vec();
-        let mut padded_report_data = [0_u8; 64];
-        padded_report_data[..hashed.len()].copy_from_slice(&hashed);
-
-        padded_report_data
-    }
-
     #[tokio::test]
     async fn test_verify_quote() {
         let url = "http://34.19.110.223:3032/attest";
@@ -86,12 +34,10 @@ mod test {
         .unwrap();
         let pubkey = hex::decode(response.pubkey).unwrap();
 
-        let attestation = Attestation::new();
-        let verification = attestation.verify_quote(response.quote.to_string()).await;
+        let verification = TdxProvider::new()
+            .verify_quote(&response.quote, &pubkey)
+            .await;
         assert!(verification.is_ok());
-        assert_eq!(
-            verification.unwrap().report.as_td10().unwrap().report_data,
-            calc_report_data(&pubkey)
-        );
+        assert_eq!(verification.unwrap().report_data, report_data(&pubkey));
     }
 }
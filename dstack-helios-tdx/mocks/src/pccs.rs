@@ -0,0 +1,133 @@
+//! Local stand-in for a PCCS.
+//!
+//! Serves a fixed set of collateral over the subset of the PCCS v4 API that collateral fetching
+//! uses, so that nodes and tests can be pointed at it (see
+//! [`crate::attestation::CollateralConfig::pccs_url`]) instead of Intel.
+
+use dcap_qvl::QuoteCollateralV3;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+};
+use warp::{http::Response, Filter};
+
+#[derive(Clone)]
+pub struct LocalPccs {
+    collateral: Arc<RwLock<QuoteCollateralV3>>,
+    requests: Arc<AtomicUsize>,
+}
+
+impl LocalPccs {
+    pub fn new(collateral: QuoteCollateralV3) -> Self {
+        Self {
+            collateral: Arc::new(RwLock::new(collateral)),
+            requests: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn collateral(&self) -> QuoteCollateralV3 {
+        self.collateral.read().unwrap().clone()
+    }
+
+    /// Replaces the served collateral, like a TCB recovery would.
+    pub fn set_collateral(&self, collateral: QuoteCollateralV3) {
+        *self.collateral.write().unwrap() = collateral;
+    }
+
+    /// Number of requests served so far.
+    pub fn requests(&self) -> usize {
+        self.requests.load(Ordering::SeqCst)
+    }
+
+    /// Serves on `addr` until the runtime shuts down, returns the bound address.
+    pub fn serve(&self, addr: SocketAddr) -> SocketAddr {
+        let (addr, server) = warp::serve(self.routes()).bind_ephemeral(addr);
+        tokio::spawn(server);
+        addr
+    }
+
+    /// Serves on a random local port, returns the base url.
+    pub fn spawn(&self) -> String {
+        format!("http://{}", self.serve(([127, 0, 0, 1], 0).into()))
+    }
+
+    fn routes(
+        &self,
+    ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        let pccs = self.clone();
+        let with_collateral = warp::any().map(move || {
+            pccs.requests.fetch_add(1, Ordering::SeqCst);
+            pccs.collateral()
+        });
+        // Same collateral for sgx and tdx, for any ca and fmspc.
+        let tee = warp::path("sgx")
+            .or(warp::path("tdx"))
+            .unify()
+            .and(warp::path("certification"))
+            .and(warp::path("v4"));
+
+        let pck_crl = tee
+            .and(warp::path!("pckcrl"))
+            .and(with_collateral.clone())
+            .map(|collateral: QuoteCollateralV3| {
+                issued_by(
+                    "SGX-PCK-CRL-Issuer-Chain",
+                    &collateral.pck_crl_issuer_chain,
+                    collateral.pck_crl,
+                )
+            });
+
+        let tcb_info = tee
+            .and(warp::path!("tcb"))
+            .and(with_collateral.clone())
+            .map(|collateral: QuoteCollateralV3| {
+                let body = format!(
+                    r#"{{"tcbInfo":{},"signature":"{}"}}"#,
+                    collateral.tcb_info,
+                    hex::encode(&collateral.tcb_info_signature)
+                );
+                issued_by(
+                    "TCB-Info-Issuer-Chain",
+                    &collateral.tcb_info_issuer_chain,
+                    body.into_bytes(),
+                )
+            });
+
+        let qe_identity = tee
+            .and(warp::path!("qe" / "identity"))
+            .and(with_collateral.clone())
+            .map(|collateral: QuoteCollateralV3| {
+                let body = format!(
+                    r#"{{"enclaveIdentity":{},"signature":"{}"}}"#,
+                    collateral.qe_identity,
+                    hex::encode(&collateral.qe_identity_signature)
+                );
+                issued_by(
+                    "SGX-Enclave-Identity-Issuer-Chain",
+                    &collateral.qe_identity_issuer_chain,
+                    body.into_bytes(),
+                )
+            });
+
+        // NB: unlike the PCS the PCCS serves the root CA CRL hex encoded.
+        let root_ca_crl = warp::path!("sgx" / "certification" / "v4" / "rootcacrl")
+            .and(with_collateral)
+            .map(|collateral: QuoteCollateralV3| hex::encode(collateral.root_ca_crl));
+
+        warp::get().and(pck_crl.or(tcb_info).or(qe_identity).or(root_ca_crl))
+    }
+}
+
+fn issued_by(header: &str, chain: &str, body: Vec<u8>) -> Response<Vec<u8>> {
+    Response::builder()
+        .header(
+            header,
+            utf8_percent_encode(chain, NON_ALPHANUMERIC).to_string(),
+        )
+        .body(body)
+        .unwrap()
+}
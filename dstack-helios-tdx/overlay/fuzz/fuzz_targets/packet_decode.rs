@@ -0,0 +1,21 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use overlay::{
+    codec,
+    message::{MaybeEncrypted, OverlayMessageType, OverlayPacket},
+};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(packet) = codec::decode::<OverlayPacket>(data) else {
+        return;
+    };
+
+    // the encoding is canonical: anything we accept re-encodes to the same bytes.
+    assert_eq!(codec::encode(&packet).unwrap(), data);
+    let _ = packet.to_payload();
+
+    let (MaybeEncrypted::EncryptedP2P(inner) | MaybeEncrypted::Overlay(inner)) =
+        &packet.message.message;
+    let _ = codec::decode::<OverlayMessageType>(inner);
+});
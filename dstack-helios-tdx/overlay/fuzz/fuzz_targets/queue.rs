@@ -0,0 +1,188 @@
+#![no_main]
+
+//! Drives [`P2PConnectionManager::queue`] with an in-memory transport. Frames are either raw
+//! wire bytes or structurally valid packets signed by a fixed peer key, so that the fuzzer can
+//! get past decoding and reach the handshake, session and nonce checks. Onboard frames are built
+//! when the queue pulls them so that they can answer the challenge it sent.
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use mocks::attestation::{AttestationProvider, MockProvider};
+use overlay::{
+    codec,
+    message::{
+        attestation_appdata, MaybeEncrypted, OverlayChallenge, OverlayHeader, OverlayMessage,
+        OverlayMessageType, OverlayOnboard, OverlayPacket,
+    },
+    p2p::P2PConnectionManager,
+    OverlayContext, P2PTransportRecvMiddleman, P2PTransportSendMiddleman,
+};
+use secp256k1::{Message, Secp256k1, SecretKey};
+use sha2::{Digest, Sha256};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+const LOCAL_SECRET: [u8; 32] = [1; 32];
+const PEER_SECRET: [u8; 32] = [7; 32];
+const PEER_EPHEMERAL: [u8; 32] = [8; 32];
+
+/// Last challenge the queue sent.
+type Challenge = Arc<Mutex<[u8; 32]>>;
+
+#[derive(Arbitrary, Debug)]
+enum Frame {
+    Raw(Vec<u8>),
+    Challenge([u8; 32]),
+    /// `None` sends a quote the mock provider accepts, answering `challenge` or if `None` the
+    /// queue's challenge.
+    Onboard {
+        session: i64,
+        quote: Option<String>,
+        challenge: Option<[u8; 32]>,
+    },
+    Signed {
+        nonce: i64,
+        session_key: i64,
+        message: Vec<u8>,
+        sign: bool,
+    },
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    frames: Vec<Frame>,
+    outbound: Vec<Vec<u8>>,
+}
+
+struct Sink(Challenge);
+
+#[async_trait::async_trait]
+impl P2PTransportSendMiddleman for Sink {
+    async fn connection_send_message(&mut self, message: Vec<u8>) -> anyhow::Result<()> {
+        // whatever we put on the wire must decode on the other side.
+        let packet =
+            codec::decode::<OverlayPacket>(&message).expect("queue produced an undecodable packet");
+        if let (None, MaybeEncrypted::EncryptedP2P(message)) =
+            (packet.header, packet.message.message)
+        {
+            if let Ok(OverlayMessageType::Challenge(OverlayChallenge { challenge })) =
+                codec::decode(&message)
+            {
+                *self.0.lock().unwrap() = challenge;
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Source {
+    frames: std::vec::IntoIter<Frame>,
+    challenge: Challenge,
+}
+
+#[async_trait::async_trait]
+impl P2PTransportRecvMiddleman for Source {
+    async fn incoming_requests(&mut self) -> anyhow::Result<Option<Vec<u8>>> {
+        let Some(frame) = self.frames.next() else {
+            return Ok(None);
+        };
+        let challenge = *self.challenge.lock().unwrap();
+        Ok(Some(frame_bytes(frame, challenge).await))
+    }
+}
+
+async fn frame_bytes(frame: Frame, queue_challenge: [u8; 32]) -> Vec<u8> {
+    let secp = Secp256k1::new();
+    let peer_key = SecretKey::from_byte_array(&PEER_SECRET).unwrap();
+    let peer_pubkey = peer_key.public_key(&secp).serialize().to_vec();
+
+    match frame {
+        Frame::Raw(bytes) => bytes,
+        Frame::Challenge(challenge) => {
+            let challenge = OverlayMessageType::Challenge(OverlayChallenge { challenge });
+            codec::encode(&OverlayPacket::handshake(&peer_pubkey, &challenge).unwrap()).unwrap()
+        }
+        Frame::Onboard {
+            session,
+            quote,
+            challenge,
+        } => {
+            let ephemeral = SecretKey::from_byte_array(&PEER_EPHEMERAL)
+                .unwrap()
+                .public_key(&secp);
+            let quote = match quote {
+                Some(quote) => quote,
+                None => {
+                    let appdata = attestation_appdata(
+                        &peer_key.public_key(&secp),
+                        &challenge.unwrap_or(queue_challenge),
+                        &ephemeral,
+                    );
+                    MockProvider::new().get_quote(&appdata).await.unwrap()
+                }
+            };
+            let onboard = OverlayMessageType::Onboard(OverlayOnboard {
+                quote,
+                session,
+                ephemeral: ephemeral.serialize().to_vec(),
+                want_shared: false,
+                event_log: None,
+                genesis: None,
+            });
+            codec::encode(&OverlayPacket::handshake(&peer_pubkey, &onboard).unwrap()).unwrap()
+        }
+        Frame::Signed {
+            nonce,
+            session_key,
+            message,
+            sign,
+        } => {
+            let mut packet = OverlayPacket {
+                header: Some(OverlayHeader {
+                    nonce,
+                    session_key,
+                    signature: vec![],
+                }),
+                pubkey: peer_pubkey,
+                message: OverlayMessage::new_p2p_encrypted(None, message),
+            };
+            if sign {
+                let digest: [u8; 32] = Sha256::digest(packet.to_payload().unwrap()).into();
+                let signature = secp.sign_ecdsa(&Message::from_digest(digest), &peer_key);
+                packet.add_signature(signature.serialize_compact().to_vec());
+            }
+            codec::encode(&packet).unwrap()
+        }
+    }
+}
+
+fuzz_target!(|input: Input| {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    runtime.block_on(async move {
+        let (broadcast_tx, comms_receiver) = tokio::sync::broadcast::channel(1024);
+        let (app_tx, _app_rx) = tokio::sync::mpsc::channel(1024);
+        for message in input.outbound {
+            let _ = broadcast_tx.send(OverlayMessage::new_p2p_encrypted(None, message));
+        }
+        drop(broadcast_tx);
+
+        let mut manager = P2PConnectionManager::new(
+            SecretKey::from_byte_array(&LOCAL_SECRET).unwrap(),
+            OverlayContext::new(Arc::new(MockProvider::new())),
+        );
+        let challenge = Challenge::default();
+        let source = Source {
+            frames: input.frames.into_iter(),
+            challenge: challenge.clone(),
+        };
+        let queue = manager.queue(comms_receiver, Sink(challenge), source, app_tx);
+        // errors are expected, we're only after panics and hangs.
+        let _ = tokio::time::timeout(Duration::from_secs(5), queue).await;
+    });
+});
@@ -0,0 +1,80 @@
+//! Detection of split brains, i.e. nodes of different clusters connecting to each other.
+//!
+//! Any node started without peers bootstraps a cluster of its own, so two clusters can end up
+//! overlapping, each holding a secret it believes canonical. Nodes hand their cluster's genesis
+//! record (see [`crate::genesis`]) to each other in the handshake, and a peer whose record has
+//! another [`Genesis::id`] is in another cluster. Its record is verified like any genesis record,
+//! then [`SplitBrainPolicy`] decides what becomes of the connection, and the conflict is published
+//! as a [`crate::peers::PeerEvent::SplitBrain`].
+//!
+//! Nodes that don't hold a genesis record yet (e.g. joining ones) don't take part.
+
+use crate::genesis::Genesis;
+use serde::Deserialize;
+use std::sync::{Arc, RwLock};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SplitBrainPolicy {
+    /// Close the connection.
+    #[default]
+    Refuse,
+    /// Keep the connection, but don't exchange any application message over it.
+    Isolate,
+    /// Members of the newer cluster switch to the older one's secret.
+    FollowOlder,
+}
+
+/// What became of a connection to a peer of another cluster.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    Refused,
+    Isolated,
+    /// Their cluster is older, we switch to it.
+    Following,
+    /// Our cluster is older, the peer switches to it.
+    Followed,
+}
+
+#[derive(Debug, Clone)]
+pub struct ClusterConflict {
+    /// Node pubkey of the peer.
+    pub pubkey: Vec<u8>,
+    /// ID of our genesis record.
+    pub ours: [u8; 32],
+    /// Verified genesis record of the peer's cluster.
+    pub theirs: Genesis,
+    pub resolution: Resolution,
+}
+
+/// Genesis record of the cluster we're in, handed to peers in the handshake. Shared by the
+/// connection managers and the application, which sets it once it holds the secret.
+#[derive(Debug, Clone, Default)]
+pub struct ClusterIdentity {
+    genesis: Arc<RwLock<Option<Genesis>>>,
+}
+
+impl ClusterIdentity {
+    pub fn genesis(&self) -> Option<Genesis> {
+        self.genesis.read().unwrap().clone()
+    }
+
+    pub fn set(&self, genesis: Option<Genesis>) {
+        *self.genesis.write().unwrap() = genesis;
+    }
+}
+
+/// Whether `genesis` was created before `other`. Records created in the same second are ordered
+/// by ID, so that both sides agree on which is older.
+pub fn is_older(genesis: &Genesis, other: &Genesis) -> bool {
+    (genesis.created_at, genesis.id()) < (other.created_at, other.id())
+}
+
+pub fn resolve(policy: SplitBrainPolicy, ours: &Genesis, theirs: &Genesis) -> Resolution {
+    match policy {
+        SplitBrainPolicy::Refuse => Resolution::Refused,
+        SplitBrainPolicy::Isolate => Resolution::Isolated,
+        SplitBrainPolicy::FollowOlder if is_older(theirs, ours) => Resolution::Following,
+        SplitBrainPolicy::FollowOlder => Resolution::Followed,
+    }
+}
@@ -0,0 +1,215 @@
+//! Wire codec for the overlay.
+//!
+//! Everything that crosses a transport goes through [`encode`] and [`decode`] rather than the
+//! bincode free functions. The options are pinned here (fixed-int, little endian, no trailing
+//! bytes) so that the encoding doesn't silently change with bincode's defaults, and decoding is
+//! bounded by [`MAX_PACKET_SIZE`] so that a peer (or the host) can't make us allocate
+//! arbitrarily large buffers from a forged length prefix.
+//!
+//! Signed payloads are built with [`PayloadWriter`], which length-prefixes every variable size
+//! field so that field boundaries can't be shifted around without invalidating the signature.
+
+use bincode::Options;
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Upper bound for a single encoded overlay packet.
+pub const MAX_PACKET_SIZE: u64 = 1024 * 1024;
+
+/// Domain separator for signed packet payloads. Bump the version on any layout change.
+pub const PACKET_PAYLOAD_DOMAIN: &[u8] = b"tplus/overlay/packet/v1";
+
+fn options() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .with_little_endian()
+        .with_limit(MAX_PACKET_SIZE)
+        .reject_trailing_bytes()
+}
+
+/// Encodes a value with the overlay wire options.
+pub fn encode<T: Serialize + ?Sized>(value: &T) -> anyhow::Result<Vec<u8>> {
+    Ok(options().serialize(value)?)
+}
+
+/// Decodes a value with the overlay wire options. Input that is larger than
+/// [`MAX_PACKET_SIZE`], that declares lengths exceeding it, or that carries trailing bytes
+/// is rejected.
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> anyhow::Result<T> {
+    if bytes.len() as u64 > MAX_PACKET_SIZE {
+        anyhow::bail!("packet of {} bytes exceeds the wire limit", bytes.len());
+    }
+
+    Ok(options().deserialize(bytes)?)
+}
+
+/// Builds canonical byte strings to be hashed and signed.
+///
+/// Variable size fields are written as a big endian `u32` length followed by the bytes, fixed
+/// size integers are written big endian without prefix.
+pub struct PayloadWriter {
+    buf: Vec<u8>,
+}
+
+impl PayloadWriter {
+    pub fn new(domain: &[u8]) -> Self {
+        let mut writer = Self { buf: vec![] };
+        writer.put_bytes(domain);
+        writer
+    }
+
+    pub fn put_bytes(&mut self, bytes: &[u8]) -> &mut Self {
+        // NB: fields are bounded by MAX_PACKET_SIZE so this can't truncate for anything we'd decode.
+        self.buf
+            .extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+        self.buf.extend_from_slice(bytes);
+        self
+    }
+
+    pub fn put_u8(&mut self, value: u8) -> &mut Self {
+        self.buf.push(value);
+        self
+    }
+
+    pub fn put_i64(&mut self, value: i64) -> &mut Self {
+        self.buf.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        message::*,
+        rotation::{KeyHistory, KeyLink, RotationProposal},
+        shamir::{Dealing, DealtShare, Share},
+    };
+
+    fn packet(pubkey: Vec<u8>, message: Vec<u8>) -> OverlayPacket {
+        OverlayPacket {
+            header: Some(OverlayHeader {
+                nonce: 1,
+                session_key: 2,
+                signature: vec![],
+            }),
+            pubkey,
+            message: OverlayMessage::new_p2p_encrypted(None, message),
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let packet = packet(vec![2; 33], vec![1, 2, 3]);
+        let decoded: OverlayPacket = decode(&encode(&packet).unwrap()).unwrap();
+        assert_eq!(decoded.pubkey, packet.pubkey);
+        assert_eq!(decoded.to_payload(), packet.to_payload());
+    }
+
+    #[test]
+    fn rejects_trailing_bytes() {
+        let mut bytes = encode(&packet(vec![2; 33], vec![1])).unwrap();
+        bytes.push(0);
+        assert!(decode::<OverlayPacket>(&bytes).is_err());
+    }
+
+    #[test]
+    fn rejects_oversized_length_prefix() {
+        // `None` header followed by a pubkey whose declared length is far above the limit.
+        let mut bytes = vec![0];
+        bytes.extend_from_slice(&u64::MAX.to_le_bytes());
+        assert!(decode::<OverlayPacket>(&bytes).is_err());
+    }
+
+    /// Variant indices are on the wire, changing one breaks mixed-version clusters.
+    #[test]
+    fn message_type_tags() {
+        let link = KeyLink {
+            epoch: 1,
+            activates_at: 0,
+            old_pubkey: [2; 33],
+            new_pubkey: [2; 33],
+            old_signature: [0; 64],
+            new_signature: [0; 64],
+        };
+        let dealt = DealtShare {
+            dealing: Dealing {
+                generation: 0,
+                threshold: 1,
+                members: vec![],
+                commitments: vec![],
+            },
+            share: Share {
+                index: 1,
+                value: [0; 32],
+            },
+        };
+        let messages = [
+            OverlayMessageType::SharedSecret(NotifySharedSecret {
+                secret: vec![],
+                genesis: None,
+                history: KeyHistory::default(),
+            }),
+            OverlayMessageType::RequestSharedSecret,
+            OverlayMessageType::Onboard(OverlayOnboard {
+                quote: String::new(),
+                session: 0,
+                ephemeral: vec![],
+                want_shared: false,
+                event_log: None,
+                genesis: None,
+            }),
+            OverlayMessageType::Challenge(OverlayChallenge::random()),
+            OverlayMessageType::Reattestation(OverlayReattestation {
+                quote: String::new(),
+                event_log: None,
+            }),
+            OverlayMessageType::Rotation(RotationProposal {
+                secret: vec![],
+                link,
+            }),
+            OverlayMessageType::Share(NotifyShare {
+                dealt,
+                genesis: None,
+                history: KeyHistory::default(),
+                signature: None,
+            }),
+            OverlayMessageType::Frost(FrostMessage::Commit {
+                session: [0; 32],
+                dealing: [0; 32],
+                response: SignedResponse::Block {
+                    number: String::new(),
+                },
+            }),
+            OverlayMessageType::Dkg(DkgMessage::Start {
+                session: [0; 32],
+                threshold: 1,
+                members: vec![],
+            }),
+            OverlayMessageType::Call(CallMessage::Request {
+                request: [0; 32],
+                tx: String::new(),
+            }),
+            OverlayMessageType::Election(ElectionMessage::Heartbeat { term: 0 }),
+            OverlayMessageType::State(NotifyState { entries: vec![] }),
+        ];
+        for (tag, message) in messages.iter().enumerate() {
+            assert_eq!(
+                encode(message).unwrap()[..4],
+                (tag as u32).to_le_bytes(),
+                "{message:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn payload_field_boundaries() {
+        // same concatenation, different split between pubkey and message.
+        let a = packet(vec![1, 2], vec![3, 4]);
+        let b = packet(vec![1], vec![2, 3, 4]);
+        assert_ne!(a.to_payload(), b.to_payload());
+    }
+}
@@ -0,0 +1,611 @@
+//! Distributed generation of a threshold key, without a trusted dealer.
+//!
+//! Every member of the session deals a random secret to all members, as in [`crate::shamir`], and
+//! the key is the sum of the secrets of the members that dealt correctly: each member's share is
+//! the sum of the shares it got, and the key's commitments the sum of the dealings'. Nobody ever
+//! learns the key, and the result is a [`DealtShare`] like any other dealing's.
+//!
+//! The session runs in three rounds over the members' channels:
+//! 1. every member sends its [`Dealing`] to every member, and each member its share;
+//! 2. every member sends the IDs of the dealings it got and accuses the dealers that didn't send
+//!    it a share matching their dealing;
+//! 3. accused dealers answer every complaint with the complainer's share in the clear, which
+//!    every member checks against the dealing.
+//!
+//! Dealers that sent different dealings to different members, or didn't answer a complaint with a
+//! valid share, are disqualified. Members agree on who is as long as they got the same complaints
+//! and answers, so a round ends once every member was heard from, or when the caller gives up
+//! waiting, see [`Dkg::timeout`].
+
+use crate::error::OverlayError;
+use crate::message::DkgMessage;
+use crate::shamir::{
+    decode_point, encode_point, random_scalar, split, to_scalar, Dealing, DealtShare, Share,
+};
+use k256::{ProjectivePoint, Scalar};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A message for the given members.
+pub type Outgoing = (Vec<Vec<u8>>, DkgMessage);
+
+/// Where a member is at in a session.
+enum Phase {
+    Dealing,
+    Complaining,
+    Justifying,
+    Done(Result<DealtShare, &'static str>),
+}
+
+/// What a member sent in the second round.
+struct Complaints {
+    dealings: BTreeMap<u16, [u8; 32]>,
+    accused: BTreeSet<u16>,
+}
+
+/// A member's side of a key generation session.
+pub struct Dkg {
+    session: [u8; 32],
+    threshold: u16,
+    members: Vec<Vec<u8>>,
+    index: u16,
+    phase: Phase,
+    /// Our dealing, and every member's share of it.
+    dealt: (Dealing, Vec<Share>),
+    /// What we got from each dealer, by dealer index.
+    dealings: BTreeMap<u16, Dealing>,
+    shares: BTreeMap<u16, Share>,
+    /// By complainer index.
+    complaints: BTreeMap<u16, Complaints>,
+    /// By dealer and complainer index.
+    justifications: BTreeMap<(u16, u16), (Dealing, Share)>,
+    disqualified: BTreeSet<u16>,
+}
+
+impl Dkg {
+    /// Joins `session` as `member`, returns the first round's messages.
+    pub fn start(
+        session: [u8; 32],
+        threshold: u16,
+        mut members: Vec<Vec<u8>>,
+        member: &[u8],
+    ) -> Result<(Self, Vec<Outgoing>), OverlayError> {
+        members.sort();
+        members.dedup();
+        let index = members
+            .iter()
+            .position(|other| other == member)
+            .ok_or(OverlayError::InvalidDkg("not a member"))? as u16
+            + 1;
+        let (dealing, shares) = split(
+            &random_scalar().to_bytes().into(),
+            0,
+            threshold,
+            members.clone(),
+        )?;
+
+        let mut dkg = Self {
+            session,
+            threshold,
+            members,
+            index,
+            phase: Phase::Dealing,
+            dealt: (dealing.clone(), shares.clone()),
+            dealings: BTreeMap::from([(index, dealing.clone())]),
+            shares: BTreeMap::from([(index, shares[index as usize - 1].clone())]),
+            complaints: BTreeMap::new(),
+            justifications: BTreeMap::new(),
+            disqualified: BTreeSet::new(),
+        };
+        let mut outgoing = vec![(dkg.others(), DkgMessage::Dealing { session, dealing })];
+        for share in shares.into_iter().filter(|share| share.index != index) {
+            let member = dkg.members[share.index as usize - 1].clone();
+            outgoing.push((vec![member], DkgMessage::Share { session, share }));
+        }
+        // NB: nobody else to hear from in a session of one.
+        outgoing.extend(dkg.advance());
+
+        Ok((dkg, outgoing))
+    }
+
+    pub fn session(&self) -> [u8; 32] {
+        self.session
+    }
+
+    pub fn members(&self) -> &[Vec<u8>] {
+        &self.members
+    }
+
+    /// Rounds we're done with, the session is over after the third.
+    pub fn round(&self) -> u8 {
+        match self.phase {
+            Phase::Dealing => 0,
+            Phase::Complaining => 1,
+            Phase::Justifying => 2,
+            Phase::Done(_) => 3,
+        }
+    }
+
+    /// Our share of the key once the session is over, or why it failed.
+    pub fn outcome(&self) -> Option<Result<&DealtShare, OverlayError>> {
+        match &self.phase {
+            Phase::Done(Ok(key)) => Some(Ok(key)),
+            Phase::Done(Err(e)) => Some(Err(OverlayError::InvalidDkg(e))),
+            _ => None,
+        }
+    }
+
+    /// Members disqualified from the key, once the session is over.
+    pub fn disqualified(&self) -> Vec<Vec<u8>> {
+        self.disqualified
+            .iter()
+            .map(|index| self.members[*index as usize - 1].clone())
+            .collect()
+    }
+
+    /// Handles a message of `from`, returns what it leads us to send. Errors on messages no
+    /// honest member sends.
+    pub fn handle(
+        &mut self,
+        from: &[u8],
+        message: DkgMessage,
+    ) -> Result<Vec<Outgoing>, OverlayError> {
+        let from = self
+            .members
+            .iter()
+            .position(|member| member == from)
+            .ok_or(OverlayError::InvalidDkg("not a member"))? as u16
+            + 1;
+        match message {
+            DkgMessage::Start { .. } => {
+                return Err(OverlayError::InvalidDkg("session already started"))
+            }
+            DkgMessage::Dealing { session, dealing } => {
+                self.check_session(session)?;
+                if dealing.threshold != self.threshold
+                    || dealing.members != self.members
+                    || dealing.commitments.len() != self.threshold as usize
+                {
+                    return Err(OverlayError::InvalidDkg("dealing of another session"));
+                }
+                if self
+                    .dealings
+                    .get(&from)
+                    .is_some_and(|other| *other != dealing)
+                {
+                    return Err(OverlayError::InvalidDkg("dealt twice"));
+                }
+                if matches!(self.phase, Phase::Dealing) {
+                    self.dealings.insert(from, dealing);
+                }
+            }
+            DkgMessage::Share { session, share } => {
+                self.check_session(session)?;
+                if share.index != self.index {
+                    return Err(OverlayError::InvalidDkg("share of another member"));
+                }
+                if matches!(self.phase, Phase::Dealing) {
+                    self.shares.entry(from).or_insert(share);
+                }
+            }
+            DkgMessage::Complaints {
+                session,
+                dealings,
+                accused,
+            } => {
+                self.check_session(session)?;
+                if accused.iter().any(|dealer| !self.is_member(*dealer)) {
+                    return Err(OverlayError::InvalidDkg("accused a non member"));
+                }
+                if matches!(self.phase, Phase::Dealing | Phase::Complaining) {
+                    self.complaints.entry(from).or_insert(Complaints {
+                        dealings: dealings.into_iter().collect(),
+                        accused: accused.into_iter().collect(),
+                    });
+                }
+            }
+            DkgMessage::Justification {
+                session,
+                complainer,
+                dealing,
+                share,
+            } => {
+                self.check_session(session)?;
+                if share.index != complainer || !self.is_member(complainer) {
+                    return Err(OverlayError::InvalidDkg("justified with another share"));
+                }
+                if !matches!(self.phase, Phase::Done(_)) {
+                    self.justifications
+                        .entry((from, complainer))
+                        .or_insert((dealing, share));
+                }
+            }
+        }
+
+        Ok(self.advance())
+    }
+
+    /// Gives up waiting on the members we didn't hear from in the current round, returns what it
+    /// leads us to send.
+    pub fn timeout(&mut self) -> Vec<Outgoing> {
+        match self.phase {
+            Phase::Dealing => self.complain(),
+            Phase::Complaining => self.justify(),
+            Phase::Justifying => {
+                self.finish();
+                Vec::new()
+            }
+            Phase::Done(_) => Vec::new(),
+        }
+    }
+
+    /// Moves on to the next rounds while every member was heard from.
+    fn advance(&mut self) -> Vec<Outgoing> {
+        let mut outgoing = Vec::new();
+        loop {
+            let next = match self.phase {
+                Phase::Dealing
+                    if self.dealings.len() == self.members.len()
+                        && self.shares.len() == self.members.len() =>
+                {
+                    self.complain()
+                }
+                Phase::Complaining if self.complaints.len() == self.members.len() => self.justify(),
+                Phase::Justifying if self.unanswered().is_empty() => {
+                    self.finish();
+                    Vec::new()
+                }
+                _ => return outgoing,
+            };
+            outgoing.extend(next);
+        }
+    }
+
+    fn complain(&mut self) -> Vec<Outgoing> {
+        self.phase = Phase::Complaining;
+        let accused: BTreeSet<u16> = (1..=self.members.len() as u16)
+            .filter(|dealer| {
+                let (Some(dealing), Some(share)) =
+                    (self.dealings.get(dealer), self.shares.get(dealer))
+                else {
+                    return true;
+                };
+                dealing.verify_share(share).is_err()
+            })
+            .collect();
+        let dealings: BTreeMap<u16, [u8; 32]> = self
+            .dealings
+            .iter()
+            .map(|(dealer, dealing)| (*dealer, dealing.id()))
+            .collect();
+        let message = DkgMessage::Complaints {
+            session: self.session,
+            dealings: dealings.clone().into_iter().collect(),
+            accused: accused.iter().copied().collect(),
+        };
+        self.complaints
+            .insert(self.index, Complaints { dealings, accused });
+
+        vec![(self.others(), message)]
+    }
+
+    fn justify(&mut self) -> Vec<Outgoing> {
+        self.phase = Phase::Justifying;
+        // NB: members not heard from don't complain, nor vouch for any dealing.
+        for dealer in 1..=self.members.len() as u16 {
+            let ids: BTreeSet<[u8; 32]> = self
+                .complaints
+                .values()
+                .filter_map(|complaints| complaints.dealings.get(&dealer).copied())
+                .collect();
+            if ids.len() > 1 {
+                self.disqualified.insert(dealer);
+            }
+        }
+
+        let (dealing, shares) = &self.dealt;
+        let mut outgoing = Vec::new();
+        for (complainer, complaints) in &self.complaints {
+            if !complaints.accused.contains(&self.index) {
+                continue;
+            }
+            let share = shares[*complainer as usize - 1].clone();
+            self.justifications
+                .insert((self.index, *complainer), (dealing.clone(), share.clone()));
+            outgoing.push((
+                self.others(),
+                DkgMessage::Justification {
+                    session: self.session,
+                    complainer: *complainer,
+                    dealing: dealing.clone(),
+                    share,
+                },
+            ));
+        }
+
+        outgoing
+    }
+
+    /// Complaints against dealers that aren't disqualified yet, and weren't answered.
+    fn unanswered(&self) -> Vec<(u16, u16)> {
+        self.complaints
+            .iter()
+            .flat_map(|(complainer, complaints)| {
+                complaints
+                    .accused
+                    .iter()
+                    .map(move |dealer| (*dealer, *complainer))
+            })
+            .filter(|(dealer, _)| !self.disqualified.contains(dealer))
+            .filter(|complaint| !self.justifications.contains_key(complaint))
+            .collect()
+    }
+
+    fn finish(&mut self) {
+        for (dealer, _) in self.unanswered() {
+            self.disqualified.insert(dealer);
+        }
+        for dealer in 1..=self.members.len() as u16 {
+            if !self.answers_complaints(dealer) {
+                self.disqualified.insert(dealer);
+            }
+        }
+        // NB: answers to our complaints replace what we got from the dealer.
+        for ((dealer, complainer), (dealing, share)) in &self.justifications {
+            if *complainer == self.index && !self.disqualified.contains(dealer) {
+                self.dealings.insert(*dealer, dealing.clone());
+                self.shares.insert(*dealer, share.clone());
+            }
+        }
+
+        let qualified: Vec<u16> = (1..=self.members.len() as u16)
+            .filter(|dealer| !self.disqualified.contains(dealer))
+            .collect();
+        self.phase = Phase::Done(self.combine(&qualified));
+    }
+
+    /// Whether `dealer` answered every complaint against it with a share of the dealing members
+    /// vouched for.
+    fn answers_complaints(&self, dealer: u16) -> bool {
+        let vouched: Option<[u8; 32]> = self
+            .complaints
+            .values()
+            .find_map(|complaints| complaints.dealings.get(&dealer).copied());
+        if vouched.is_none() && !self.dealings.contains_key(&dealer) {
+            // NB: a dealing nobody got is only known from the answers to the complaints.
+            if !self
+                .justifications
+                .keys()
+                .any(|(other, _)| *other == dealer)
+            {
+                return false;
+            }
+        }
+
+        let mut ids = BTreeSet::from_iter(vouched);
+        for ((other, _), (dealing, share)) in &self.justifications {
+            if *other != dealer {
+                continue;
+            }
+            if dealing.threshold != self.threshold
+                || dealing.members != self.members
+                || dealing.verify_share(share).is_err()
+            {
+                return false;
+            }
+            ids.insert(dealing.id());
+        }
+        ids.len() <= 1
+    }
+
+    /// Sums the dealings and our shares of the `qualified` dealers.
+    fn combine(&self, qualified: &[u16]) -> Result<DealtShare, &'static str> {
+        if qualified.len() < self.threshold as usize {
+            return Err("fewer dealers qualified than the threshold");
+        }
+        if !qualified.contains(&self.index) {
+            return Err("we were disqualified");
+        }
+
+        let mut commitments = vec![ProjectivePoint::IDENTITY; self.threshold as usize];
+        let mut value = Scalar::ZERO;
+        for dealer in qualified {
+            let (Some(dealing), Some(share)) = (self.dealings.get(dealer), self.shares.get(dealer))
+            else {
+                return Err("missing the dealing of a qualified dealer");
+            };
+            for (sum, commitment) in commitments.iter_mut().zip(&dealing.commitments) {
+                *sum += decode_point(commitment).map_err(|_| "malformed commitment")?;
+            }
+            value += to_scalar(&share.value).map_err(|_| "malformed share")?;
+        }
+
+        Ok(DealtShare {
+            dealing: Dealing {
+                generation: 1,
+                threshold: self.threshold,
+                members: self.members.clone(),
+                commitments: commitments.into_iter().map(encode_point).collect(),
+            },
+            share: Share {
+                index: self.index,
+                value: value.to_bytes().into(),
+            },
+        })
+    }
+
+    fn check_session(&self, session: [u8; 32]) -> Result<(), OverlayError> {
+        if session != self.session {
+            return Err(OverlayError::InvalidDkg("message of another session"));
+        }
+        Ok(())
+    }
+
+    fn is_member(&self, index: u16) -> bool {
+        index >= 1 && index as usize <= self.members.len()
+    }
+
+    fn others(&self) -> Vec<Vec<u8>> {
+        self.members
+            .iter()
+            .enumerate()
+            .filter(|(position, _)| *position as u16 + 1 != self.index)
+            .map(|(_, member)| member.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frost;
+    use crate::shamir::reconstruct;
+    use std::collections::VecDeque;
+
+    /// Delivers messages between members in order, `tamper` sees each one first and may drop it.
+    struct Network {
+        members: BTreeMap<Vec<u8>, Dkg>,
+        queue: VecDeque<(Vec<u8>, Vec<u8>, DkgMessage)>,
+        misbehaviour: Vec<Vec<u8>>,
+    }
+
+    impl Network {
+        fn start(count: u8, threshold: u16) -> Self {
+            let members: Vec<Vec<u8>> = (1..=count).map(|member| vec![member]).collect();
+            let mut network = Self {
+                members: BTreeMap::new(),
+                queue: VecDeque::new(),
+                misbehaviour: Vec::new(),
+            };
+            for member in &members {
+                let (dkg, outgoing) =
+                    Dkg::start([7; 32], threshold, members.clone(), member).unwrap();
+                network.members.insert(member.clone(), dkg);
+                network.send(member, outgoing);
+            }
+            network
+        }
+
+        fn send(&mut self, from: &[u8], outgoing: Vec<Outgoing>) {
+            for (to, message) in outgoing {
+                for member in to {
+                    self.queue
+                        .push_back((from.to_vec(), member, message.clone()));
+                }
+            }
+        }
+
+        /// Runs the session to the end. Whenever messages run out, the round of the members
+        /// furthest behind times out.
+        fn run(&mut self, tamper: impl Fn(&[u8], &[u8], &mut DkgMessage) -> bool) {
+            while self.members.values().any(|dkg| dkg.outcome().is_none()) {
+                while let Some((from, to, mut message)) = self.queue.pop_front() {
+                    if !tamper(&from, &to, &mut message) {
+                        continue;
+                    }
+                    match self.members.get_mut(&to).unwrap().handle(&from, message) {
+                        Ok(outgoing) => self.send(&to, outgoing),
+                        Err(_) => self.misbehaviour.push(from),
+                    }
+                }
+                let behind = self.members.values().map(Dkg::round).min().unwrap();
+                let members: Vec<Vec<u8>> = self
+                    .members
+                    .iter()
+                    .filter(|(_, dkg)| dkg.round() == behind)
+                    .map(|(member, _)| member.clone())
+                    .collect();
+                for member in members {
+                    let outgoing = self.members.get_mut(&member).unwrap().timeout();
+                    self.send(&member, outgoing);
+                }
+            }
+        }
+
+        fn keys(&self) -> Vec<DealtShare> {
+            self.members
+                .values()
+                .filter_map(|dkg| dkg.outcome()?.ok().cloned())
+                .collect()
+        }
+    }
+
+    #[test]
+    fn generates_a_key_without_a_dealer() {
+        let mut network = Network::start(4, 3);
+        network.run(|_, _, _| true);
+
+        let keys = network.keys();
+        assert_eq!(keys.len(), 4);
+        assert!(keys.iter().all(|key| key.dealing == keys[0].dealing));
+        for key in &keys {
+            keys[0].dealing.verify_share(&key.share).unwrap();
+        }
+        assert!(network.misbehaviour.is_empty());
+
+        // Any threshold of the members signs under the key.
+        let signers = [&keys[3], &keys[0], &keys[2]];
+        let nonces: Vec<_> = signers
+            .iter()
+            .map(|key| frost::commit(key.share.index))
+            .collect();
+        let mut commitments: Vec<_> = nonces
+            .iter()
+            .map(|nonces| nonces.commitment().clone())
+            .collect();
+        commitments.sort_by_key(|commitment| commitment.index);
+        let shares: Vec<_> = signers
+            .iter()
+            .zip(nonces)
+            .map(|(key, nonces)| frost::sign(key, nonces, &[1; 32], &commitments).unwrap())
+            .collect();
+        let signature =
+            frost::aggregate(&keys[0].dealing, &[1; 32], &commitments, &shares).unwrap();
+        let group_pubkey = frost::group_pubkey(&keys[0].dealing).unwrap();
+        signature.verify(&group_pubkey, &[1; 32]).unwrap();
+        let shares: Vec<Share> = keys.iter().map(|key| key.share.clone()).collect();
+        let secret = reconstruct(&keys[0].dealing, &shares[1..]).unwrap();
+        assert!(keys[0].dealing.shares_secret(&secret).unwrap());
+    }
+
+    #[test]
+    fn disqualifies_misbehaving_dealers() {
+        let mut network = Network::start(5, 3);
+        network.run(|from, to, message| match (from[0], to[0], message) {
+            // A share corrupted on the way, its dealer answers the complaint.
+            (2, 1, DkgMessage::Share { share, .. }) => {
+                share.value = [1; 32];
+                true
+            }
+            // A dealer sending a bad share, and not answering the complaint.
+            (3, 4, DkgMessage::Share { share, .. }) => {
+                share.value = [1; 32];
+                true
+            }
+            (3, _, DkgMessage::Justification { .. }) => false,
+            // A dealer sending other members another dealing.
+            (4, 1, DkgMessage::Dealing { dealing, .. }) => {
+                dealing.commitments.reverse();
+                true
+            }
+            // A member that goes silent after dealing.
+            (5, _, DkgMessage::Complaints { .. } | DkgMessage::Justification { .. }) => false,
+            _ => true,
+        });
+
+        // NB: the dealer that didn't answer thinks it did, only the others agree on the key.
+        let honest = [vec![1], vec![2], vec![5]];
+        let keys: Vec<DealtShare> = honest
+            .iter()
+            .map(|member| {
+                let dkg = &network.members[member];
+                assert_eq!(dkg.disqualified(), [vec![3], vec![4]]);
+                dkg.outcome().unwrap().unwrap().clone()
+            })
+            .collect();
+        assert!(keys.iter().all(|key| key.dealing == keys[0].dealing));
+        assert!(network.members[&vec![4]].outcome().unwrap().is_err());
+        let shares: Vec<Share> = keys.iter().map(|key| key.share.clone()).collect();
+        let secret = reconstruct(&keys[0].dealing, &shares).unwrap();
+        assert!(keys[0].dealing.shares_secret(&secret).unwrap());
+    }
+}
@@ -0,0 +1,676 @@
+//! Raft-style election of a cluster leader.
+//!
+//! Members are the node and its attested peers. Time is split in terms, each with at most one
+//! leader: a member that doesn't hear from a leader for an election timeout (randomized, so that
+//! members rarely run at once) starts a new term and asks the others for their vote, and becomes
+//! the leader once a majority of members voted for it. Members vote once per term, for the first
+//! candidate that asks. The leader then sends heartbeats, which keep the others from running.
+//!
+//! The majority is counted out of every member we know of, whether it's still connected or not,
+//! and at least the configured [`ElectionConfig::cluster_size`]: a partition cuts each side off
+//! from the other, and counting only connected members would let both sides elect a leader.
+//!
+//! Three additions from the Raft thesis keep a partition from leaving the cluster with two leaders
+//! for long, or from disrupting it once it heals: a leader that didn't hear back from a majority
+//! within an election timeout steps down, members that heard from a leader within an election
+//! timeout don't vote for anyone else, and members only start a term once a majority would vote
+//! for them (pre-vote), so that a minority doesn't run up terms the others would have to adopt.
+//!
+//! [`Election`] only keeps state, the caller delivers messages and calls [`Election::tick`] by
+//! [`Election::deadline`]. Applications follow the leader through a [`Leadership`].
+
+use crate::message::ElectionMessage;
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct ElectionConfig {
+    /// How often the leader sends heartbeats.
+    pub heartbeat_ms: u64,
+    /// How long members wait on the leader before running, at least. Randomized up to twice
+    /// that.
+    pub election_timeout_ms: u64,
+    /// Members of the cluster, us included, the majority is counted out of at least that many.
+    /// Nodes default to themselves and the peers they're configured with.
+    pub cluster_size: Option<usize>,
+}
+
+impl Default for ElectionConfig {
+    fn default() -> Self {
+        Self {
+            heartbeat_ms: 500,
+            election_timeout_ms: 2_000,
+            cluster_size: None,
+        }
+    }
+}
+
+/// A message to send, to the given member or to every member.
+pub type Outgoing = (Option<Vec<u8>>, ElectionMessage);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+/// Who leads the cluster, as far as a member knows.
+#[derive(Debug, Clone)]
+pub struct Leadership {
+    me: Vec<u8>,
+    leader: watch::Receiver<Option<Vec<u8>>>,
+}
+
+impl Leadership {
+    /// Node pubkey of the current leader, if any.
+    pub fn current_leader(&self) -> Option<Vec<u8>> {
+        self.leader.borrow().clone()
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.leader.borrow().as_ref() == Some(&self.me)
+    }
+
+    /// Waits for the leader to change, returns the new one. Errors once the election stopped.
+    pub async fn changed(&mut self) -> Result<Option<Vec<u8>>, watch::error::RecvError> {
+        self.leader.changed().await?;
+        Ok(self.current_leader())
+    }
+}
+
+/// A member's side of the election.
+pub struct Election {
+    me: Vec<u8>,
+    config: ElectionConfig,
+    term: u64,
+    role: Role,
+    voted_for: Option<Vec<u8>>,
+    votes: BTreeSet<Vec<u8>>,
+    /// Pre-votes for the next term, while polling for one.
+    pre_votes: Option<BTreeSet<Vec<u8>>>,
+    /// Other members we're connected to, with when we last heard back from them while leading.
+    members: BTreeMap<Vec<u8>, Option<Instant>>,
+    /// Every other member that joined since we started, connected or not.
+    known: BTreeSet<Vec<u8>>,
+    leader: watch::Sender<Option<Vec<u8>>>,
+    /// When we last heard from the leader, or won.
+    heard_from_leader: Option<Instant>,
+    deadline: Instant,
+}
+
+impl Election {
+    pub fn new(me: Vec<u8>, config: ElectionConfig, now: Instant) -> Self {
+        let (leader, _) = watch::channel(None);
+        let mut election = Self {
+            me,
+            config,
+            term: 0,
+            role: Role::Follower,
+            voted_for: None,
+            votes: BTreeSet::new(),
+            pre_votes: None,
+            members: BTreeMap::new(),
+            known: BTreeSet::new(),
+            leader,
+            heard_from_leader: None,
+            deadline: now,
+        };
+        election.deadline = election.election_deadline(now);
+        election
+    }
+
+    pub fn leadership(&self) -> Leadership {
+        Leadership {
+            me: self.me.clone(),
+            leader: self.leader.subscribe(),
+        }
+    }
+
+    pub fn current_leader(&self) -> Option<Vec<u8>> {
+        self.leader.borrow().clone()
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.role == Role::Leader
+    }
+
+    pub fn term(&self) -> u64 {
+        self.term
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    /// When [`Election::tick`] is due.
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    pub fn member_joined(&mut self, member: Vec<u8>) {
+        if member != self.me {
+            self.known.insert(member.clone());
+            self.members.entry(member).or_insert(None);
+        }
+    }
+
+    /// Stops counting on `member`'s votes and acks, it still counts towards the majority.
+    pub fn member_left(&mut self, member: &[u8]) {
+        self.members.remove(member);
+        self.votes.remove(member);
+        if let Some(pre_votes) = &mut self.pre_votes {
+            pre_votes.remove(member);
+        }
+    }
+
+    /// Runs for leader when the leader went quiet, sends heartbeats when leading.
+    pub fn tick(&mut self, now: Instant) -> Vec<Outgoing> {
+        if now < self.deadline {
+            return Vec::new();
+        }
+
+        match self.role {
+            Role::Leader => {
+                // NB: members that joined since get an election timeout to answer.
+                for heard in self.members.values_mut() {
+                    heard.get_or_insert(now);
+                }
+                let heard_back = self
+                    .members
+                    .values()
+                    .filter(|heard| heard.is_some_and(|heard| heard + self.timeout() > now))
+                    .count();
+                // NB: we'd rather have no leader than one on the wrong side of a partition.
+                if heard_back + 1 < self.majority() {
+                    tracing::info!(
+                        "lost touch with a majority, stepping down in term {}",
+                        self.term
+                    );
+                    self.become_follower(now, None);
+                    return Vec::new();
+                }
+                self.deadline = now + Duration::from_millis(self.config.heartbeat_ms);
+                vec![(None, ElectionMessage::Heartbeat { term: self.term })]
+            }
+            Role::Follower | Role::Candidate => {
+                self.role = Role::Follower;
+                self.pre_votes = Some(BTreeSet::from([self.me.clone()]));
+                self.set_leader(None);
+                self.heard_from_leader = None;
+                self.deadline = self.election_deadline(now);
+                if self.majority() <= 1 {
+                    return self.run(now);
+                }
+                vec![(
+                    None,
+                    ElectionMessage::RequestVote {
+                        term: self.term + 1,
+                        pre: true,
+                    },
+                )]
+            }
+        }
+    }
+
+    /// Handles a message of `from`, returns what it leads us to send.
+    pub fn handle(&mut self, from: &[u8], message: ElectionMessage, now: Instant) -> Vec<Outgoing> {
+        // NB: peers that aren't attested (anymore) don't take part.
+        if !self.members.contains_key(from) {
+            return Vec::new();
+        }
+        match message {
+            ElectionMessage::RequestVote { term, pre: true } => {
+                // NB: polls don't touch our term, only actual votes do.
+                let granted = term > self.term && !self.leader_is_alive(now);
+                return vec![(
+                    Some(from.to_vec()),
+                    ElectionMessage::Vote {
+                        term,
+                        granted,
+                        pre: true,
+                    },
+                )];
+            }
+            ElectionMessage::Vote {
+                term,
+                granted,
+                pre: true,
+            } => {
+                let Some(pre_votes) = &mut self.pre_votes else {
+                    return Vec::new();
+                };
+                if term != self.term + 1 || !granted {
+                    return Vec::new();
+                }
+                pre_votes.insert(from.to_vec());
+                if pre_votes.len() >= self.majority() {
+                    return self.run(now);
+                }
+                return Vec::new();
+            }
+            // NB: a member cut off from the leader doesn't get to depose it once back.
+            ElectionMessage::RequestVote { .. } if self.leader_is_alive(now) => {
+                return vec![(
+                    Some(from.to_vec()),
+                    ElectionMessage::Vote {
+                        term: self.term,
+                        granted: false,
+                        pre: false,
+                    },
+                )];
+            }
+            _ => {}
+        }
+
+        let term = message.term();
+        if term > self.term {
+            self.term = term;
+            self.voted_for = None;
+            self.become_follower(now, None);
+        }
+
+        match message {
+            ElectionMessage::RequestVote { term, .. } => {
+                let granted = term == self.term
+                    && self.voted_for.as_deref().is_none_or(|voted| voted == from);
+                if granted {
+                    self.voted_for = Some(from.to_vec());
+                    self.deadline = self.election_deadline(now);
+                }
+                vec![(
+                    Some(from.to_vec()),
+                    ElectionMessage::Vote {
+                        term: self.term,
+                        granted,
+                        pre: false,
+                    },
+                )]
+            }
+            ElectionMessage::Vote { term, granted, .. } => {
+                if self.role != Role::Candidate || term != self.term || !granted {
+                    return Vec::new();
+                }
+                self.votes.insert(from.to_vec());
+                if self.votes.len() >= self.majority() {
+                    return self.become_leader(now);
+                }
+                Vec::new()
+            }
+            ElectionMessage::Heartbeat { term } => {
+                if term < self.term {
+                    // NB: tells a stale leader it was deposed.
+                    return vec![(
+                        Some(from.to_vec()),
+                        ElectionMessage::HeartbeatAck { term: self.term },
+                    )];
+                }
+                if self.role != Role::Follower || self.current_leader().as_deref() != Some(from) {
+                    self.become_follower(now, Some(from.to_vec()));
+                }
+                self.heard_from_leader = Some(now);
+                self.deadline = self.election_deadline(now);
+                vec![(Some(from.to_vec()), ElectionMessage::HeartbeatAck { term })]
+            }
+            ElectionMessage::HeartbeatAck { term } => {
+                if self.role == Role::Leader && term == self.term {
+                    if let Some(heard) = self.members.get_mut(from) {
+                        *heard = Some(now);
+                    }
+                }
+                Vec::new()
+            }
+        }
+    }
+
+    /// Starts a term, running for leader.
+    fn run(&mut self, now: Instant) -> Vec<Outgoing> {
+        self.term += 1;
+        self.role = Role::Candidate;
+        self.pre_votes = None;
+        self.voted_for = Some(self.me.clone());
+        self.votes = BTreeSet::from([self.me.clone()]);
+        self.deadline = self.election_deadline(now);
+        tracing::debug!("running for leader in term {}", self.term);
+        if self.votes.len() >= self.majority() {
+            return self.become_leader(now);
+        }
+        vec![(
+            None,
+            ElectionMessage::RequestVote {
+                term: self.term,
+                pre: false,
+            },
+        )]
+    }
+
+    fn become_leader(&mut self, now: Instant) -> Vec<Outgoing> {
+        self.role = Role::Leader;
+        // NB: members get an election timeout to hear from us before we count them out.
+        for heard in self.members.values_mut() {
+            *heard = Some(now);
+        }
+        self.heard_from_leader = Some(now);
+        self.set_leader(Some(self.me.clone()));
+        self.deadline = now + Duration::from_millis(self.config.heartbeat_ms);
+        vec![(None, ElectionMessage::Heartbeat { term: self.term })]
+    }
+
+    fn become_follower(&mut self, now: Instant, leader: Option<Vec<u8>>) {
+        self.role = Role::Follower;
+        self.votes.clear();
+        self.pre_votes = None;
+        if leader.is_none() {
+            self.heard_from_leader = None;
+        }
+        self.set_leader(leader);
+        self.deadline = self.election_deadline(now);
+    }
+
+    fn set_leader(&mut self, leader: Option<Vec<u8>>) {
+        self.leader.send_if_modified(|current| {
+            let changed = *current != leader;
+            if changed {
+                match &leader {
+                    Some(leader) => {
+                        tracing::info!("{} leads term {}", hex::encode(leader), self.term)
+                    }
+                    None => tracing::info!("no leader in term {}", self.term),
+                }
+            }
+            *current = leader;
+            changed
+        });
+    }
+
+    fn leader_is_alive(&self, now: Instant) -> bool {
+        self.role == Role::Leader
+            || self.current_leader().is_some()
+                && self
+                    .heard_from_leader
+                    .is_some_and(|heard| heard + self.timeout() > now)
+    }
+
+    fn majority(&self) -> usize {
+        // NB: of the other members and us, including those we lost touch with.
+        let cluster = (self.known.len() + 1).max(self.config.cluster_size.unwrap_or(0));
+        cluster / 2 + 1
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_millis(self.config.election_timeout_ms)
+    }
+
+    fn election_deadline(&self, now: Instant) -> Instant {
+        let jitter = rand::random::<u64>() % self.config.election_timeout_ms.max(1);
+        now + self.timeout() + Duration::from_millis(jitter)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Members delivering each other's messages in order, except across `partition`.
+    struct Cluster {
+        members: BTreeMap<Vec<u8>, Election>,
+        partition: BTreeSet<Vec<u8>>,
+        now: Instant,
+    }
+
+    impl Cluster {
+        fn new(count: u8) -> Self {
+            let now = Instant::now();
+            let pubkeys: Vec<Vec<u8>> = (1..=count).map(|member| vec![member]).collect();
+            let mut members = BTreeMap::new();
+            for pubkey in &pubkeys {
+                let mut election = Election::new(pubkey.clone(), ElectionConfig::default(), now);
+                for other in &pubkeys {
+                    election.member_joined(other.clone());
+                }
+                members.insert(pubkey.clone(), election);
+            }
+            Self {
+                members,
+                partition: BTreeSet::new(),
+                now,
+            }
+        }
+
+        /// Cuts `minority` off from the others, who see each other disconnect.
+        fn split(&mut self, minority: &[Vec<u8>]) {
+            self.partition = minority.iter().cloned().collect();
+            let members: Vec<Vec<u8>> = self.members.keys().cloned().collect();
+            for member in &members {
+                for other in &members {
+                    if !self.connected(member, other) {
+                        self.members.get_mut(member).unwrap().member_left(other);
+                    }
+                }
+            }
+        }
+
+        fn heal(&mut self) {
+            self.partition.clear();
+            let members: Vec<Vec<u8>> = self.members.keys().cloned().collect();
+            for election in self.members.values_mut() {
+                for other in &members {
+                    election.member_joined(other.clone());
+                }
+            }
+        }
+
+        fn connected(&self, from: &[u8], to: &[u8]) -> bool {
+            self.partition.contains(from) == self.partition.contains(to)
+        }
+
+        fn deliver(&mut self, from: &[u8], outgoing: Vec<Outgoing>) {
+            let members: Vec<Vec<u8>> = self.members.keys().cloned().collect();
+            let mut queue: Vec<(Vec<u8>, Vec<u8>, ElectionMessage)> = Vec::new();
+            let push = |from: &[u8], outgoing: Vec<Outgoing>, queue: &mut Vec<_>| {
+                for (to, message) in outgoing {
+                    let targets = match to {
+                        Some(to) => vec![to],
+                        None => members
+                            .iter()
+                            .filter(|member| *member != from)
+                            .cloned()
+                            .collect(),
+                    };
+                    for to in targets {
+                        queue.push((from.to_vec(), to, message.clone()));
+                    }
+                }
+            };
+            push(from, outgoing, &mut queue);
+            while !queue.is_empty() {
+                let (from, to, message) = queue.remove(0);
+                if !self.connected(&from, &to) {
+                    continue;
+                }
+                let outgoing = self
+                    .members
+                    .get_mut(&to)
+                    .unwrap()
+                    .handle(&from, message, self.now);
+                push(&to, outgoing, &mut queue);
+            }
+        }
+
+        /// Moves time on by `millis`, ticking every member due in between.
+        fn advance(&mut self, millis: u64) {
+            let end = self.now + Duration::from_millis(millis);
+            loop {
+                let (member, deadline) = self
+                    .members
+                    .iter()
+                    .map(|(member, election)| (member.clone(), election.deadline()))
+                    .min_by_key(|(_, deadline)| *deadline)
+                    .unwrap();
+                if deadline > end {
+                    break;
+                }
+                self.now = self.now.max(deadline);
+                let outgoing = self.members.get_mut(&member).unwrap().tick(self.now);
+                self.deliver(&member, outgoing);
+            }
+            self.now = end;
+        }
+
+        fn leaders(&self) -> BTreeSet<Option<Vec<u8>>> {
+            self.members
+                .values()
+                .map(Election::current_leader)
+                .collect()
+        }
+    }
+
+    #[test]
+    fn elects_a_single_leader() {
+        let mut cluster = Cluster::new(5);
+        let mut leadership = cluster.members[&vec![1]].leadership();
+        cluster.advance(10_000);
+
+        let leaders = cluster.leaders();
+        assert_eq!(leaders.len(), 1);
+        let leader = leaders.into_iter().next().unwrap().unwrap();
+        assert_eq!(
+            cluster
+                .members
+                .values()
+                .filter(|election| election.role() == Role::Leader)
+                .count(),
+            1
+        );
+        assert_eq!(leadership.current_leader(), Some(leader.clone()));
+        assert_eq!(leadership.is_leader(), leader == [1]);
+        assert!(leadership.leader.has_changed().unwrap());
+        let _ = leadership.leader.borrow_and_update();
+
+        // Heartbeats keep it leading.
+        let term = cluster.members[&leader].term();
+        cluster.advance(10_000);
+        assert_eq!(cluster.members[&leader].term(), term);
+        assert!(!leadership.leader.has_changed().unwrap());
+    }
+
+    #[test]
+    fn steps_down_on_partition() {
+        let mut cluster = Cluster::new(5);
+        cluster.advance(10_000);
+        let old = cluster
+            .members
+            .values()
+            .find_map(Election::current_leader)
+            .unwrap();
+
+        // The leader ends up on the minority side, which can't elect anyone.
+        let minority: Vec<Vec<u8>> = std::iter::once(old.clone())
+            .chain(
+                cluster
+                    .members
+                    .keys()
+                    .filter(|member| **member != old)
+                    .take(1)
+                    .cloned(),
+            )
+            .collect();
+        cluster.partition = minority.iter().cloned().collect();
+        cluster.advance(10_000);
+        for member in &minority {
+            assert_eq!(cluster.members[member].current_leader(), None);
+        }
+        let majority_leaders: BTreeSet<_> = cluster
+            .members
+            .iter()
+            .filter(|(member, _)| !minority.contains(member))
+            .map(|(_, election)| election.current_leader())
+            .collect();
+        assert_eq!(majority_leaders.len(), 1);
+        let new = majority_leaders.into_iter().next().unwrap().unwrap();
+        assert!(!minority.contains(&new));
+
+        // Once healed, the minority follows the new leader instead of deposing it.
+        cluster.partition.clear();
+        cluster.advance(10_000);
+        assert_eq!(cluster.leaders(), BTreeSet::from([Some(new)]));
+    }
+
+    /// Members on either side see those across the partition disconnect, which must not make
+    /// the minority a majority of its own.
+    #[test]
+    fn disconnects_dont_shrink_the_majority() {
+        let mut cluster = Cluster::new(5);
+        cluster.advance(10_000);
+        let old = cluster
+            .members
+            .values()
+            .find_map(Election::current_leader)
+            .unwrap();
+
+        let minority: Vec<Vec<u8>> = std::iter::once(old.clone())
+            .chain(
+                cluster
+                    .members
+                    .keys()
+                    .filter(|member| **member != old)
+                    .take(1)
+                    .cloned(),
+            )
+            .collect();
+        cluster.split(&minority);
+        cluster.advance(10_000);
+        let leading: Vec<&Vec<u8>> = cluster
+            .members
+            .iter()
+            .filter(|(_, election)| election.is_leader())
+            .map(|(member, _)| member)
+            .collect();
+        assert_eq!(leading.len(), 1);
+        assert!(!minority.contains(leading[0]));
+        for member in &minority {
+            assert_eq!(cluster.members[member].current_leader(), None);
+        }
+
+        // Nor does a node cut off from everyone elect itself.
+        let new = leading[0].clone();
+        cluster.heal();
+        cluster.advance(10_000);
+        cluster.split(std::slice::from_ref(&new));
+        cluster.advance(10_000);
+        assert_eq!(cluster.members[&new].current_leader(), None);
+        assert_eq!(
+            cluster
+                .members
+                .values()
+                .filter(|election| election.is_leader())
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn counts_configured_members() {
+        let now = Instant::now();
+        let config = ElectionConfig {
+            cluster_size: Some(3),
+            ..Default::default()
+        };
+        let mut election = Election::new(vec![1], config, now);
+        let outgoing = election.tick(election.deadline());
+        assert!(matches!(
+            outgoing[..],
+            [(None, ElectionMessage::RequestVote { pre: true, .. })]
+        ));
+        assert!(!election.is_leader());
+
+        // Without one, a lone node leads itself.
+        let mut election = Election::new(vec![1], ElectionConfig::default(), now);
+        election.tick(election.deadline());
+        assert!(election.is_leader());
+    }
+}
@@ -7,8 +7,35 @@ pub enum OverlayError {
 
     //#[error("Expected to onboard due to absent local view, but got full message with header")]
     //MalformedOnboard,
-    #[error("Got invalid quote {0}")]
-    InvaildQuote(crate::message::Quote),
+    #[error("Rejected peer quote: {0}")]
+    RejectedQuote(#[from] mocks::attestation::VerificationError),
+
+    #[error("Peer doesn't satisfy the attestation policy: {0}")]
+    PolicyViolation(#[from] mocks::attestation::PolicyViolation),
+
+    #[error("Peer didn't answer the re-attestation request in time")]
+    ReattestationTimeout,
+
+    #[error("Peer TCB level degraded from {0:?} to {1:?}")]
+    TcbDegraded(mocks::attestation::TcbStatus, mocks::attestation::TcbStatus),
+
+    #[error("Peer is in another cluster: {0}")]
+    ClusterConflict(String),
+
+    #[error("Invalid genesis record: {0}")]
+    InvalidGenesis(&'static str),
+
+    #[error("Invalid key link: {0}")]
+    InvalidKeyLink(&'static str),
+
+    #[error("Invalid secret sharing: {0}")]
+    InvalidSharing(&'static str),
+
+    #[error("Invalid key generation: {0}")]
+    InvalidDkg(&'static str),
+
+    #[error("Invalid threshold signature: {0}")]
+    InvalidSignature(&'static str),
 
     #[error("Invalid session key. Have {0}, got {1}")]
     InvalidSessionKey(i64, i64),
@@ -0,0 +1,311 @@
+//! FROST threshold Schnorr signatures over secp256k1.
+//!
+//! Signers hold shares of a key dealt along with Feldman commitments (see [`crate::shamir`]), the
+//! first of which is the group's public key, and any `threshold` of them sign together without
+//! the key ever being put back together. Signing takes two rounds driven by a coordinator:
+//! signers first [`commit`] to a pair of one-time nonces, then [`sign`] the message given every
+//! participant's commitments. The coordinator checks each signature share against the signer's
+//! public share, so a misbehaving signer is caught, and [`aggregate`]s them into a plain Schnorr
+//! signature that verifies under the group key.
+//!
+//! Follows RFC 9591, with our own domain separated SHA-256 challenges rather than its ciphersuite.
+
+use crate::error::OverlayError;
+use crate::shamir::{
+    decode_point, encode_point, lagrange, random_scalar, to_scalar, Dealing, DealtShare,
+};
+use k256::{elliptic_curve::ops::Reduce, ProjectivePoint, Scalar, U256};
+use mocks::attestation::hex_array;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+const BINDING_DOMAIN: &[u8] = b"tplus/frost/binding";
+const CHALLENGE_DOMAIN: &[u8] = b"tplus/frost/challenge";
+
+/// One-time nonces of a signer, kept until it signs and never reused.
+pub struct SigningNonces {
+    hiding: Scalar,
+    binding: Scalar,
+    commitment: SigningCommitment,
+}
+
+/// Commitments to a signer's nonces, sent to the coordinator in the first round.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SigningCommitment {
+    pub index: u16,
+    pub hiding: Vec<u8>,
+    pub binding: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignatureShare {
+    pub index: u16,
+    #[serde(with = "hex_array")]
+    pub value: [u8; 32],
+}
+
+/// Schnorr signature, `z * G == R + H(R || pubkey || message) * pubkey`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Signature {
+    /// Compressed group commitment.
+    #[serde(with = "hex_array")]
+    pub r: [u8; 33],
+    #[serde(with = "hex_array")]
+    pub z: [u8; 32],
+}
+
+impl Signature {
+    /// `r || z`.
+    pub fn to_bytes(&self) -> [u8; 65] {
+        let mut bytes = [0; 65];
+        bytes[..33].copy_from_slice(&self.r);
+        bytes[33..].copy_from_slice(&self.z);
+        bytes
+    }
+
+    pub fn verify(&self, pubkey: &[u8], message: &[u8; 32]) -> Result<(), OverlayError> {
+        let r = decode_point(&self.r)?;
+        let challenge = challenge(&r, pubkey, message);
+        if ProjectivePoint::GENERATOR * to_scalar(&self.z)? != r + decode_point(pubkey)? * challenge
+        {
+            return Err(OverlayError::InvalidSignature(
+                "doesn't verify under the key",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Public key of the group holding the shares of `dealing`.
+pub fn group_pubkey(dealing: &Dealing) -> Result<Vec<u8>, OverlayError> {
+    dealing
+        .commitments
+        .first()
+        .cloned()
+        .ok_or(OverlayError::InvalidSharing("no commitments"))
+}
+
+/// First round: fresh nonces for the signer at `index`, and the commitments to send.
+pub fn commit(index: u16) -> SigningNonces {
+    let (hiding, binding) = (random_scalar(), random_scalar());
+    SigningNonces {
+        hiding,
+        binding,
+        commitment: SigningCommitment {
+            index,
+            hiding: encode_point(ProjectivePoint::GENERATOR * hiding),
+            binding: encode_point(ProjectivePoint::GENERATOR * binding),
+        },
+    }
+}
+
+impl SigningNonces {
+    pub fn commitment(&self) -> &SigningCommitment {
+        &self.commitment
+    }
+}
+
+/// Second round: our share of the signature of `message`, given the commitments of every signer.
+/// Consumes the nonces, they must never sign twice.
+pub fn sign(
+    key: &DealtShare,
+    nonces: SigningNonces,
+    message: &[u8; 32],
+    commitments: &[SigningCommitment],
+) -> Result<SignatureShare, OverlayError> {
+    if !commitments.contains(&nonces.commitment) || nonces.commitment.index != key.share.index {
+        return Err(OverlayError::InvalidSignature("our commitment is missing"));
+    }
+    let group_pubkey = group_pubkey(&key.dealing)?;
+    let session = Session::new(&key.dealing, &group_pubkey, message, commitments)?;
+
+    let binding = session.binding_factor(key.share.index);
+    let value = nonces.hiding
+        + nonces.binding * binding
+        + session.lagrange(key.share.index)? * to_scalar(&key.share.value)? * session.challenge;
+
+    Ok(SignatureShare {
+        index: key.share.index,
+        value: value.to_bytes().into(),
+    })
+}
+
+/// Checks `share` against its signer's public share.
+pub fn verify_share(
+    dealing: &Dealing,
+    message: &[u8; 32],
+    commitments: &[SigningCommitment],
+    share: &SignatureShare,
+) -> Result<(), OverlayError> {
+    let group_pubkey = group_pubkey(dealing)?;
+    Session::new(dealing, &group_pubkey, message, commitments)?.verify_share(dealing, share)
+}
+
+/// Checks every share against its signer's public share and combines them.
+pub fn aggregate(
+    dealing: &Dealing,
+    message: &[u8; 32],
+    commitments: &[SigningCommitment],
+    shares: &[SignatureShare],
+) -> Result<Signature, OverlayError> {
+    let group_pubkey = group_pubkey(dealing)?;
+    let session = Session::new(dealing, &group_pubkey, message, commitments)?;
+    if shares.len() != commitments.len() {
+        return Err(OverlayError::InvalidSignature("missing signature shares"));
+    }
+
+    let mut z = Scalar::ZERO;
+    for share in shares {
+        session.verify_share(dealing, share)?;
+        z += to_scalar(&share.value)?;
+    }
+    let signature = Signature {
+        r: encode_point(session.group_commitment)
+            .try_into()
+            .map_err(|_| OverlayError::InvalidSignature("degenerate group commitment"))?,
+        z: z.to_bytes().into(),
+    };
+    signature.verify(&group_pubkey, message)?;
+
+    Ok(signature)
+}
+
+/// What every participant derives from the commitments of a signing session.
+struct Session<'a> {
+    group_pubkey: &'a [u8],
+    message: &'a [u8; 32],
+    commitments: &'a [SigningCommitment],
+    group_commitment: ProjectivePoint,
+    challenge: Scalar,
+}
+
+impl<'a> Session<'a> {
+    fn new(
+        dealing: &Dealing,
+        group_pubkey: &'a [u8],
+        message: &'a [u8; 32],
+        commitments: &'a [SigningCommitment],
+    ) -> Result<Self, OverlayError> {
+        if commitments.len() != dealing.threshold as usize {
+            return Err(OverlayError::InvalidSignature(
+                "expected a commitment per signer",
+            ));
+        }
+        // NB: sorted and distinct, so that everyone binds to the same list.
+        if commitments
+            .windows(2)
+            .any(|pair| pair[0].index >= pair[1].index)
+        {
+            return Err(OverlayError::InvalidSignature("commitments out of order"));
+        }
+
+        let mut session = Self {
+            group_pubkey,
+            message,
+            commitments,
+            group_commitment: ProjectivePoint::IDENTITY,
+            challenge: Scalar::ZERO,
+        };
+        for commitment in commitments {
+            session.group_commitment += decode_point(&commitment.hiding)?
+                + decode_point(&commitment.binding)? * session.binding_factor(commitment.index);
+        }
+        session.challenge = challenge(&session.group_commitment, group_pubkey, message);
+
+        Ok(session)
+    }
+
+    fn binding_factor(&self, index: u16) -> Scalar {
+        let mut hasher = Sha256::new();
+        hasher.update(BINDING_DOMAIN);
+        hasher.update(self.group_pubkey);
+        hasher.update(self.message);
+        for commitment in self.commitments {
+            hasher.update(commitment.index.to_le_bytes());
+            hasher.update(&commitment.hiding);
+            hasher.update(&commitment.binding);
+        }
+        hasher.update(index.to_le_bytes());
+        <Scalar as Reduce<U256>>::reduce_bytes(&hasher.finalize())
+    }
+
+    fn lagrange(&self, index: u16) -> Result<Scalar, OverlayError> {
+        let signers: Vec<u16> = self.commitments.iter().map(|c| c.index).collect();
+        lagrange(index, &signers)
+    }
+
+    fn verify_share(&self, dealing: &Dealing, share: &SignatureShare) -> Result<(), OverlayError> {
+        let commitment = self
+            .commitments
+            .iter()
+            .find(|commitment| commitment.index == share.index)
+            .ok_or(OverlayError::InvalidSignature(
+                "share of a signer without commitment",
+            ))?;
+        let expected = decode_point(&commitment.hiding)?
+            + decode_point(&commitment.binding)? * self.binding_factor(share.index)
+            + dealing.public_share(share.index)? * (self.lagrange(share.index)? * self.challenge);
+        if ProjectivePoint::GENERATOR * to_scalar(&share.value)? != expected {
+            return Err(OverlayError::InvalidSignature("share doesn't verify"));
+        }
+
+        Ok(())
+    }
+}
+
+fn challenge(group_commitment: &ProjectivePoint, pubkey: &[u8], message: &[u8; 32]) -> Scalar {
+    let mut hasher = Sha256::new();
+    hasher.update(CHALLENGE_DOMAIN);
+    hasher.update(encode_point(*group_commitment));
+    hasher.update(pubkey);
+    hasher.update(message);
+    <Scalar as Reduce<U256>>::reduce_bytes(&hasher.finalize())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::shamir::split;
+
+    #[test]
+    fn signs_with_a_threshold_of_shares() {
+        let members = (1..=5).map(|member| vec![member]).collect();
+        let (dealing, shares) = split(&[9; 32], 1, 3, members).unwrap();
+        let keys: Vec<DealtShare> = shares
+            .into_iter()
+            .map(|share| DealtShare {
+                dealing: dealing.clone(),
+                share,
+            })
+            .collect();
+        let message = [1; 32];
+
+        let signers = [&keys[4], &keys[1], &keys[2]];
+        let nonces: Vec<SigningNonces> =
+            signers.iter().map(|key| commit(key.share.index)).collect();
+        let mut commitments: Vec<SigningCommitment> = nonces
+            .iter()
+            .map(|nonces| nonces.commitment().clone())
+            .collect();
+        commitments.sort_by_key(|commitment| commitment.index);
+        let mut shares: Vec<SignatureShare> = signers
+            .iter()
+            .zip(nonces)
+            .map(|(key, nonces)| sign(key, nonces, &message, &commitments).unwrap())
+            .collect();
+
+        let signature = aggregate(&dealing, &message, &commitments, &shares).unwrap();
+        let group_pubkey = group_pubkey(&dealing).unwrap();
+        signature.verify(&group_pubkey, &message).unwrap();
+        assert!(signature.verify(&group_pubkey, &[2; 32]).is_err());
+
+        // A signer that didn't sign what the others did is caught.
+        shares[0].value = [3; 32];
+        assert!(aggregate(&dealing, &message, &commitments, &shares).is_err());
+        // Nonces only sign the session they committed to.
+        let nonces = commit(keys[0].share.index);
+        assert!(sign(&keys[0], nonces, &message, &commitments).is_err());
+        assert!(aggregate(&dealing, &message, &commitments[1..], &shares[1..]).is_err());
+    }
+}
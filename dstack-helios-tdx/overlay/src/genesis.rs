@@ -0,0 +1,134 @@
+//! Record of a cluster's creation.
+//!
+//! The bootstrap node creates it along with the shared secret: the cluster pubkey, the node's
+//! quote, a hash of the attestation policy it bootstrapped under and the creation time, signed
+//! under the cluster key. The quote commits to the rest of the record, so clients and peers given
+//! the record can check which build created the cluster they're talking to, and under which
+//! policy. Joining nodes get it along with the secret.
+
+use crate::error::OverlayError;
+use mocks::attestation::{hex_array, AttestationPolicy, AttestationProvider, Quote};
+use secp256k1::{ecdsa::Signature, Message, PublicKey, Secp256k1, SecretKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const GENESIS_DOMAIN: &[u8] = b"tplus/genesis";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Genesis {
+    /// Compressed public key of the cluster.
+    #[serde(with = "hex_array")]
+    pub cluster_pubkey: [u8; 33],
+    /// Quote of the bootstrap node, committing to [`Genesis::commitment`].
+    pub quote: Quote,
+    /// sha256 of the JSON encoded attestation policy of the bootstrap node.
+    #[serde(with = "hex_array")]
+    pub policy_hash: [u8; 32],
+    /// Unix time.
+    pub created_at: u64,
+    /// Compact ECDSA signature of the record under the cluster key.
+    #[serde(with = "hex_array")]
+    pub signature: [u8; 64],
+}
+
+impl Genesis {
+    /// Creates the record of the cluster signing under `signing_key`, with our quote.
+    pub async fn create(
+        signing_key: &SecretKey,
+        attestation: &dyn AttestationProvider,
+        policy: &AttestationPolicy,
+    ) -> anyhow::Result<Self> {
+        let secp = Secp256k1::new();
+        let mut genesis = Self {
+            cluster_pubkey: signing_key.public_key(&secp).serialize(),
+            quote: Quote::new(),
+            policy_hash: policy_hash(policy),
+            created_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            signature: [0; 64],
+        };
+        genesis.quote = attestation.get_quote(&genesis.commitment()).await?;
+        genesis.signature = secp
+            .sign_ecdsa(&genesis.digest(), signing_key)
+            .serialize_compact();
+
+        Ok(genesis)
+    }
+
+    /// What the bootstrap quote commits to.
+    pub fn commitment(&self) -> Vec<u8> {
+        let mut commitment = GENESIS_DOMAIN.to_vec();
+        commitment.extend_from_slice(&self.cluster_pubkey);
+        commitment.extend_from_slice(&self.policy_hash);
+        commitment.extend_from_slice(&self.created_at.to_le_bytes());
+        commitment
+    }
+
+    /// Identifies the cluster, two clusters never share it even with the same key.
+    pub fn id(&self) -> [u8; 32] {
+        Sha256::digest([self.commitment().as_slice(), self.quote.as_bytes()].concat()).into()
+    }
+
+    pub fn cluster_pubkey(&self) -> Result<PublicKey, OverlayError> {
+        PublicKey::from_slice(&self.cluster_pubkey)
+            .map_err(|_| OverlayError::InvalidGenesis("malformed cluster pubkey"))
+    }
+
+    /// Checks the record is signed under its cluster key. See
+    /// [`crate::OverlayContext::verify_genesis`] to also verify the bootstrap quote.
+    pub fn verify_signature(&self) -> Result<PublicKey, OverlayError> {
+        let pubkey = self.cluster_pubkey()?;
+        Signature::from_compact(&self.signature)
+            .and_then(|signature| {
+                Secp256k1::verification_only().verify_ecdsa(&self.digest(), &signature, &pubkey)
+            })
+            .map_err(|_| OverlayError::InvalidGenesis("bad signature"))?;
+
+        Ok(pubkey)
+    }
+
+    fn digest(&self) -> Message {
+        Message::from_digest(self.id())
+    }
+}
+
+pub fn policy_hash(policy: &AttestationPolicy) -> [u8; 32] {
+    Sha256::digest(serde_json::to_vec(policy).unwrap()).into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::OverlayContext;
+    use mocks::attestation::MockProvider;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn verifies_genesis_records() {
+        let overlay = OverlayContext::new(Arc::new(MockProvider::new()));
+        let signing_key = SecretKey::from_byte_array(&[1; 32]).unwrap();
+        let genesis = Genesis::create(&signing_key, overlay.attestation.as_ref(), &overlay.policy)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            genesis.verify_signature().unwrap(),
+            signing_key.public_key(&Secp256k1::new())
+        );
+        overlay.verify_genesis(&genesis).await.unwrap();
+        let json = serde_json::to_string(&genesis).unwrap();
+        assert_eq!(serde_json::from_str::<Genesis>(&json).unwrap(), genesis);
+
+        let mut backdated = genesis.clone();
+        backdated.created_at -= 1;
+        assert!(backdated.verify_signature().is_err());
+
+        // Re-signed by whoever holds the key, but the quote still commits to the original.
+        let secp = Secp256k1::new();
+        backdated.signature = secp
+            .sign_ecdsa(&backdated.digest(), &signing_key)
+            .serialize_compact();
+        backdated.verify_signature().unwrap();
+        assert!(overlay.verify_genesis(&backdated).await.is_err());
+    }
+}
@@ -4,6 +4,7 @@
 //! method is the entry point for spawning the overlay and will return an array of the join handles for the futures being executed
 //! on tokio's threads pool. `forward_messages` requires:
 //! - an owned copy of the secret key associated to the node. This will be used to sign message headers.
+//! - the [`OverlayContext`] shared by all connections (attestation backend, attested peers book).
 //! - an address to listen requests on.
 //! - an array of peers we want to connect to. No discovery.
 //! - a sender for the comms channel to send messages from the overlay to the app.
@@ -16,14 +17,32 @@
 //! are purposefully split to enable for more specific ownership systems.
 //!
 
+use cluster::{ClusterConflict, ClusterIdentity, Resolution, SplitBrainPolicy};
+use error::OverlayError;
+use genesis::Genesis;
+use mocks::attestation::{
+    verify_event_log, AttestationPolicy, AttestationProvider, EventLogEntry, QuoteReport,
+    VerificationCache, VerificationCacheConfig, VerificationError,
+};
+use peers::PeerRegistry;
 use secp256k1::SecretKey;
-use std::net::SocketAddr;
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 use tokio::{runtime::Handle, sync::mpsc::Sender, task::JoinHandle};
+pub mod cluster;
+pub mod codec;
+pub mod dkg;
+pub mod election;
 mod encryption;
 mod error;
+pub mod frost;
+pub mod genesis;
 pub mod macros;
 pub mod message;
 pub mod p2p;
+pub mod peers;
+pub mod rotation;
+pub mod shamir;
+pub mod state;
 
 // NB: on stripped down implemenation this is the only transport.
 #[cfg(feature = "quic")]
@@ -34,6 +53,134 @@ pub mod utils;
 pub const GLOB_CHANNEL_BUFFER: usize = 20000;
 pub const NONCE_WINDOW: i64 = 10;
 
+/// Node-wide state shared by all the connection managers.
+#[derive(Clone)]
+pub struct OverlayContext {
+    /// Produces our quote and verifies the peers' ones.
+    pub attestation: Arc<dyn AttestationProvider>,
+    /// What we require from the peers' verified quotes.
+    pub policy: AttestationPolicy,
+    /// Peers we currently hold an attested connection with.
+    pub peers: PeerRegistry,
+    /// How often connected peers are challenged for a fresh quote, `None` trusts the handshake
+    /// for the life of the connection.
+    pub reattestation: Option<Duration>,
+    /// Results of recent quote verifications.
+    pub verification_cache: Arc<VerificationCache>,
+    /// Genesis record of our cluster, handed to peers in the handshake.
+    pub cluster: ClusterIdentity,
+    /// What to do with peers of another cluster.
+    pub split_brain: SplitBrainPolicy,
+}
+
+impl OverlayContext {
+    pub fn new(attestation: Arc<dyn AttestationProvider>) -> Self {
+        Self {
+            attestation,
+            policy: AttestationPolicy::default(),
+            peers: PeerRegistry::new(),
+            reattestation: None,
+            verification_cache: Arc::new(VerificationCache::default()),
+            cluster: ClusterIdentity::default(),
+            split_brain: SplitBrainPolicy::default(),
+        }
+    }
+
+    pub fn with_policy(mut self, policy: AttestationPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn with_reattestation(mut self, interval: Duration) -> Self {
+        self.reattestation = Some(interval);
+        self
+    }
+
+    pub fn with_verification_cache(mut self, config: VerificationCacheConfig) -> Self {
+        self.verification_cache = Arc::new(VerificationCache::new(config));
+        self
+    }
+
+    pub fn with_split_brain_policy(mut self, policy: SplitBrainPolicy) -> Self {
+        self.split_brain = policy;
+        self
+    }
+
+    /// Verifies `quote` with our attestation backend, reusing the result of an earlier
+    /// verification of a quote of the same platform when it's still valid under our TCB policy.
+    pub async fn verify_quote(
+        &self,
+        quote: &str,
+        appdata: &[u8],
+    ) -> Result<QuoteReport, VerificationError> {
+        self.verification_cache
+            .verify(self.attestation.as_ref(), &self.policy.tcb, quote, appdata)
+            .await
+    }
+
+    /// Verifies a peer's quote, and the event log it came with, and checks them against our
+    /// policy.
+    pub async fn verify_peer(
+        &self,
+        quote: &str,
+        appdata: &[u8],
+        event_log: Option<&[EventLogEntry]>,
+    ) -> Result<QuoteReport, OverlayError> {
+        let mut report = self.verify_quote(quote, appdata).await?;
+        if let Some(event_log) = event_log {
+            report.events = verify_event_log(event_log, &report)?;
+        }
+        self.policy.check(&report)?;
+
+        Ok(report)
+    }
+
+    /// Checks a cluster's genesis record is signed under its key, and that the bootstrap quote
+    /// commits to it and satisfies our policy.
+    pub async fn verify_genesis(&self, genesis: &Genesis) -> Result<QuoteReport, OverlayError> {
+        genesis.verify_signature()?;
+        self.verify_peer(&genesis.quote, &genesis.commitment(), None)
+            .await
+    }
+
+    /// Compares the genesis record a peer handed us in the handshake to ours, see [`cluster`].
+    /// `None` if the peer is in our cluster, or either of us isn't in one yet. Errors, closing
+    /// the connection, if the record doesn't verify or our policy refuses the peer.
+    pub async fn check_cluster(
+        &self,
+        pubkey: &[u8],
+        theirs: Option<Genesis>,
+    ) -> Result<Option<Resolution>, OverlayError> {
+        let (Some(ours), Some(theirs)) = (self.cluster.genesis(), theirs) else {
+            return Ok(None);
+        };
+        if ours.id() == theirs.id() {
+            return Ok(None);
+        }
+        self.verify_genesis(&theirs).await?;
+
+        let resolution = cluster::resolve(self.split_brain, &ours, &theirs);
+        tracing::warn!(
+            "peer {} is in cluster {}, we're in {}: {resolution:?}",
+            hex::encode(pubkey),
+            hex::encode(theirs.id()),
+            hex::encode(ours.id())
+        );
+        let id = theirs.id();
+        self.peers.record_conflict(ClusterConflict {
+            pubkey: pubkey.to_vec(),
+            ours: ours.id(),
+            theirs,
+            resolution,
+        });
+        if resolution == Resolution::Refused {
+            return Err(OverlayError::ClusterConflict(hex::encode(id)));
+        }
+
+        Ok(Some(resolution))
+    }
+}
+
 #[async_trait::async_trait]
 pub trait P2PTransportLayer
 where
@@ -52,6 +199,7 @@ where
     async fn connect_peer(
         secret_key: SecretKey,
         //shared_secret: Option<SecretKey>,
+        overlay: OverlayContext,
         ctx: Self::ConnectContext,
         peers: Vec<SocketAddr>,
         sender: Sender<Self::Message>,
@@ -62,6 +210,7 @@ where
     async fn serve(
         secret_key: SecretKey,
         //shared_secret: Option<SecretKey>,
+        overlay: OverlayContext,
         ctx: Self::ServeContext,
         sender: Sender<Self::Message>,
         receiver: tokio::sync::broadcast::Sender<Self::Message>,
@@ -71,6 +220,7 @@ where
     async fn forward_messages(
         secret_key: SecretKey,
         //shared_secret: Option<SecretKey>,
+        overlay: OverlayContext,
         listener: SocketAddr,
         peers: Vec<SocketAddr>,
         sender: Sender<Self::Message>,
@@ -80,12 +230,19 @@ where
         let handle = Handle::current();
         let join_network = handle.spawn(Self::connect_peer(
             secret_key,
+            overlay.clone(),
             connect_ctx,
             peers,
             sender.clone(),
             comms_broadcast.clone(),
         ));
-        let serve = handle.spawn(Self::serve(secret_key, serve_ctx, sender, comms_broadcast));
+        let serve = handle.spawn(Self::serve(
+            secret_key,
+            overlay,
+            serve_ctx,
+            sender,
+            comms_broadcast,
+        ));
         Ok(vec![join_network, serve])
     }
 }
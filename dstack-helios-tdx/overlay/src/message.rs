@@ -1,17 +1,50 @@
+use crate::codec::{self, PayloadWriter, PACKET_PAYLOAD_DOMAIN};
+use crate::frost::{SignatureShare, SigningCommitment};
+use crate::genesis::Genesis;
+use crate::rotation::{KeyHistory, RotationProposal};
+use crate::shamir::{Dealing, DealingSignature, DealtShare, Share};
+use crate::state::Entry;
+use mocks::attestation::hex_array;
 use rand::Rng;
+use secp256k1::PublicKey;
 use serde::{Deserialize, Serialize};
 
-pub type Quote = String;
+pub use mocks::attestation::{EventLog, Quote};
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct NotifySharedSecret {
     pub secret: Vec<u8>,
+    /// Record of the cluster the secret is of, `None` for clusters bootstrapped before records.
+    pub genesis: Option<Genesis>,
+    /// Rotations from the genesis key to the secret's, and the next one if already proposed.
+    pub history: KeyHistory,
+}
+
+/// A share of the cluster secret, dealt to its member or handed to a node reconstructing the
+/// secret, see [`crate::shamir`].
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct NotifyShare {
+    pub dealt: DealtShare,
+    pub genesis: Option<Genesis>,
+    pub history: KeyHistory,
+    /// The dealer's, when dealt to its member. `None` for shares handed over for reconstruction.
+    pub signature: Option<DealingSignature>,
+}
+
+/// Entries of the replicated state, a write or everything the sender holds, see
+/// [`crate::state`].
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct NotifyState {
+    pub entries: Vec<(String, Entry)>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub enum MaybeEncrypted {
     /// Encrypted to the target pubkey.
     EncryptedP2P(Vec<u8>),
+    /// Encoded [`OverlayMessageType`] encrypted to the target pubkey, handled by the connection
+    /// manager itself (e.g. re-attestation) instead of being forwarded to the app.
+    Overlay(Vec<u8>),
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -23,7 +56,7 @@ pub struct OverlayHeader {
     /// random number and the highest one is chosen as session key.
     /// There's a handful of ways we can deal with this, I'll leave it TBD.
     pub session_key: i64,
-    /// Signature of [`pubkey`] for sha256(serialize(header)+serialize(message)).
+    /// Signature of [`pubkey`] for sha256([`OverlayPacket::to_payload`]).
     pub signature: Vec<u8>,
 }
 
@@ -46,6 +79,13 @@ impl OverlayMessage {
             message: MaybeEncrypted::EncryptedP2P(message),
         }
     }
+
+    pub fn new_overlay(message: Vec<u8>) -> Self {
+        Self {
+            targets: None,
+            message: MaybeEncrypted::Overlay(message),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -59,51 +99,36 @@ pub struct OverlayPacket {
 }
 
 impl OverlayPacket {
-    pub fn from_quote(
-        pubkey: &[u8],
-        quote: Quote,
-        want_shared: bool,
-    ) -> anyhow::Result<(Self, i64)> {
-        let mut rng = rand::rng();
-        let session_key = rng.random();
-        let onboard = OverlayOnboard {
-            quote,
-            session: session_key,
-            want_shared,
-        };
-        let message = OverlayMessage::new_p2p_encrypted(
-            None,
-            bincode::serialize(&OverlayMessageType::Onboard(onboard)).unwrap(),
-        );
+    /// Unsigned packet for the handshake messages, exchanged before there's a session to sign
+    /// under.
+    pub fn handshake(pubkey: &[u8], message: &OverlayMessageType) -> anyhow::Result<Self> {
+        // NB: this isn't actually encrypted since there's no channel yet.
+        let message = OverlayMessage::new_p2p_encrypted(None, codec::encode(message)?);
 
-        let packet = Self {
+        Ok(Self {
             pubkey: pubkey.to_vec(),
             header: None,
             message,
-        };
-
-        Ok((packet, session_key))
+        })
     }
 
+    /// Canonical bytes covered by the header signature. Every variable size field is length
+    /// prefixed, see [`PayloadWriter`].
     pub fn to_payload(&self) -> Option<Vec<u8>> {
-        if let Some(header) = &self.header {
-            let message_encrypted = match &self.message.message {
-                MaybeEncrypted::EncryptedP2P(message) => message.to_vec(),
-                // .. full implemenation reserves more messages here.
-            };
-
-            Some(
-                [
-                    self.pubkey.clone(),
-                    message_encrypted.to_vec(),
-                    header.nonce.to_be_bytes().to_vec(),
-                    header.session_key.to_be_bytes().to_vec(),
-                ]
-                .concat(),
-            )
-        } else {
-            None
-        }
+        let header = self.header.as_ref()?;
+        let mut payload = PayloadWriter::new(PACKET_PAYLOAD_DOMAIN);
+        payload.put_bytes(&self.pubkey);
+        match &self.message.message {
+            MaybeEncrypted::EncryptedP2P(message) => {
+                payload.put_u8(0).put_bytes(message);
+            }
+            MaybeEncrypted::Overlay(message) => {
+                payload.put_u8(1).put_bytes(message);
+            } // .. full implemenation reserves more messages here.
+        };
+        payload.put_i64(header.nonce).put_i64(header.session_key);
+
+        Some(payload.finish())
     }
 
     pub fn add_signature(&mut self, signature: Vec<u8>) {
@@ -114,18 +139,208 @@ impl OverlayPacket {
     }
 }
 
+/// First handshake message. The peer's quote must commit to this challenge, so that a recorded
+/// handshake can't be replayed to us.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OverlayChallenge {
+    pub challenge: [u8; 32],
+}
+
+impl OverlayChallenge {
+    pub fn random() -> Self {
+        Self {
+            challenge: rand::rng().random(),
+        }
+    }
+}
+
+/// Answer to the peer's [`OverlayChallenge`].
 #[derive(Serialize, Deserialize, Debug)]
 pub struct OverlayOnboard {
+    /// Quote over [`attestation_appdata`].
     pub quote: Quote,
     pub session: i64,
+    /// Compressed ephemeral pubkey for this connection, the channel is encrypted under the ECDH
+    /// of both sides' ephemeral keys.
+    pub ephemeral: Vec<u8>,
     pub want_shared: bool,
+    /// Event log the quote's RTMRs replay from, if our attestation backend has it.
+    pub event_log: Option<EventLog>,
+    /// Record of the cluster we're in, if any, see [`crate::cluster`].
+    pub genesis: Option<Genesis>,
+}
+
+/// Answer to a [`OverlayChallenge`] sent over an established session, committing to the
+/// challenge and the ephemeral key the session was established with.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OverlayReattestation {
+    pub quote: Quote,
+    pub event_log: Option<EventLog>,
+}
+
+/// What a handshake quote commits to: the node key, the challenge it answers and the node's
+/// ephemeral key for the connection (`pubkey ‖ challenge ‖ ephemeral`, keys compressed).
+pub fn attestation_appdata(
+    pubkey: &PublicKey,
+    challenge: &[u8; 32],
+    ephemeral: &PublicKey,
+) -> Vec<u8> {
+    let mut appdata = pubkey.serialize().to_vec();
+    appdata.extend_from_slice(challenge);
+    appdata.extend_from_slice(&ephemeral.serialize());
+    appdata
+}
+
+/// Rounds of a threshold signing session, see [`crate::frost`]. Sessions are named by their
+/// coordinator.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub enum FrostMessage {
+    /// Asks the signers holding a share of the dealing with this ID for nonce commitments, to
+    /// sign `response` once they checked it against their own view.
+    Commit {
+        session: [u8; 32],
+        dealing: [u8; 32],
+        response: SignedResponse,
+    },
+    Commitment {
+        session: [u8; 32],
+        commitment: SigningCommitment,
+    },
+    /// Asks the signers of `commitments` to sign the response they committed to.
+    Sign {
+        session: [u8; 32],
+        commitments: Vec<SigningCommitment>,
+    },
+    SignatureShare {
+        session: [u8; 32],
+        share: SignatureShare,
+    },
+}
+
+/// A response to threshold sign, as the node serving it got it.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub enum SignedResponse {
+    /// Latest block number, as `/block` serves it.
+    Block { number: String },
+    /// JSON encoded transaction request and response, as `/call` serves them.
+    Call { tx: String, response: String },
+}
+
+impl SignedResponse {
+    /// What the signature is over: the block number or the call response.
+    pub fn message(&self) -> &str {
+        match self {
+            Self::Block { number } => number,
+            Self::Call { response, .. } => response,
+        }
+    }
+}
+
+/// Rounds of a distributed key generation, see [`crate::dkg`]. Sessions are named by their
+/// initiator, and only sent to the session's members.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub enum DkgMessage {
+    /// Asks `members` to generate a key that any `threshold` of them sign under.
+    Start {
+        session: [u8; 32],
+        threshold: u16,
+        members: Vec<Vec<u8>>,
+    },
+    /// Commitments to the sender's polynomial.
+    Dealing { session: [u8; 32], dealing: Dealing },
+    /// The recipient's share of the sender's polynomial.
+    Share { session: [u8; 32], share: Share },
+    /// IDs of the dealings the sender got, by dealer, and the dealers it accuses of not sending
+    /// a valid share.
+    Complaints {
+        session: [u8; 32],
+        dealings: Vec<(u16, [u8; 32])>,
+        accused: Vec<u16>,
+    },
+    /// An accused dealer's answer, the complainer's share in the clear.
+    Justification {
+        session: [u8; 32],
+        complainer: u16,
+        dealing: Dealing,
+        share: Share,
+    },
+}
+
+impl DkgMessage {
+    pub fn session(&self) -> [u8; 32] {
+        match self {
+            Self::Start { session, .. }
+            | Self::Dealing { session, .. }
+            | Self::Share { session, .. }
+            | Self::Complaints { session, .. }
+            | Self::Justification { session, .. } => *session,
+        }
+    }
+}
+
+/// A `/call` forwarded to peers for them to answer from their own view, and their answers.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub enum CallMessage {
+    /// JSON encoded transaction request to call.
+    Request { request: [u8; 32], tx: String },
+    /// JSON encoded response, signed under the answering node's key.
+    Response {
+        request: [u8; 32],
+        response: String,
+        #[serde(with = "hex_array")]
+        signature: [u8; 64],
+    },
+}
+
+/// Leader election among members, see [`crate::election`].
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub enum ElectionMessage {
+    /// Asks for the recipient's vote in `term`. Pre-votes only poll whether it would vote.
+    RequestVote {
+        term: u64,
+        pre: bool,
+    },
+    Vote {
+        term: u64,
+        granted: bool,
+        pre: bool,
+    },
+    /// Sent by the leader of `term`.
+    Heartbeat {
+        term: u64,
+    },
+    HeartbeatAck {
+        term: u64,
+    },
+}
+
+impl ElectionMessage {
+    pub fn term(&self) -> u64 {
+        match self {
+            Self::RequestVote { term, .. }
+            | Self::Vote { term, .. }
+            | Self::Heartbeat { term }
+            | Self::HeartbeatAck { term } => *term,
+        }
+    }
 }
 
 // NB: Full impl overlay has many more messages types that can also be passed
 // as generics depending on app layer.
+// NB: variants are encoded by index, new ones go last so that nodes of different versions still
+// understand each other's handshakes (see the codec tests).
 #[derive(Serialize, Deserialize, Debug)]
 pub enum OverlayMessageType {
     SharedSecret(NotifySharedSecret),
     RequestSharedSecret,
     Onboard(OverlayOnboard),
+    Challenge(OverlayChallenge),
+    Reattestation(OverlayReattestation),
+    Rotation(RotationProposal),
+    Share(NotifyShare),
+    Frost(FrostMessage),
+    Dkg(DkgMessage),
+    Call(CallMessage),
+    Election(ElectionMessage),
+    State(NotifyState),
 }
@@ -1,14 +1,19 @@
 use crate::{
+    cluster::Resolution,
+    codec,
     encryption::{self, ChiperWrapper},
     error::OverlayError,
     macros::helper::make_continue,
     message::{
-        MaybeEncrypted, OverlayHeader, OverlayMessage, OverlayMessageType, OverlayOnboard,
-        OverlayPacket,
+        attestation_appdata, MaybeEncrypted, OverlayChallenge, OverlayHeader, OverlayMessage,
+        OverlayMessageType, OverlayOnboard, OverlayPacket, OverlayReattestation,
     },
-    P2PTransportRecvMiddleman, P2PTransportSendMiddleman, GLOB_CHANNEL_BUFFER, NONCE_WINDOW,
+    OverlayContext, P2PTransportRecvMiddleman, P2PTransportSendMiddleman, GLOB_CHANNEL_BUFFER,
+    NONCE_WINDOW,
 };
-use secp256k1::{ecdsa, Message, PublicKey, Secp256k1};
+use mocks::attestation::{EventLogEntry, QuoteReport};
+use rand::Rng;
+use secp256k1::{ecdsa, Message, PublicKey, Secp256k1, SecretKey};
 use sha2::{Digest, Sha256};
 use tokio::{
     runtime::Handle,
@@ -19,6 +24,14 @@ pub struct P2PSessionData {
     pub session: i64,
     pub peer: Vec<u8>,
     pub chiper: encryption::ChiperWrapper,
+    /// Verified report of the peer's latest quote.
+    pub report: QuoteReport,
+    /// Ephemeral key the peer committed to in the handshake, re-attestation quotes commit to it
+    /// too.
+    pub peer_ephemeral: PublicKey,
+    /// The peer is in another cluster and we don't exchange application messages with it, see
+    /// [`crate::cluster::SplitBrainPolicy::Isolate`].
+    pub isolated: bool,
 }
 
 /// Middleware between raw layer and app layer. Likely should get abstracted too.
@@ -33,17 +46,22 @@ pub struct P2PConnectionManager {
     pub secret: secp256k1::SecretKey,
     //pub shared_secret: Option<secp256k1::SecretKey>,
     pub data: Option<P2PSessionData>,
+    /// Node-wide attestation backend and peers book.
+    pub overlay: OverlayContext,
 }
 
 enum InternalMessage {
     Inbound(OverlayPacket),
     Outbound(OverlayMessage),
+    /// Time to challenge the peer for a fresh quote.
+    Reattest,
 }
 
 impl P2PConnectionManager {
     pub fn new(
         secret: secp256k1::SecretKey,
         //    _shared_secret: Option<secp256k1::SecretKey>
+        overlay: OverlayContext,
     ) -> Self {
         Self {
             cached_nonces: vec![],
@@ -52,6 +70,7 @@ impl P2PConnectionManager {
             secret,
             //shared_secret,
             data: None,
+            overlay,
         }
     }
 
@@ -72,7 +91,124 @@ impl P2PConnectionManager {
         Ok(())
     }
 
+    /// Encrypts `message` to the peer, signs it under the session and sends it.
+    async fn send_sealed<S: P2PTransportSendMiddleman>(
+        &mut self,
+        connection: &mut S,
+        pubkey: &[u8],
+        mut message: OverlayMessage,
+    ) -> anyhow::Result<()> {
+        {
+            // NB: if it's EncryptedP2P we want to encrypt it to the peer else we leave it up to the app.
+            let chiper = &self.data.as_ref().unwrap().chiper;
+            message.message = match message.message {
+                MaybeEncrypted::EncryptedP2P(to_encrypt) => {
+                    MaybeEncrypted::EncryptedP2P(chiper.get_encrypted_message(&to_encrypt)?)
+                }
+                MaybeEncrypted::Overlay(to_encrypt) => {
+                    MaybeEncrypted::Overlay(chiper.get_encrypted_message(&to_encrypt)?)
+                }
+            };
+        }
+
+        // need to send to connection
+        let mut packet = OverlayPacket {
+            header: Some(OverlayHeader {
+                nonce: self.nonce,
+                session_key: self.data.as_ref().unwrap().session,
+                signature: vec![],
+            }),
+            pubkey: pubkey.to_vec(),
+            message,
+        };
+        let h_payload: [u8; 32] = Sha256::digest(&packet.to_payload().unwrap())
+            .try_into()
+            .unwrap();
+        let signature = {
+            let secp = Secp256k1::new();
+            let message = Message::from_digest(h_payload);
+            let signature = secp.sign_ecdsa(&message, &self.secret);
+            signature.serialize_compact()
+        };
+        packet.add_signature(signature.to_vec());
+        connection
+            .connection_send_message(codec::encode(&packet)?)
+            .await?;
+
+        self.nonce += 1;
+        Ok(())
+    }
+
+    /// Verifies the peer's answer to our re-attestation `challenge`. Errors (closing the
+    /// connection) if the quote doesn't verify, doesn't satisfy the policy anymore or the peer's
+    /// TCB level got worse since it last attested.
+    async fn handle_reattestation(
+        &mut self,
+        quote: &str,
+        event_log: Option<&[EventLogEntry]>,
+        challenge: &[u8; 32],
+    ) -> anyhow::Result<()> {
+        let data = self.data.as_mut().unwrap();
+        let appdata = attestation_appdata(
+            &PublicKey::from_slice(&data.peer)?,
+            challenge,
+            &data.peer_ephemeral,
+        );
+
+        let report = match self.overlay.verify_peer(quote, &appdata, event_log).await {
+            Ok(report) => report,
+            Err(e) => {
+                tracing::warn!(
+                    "peer {} failed re-attestation: {e}",
+                    hex::encode(&data.peer)
+                );
+                return Err(e.into());
+            }
+        };
+        if report.tcb_status.is_worse_than(&data.report.tcb_status) {
+            tracing::warn!("peer {} TCB level degraded", hex::encode(&data.peer));
+            return Err(OverlayError::TcbDegraded(
+                data.report.tcb_status.clone(),
+                report.tcb_status,
+            )
+            .into());
+        }
+
+        tracing::debug!("re-attested peer {}", hex::encode(&data.peer));
+        data.report = report.clone();
+        if !data.isolated {
+            self.overlay.peers.insert(data.peer.clone(), report);
+        }
+
+        Ok(())
+    }
+
+    /// Runs the connection until it closes or errors. The peer is removed from the peers book on
+    /// exit.
     pub async fn queue<S, R>(
+        &mut self,
+        comms_receiver: tokio::sync::broadcast::Receiver<OverlayMessage>,
+        connection: S,
+        incoming: R,
+        sender: Sender<OverlayMessage>,
+    ) -> anyhow::Result<()>
+    where
+        S: P2PTransportSendMiddleman,
+        // NB: incoming must not outlive the task
+        R: P2PTransportRecvMiddleman + Send + 'static,
+    {
+        let result = self
+            .run_queue(comms_receiver, connection, incoming, sender)
+            .await;
+        if let Some(data) = &self.data {
+            let reason = result.as_ref().err().map(|e| e.to_string());
+            self.overlay.peers.remove(&data.peer, reason);
+        }
+
+        result
+    }
+
+    async fn run_queue<S, R>(
         &mut self,
         mut comms_receiver: tokio::sync::broadcast::Receiver<OverlayMessage>,
         mut connection: S,
@@ -81,7 +217,6 @@ impl P2PConnectionManager {
     ) -> anyhow::Result<()>
     where
         S: P2PTransportSendMiddleman,
-        // NB: incoming must not outlive the task
         R: P2PTransportRecvMiddleman + Send + 'static,
     {
         tracing::debug!("started queue service on p2p connection");
@@ -91,19 +226,22 @@ impl P2PConnectionManager {
         let key = self.secret;
         let pubkey = key.public_key(&Secp256k1::new()).serialize().to_vec();
 
-        // NB: we need to adapt based on the dstack-guest interface that the community agrees upon.
-        // Currently our tsm-quote-generation lib takes in any bytes and does the hashing, but some other
-        // impls might require the hashed report data directly. The latter approach is probably better
-        // because it lets you choose the hashing algo.
-        let quote = mocks::get_quote(&pubkey).await?;
-        // let self_want_shared = self.shared_secret.is_none(); NB: we are not using this field anyways
-
-        // NB: this isn't actually encrypted since it's the handshake message.
-        let (send_quote, local_session_key) = OverlayPacket::from_quote(&pubkey, quote, false)?;
+        // NB: the handshake is a challenge-response. Each side first sends a fresh challenge and
+        // answers the other's with a quote committing to it (see [`attestation_appdata`]), so a
+        // recorded handshake can't be replayed by a malicious host.
+        let local_challenge = OverlayChallenge::random();
+        let challenge = local_challenge.challenge;
+        let ephemeral = SecretKey::new(&mut secp256k1::rand::thread_rng());
+        let local_session_key: i64 = rand::rng().random();
+        let mut answered = false;
+        // challenge of the re-attestation request we're waiting an answer for.
+        let mut pending_reattestation: Option<[u8; 32]> = None;
 
         // NB: error propagation here is correct, we need to close the task.
+        let send_challenge =
+            OverlayPacket::handshake(&pubkey, &OverlayMessageType::Challenge(local_challenge))?;
         connection
-            .connection_send_message(bincode::serialize(&send_quote)?)
+            .connection_send_message(codec::encode(&send_challenge)?)
             .await?;
 
         let cloned = tx.clone();
@@ -126,18 +264,34 @@ impl P2PConnectionManager {
                     Err(RecvError::Lagged(n)) => {
                         tracing::warn!("lagged by {n}")
                     }
-                    Err(_) => {
-                        tracing::error!("channel closed")
+                    Err(RecvError::Closed) => {
+                        tracing::error!("channel closed");
+                        break;
                     }
                 }
             }
         });
 
+        if let Some(interval) = self.overlay.reattestation {
+            let ticker = tx.clone();
+            handle.spawn(async move {
+                let mut ticks = tokio::time::interval(interval);
+                // NB: the first tick completes immediately, the handshake attests the peer.
+                ticks.tick().await;
+                loop {
+                    ticks.tick().await;
+                    if ticker.send(InternalMessage::Reattest).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
         handle.spawn(async move {
             while let Ok(Some(bytes)) = incoming.incoming_requests().await {
-                // we discard malformed messages
-                if let Ok(packet) = bincode::deserialize::<OverlayPacket>(&bytes) {
-                    if let Err(_) = tx.clone().send(InternalMessage::Inbound(packet)).await {
+                // we discard malformed and oversized messages
+                if let Ok(packet) = codec::decode::<OverlayPacket>(&bytes) {
+                    if tx.send(InternalMessage::Inbound(packet)).await.is_err() {
                         // receiver dropped, in prod it means that we need to log this and
                         // try to re-establish the connection.
                         tracing::error!(
@@ -190,6 +344,11 @@ impl P2PConnectionManager {
                             self.handle_check_nonce(header.nonce)?;
 
                             match &packet.message.message {
+                                MaybeEncrypted::EncryptedP2P(_)
+                                    if self.data.as_ref().unwrap().isolated =>
+                                {
+                                    tracing::debug!("dropping message from isolated peer");
+                                }
                                 MaybeEncrypted::EncryptedP2P(to_decrypt) => {
                                     let decrypted_message = self
                                         .data
@@ -204,61 +363,200 @@ impl P2PConnectionManager {
                                             decrypted_message,
                                         ))
                                         .await;
-                                } // NB: full implementation reserves other messages
+                                }
+                                MaybeEncrypted::Overlay(to_decrypt) => {
+                                    let decrypted_message = self
+                                        .data
+                                        .as_ref()
+                                        .unwrap()
+                                        .chiper
+                                        .get_decrypted_message(to_decrypt)?;
+
+                                    match codec::decode(&decrypted_message) {
+                                        // the peer wants a fresh quote.
+                                        Ok(OverlayMessageType::Challenge(OverlayChallenge {
+                                            challenge,
+                                        })) => {
+                                            let secp = Secp256k1::new();
+                                            let appdata = attestation_appdata(
+                                                &key.public_key(&secp),
+                                                &challenge,
+                                                &ephemeral.public_key(&secp),
+                                            );
+                                            let quote = self
+                                                .overlay
+                                                .attestation
+                                                .get_quote(&appdata)
+                                                .await?;
+                                            let event_log =
+                                                self.overlay.attestation.event_log().await?;
+                                            let answer = OverlayMessageType::Reattestation(
+                                                OverlayReattestation { quote, event_log },
+                                            );
+                                            self.send_sealed(
+                                                &mut connection,
+                                                &pubkey,
+                                                OverlayMessage::new_overlay(codec::encode(
+                                                    &answer,
+                                                )?),
+                                            )
+                                            .await?;
+                                        }
+                                        Ok(OverlayMessageType::Reattestation(
+                                            OverlayReattestation { quote, event_log },
+                                        )) => {
+                                            // NB: unsolicited quotes are ignored.
+                                            if let Some(challenge) = pending_reattestation.take() {
+                                                self.handle_reattestation(
+                                                    &quote,
+                                                    event_log.as_deref(),
+                                                    &challenge,
+                                                )
+                                                .await?;
+                                            }
+                                        }
+                                        _ => tracing::debug!("ignoring unexpected overlay message"),
+                                    }
+                                }
                             }
 
                             self.peer_nonce += 1;
                         }
-                        // very first message
+                        // handshake
                         None => {
-                            if self.data.is_some() {
-                                // we actually don't want to error here since it's vulnerable to reply by malicious
-                                // host since quotes don't carry nonces. We just ignore the message and potentially
-                                // notify other nodes that this peer is not behaving as expected.
-                                continue;
-                            }
-
                             // NB: this isn't actually encrypted
                             let MaybeEncrypted::EncryptedP2P(decrypted_message) =
-                                packet.message.message;
-
-                            let message_deser: OverlayMessageType =
-                                bincode::deserialize(&decrypted_message)?;
-
-                            let OverlayMessageType::Onboard(OverlayOnboard {
-                                quote,
-                                session,
-                                want_shared: _,
-                            }) = message_deser
+                                packet.message.message
                             else {
                                 return Err(crate::error::OverlayError::GotNoQuote.into());
                             };
 
-                            // NB: any deterministic function works good here.
-                            let session_key = if session < local_session_key {
-                                local_session_key
-                            } else {
-                                session
-                            };
+                            let message_deser: OverlayMessageType =
+                                codec::decode(&decrypted_message)?;
 
-                            let quote_verification = mocks::verify_quote(&quote, &packet.pubkey);
-                            if !quote_verification.await.is_valid {
-                                return Err(crate::error::OverlayError::InvaildQuote(quote).into());
-                            }
+                            match message_deser {
+                                OverlayMessageType::Challenge(OverlayChallenge {
+                                    challenge: peer_challenge,
+                                }) => {
+                                    if answered {
+                                        continue;
+                                    }
+
+                                    // NB: we need to adapt based on the dstack-guest interface that the community agrees upon.
+                                    // Currently our tsm-quote-generation lib takes in any bytes and does the hashing, but some other
+                                    // impls might require the hashed report data directly. The latter approach is probably better
+                                    // because it lets you choose the hashing algo.
+                                    let secp = Secp256k1::new();
+                                    let appdata = attestation_appdata(
+                                        &key.public_key(&secp),
+                                        &peer_challenge,
+                                        &ephemeral.public_key(&secp),
+                                    );
+                                    let quote =
+                                        self.overlay.attestation.get_quote(&appdata).await?;
+                                    // let self_want_shared = self.shared_secret.is_none(); NB: we are not using this field anyways
 
-                            self.data = Some(P2PSessionData {
-                                session: session_key,
-                                peer: packet.pubkey.clone(),
-                                chiper: ChiperWrapper::new(
-                                    &self.secret.secret_bytes(),
-                                    &packet.pubkey,
-                                )?,
-                            });
+                                    let onboard = OverlayOnboard {
+                                        quote,
+                                        session: local_session_key,
+                                        ephemeral: ephemeral.public_key(&secp).serialize().to_vec(),
+                                        want_shared: false,
+                                        event_log: self.overlay.attestation.event_log().await?,
+                                        genesis: self.overlay.cluster.genesis(),
+                                    };
+                                    let send_quote = OverlayPacket::handshake(
+                                        &pubkey,
+                                        &OverlayMessageType::Onboard(onboard),
+                                    )?;
+                                    connection
+                                        .connection_send_message(codec::encode(&send_quote)?)
+                                        .await?;
+                                    answered = true;
+                                }
+                                OverlayMessageType::Onboard(OverlayOnboard {
+                                    quote,
+                                    session,
+                                    ephemeral: peer_ephemeral,
+                                    want_shared: _,
+                                    event_log,
+                                    genesis,
+                                }) => {
+                                    if self.data.is_some() {
+                                        // NB: a second onboard is a duplicate or a replay, and a replay can't verify since
+                                        // it doesn't answer our challenge. We ignore it rather than closing the connection
+                                        // and potentially notify other nodes that this peer is not behaving as expected.
+                                        continue;
+                                    }
+
+                                    // NB: any deterministic function works good here.
+                                    let session_key = if session < local_session_key {
+                                        local_session_key
+                                    } else {
+                                        session
+                                    };
+
+                                    let peer_ephemeral = PublicKey::from_slice(&peer_ephemeral)?;
+                                    let appdata = attestation_appdata(
+                                        &PublicKey::from_slice(&packet.pubkey)?,
+                                        &challenge,
+                                        &peer_ephemeral,
+                                    );
+                                    let report = match self
+                                        .overlay
+                                        .verify_peer(&quote, &appdata, event_log.as_deref())
+                                        .await
+                                    {
+                                        Ok(report) => report,
+                                        Err(e) => {
+                                            tracing::warn!(
+                                                "rejecting peer {}: {e}",
+                                                hex::encode(&packet.pubkey)
+                                            );
+                                            return Err(e.into());
+                                        }
+                                    };
+                                    tracing::info!(
+                                        "attested peer {}: {report}",
+                                        hex::encode(&packet.pubkey)
+                                    );
+                                    let isolated =
+                                        self.overlay.check_cluster(&packet.pubkey, genesis).await?
+                                            == Some(Resolution::Isolated);
+
+                                    self.data = Some(P2PSessionData {
+                                        session: session_key,
+                                        peer: packet.pubkey.clone(),
+                                        chiper: ChiperWrapper::new(
+                                            &ephemeral.secret_bytes(),
+                                            &peer_ephemeral.serialize(),
+                                        )?,
+                                        report: report.clone(),
+                                        peer_ephemeral,
+                                        isolated,
+                                    });
+                                    // NB: isolated peers are kept out of the application's sight.
+                                    if !isolated {
+                                        self.overlay.peers.insert(packet.pubkey.clone(), report);
+                                    }
+                                }
+                                _ => return Err(crate::error::OverlayError::GotNoQuote.into()),
+                            }
                         }
                     }
                 }
 
-                InternalMessage::Outbound(mut message) => {
+                InternalMessage::Outbound(message) => {
+                    if self.data.is_none() {
+                        // NB: the app may broadcast before this peer completed the handshake, there is
+                        // no channel to encrypt to yet so we drop the message.
+                        tracing::debug!("dropping outbound message, peer not onboarded yet");
+                        continue;
+                    }
+
+                    if self.data.as_ref().unwrap().isolated {
+                        continue;
+                    }
+
                     if let Some(targets) = &message.targets {
                         // if the app specified targets and if our connection's peer is not
                         // in that array then we ignore the outbound request.
@@ -267,39 +565,28 @@ impl P2PConnectionManager {
                         }
                     }
 
-                    {
-                        let MaybeEncrypted::EncryptedP2P(to_encrypt) = message.message;
-                        // NB: if it's EncryptedP2P we want to encrypt it to the peer else we leave it up to the app.
-                        let chiper = &self.data.as_ref().unwrap().chiper;
-                        let encrypted = chiper.get_encrypted_message(&to_encrypt)?;
-                        message.message = MaybeEncrypted::EncryptedP2P(encrypted);
+                    self.send_sealed(&mut connection, &pubkey, message).await?;
+                }
+
+                InternalMessage::Reattest => {
+                    if self.data.is_none() {
+                        continue;
+                    }
+
+                    if pending_reattestation.is_some() {
+                        // NB: the peer had a whole interval to answer.
+                        return Err(OverlayError::ReattestationTimeout.into());
                     }
 
-                    // need to send to connection
-                    let mut packet = OverlayPacket {
-                        header: Some(OverlayHeader {
-                            nonce: self.nonce,
-                            session_key: self.data.as_ref().unwrap().session,
-                            signature: vec![],
-                        }),
-                        pubkey: pubkey.clone(),
-                        message,
-                    };
-                    let h_payload: [u8; 32] = Sha256::digest(&packet.to_payload().unwrap())
-                        .try_into()
-                        .unwrap();
-                    let signature = {
-                        let secp = Secp256k1::new();
-                        let message = Message::from_digest(h_payload);
-                        let signature = secp.sign_ecdsa(&message, &key);
-                        signature.serialize_compact()
-                    };
-                    packet.add_signature(signature.to_vec());
-                    connection
-                        .connection_send_message(bincode::serialize(&packet)?)
-                        .await?;
-
-                    self.nonce += 1;
+                    let request = OverlayChallenge::random();
+                    pending_reattestation = Some(request.challenge);
+                    let request = codec::encode(&OverlayMessageType::Challenge(request))?;
+                    self.send_sealed(
+                        &mut connection,
+                        &pubkey,
+                        OverlayMessage::new_overlay(request),
+                    )
+                    .await?;
                 }
             }
         }
@@ -307,3 +594,445 @@ impl P2PConnectionManager {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cluster::SplitBrainPolicy;
+    use crate::genesis::Genesis;
+    use crate::peers::PeerEvent;
+    use mocks::attestation::{
+        AllowedMeasurements, AttestationPolicy, AttestationProvider, EventLogEntry, Measurement,
+        MockProvider, PolicyViolation, TcbPolicy, TcbStatus, TeeType, VerificationError,
+    };
+    use std::{sync::Arc, time::Duration};
+
+    use tokio::{
+        sync::{broadcast, mpsc},
+        task::JoinHandle,
+    };
+
+    struct ChannelSend(mpsc::Sender<Vec<u8>>);
+
+    #[async_trait::async_trait]
+    impl P2PTransportSendMiddleman for ChannelSend {
+        async fn connection_send_message(&mut self, message: Vec<u8>) -> anyhow::Result<()> {
+            Ok(self.0.send(message).await?)
+        }
+    }
+
+    struct ChannelRecv(mpsc::Receiver<Vec<u8>>);
+
+    #[async_trait::async_trait]
+    impl P2PTransportRecvMiddleman for ChannelRecv {
+        async fn incoming_requests(&mut self) -> anyhow::Result<Option<Vec<u8>>> {
+            Ok(self.0.recv().await)
+        }
+    }
+
+    struct TestNode {
+        overlay: OverlayContext,
+        broadcast: broadcast::Sender<OverlayMessage>,
+        app: mpsc::Receiver<OverlayMessage>,
+        queue: JoinHandle<anyhow::Result<()>>,
+    }
+
+    fn spawn_node(
+        secret: [u8; 32],
+        overlay: OverlayContext,
+        send: ChannelSend,
+        recv: ChannelRecv,
+    ) -> TestNode {
+        let (broadcast, comms_receiver) = broadcast::channel(64);
+        let (app_tx, app) = mpsc::channel(64);
+        let queue = tokio::spawn({
+            let overlay = overlay.clone();
+            async move {
+                let secret = secp256k1::SecretKey::from_byte_array(&secret).unwrap();
+                P2PConnectionManager::new(secret, overlay)
+                    .queue(comms_receiver, send, recv, app_tx)
+                    .await
+            }
+        });
+
+        TestNode {
+            overlay,
+            broadcast,
+            app,
+            queue,
+        }
+    }
+
+    /// Mock provider that can be swapped mid-connection.
+    struct Switch(std::sync::RwLock<MockProvider>);
+
+    #[async_trait::async_trait]
+    impl AttestationProvider for Switch {
+        async fn get_quote(&self, appdata: &[u8]) -> anyhow::Result<String> {
+            let provider = self.0.read().unwrap().clone();
+            provider.get_quote(appdata).await
+        }
+
+        async fn verify_quote(
+            &self,
+            quote: &str,
+            appdata: &[u8],
+        ) -> Result<QuoteReport, VerificationError> {
+            let provider = self.0.read().unwrap().clone();
+            provider.verify_quote(quote, appdata).await
+        }
+    }
+
+    fn mock_context() -> OverlayContext {
+        OverlayContext::new(Arc::new(MockProvider::new()))
+    }
+
+    /// Two connection managers talking over an in-memory duplex.
+    fn connect(a: OverlayContext, b: OverlayContext) -> (TestNode, TestNode) {
+        let (a_tx, b_rx) = mpsc::channel(64);
+        let (b_tx, a_rx) = mpsc::channel(64);
+
+        (
+            spawn_node([1; 32], a, ChannelSend(a_tx), ChannelRecv(a_rx)),
+            spawn_node([2; 32], b, ChannelSend(b_tx), ChannelRecv(b_rx)),
+        )
+    }
+
+    #[tokio::test]
+    async fn handshake_and_message() {
+        let (a, mut b) = connect(mock_context(), mock_context());
+
+        // outbound messages are dropped until the handshake completes, so we retry.
+        let received = loop {
+            a.broadcast
+                .send(OverlayMessage::new_p2p_encrypted(None, b"hello".to_vec()))
+                .unwrap();
+            if let Ok(Some(message)) =
+                tokio::time::timeout(Duration::from_millis(50), b.app.recv()).await
+            {
+                break message;
+            }
+        };
+
+        let MaybeEncrypted::EncryptedP2P(message) = received.message else {
+            panic!("app got an overlay message");
+        };
+        assert_eq!(message, b"hello");
+
+        // the app can look up what the peer attested to.
+        let sender = &received.targets.unwrap()[0];
+        let peer = b.overlay.peers.get(sender).unwrap();
+        assert_eq!(peer.report.tcb_status, TcbStatus::UpToDate);
+        assert_eq!(a.overlay.peers.list().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn rejected_quote_closes_connection() {
+        let (_a, b) = connect(
+            mock_context(),
+            OverlayContext::new(Arc::new(MockProvider::rejecting())),
+        );
+
+        let error = b.queue.await.unwrap().unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<OverlayError>(),
+            Some(OverlayError::RejectedQuote(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn replayed_handshake_is_rejected() {
+        // An onboard recorded from an earlier connection answers another challenge.
+        let secp = Secp256k1::new();
+        let peer_pubkey = SecretKey::from_byte_array(&[2; 32])
+            .unwrap()
+            .public_key(&secp);
+        let ephemeral = SecretKey::from_byte_array(&[3; 32])
+            .unwrap()
+            .public_key(&secp);
+        let quote = MockProvider::new()
+            .get_quote(&attestation_appdata(&peer_pubkey, &[9; 32], &ephemeral))
+            .await
+            .unwrap();
+        let recorded = OverlayMessageType::Onboard(OverlayOnboard {
+            quote,
+            session: 0,
+            ephemeral: ephemeral.serialize().to_vec(),
+            want_shared: false,
+            event_log: None,
+            genesis: None,
+        });
+
+        let (host_tx, a_rx) = mpsc::channel(64);
+        let (a_tx, _host_rx) = mpsc::channel(64);
+        let a = spawn_node(
+            [1; 32],
+            mock_context(),
+            ChannelSend(a_tx),
+            ChannelRecv(a_rx),
+        );
+        let replayed = OverlayPacket::handshake(&peer_pubkey.serialize(), &recorded).unwrap();
+        host_tx
+            .send(codec::encode(&replayed).unwrap())
+            .await
+            .unwrap();
+
+        let error = a.queue.await.unwrap().unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<OverlayError>(),
+            Some(OverlayError::RejectedQuote(
+                VerificationError::ReportDataMismatch
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    async fn policy_violation_closes_connection() {
+        let policy = AttestationPolicy {
+            tcb: TcbPolicy {
+                allowed_statuses: vec![TcbStatus::OutOfDate],
+                allowed_advisory_ids: None,
+            },
+            ..Default::default()
+        };
+        let (_a, b) = connect(mock_context(), mock_context().with_policy(policy));
+
+        let error = b.queue.await.unwrap().unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<OverlayError>(),
+            Some(OverlayError::PolicyViolation(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn unexpected_measurements_close_connection() {
+        let policy = AttestationPolicy {
+            measurements: vec![AllowedMeasurements {
+                mrtd: Some(Measurement([1; 48])),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let other_build =
+            MockProvider::new().with_measurements(Measurement([2; 48]), Default::default());
+        let (_a, b) = connect(
+            OverlayContext::new(Arc::new(other_build)),
+            mock_context().with_policy(policy),
+        );
+
+        let error = b.queue.await.unwrap().unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<OverlayError>(),
+            Some(OverlayError::PolicyViolation(
+                PolicyViolation::Measurements { .. }
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    async fn mixed_tee_peers_attest_each_other() {
+        let only = |tee: TeeType, mrtd: Measurement| AttestationPolicy {
+            measurements: vec![AllowedMeasurements {
+                tee: Some(tee),
+                mrtd: Some(mrtd),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let snp = MockProvider::new()
+            .with_tee(TeeType::SevSnp)
+            .with_measurements(Measurement([1; 48]), Default::default());
+        let tdx = MockProvider::new().with_measurements(Measurement([2; 48]), Default::default());
+
+        let (a, b) = connect(
+            OverlayContext::new(Arc::new(snp))
+                .with_policy(only(TeeType::Tdx, Measurement([2; 48]))),
+            OverlayContext::new(Arc::new(tdx))
+                .with_policy(only(TeeType::SevSnp, Measurement([1; 48]))),
+        );
+        let subscriptions = [
+            (a.overlay.peers.subscribe(), TeeType::Tdx),
+            (b.overlay.peers.subscribe(), TeeType::SevSnp),
+        ];
+        for (mut events, tee) in subscriptions {
+            let Ok(PeerEvent::Attested(peer)) = events.recv().await else {
+                panic!("peer wasn't attested");
+            };
+            assert_eq!(peer.report.tee, tee);
+        }
+    }
+
+    #[tokio::test]
+    async fn measured_events_reach_policy() {
+        let app = |compose_hash: &[u8]| {
+            let provider = MockProvider::new()
+                .with_event_log(vec![
+                    EventLogEntry::runtime("app-id", &[1; 20]),
+                    EventLogEntry::runtime("compose-hash", compose_hash),
+                ])
+                .unwrap();
+            OverlayContext::new(Arc::new(provider))
+        };
+        let policy = AttestationPolicy {
+            measurements: vec![AllowedMeasurements {
+                events: [("compose-hash".to_string(), hex::encode([2; 32]))].into(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let (_a, b) = connect(app(&[2; 32]), mock_context().with_policy(policy.clone()));
+        let mut events = b.overlay.peers.subscribe();
+        let Ok(PeerEvent::Attested(peer)) = events.recv().await else {
+            panic!("peer wasn't attested");
+        };
+        assert_eq!(peer.report.events["app-id"], hex::encode([1; 20]));
+
+        let (_a, b) = connect(app(&[3; 32]), mock_context().with_policy(policy));
+        let error = b.queue.await.unwrap().unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<OverlayError>(),
+            Some(OverlayError::PolicyViolation(
+                PolicyViolation::Measurements { .. }
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    async fn reattestation_refreshes_peers() {
+        let reattesting = || mock_context().with_reattestation(Duration::from_millis(20));
+        let (a, _b) = connect(reattesting(), reattesting());
+        let mut events = a.overlay.peers.subscribe();
+
+        let reattested = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let Ok(PeerEvent::Reattested(peer)) = events.recv().await {
+                    break peer;
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(reattested.report.tcb_status, TcbStatus::UpToDate);
+    }
+
+    /// Swaps the verifier's provider once the peers are connected, and returns the error that
+    /// closed the connection.
+    async fn reattest_with(provider: MockProvider) -> (anyhow::Error, PeerEvent) {
+        let switch = Arc::new(Switch(std::sync::RwLock::new(MockProvider::new())));
+        let (_a, b) = connect(
+            mock_context(),
+            OverlayContext::new(switch.clone()).with_reattestation(Duration::from_millis(20)),
+        );
+        let mut events = b.overlay.peers.subscribe();
+        assert!(matches!(events.recv().await, Ok(PeerEvent::Attested(_))));
+
+        *switch.0.write().unwrap() = provider;
+        let error = b.queue.await.unwrap().unwrap_err();
+        let event = loop {
+            match events.recv().await.unwrap() {
+                PeerEvent::Reattested(_) => continue,
+                event => break event,
+            }
+        };
+        (error, event)
+    }
+
+    #[tokio::test]
+    async fn failed_reattestation_disconnects() {
+        let (error, event) = reattest_with(MockProvider::rejecting()).await;
+        assert!(matches!(
+            error.downcast_ref::<OverlayError>(),
+            Some(OverlayError::RejectedQuote(_))
+        ));
+        assert!(matches!(
+            event,
+            PeerEvent::Disconnected {
+                reason: Some(_),
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn degraded_tcb_disconnects() {
+        // still allowed by the default policy, but worse than at connection time.
+        let degraded = MockProvider::new().with_tcb_status(TcbStatus::SWHardeningNeeded, vec![]);
+        let (error, _) = reattest_with(degraded).await;
+        assert!(matches!(
+            error.downcast_ref::<OverlayError>(),
+            Some(OverlayError::TcbDegraded(
+                TcbStatus::UpToDate,
+                TcbStatus::SWHardeningNeeded
+            ))
+        ));
+    }
+
+    /// A node of the cluster bootstrapped with `secret`, under `policy`.
+    async fn cluster_member(secret: u8, policy: SplitBrainPolicy) -> OverlayContext {
+        let overlay = mock_context().with_split_brain_policy(policy);
+        let signing_key = SecretKey::from_byte_array(&[secret; 32]).unwrap();
+        let genesis = Genesis::create(&signing_key, overlay.attestation.as_ref(), &overlay.policy)
+            .await
+            .unwrap();
+        overlay.cluster.set(Some(genesis));
+        overlay
+    }
+
+    #[tokio::test]
+    async fn split_brain_policies() {
+        let (_a, b) = connect(
+            cluster_member(1, SplitBrainPolicy::Refuse).await,
+            cluster_member(2, SplitBrainPolicy::Refuse).await,
+        );
+        let error = b.queue.await.unwrap().unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<OverlayError>(),
+            Some(OverlayError::ClusterConflict(_))
+        ));
+
+        let isolated = cluster_member(1, SplitBrainPolicy::Isolate).await;
+        let mut events = isolated.peers.subscribe();
+        let (a, mut b) = connect(isolated, cluster_member(2, SplitBrainPolicy::Isolate).await);
+        let Ok(PeerEvent::SplitBrain(conflict)) = events.recv().await else {
+            panic!("conflict wasn't reported");
+        };
+        assert_eq!(conflict.resolution, Resolution::Isolated);
+        a.broadcast
+            .send(OverlayMessage::new_p2p_encrypted(None, b"hello".to_vec()))
+            .unwrap();
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), b.app.recv())
+                .await
+                .is_err()
+        );
+        assert!(a.overlay.peers.list().is_empty());
+        assert!(!a.queue.is_finished());
+
+        let (older, newer) = (
+            cluster_member(1, SplitBrainPolicy::FollowOlder).await,
+            cluster_member(2, SplitBrainPolicy::FollowOlder).await,
+        );
+        let (older, newer) = if crate::cluster::is_older(
+            &older.cluster.genesis().unwrap(),
+            &newer.cluster.genesis().unwrap(),
+        ) {
+            (older, newer)
+        } else {
+            (newer, older)
+        };
+        let subscriptions = [
+            (older.peers.subscribe(), Resolution::Followed),
+            (newer.peers.subscribe(), Resolution::Following),
+        ];
+        let _nodes = connect(older, newer);
+        for (mut events, resolution) in subscriptions {
+            let Ok(PeerEvent::SplitBrain(conflict)) = events.recv().await else {
+                panic!("conflict wasn't reported");
+            };
+            assert_eq!(conflict.resolution, resolution);
+            // NB: still attested, the newer cluster's members switch over it.
+            assert!(matches!(events.recv().await, Ok(PeerEvent::Attested(_))));
+        }
+    }
+}
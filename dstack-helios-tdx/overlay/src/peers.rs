@@ -0,0 +1,149 @@
+//! Book of the peers we currently hold an attested connection with.
+//!
+//! Connection managers register the peer once its quote verified and drop it when the connection
+//! closes, the application gets a clone of the registry to read the peers' verified measurements
+//! and can [`PeerRegistry::subscribe`] to be told when peers come, get re-attested or go.
+//!
+//! Being attested only tells us what a peer runs, the application records what they do wrong
+//! with [`PeerRegistry::record_misbehaviour`].
+
+use crate::cluster::ClusterConflict;
+use mocks::attestation::QuoteReport;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, RwLock},
+    time::SystemTime,
+};
+use tokio::sync::broadcast;
+
+const EVENTS_BUFFER: usize = 256;
+/// Misbehaviour records kept, the oldest are dropped first.
+const MISBEHAVIOUR_LOG_LEN: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub struct AttestedPeer {
+    /// Node pubkey the peer signs packets with.
+    pub pubkey: Vec<u8>,
+    /// Result of the verification of the peer's quote.
+    pub report: QuoteReport,
+    /// When the quote was verified.
+    pub attested_at: SystemTime,
+}
+
+/// Something a peer did that it shouldn't have, e.g. sending the secret of another cluster.
+#[derive(Debug, Clone)]
+pub struct Misbehaviour {
+    pub pubkey: Vec<u8>,
+    pub reason: String,
+    pub at: SystemTime,
+}
+
+#[derive(Debug, Clone)]
+pub enum PeerEvent {
+    /// Handshake completed.
+    Attested(AttestedPeer),
+    /// The peer answered a re-attestation request with a valid quote.
+    Reattested(AttestedPeer),
+    /// The connection closed, `reason` is set when we closed it because of the peer (e.g. it
+    /// failed re-attestation).
+    Disconnected {
+        pubkey: Vec<u8>,
+        reason: Option<String>,
+    },
+    Misbehaved(Misbehaviour),
+    /// The peer is in another cluster, see [`crate::cluster`].
+    SplitBrain(ClusterConflict),
+}
+
+#[derive(Debug, Clone)]
+pub struct PeerRegistry {
+    inner: Arc<RwLock<HashMap<Vec<u8>, AttestedPeer>>>,
+    misbehaviour: Arc<RwLock<VecDeque<Misbehaviour>>>,
+    events: broadcast::Sender<PeerEvent>,
+}
+
+impl Default for PeerRegistry {
+    fn default() -> Self {
+        Self {
+            inner: Default::default(),
+            misbehaviour: Default::default(),
+            events: broadcast::channel(EVENTS_BUFFER).0,
+        }
+    }
+}
+
+impl PeerRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, pubkey: &[u8]) -> Option<AttestedPeer> {
+        self.inner.read().unwrap().get(pubkey).cloned()
+    }
+
+    /// All currently attested peers.
+    pub fn list(&self) -> Vec<AttestedPeer> {
+        self.inner.read().unwrap().values().cloned().collect()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<PeerEvent> {
+        self.events.subscribe()
+    }
+
+    /// Records that `pubkey` misbehaved and publishes it.
+    pub fn record_misbehaviour(&self, pubkey: &[u8], reason: impl Into<String>) {
+        let misbehaviour = Misbehaviour {
+            pubkey: pubkey.to_vec(),
+            reason: reason.into(),
+            at: SystemTime::now(),
+        };
+        {
+            let mut log = self.misbehaviour.write().unwrap();
+            if log.len() == MISBEHAVIOUR_LOG_LEN {
+                log.pop_front();
+            }
+            log.push_back(misbehaviour.clone());
+        }
+
+        let _ = self.events.send(PeerEvent::Misbehaved(misbehaviour));
+    }
+
+    /// Recorded misbehaviour, oldest first.
+    pub fn misbehaviour(&self) -> Vec<Misbehaviour> {
+        self.misbehaviour.read().unwrap().iter().cloned().collect()
+    }
+
+    pub(crate) fn record_conflict(&self, conflict: ClusterConflict) {
+        let _ = self.events.send(PeerEvent::SplitBrain(conflict));
+    }
+
+    pub(crate) fn insert(&self, pubkey: Vec<u8>, report: QuoteReport) {
+        let peer = AttestedPeer {
+            pubkey: pubkey.clone(),
+            report,
+            attested_at: SystemTime::now(),
+        };
+        let known = self
+            .inner
+            .write()
+            .unwrap()
+            .insert(pubkey, peer.clone())
+            .is_some();
+
+        // NB: no subscribers isn't an error.
+        let _ = self.events.send(if known {
+            PeerEvent::Reattested(peer)
+        } else {
+            PeerEvent::Attested(peer)
+        });
+    }
+
+    pub(crate) fn remove(&self, pubkey: &[u8], reason: Option<String>) {
+        if self.inner.write().unwrap().remove(pubkey).is_some() {
+            let _ = self.events.send(PeerEvent::Disconnected {
+                pubkey: pubkey.to_vec(),
+                reason,
+            });
+        }
+    }
+}
@@ -1,5 +1,5 @@
 use crate::{
-    message::OverlayMessage, p2p::P2PConnectionManager, P2PTransportLayer,
+    message::OverlayMessage, p2p::P2PConnectionManager, OverlayContext, P2PTransportLayer,
     P2PTransportRecvMiddleman, P2PTransportSendMiddleman,
 };
 use async_trait::async_trait;
@@ -31,6 +31,7 @@ impl P2PTransportLayer for QUICTransport {
 
     async fn connect_peer(
         secret_key: secp256k1::SecretKey,
+        overlay: OverlayContext,
         ctx: Self::ConnectContext,
         peers: Vec<SocketAddr>,
         sender: Sender<Self::Message>,
@@ -42,6 +43,7 @@ impl P2PTransportLayer for QUICTransport {
         for peer in peers {
             let comms_broadcast = comms_broadcast.clone();
             let comms_sender = sender.clone();
+            let overlay = overlay.clone();
 
             println!("connecting to peer {}", peer);
             let (connection, incoming) = ctx.connect_to(&peer).await.unwrap();
@@ -51,7 +53,7 @@ impl P2PTransportLayer for QUICTransport {
                 let rx = comms_broadcast.subscribe();
                 let connection_wrapper = QUICTransportConnection { connection };
                 let recv_wrapper = QUICTransportIncomingConnection { incoming };
-                let r = P2PConnectionManager::new(secret_key)
+                let r = P2PConnectionManager::new(secret_key, overlay)
                     .queue::<QUICTransportConnection, QUICTransportIncomingConnection>(
                         rx,
                         connection_wrapper,
@@ -72,6 +74,7 @@ impl P2PTransportLayer for QUICTransport {
     async fn serve(
         secret_key: secp256k1::SecretKey,
         //shared_secret: Option<secp256k1::SecretKey>,
+        overlay: OverlayContext,
         mut ctx: Self::ServeContext,
         sender: Sender<Self::Message>,
         comms_broadcast: tokio::sync::broadcast::Sender<Self::Message>,
@@ -80,12 +83,13 @@ impl P2PTransportLayer for QUICTransport {
         while let Some((connection, incoming)) = ctx.next().await {
             let comms_broadcast = comms_broadcast.clone();
             let comms_sender = sender.clone();
+            let overlay = overlay.clone();
             // we use a dedicated task for each connection
             handle.spawn(async move {
                 let rx = comms_broadcast.subscribe();
                 let connection_wrapper = QUICTransportConnection { connection };
                 let recv_wrapper = QUICTransportIncomingConnection { incoming };
-                let r = P2PConnectionManager::new(secret_key)
+                let r = P2PConnectionManager::new(secret_key, overlay)
                     .queue::<QUICTransportConnection, QUICTransportIncomingConnection>(
                         rx,
                         connection_wrapper,
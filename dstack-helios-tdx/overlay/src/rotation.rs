@@ -0,0 +1,148 @@
+//! Rotation of the cluster key.
+//!
+//! A member proposes a new secret to every attested peer, along with a link between the current
+//! and the new cluster key, and the cluster switches to it at the time the link names. The link
+//! is signed by both keys, so whoever trusts the old key (e.g. from the genesis record) can follow
+//! the rotations to the current one, see [`follow`].
+
+use crate::error::OverlayError;
+use mocks::attestation::hex_array;
+use secp256k1::{ecdsa::Signature, Message, PublicKey, Secp256k1, SecretKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+const KEY_LINK_DOMAIN: &[u8] = b"tplus/key-link";
+
+/// The cluster key of `epoch - 1` handing over to the one of `epoch`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyLink {
+    /// Rotations since genesis once the new key is active.
+    pub epoch: u64,
+    /// Unix time the new key signs from.
+    pub activates_at: u64,
+    #[serde(with = "hex_array")]
+    pub old_pubkey: [u8; 33],
+    #[serde(with = "hex_array")]
+    pub new_pubkey: [u8; 33],
+    /// Compact ECDSA signatures of the link under the old and the new key.
+    #[serde(with = "hex_array")]
+    pub old_signature: [u8; 64],
+    #[serde(with = "hex_array")]
+    pub new_signature: [u8; 64],
+}
+
+impl KeyLink {
+    pub fn new(epoch: u64, activates_at: u64, old: &SecretKey, new: &SecretKey) -> Self {
+        let secp = Secp256k1::new();
+        let mut link = Self {
+            epoch,
+            activates_at,
+            old_pubkey: old.public_key(&secp).serialize(),
+            new_pubkey: new.public_key(&secp).serialize(),
+            old_signature: [0; 64],
+            new_signature: [0; 64],
+        };
+        link.old_signature = secp.sign_ecdsa(&link.digest(), old).serialize_compact();
+        link.new_signature = secp.sign_ecdsa(&link.digest(), new).serialize_compact();
+        link
+    }
+
+    /// Checks both keys signed the link.
+    pub fn verify(&self) -> Result<(), OverlayError> {
+        let secp = Secp256k1::verification_only();
+        for (pubkey, signature) in [
+            (&self.old_pubkey, &self.old_signature),
+            (&self.new_pubkey, &self.new_signature),
+        ] {
+            let pubkey = PublicKey::from_slice(pubkey)
+                .map_err(|_| OverlayError::InvalidKeyLink("malformed pubkey"))?;
+            Signature::from_compact(signature)
+                .and_then(|signature| secp.verify_ecdsa(&self.digest(), &signature, &pubkey))
+                .map_err(|_| OverlayError::InvalidKeyLink("bad signature"))?;
+        }
+
+        Ok(())
+    }
+
+    fn digest(&self) -> Message {
+        let mut hasher = Sha256::new();
+        hasher.update(KEY_LINK_DOMAIN);
+        hasher.update(self.epoch.to_le_bytes());
+        hasher.update(self.activates_at.to_le_bytes());
+        hasher.update(self.old_pubkey);
+        hasher.update(self.new_pubkey);
+        Message::from_digest(hasher.finalize().into())
+    }
+}
+
+/// A new cluster secret, sent to members ahead of its activation. Empty when members share the
+/// secret, they're dealt shares of the new one instead, see [`crate::shamir`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RotationProposal {
+    pub secret: Vec<u8>,
+    pub link: KeyLink,
+}
+
+/// How the cluster key got from genesis to the current one, and where it's going next.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyHistory {
+    /// Activated rotations, oldest first.
+    pub links: Vec<KeyLink>,
+    pub pending: Option<Box<RotationProposal>>,
+}
+
+impl KeyHistory {
+    /// Rotations since genesis.
+    pub fn epoch(&self) -> u64 {
+        self.links.len() as u64
+    }
+}
+
+/// The key `from` was rotated to through `links`, checking every link on the way.
+pub fn follow(from: &PublicKey, links: &[KeyLink]) -> Result<PublicKey, OverlayError> {
+    let mut current = from.serialize();
+    for link in links {
+        link.verify()?;
+        if link.old_pubkey == current {
+            current = link.new_pubkey;
+        }
+    }
+
+    PublicKey::from_slice(&current).map_err(|_| OverlayError::InvalidKeyLink("malformed pubkey"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn key(byte: u8) -> SecretKey {
+        SecretKey::from_byte_array(&[byte; 32]).unwrap()
+    }
+
+    #[test]
+    fn follows_cross_signed_links() {
+        let secp = Secp256k1::new();
+        let links = [
+            KeyLink::new(1, 10, &key(1), &key(2)),
+            KeyLink::new(2, 20, &key(2), &key(3)),
+        ];
+
+        let genesis = key(1).public_key(&secp);
+        assert_eq!(follow(&genesis, &links).unwrap(), key(3).public_key(&secp));
+        // A member that was there for the second rotation only.
+        assert_eq!(
+            follow(&key(2).public_key(&secp), &links).unwrap(),
+            key(3).public_key(&secp)
+        );
+        // Someone else's rotations don't lead anywhere.
+        assert_eq!(
+            follow(&key(4).public_key(&secp), &links).unwrap(),
+            key(4).public_key(&secp)
+        );
+
+        // Taking over a cluster takes its key, the new key's signature alone isn't enough.
+        let mut hijacked = KeyLink::new(2, 20, &key(4), &key(4));
+        hijacked.old_pubkey = links[1].old_pubkey;
+        assert!(follow(&genesis, &[links[0].clone(), hijacked]).is_err());
+    }
+}
@@ -0,0 +1,327 @@
+//! Shamir sharing of the cluster secret.
+//!
+//! Instead of replicating the secret to every member, a member holding it splits it among the
+//! current members so that any `threshold` of them can reconstruct it and fewer learn nothing.
+//! Shares are over the secp256k1 scalar field and each dealing carries Feldman commitments to its
+//! polynomial: anyone holding a dealing can check a share against it, so shares that would
+//! reconstruct another secret are caught before they're used. Resharing, e.g. when membership
+//! changes, is a new dealing of the same secret to the new members, with a fresh polynomial so
+//! that the shares of the previous one don't combine with it.
+//!
+//! A dealing is signed under the cluster key of the secret it splits, see [`DealingSignature`], so
+//! that a member can tell a share of its cluster's secret from one a peer made up.
+
+use crate::error::OverlayError;
+use k256::{
+    elliptic_curve::{sec1::ToEncodedPoint, PrimeField},
+    ProjectivePoint, Scalar,
+};
+use mocks::attestation::hex_array;
+use secp256k1::{ecdsa::Signature, Message, PublicKey, Secp256k1, SecretKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+const DEALING_DOMAIN: &[u8] = b"tplus/dealing";
+const DEALING_SIGNATURE_DOMAIN: &[u8] = b"tplus/dealing-signature";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Share {
+    /// Where the polynomial was evaluated, the share is the one of `members[index - 1]`.
+    pub index: u16,
+    #[serde(with = "hex_array")]
+    pub value: [u8; 32],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Dealing {
+    /// Dealings since the secret was first split, members keep the share of the latest one.
+    pub generation: u64,
+    /// Shares needed to reconstruct the secret.
+    pub threshold: u16,
+    /// Node pubkeys of the members, in share order.
+    pub members: Vec<Vec<u8>>,
+    /// Compressed commitments to the polynomial's coefficients, the first one to the secret.
+    pub commitments: Vec<Vec<u8>>,
+}
+
+/// Compact ECDSA signature of a dealing under the cluster key of the secret it splits.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DealingSignature {
+    #[serde(with = "hex_array")]
+    pub pubkey: [u8; 33],
+    #[serde(with = "hex_array")]
+    pub signature: [u8; 64],
+}
+
+/// A dealing along with the share of one of its members.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DealtShare {
+    pub dealing: Dealing,
+    pub share: Share,
+}
+
+impl Dealing {
+    pub fn id(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(DEALING_DOMAIN);
+        hasher.update(self.generation.to_le_bytes());
+        hasher.update(self.threshold.to_le_bytes());
+        for member in &self.members {
+            hasher.update((member.len() as u64).to_le_bytes());
+            hasher.update(member);
+        }
+        for commitment in &self.commitments {
+            hasher.update(commitment);
+        }
+        hasher.finalize().into()
+    }
+
+    /// Signs the dealing under `signing_key`, the cluster key of the secret it splits.
+    pub fn sign(&self, signing_key: &SecretKey) -> DealingSignature {
+        let secp = Secp256k1::signing_only();
+        DealingSignature {
+            pubkey: signing_key.public_key(&secp).serialize(),
+            signature: secp
+                .sign_ecdsa(&self.signature_digest(), signing_key)
+                .serialize_compact(),
+        }
+    }
+
+    /// Checks `signature` is of this dealing, under its own pubkey: whether that's the key of the
+    /// dealt secret is up to the caller.
+    pub fn verify_signature(&self, signature: &DealingSignature) -> Result<(), OverlayError> {
+        let pubkey = PublicKey::from_slice(&signature.pubkey)
+            .map_err(|_| OverlayError::InvalidSharing("malformed dealer pubkey"))?;
+        Signature::from_compact(&signature.signature)
+            .and_then(|compact| {
+                Secp256k1::verification_only().verify_ecdsa(
+                    &self.signature_digest(),
+                    &compact,
+                    &pubkey,
+                )
+            })
+            .map_err(|_| OverlayError::InvalidSharing("bad dealing signature"))
+    }
+
+    fn signature_digest(&self) -> Message {
+        let mut hasher = Sha256::new();
+        hasher.update(DEALING_SIGNATURE_DOMAIN);
+        hasher.update(self.id());
+        Message::from_digest(hasher.finalize().into())
+    }
+
+    /// Index of the share dealt to `member`.
+    pub fn index_of(&self, member: &[u8]) -> Option<u16> {
+        self.members
+            .iter()
+            .position(|other| other == member)
+            .map(|position| position as u16 + 1)
+    }
+
+    /// Whether this is a dealing of `secret`.
+    pub fn shares_secret(&self, secret: &[u8]) -> Result<bool, OverlayError> {
+        let secret = secret
+            .try_into()
+            .map_err(|_| OverlayError::InvalidSharing("secrets are 32 bytes"))?;
+        let committed = self
+            .commitments
+            .first()
+            .ok_or(OverlayError::InvalidSharing("no commitments"))?;
+        Ok(*committed == encode_point(ProjectivePoint::GENERATOR * to_scalar(secret)?))
+    }
+
+    /// Checks `share` is on the committed polynomial.
+    pub fn verify_share(&self, share: &Share) -> Result<(), OverlayError> {
+        if self.commitments.len() != self.threshold as usize || self.threshold == 0 {
+            return Err(OverlayError::InvalidSharing(
+                "commitments don't match the threshold",
+            ));
+        }
+        if share.index == 0 || share.index as usize > self.members.len() {
+            return Err(OverlayError::InvalidSharing("share index out of range"));
+        }
+
+        if ProjectivePoint::GENERATOR * to_scalar(&share.value)?
+            != self.public_share(share.index)?
+        {
+            return Err(OverlayError::InvalidSharing(
+                "share doesn't match the dealing",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Commitment to the share at `index`, i.e. the commitments to the polynomial at `index`.
+    pub(crate) fn public_share(&self, index: u16) -> Result<ProjectivePoint, OverlayError> {
+        let index = Scalar::from(index as u64);
+        let mut public_share = ProjectivePoint::IDENTITY;
+        for commitment in self.commitments.iter().rev() {
+            public_share = public_share * index + decode_point(commitment)?;
+        }
+        Ok(public_share)
+    }
+}
+
+/// Splits `secret` among `members`, any `threshold` of them can reconstruct it.
+pub fn split(
+    secret: &[u8; 32],
+    generation: u64,
+    threshold: u16,
+    members: Vec<Vec<u8>>,
+) -> Result<(Dealing, Vec<Share>), OverlayError> {
+    if threshold == 0 || threshold as usize > members.len() {
+        return Err(OverlayError::InvalidSharing("threshold out of range"));
+    }
+
+    let coefficients: Vec<Scalar> = std::iter::once(to_scalar(secret))
+        .chain((1..threshold).map(|_| Ok(random_scalar())))
+        .collect::<Result<_, _>>()?;
+    let shares = (1..=members.len() as u16)
+        .map(|index| {
+            let index_scalar = Scalar::from(index as u64);
+            let value = coefficients
+                .iter()
+                .rev()
+                .fold(Scalar::ZERO, |acc, coefficient| {
+                    acc * index_scalar + coefficient
+                });
+            Share {
+                index,
+                value: value.to_bytes().into(),
+            }
+        })
+        .collect();
+    let dealing = Dealing {
+        generation,
+        threshold,
+        members,
+        commitments: coefficients
+            .iter()
+            .map(|coefficient| encode_point(ProjectivePoint::GENERATOR * coefficient))
+            .collect(),
+    };
+
+    Ok((dealing, shares))
+}
+
+/// Reconstructs the secret of `dealing` from at least `threshold` of its shares, all checked.
+pub fn reconstruct(dealing: &Dealing, shares: &[Share]) -> Result<[u8; 32], OverlayError> {
+    let mut distinct: Vec<&Share> = Vec::new();
+    for share in shares {
+        dealing.verify_share(share)?;
+        if distinct.iter().all(|other| other.index != share.index) {
+            distinct.push(share);
+        }
+    }
+    if distinct.len() < dealing.threshold as usize {
+        return Err(OverlayError::InvalidSharing("not enough shares"));
+    }
+
+    let quorum: Vec<u16> = distinct[..dealing.threshold as usize]
+        .iter()
+        .map(|share| share.index)
+        .collect();
+    let mut secret = Scalar::ZERO;
+    for share in &distinct[..quorum.len()] {
+        secret += lagrange(share.index, &quorum)? * to_scalar(&share.value)?;
+    }
+
+    Ok(secret.to_bytes().into())
+}
+
+/// Lagrange coefficient of the share at `index` among the ones at `quorum`, for the polynomial
+/// at 0.
+pub(crate) fn lagrange(index: u16, quorum: &[u16]) -> Result<Scalar, OverlayError> {
+    let index = Scalar::from(index as u64);
+    let mut coefficient = Scalar::ONE;
+    for other in quorum.iter().map(|other| Scalar::from(*other as u64)) {
+        if other == index {
+            continue;
+        }
+        let inverse = Option::<Scalar>::from((other - index).invert())
+            .ok_or(OverlayError::InvalidSharing("duplicate share index"))?;
+        coefficient *= other * inverse;
+    }
+    Ok(coefficient)
+}
+
+pub(crate) fn to_scalar(bytes: &[u8; 32]) -> Result<Scalar, OverlayError> {
+    Option::from(Scalar::from_repr((*bytes).into()))
+        .ok_or(OverlayError::InvalidSharing("not a scalar"))
+}
+
+pub(crate) fn random_scalar() -> Scalar {
+    let key = secp256k1::SecretKey::new(&mut secp256k1::rand::thread_rng());
+    Scalar::from_repr(key.secret_bytes().into()).expect("secret keys are scalars")
+}
+
+pub(crate) fn encode_point(point: ProjectivePoint) -> Vec<u8> {
+    point.to_affine().to_encoded_point(true).as_bytes().to_vec()
+}
+
+pub(crate) fn decode_point(bytes: &[u8]) -> Result<ProjectivePoint, OverlayError> {
+    k256::PublicKey::from_sec1_bytes(bytes)
+        .map(|point| point.to_projective())
+        .map_err(|_| OverlayError::InvalidSharing("malformed commitment"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn members(count: u8) -> Vec<Vec<u8>> {
+        (1..=count).map(|member| vec![member]).collect()
+    }
+
+    #[test]
+    fn reconstructs_from_a_threshold_of_shares() {
+        let secret = [7; 32];
+        let (dealing, shares) = split(&secret, 1, 3, members(5)).unwrap();
+        assert!(dealing.shares_secret(&secret).unwrap());
+        assert_eq!(dealing.index_of(&[4]), Some(4));
+        for share in &shares {
+            dealing.verify_share(share).unwrap();
+        }
+
+        assert_eq!(reconstruct(&dealing, &shares[2..]).unwrap(), secret);
+        assert_eq!(
+            reconstruct(
+                &dealing,
+                &[shares[4].clone(), shares[0].clone(), shares[2].clone()]
+            )
+            .unwrap(),
+            secret
+        );
+        // The same share twice doesn't make a quorum.
+        assert!(reconstruct(
+            &dealing,
+            &[shares[0].clone(), shares[0].clone(), shares[1].clone()]
+        )
+        .is_err());
+
+        let mut forged = shares[1].clone();
+        forged.value[31] ^= 1;
+        assert!(dealing.verify_share(&forged).is_err());
+        assert!(reconstruct(&dealing, &[forged, shares[2].clone(), shares[3].clone()]).is_err());
+
+        // Resharing to other members, the shares of both dealings don't mix.
+        let (resharing, reshares) = split(&secret, 2, 2, members(3)).unwrap();
+        assert_eq!(reconstruct(&resharing, &reshares[1..]).unwrap(), secret);
+        assert!(resharing.verify_share(&shares[0]).is_err());
+        assert!(split(&secret, 2, 4, members(3)).is_err());
+    }
+
+    #[test]
+    fn signs_dealings() {
+        let (dealing, _) = split(&[7; 32], 1, 2, members(3)).unwrap();
+        let (other, _) = split(&[7; 32], 1, 2, members(3)).unwrap();
+        let signature = dealing.sign(&SecretKey::from_slice(&[1; 32]).unwrap());
+        dealing.verify_signature(&signature).unwrap();
+        assert!(other.verify_signature(&signature).is_err());
+
+        let mut forged = signature.clone();
+        forged.pubkey = other.sign(&SecretKey::from_slice(&[2; 32]).unwrap()).pubkey;
+        assert!(dealing.verify_signature(&forged).is_err());
+    }
+}
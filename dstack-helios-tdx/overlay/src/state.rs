@@ -0,0 +1,146 @@
+//! State replicated across members, as a last-writer-wins map.
+//!
+//! Every entry carries the version it was written at, and members keep the highest version they
+//! saw of each key, so they converge on the same map whatever order writes reach them in, and
+//! however often: merging is commutative, associative and idempotent. Deletes are tombstones,
+//! kept so that an older write can't bring the key back. Versions are the writer's wall clock,
+//! bumped past the version it replaces so that a write always wins over what its writer saw, and
+//! ties go to the writer with the highest pubkey.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// When and by whom an entry was written, ordered by time then writer.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Version {
+    /// Unix time in milliseconds.
+    pub timestamp_ms: u64,
+    /// Node pubkey of the writer.
+    pub writer: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    /// `None` once deleted.
+    pub value: Option<Vec<u8>>,
+    pub version: Version,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplicatedMap {
+    entries: BTreeMap<String, Entry>,
+}
+
+impl ReplicatedMap {
+    pub fn get(&self, key: &str) -> Option<&[u8]> {
+        self.entries.get(key)?.value.as_deref()
+    }
+
+    /// Live keys and their values.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        self.entries
+            .iter()
+            .filter_map(|(key, entry)| Some((key.as_str(), entry.value.as_deref()?)))
+    }
+
+    /// Every entry, deleted ones included, to hand to a member catching up.
+    pub fn entries(&self) -> Vec<(String, Entry)> {
+        self.entries
+            .iter()
+            .map(|(key, entry)| (key.clone(), entry.clone()))
+            .collect()
+    }
+
+    /// Writes `value` to `key`, deletes it when `None`. Returns the entry to replicate.
+    pub fn write(
+        &mut self,
+        key: String,
+        value: Option<Vec<u8>>,
+        writer: Vec<u8>,
+        now_ms: u64,
+    ) -> Entry {
+        let timestamp_ms = match self.entries.get(&key) {
+            Some(current) => now_ms.max(current.version.timestamp_ms + 1),
+            None => now_ms,
+        };
+        let entry = Entry {
+            value,
+            version: Version {
+                timestamp_ms,
+                writer,
+            },
+        };
+        self.entries.insert(key, entry.clone());
+        entry
+    }
+
+    /// Takes `entry` if it's newer than ours, returns whether it was.
+    pub fn merge(&mut self, key: String, entry: Entry) -> bool {
+        match self.entries.get(&key) {
+            Some(current) if current.version >= entry.version => false,
+            _ => {
+                self.entries.insert(key, entry);
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn converges_whatever_the_order() {
+        let mut writer = ReplicatedMap::default();
+        let writes = [
+            (
+                "allowlist".to_string(),
+                writer.write("allowlist".into(), Some(b"a".to_vec()), vec![1], 1_000),
+            ),
+            (
+                "checkpoint".to_string(),
+                writer.write("checkpoint".into(), Some(b"0x01".to_vec()), vec![1], 1_000),
+            ),
+            // NB: behind the write it replaces, still wins.
+            (
+                "allowlist".to_string(),
+                writer.write("allowlist".into(), Some(b"b".to_vec()), vec![1], 900),
+            ),
+            (
+                "checkpoint".to_string(),
+                writer.write("checkpoint".into(), None, vec![1], 2_000),
+            ),
+        ];
+        assert_eq!(writer.get("allowlist"), Some(&b"b"[..]));
+        assert_eq!(writer.get("checkpoint"), None);
+
+        let mut forward = ReplicatedMap::default();
+        for (key, entry) in writes.iter().cloned() {
+            forward.merge(key, entry);
+        }
+        let mut backward = ReplicatedMap::default();
+        for (key, entry) in writes.iter().rev().cloned() {
+            backward.merge(key, entry);
+        }
+        // Duplicates don't change anything.
+        for (key, entry) in writes.iter().cloned() {
+            assert!(!backward.merge(key, entry));
+        }
+        assert_eq!(forward, writer);
+        assert_eq!(backward, writer);
+        assert_eq!(
+            writer.iter().collect::<Vec<_>>(),
+            vec![("allowlist", &b"b"[..])]
+        );
+
+        // Concurrent writes at the same time go to the highest writer.
+        let mut other = ReplicatedMap::default();
+        let theirs = other.write("peer-book".into(), Some(b"theirs".to_vec()), vec![2], 5_000);
+        let ours = writer.write("peer-book".into(), Some(b"ours".to_vec()), vec![1], 5_000);
+        assert!(writer.merge("peer-book".into(), theirs));
+        assert!(!other.merge("peer-book".into(), ours));
+        assert_eq!(writer.get("peer-book"), Some(&b"theirs"[..]));
+        assert_eq!(other.get("peer-book"), Some(&b"theirs"[..]));
+    }
+}
@@ -1,6 +1,6 @@
 use crate::message::OverlayMessage;
 use crate::quic::QUICTransport;
-use crate::{P2PTransportLayer, GLOB_CHANNEL_BUFFER};
+use crate::{OverlayContext, P2PTransportLayer, GLOB_CHANNEL_BUFFER};
 use secp256k1::SecretKey;
 use std::net::{Ipv4Addr, SocketAddr};
 use tokio::sync::broadcast::Sender;
@@ -9,6 +9,7 @@ use tokio::task::JoinHandle;
 
 pub async fn setup_overlay_from_config(
     secret_key: SecretKey,
+    overlay: OverlayContext,
     peers: Vec<String>,
     listen_port: u16,
 ) -> anyhow::Result<(
@@ -27,6 +28,7 @@ pub async fn setup_overlay_from_config(
 
     let handles = QUICTransport::forward_messages(
         secret_key,
+        overlay,
         (Ipv4Addr::UNSPECIFIED, listen_port).into(),
         peers.iter().copied().collect(),
         comms_sender,